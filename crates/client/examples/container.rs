@@ -19,9 +19,10 @@ use std::{fs, fs::File};
 use client::{
     services::v1::{
         container::Runtime, containers_client::ContainersClient, tasks_client::TasksClient,
-        Container, CreateContainerRequest, CreateTaskRequest, DeleteContainerRequest,
-        DeleteTaskRequest, StartRequest, WaitRequest,
+        Container, CreateContainerRequest, DeleteContainerRequest, DeleteTaskRequest, StartRequest,
+        WaitRequest,
     },
+    task::CreateTaskRequestBuilder,
     with_namespace,
 };
 use containerd_client as client;
@@ -92,13 +93,15 @@ async fn main() {
     // creat and start task
     let mut client = TasksClient::new(channel.clone());
 
-    let req = CreateTaskRequest {
-        container_id: CID.to_string(),
-        stdin: stdin.to_str().unwrap().to_string(),
-        stdout: stdout.to_str().unwrap().to_string(),
-        stderr: stderr.to_str().unwrap().to_string(),
-        ..Default::default()
-    };
+    // The rootfs is already prepared in the spec, so no mounts are needed here. Otherwise pass
+    // the mounts returned by the snapshotter for the container's snapshot key.
+    let req = CreateTaskRequestBuilder::new(CID, vec![])
+        .stdio(
+            stdin.to_str().unwrap(),
+            stdout.to_str().unwrap(),
+            stderr.to_str().unwrap(),
+        )
+        .build();
     let req = with_namespace!(req, NAMESPACE);
 
     let _resp = client.create(req).await.expect("Failed to create task");
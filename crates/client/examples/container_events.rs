@@ -15,7 +15,7 @@
 */
 
 use client::{
-    events::{ContainerCreate, ContainerDelete},
+    events::Event,
     services::v1::{events_client::EventsClient, SubscribeRequest},
 };
 use containerd_client as client;
@@ -38,51 +38,26 @@ async fn main() {
 
     loop {
         match response.message().await {
-            Ok(event) => {
-                if let Some(event) = event {
-                    match event.topic.as_str() {
-                        "/containers/create" => {
-                            if let Some(mut payload) = event.event {
-                                // Containerd doesn't send event payloads with a leading slash on the type URL, which is
-                                // required by the `Any` type specification. We add it manually here so that `prost` can
-                                // properly decode the payload.
-                                if !payload.type_url.starts_with('/') {
-                                    payload.type_url.insert(0, '/');
-                                }
-
-                                let payload: ContainerCreate = payload
-                                    .to_msg()
-                                    .expect("failed to parse ContainerCreate payload");
-
-                                println!(
-                                    "container created: id={} payload={:?}",
-                                    payload.id, payload
-                                );
-                            }
-                        }
-                        "/containers/delete" => {
-                            if let Some(mut payload) = event.event {
-                                // Containerd doesn't send event payloads with a leading slash on the type URL, which is
-                                // required by the `Any` type specification. We add it manually here so that `prost` can
-                                // properly decode the payload.
-                                if !payload.type_url.starts_with('/') {
-                                    payload.type_url.insert(0, '/');
-                                }
-
-                                let payload: ContainerDelete = payload
-                                    .to_msg()
-                                    .expect("failed to parse ContainerDelete payload");
-
-                                println!(
-                                    "container deleted: id={} payload={:?}",
-                                    payload.id, payload
-                                );
-                            }
-                        }
-                        _ => {}
-                    }
+            Ok(Some(envelope)) => match Event::try_from(envelope) {
+                Ok(Event::ContainerCreate(payload)) => {
+                    println!("container created: id={} payload={:?}", payload.id, payload);
                 }
-            }
+                Ok(Event::ContainerDelete(payload)) => {
+                    println!("container deleted: id={} payload={:?}", payload.id, payload);
+                }
+                Ok(Event::TaskExit(payload)) => {
+                    println!(
+                        "task exited: id={} pid={} status={}",
+                        payload.container_id, payload.pid, payload.exit_status
+                    );
+                }
+                Ok(Event::TaskOom(payload)) => {
+                    println!("task oom: id={}", payload.container_id);
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("failed to decode event: {:?}", e),
+            },
+            Ok(None) => {}
             Err(e) => {
                 eprintln!("error while streaming events: {:?}", e);
                 break;
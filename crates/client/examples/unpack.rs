@@ -0,0 +1,112 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use std::{collections::HashMap, fs, process::Command};
+
+use client::{
+    image, lease::LeaseGuard, services::v1::snapshots::RemoveSnapshotRequest, types::Mount,
+    with_namespace, Client,
+};
+use containerd_client as client;
+use runc::options::{DeleteOpts, GlobalOpts};
+use tonic::Request;
+
+const CID: &str = "unpack-example";
+const NAMESPACE: &str = "default";
+const IMAGE: &str = "docker.io/library/alpine:latest";
+const SNAPSHOTTER: &str = "overlayfs";
+
+/// Mount the snapshot at `target`, the same way containerd's `mount.All` would.
+fn mount_all(mounts: &[Mount], target: &str) {
+    for m in mounts {
+        let status = Command::new("mount")
+            .args([
+                "-t",
+                &m.r#type,
+                "-o",
+                &m.options.join(","),
+                &m.source,
+                target,
+            ])
+            .status()
+            .expect("Failed to run mount");
+        assert!(status.success(), "Failed to mount {:?}", m);
+    }
+}
+
+/// Make sure you run containerd before running this example, and pull the image first, e.g.
+/// `ctr image pull docker.io/library/alpine:latest`.
+/// NOTE: this example runs the container with the runc crate directly, so it needs to be run
+/// as root with `runc` in PATH.
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let client = Client::from_path("/run/containerd/containerd.sock")
+        .await
+        .expect("Connect Failed");
+
+    // Protects the snapshots from the garbage collector while we use them.
+    let lease = LeaseGuard::create(client.leases(), NAMESPACE, CID, HashMap::new())
+        .await
+        .expect("Failed to create lease");
+    let mounts = image::unpack(&client, NAMESPACE, IMAGE, SNAPSHOTTER, CID, lease.id())
+        .await
+        .expect("Failed to unpack image");
+
+    println!("Image: {:?} unpacked, mounts: {:?}", IMAGE, mounts);
+
+    let bundle = std::env::temp_dir().join("containerd-client-unpack");
+    let rootfs = bundle.join("rootfs");
+    fs::create_dir_all(&rootfs).expect("Failed to create rootfs directory");
+    mount_all(&mounts, rootfs.to_str().unwrap());
+
+    // the container will run with command `echo $output`
+    let spec = include_str!("container_spec.json")
+        .replace("$ROOTFS", rootfs.to_str().unwrap())
+        .replace("$OUTPUT", "hello from an unpacked image");
+    fs::write(bundle.join("config.json"), spec).expect("Failed to write config.json");
+
+    let runc = GlobalOpts::new()
+        .build()
+        .expect("Failed to create runc client");
+    runc.create(CID, &bundle, None)
+        .await
+        .expect("Failed to create container");
+    runc.start(CID).await.expect("Failed to start container");
+
+    println!("Container: {:?} started", CID);
+
+    // Give the container a moment to print, then clean everything up.
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    runc.delete(CID, Some(&DeleteOpts::new().force(true)))
+        .await
+        .expect("Failed to delete container");
+    let _ = Command::new("umount").arg(&rootfs).status();
+    let _ = fs::remove_dir_all(&bundle);
+
+    let req = RemoveSnapshotRequest {
+        snapshotter: SNAPSHOTTER.to_string(),
+        key: CID.to_string(),
+    };
+    let req = with_namespace!(req, NAMESPACE);
+    client
+        .snapshots()
+        .remove(req)
+        .await
+        .expect("Failed to remove snapshot");
+    lease.release().await.expect("Failed to release lease");
+
+    println!("Container: {:?} deleted", CID);
+}
@@ -0,0 +1,173 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Classification of the errors returned by containerd's services.
+
+use std::fmt;
+
+use tonic::{Code, Status};
+
+/// Error returned by a containerd service, classified from its gRPC status.
+///
+/// The original [Status] is kept, see [Error::status].
+#[derive(Debug)]
+pub enum Error {
+    /// The object (container, task, image...) doesn't exist.
+    NotFound(Status),
+    /// An object with the same id already exists.
+    AlreadyExists(Status),
+    /// The operation requires the task to be stopped, e.g. to delete it.
+    ContainerRunning(Status),
+    /// The object isn't in a state allowing the operation.
+    FailedPrecondition(Status),
+    /// The request is invalid, e.g. a malformed id or filter.
+    InvalidArgument(Status),
+    /// Containerd can't be reached or is shutting down, the call may be retried.
+    Unavailable(Status),
+    /// Any other failure.
+    Other(Status),
+}
+
+impl Error {
+    /// Original status returned by containerd.
+    pub fn status(&self) -> &Status {
+        match self {
+            Error::NotFound(s)
+            | Error::AlreadyExists(s)
+            | Error::ContainerRunning(s)
+            | Error::FailedPrecondition(s)
+            | Error::InvalidArgument(s)
+            | Error::Unavailable(s)
+            | Error::Other(s) => s,
+        }
+    }
+
+    pub fn into_status(self) -> Status {
+        match self {
+            Error::NotFound(s)
+            | Error::AlreadyExists(s)
+            | Error::ContainerRunning(s)
+            | Error::FailedPrecondition(s)
+            | Error::InvalidArgument(s)
+            | Error::Unavailable(s)
+            | Error::Other(s) => s,
+        }
+    }
+}
+
+impl From<Status> for Error {
+    fn from(s: Status) -> Self {
+        match s.code() {
+            Code::NotFound => Error::NotFound(s),
+            Code::AlreadyExists => Error::AlreadyExists(s),
+            Code::FailedPrecondition if is_running(s.message()) => Error::ContainerRunning(s),
+            Code::FailedPrecondition => Error::FailedPrecondition(s),
+            Code::InvalidArgument => Error::InvalidArgument(s),
+            Code::Unavailable => Error::Unavailable(s),
+            _ => Error::Other(s),
+        }
+    }
+}
+
+/// Whether `message` is one of containerd's "task is running" preconditions, e.g. "cannot
+/// delete running task abc123: failed precondition", rather than e.g. "task abc123 not running".
+fn is_running(message: &str) -> bool {
+    (message.contains("is running") || message.contains("running task"))
+        && !message.contains("not running")
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Error::NotFound(_) => "not found",
+            Error::AlreadyExists(_) => "already exists",
+            Error::ContainerRunning(_) => "container is running",
+            Error::FailedPrecondition(_) => "failed precondition",
+            Error::InvalidArgument(_) => "invalid argument",
+            Error::Unavailable(_) => "containerd unavailable",
+            Error::Other(_) => "containerd request failed",
+        };
+        write!(f, "{}: {}", kind, self.status().message())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.status())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_status() {
+        let cases = [
+            (Code::NotFound, "container \"abc\": not found", "not found"),
+            (
+                Code::AlreadyExists,
+                "id abc: already exists",
+                "already exists",
+            ),
+            (
+                Code::FailedPrecondition,
+                "cannot delete running task abc: failed precondition",
+                "container is running",
+            ),
+            (
+                Code::FailedPrecondition,
+                "snapshot is committed",
+                "failed precondition",
+            ),
+            (Code::InvalidArgument, "invalid id", "invalid argument"),
+            (
+                Code::Unavailable,
+                "connection refused",
+                "containerd unavailable",
+            ),
+            (Code::Internal, "boom", "containerd request failed"),
+        ];
+        for (code, message, kind) in cases {
+            let err = Error::from(Status::new(code, message));
+            assert_eq!(err.to_string(), format!("{}: {}", kind, message));
+            assert_eq!(err.status().code(), code);
+            assert_eq!(err.into_status().message(), message);
+        }
+
+        assert!(matches!(
+            Error::from(Status::not_found("")),
+            Error::NotFound(_)
+        ));
+        assert!(matches!(
+            Error::from(Status::failed_precondition("task abc is running")),
+            Error::ContainerRunning(_)
+        ));
+        assert!(matches!(
+            Error::from(Status::failed_precondition("")),
+            Error::FailedPrecondition(_)
+        ));
+        for message in [
+            "container abc is not running",
+            "task abc not running: failed precondition",
+        ] {
+            assert!(matches!(
+                Error::from(Status::failed_precondition(message)),
+                Error::FailedPrecondition(_)
+            ));
+        }
+    }
+}
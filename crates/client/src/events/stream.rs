@@ -0,0 +1,314 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use std::{fmt, time::Duration};
+
+use tonic::{
+    body::BoxBody,
+    client::GrpcService,
+    codec::Streaming,
+    codegen::{Body, Bytes, StdError},
+    Status,
+};
+
+use super::Event;
+use crate::{
+    filter::Filter,
+    services::v1::{
+        events_client::EventsClient, version_client::VersionClient, Envelope, SubscribeRequest,
+    },
+};
+
+/// Error returned by [EventStream::next] and [wait_for_event].
+#[derive(Debug)]
+pub enum StreamError {
+    /// The subscription failed.
+    Status(Status),
+    /// No event was received for the given duration and containerd didn't answer the liveness
+    /// check either. The stream should be dropped and the caller should subscribe again.
+    StreamStalled(Duration),
+    /// Containerd closed the stream before a matching event was received.
+    Closed,
+    /// No matching event was received within the given duration.
+    Timeout(Duration),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamError::Status(s) => write!(f, "event stream failed: {}", s),
+            StreamError::StreamStalled(d) => write!(f, "event stream stalled for {:?}", d),
+            StreamError::Closed => write!(f, "event stream closed"),
+            StreamError::Timeout(d) => write!(f, "no matching event received within {:?}", d),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {}
+
+impl From<Status> for StreamError {
+    fn from(s: Status) -> Self {
+        StreamError::Status(s)
+    }
+}
+
+/// Event subscription detecting streams which silently stopped delivering events.
+///
+/// Containerd may legitimately stay quiet for a long time, so when no event arrives within the
+/// idle timeout, the liveness of containerd is checked with a `Version` call (if a probe client
+/// was set) before reporting [StreamError::StreamStalled]. Without a probe, the idle timeout alone
+/// decides.
+pub struct EventStream<T> {
+    inner: Streaming<Envelope>,
+    idle_timeout: Duration,
+    probe: Option<VersionClient<T>>,
+}
+
+impl<T> EventStream<T>
+where
+    T: GrpcService<BoxBody>,
+    T::Error: Into<StdError>,
+    T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+    /// Subscribe to the events matching `req`, waiting at most `idle_timeout` for each event.
+    pub async fn subscribe(
+        client: &mut EventsClient<T>,
+        req: SubscribeRequest,
+        idle_timeout: Duration,
+    ) -> Result<Self, Status> {
+        let inner = client.subscribe(req).await?.into_inner();
+        Ok(Self {
+            inner,
+            idle_timeout,
+            probe: None,
+        })
+    }
+
+    /// Check containerd is alive with `client` before declaring the stream stalled.
+    pub fn probe(mut self, client: VersionClient<T>) -> Self {
+        self.probe = Some(client);
+        self
+    }
+
+    /// Wait for the next event, `None` means containerd closed the stream.
+    pub async fn next(&mut self) -> Result<Option<Envelope>, StreamError> {
+        loop {
+            match tokio::time::timeout(self.idle_timeout, self.inner.message()).await {
+                Ok(res) => return Ok(res?),
+                Err(_) => {
+                    if !self.alive().await {
+                        return Err(StreamError::StreamStalled(self.idle_timeout));
+                    }
+                }
+            }
+        }
+    }
+
+    async fn alive(&mut self) -> bool {
+        let probe = match self.probe.as_mut() {
+            Some(probe) => probe,
+            None => return false,
+        };
+        matches!(
+            tokio::time::timeout(self.idle_timeout, probe.version(())).await,
+            Ok(Ok(_))
+        )
+    }
+}
+
+/// Wait at most `timeout` for the first event matching both `filter` and `predicate`.
+///
+/// The subscription only lasts for the call: it's dropped, and thus cancelled, once an event
+/// matches or the wait fails. Events on topics unknown to [Event] are passed to `predicate` as
+/// [Event::Other], while events which can't be decoded are skipped.
+pub async fn wait_for_event<T, P>(
+    client: &mut EventsClient<T>,
+    filter: Filter,
+    timeout: Duration,
+    mut predicate: P,
+) -> Result<Event, StreamError>
+where
+    T: GrpcService<BoxBody>,
+    T::Error: Into<StdError>,
+    T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+    P: FnMut(&Event) -> bool,
+{
+    let wait = async {
+        let req = SubscribeRequest {
+            filters: vec![filter.into()],
+        };
+        let mut stream = client.subscribe(req).await?.into_inner();
+        while let Some(envelope) = stream.message().await? {
+            let topic = envelope.topic.clone();
+            match Event::try_from(envelope) {
+                Ok(event) if predicate(&event) => return Ok(event),
+                Ok(_) => {}
+                Err(e) => log::debug!("skipping undecodable event on {}: {}", topic, e),
+            }
+        }
+        Err(StreamError::Closed)
+    };
+    tokio::time::timeout(timeout, wait)
+        .await
+        .map_err(|_| StreamError::Timeout(timeout))?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use prost_types::Any;
+    use tonic::codegen::{http, Service};
+
+    use super::*;
+    use crate::{events::TaskExit, testutil::grpc_frame};
+
+    /// Response body sending one gRPC message and then hanging, like a dead stream.
+    struct StallingBody(Option<Bytes>);
+
+    impl Body for StallingBody {
+        type Data = Bytes;
+        type Error = Status;
+
+        fn poll_data(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Bytes, Status>>> {
+            match self.0.take() {
+                Some(data) => Poll::Ready(Some(Ok(data))),
+                None => Poll::Pending,
+            }
+        }
+
+        fn poll_trailers(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<Option<http::HeaderMap>, Status>> {
+            Poll::Pending
+        }
+    }
+
+    /// Fake events service sending its events to subscribers, and never answering anything else.
+    #[derive(Clone)]
+    struct MockEvents(Vec<Envelope>);
+
+    impl Service<http::Request<BoxBody>> for MockEvents {
+        type Response = http::Response<BoxBody>;
+        type Error = Status;
+        type Future = std::future::Ready<Result<Self::Response, Status>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Status>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: http::Request<BoxBody>) -> Self::Future {
+            let data = if req.uri().path() == "/containerd.services.events.v1.Events/Subscribe" {
+                Some(self.0.iter().flat_map(grpc_frame).collect())
+            } else {
+                None
+            };
+            let resp = http::Response::builder()
+                .header("content-type", "application/grpc")
+                .body(BoxBody::new(StallingBody(data)))
+                .unwrap();
+            std::future::ready(Ok(resp))
+        }
+    }
+
+    fn exit_envelope(container_id: &str, exit_status: u32) -> Envelope {
+        let exit = TaskExit {
+            container_id: container_id.to_string(),
+            exit_status,
+            ..Default::default()
+        };
+        let mut payload = Any::from_msg(&exit).unwrap();
+        payload.type_url = payload.type_url.trim_start_matches('/').to_string();
+        Envelope {
+            topic: "/tasks/exit".to_string(),
+            namespace: "default".to_string(),
+            event: Some(payload),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn stalled_stream_is_detected() {
+        let events = MockEvents(vec![Envelope {
+            topic: "/tasks/exit".to_string(),
+            namespace: "default".to_string(),
+            ..Default::default()
+        }]);
+        let mut client = EventsClient::new(events.clone());
+        let mut stream = EventStream::subscribe(
+            &mut client,
+            SubscribeRequest::default(),
+            Duration::from_millis(100),
+        )
+        .await
+        .unwrap()
+        .probe(VersionClient::new(events));
+
+        let event = stream.next().await.unwrap().unwrap();
+        assert_eq!(event.topic, "/tasks/exit");
+
+        match stream.next().await {
+            Err(StreamError::StreamStalled(d)) => assert_eq!(d, Duration::from_millis(100)),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn wait_for_matching_event() {
+        let mut undecodable = exit_envelope("abc123", 1);
+        undecodable.event.as_mut().unwrap().value = vec![0xff];
+        let mut client = EventsClient::new(MockEvents(vec![
+            exit_envelope("other", 1),
+            undecodable,
+            exit_envelope("abc123", 137),
+            exit_envelope("abc123", 0),
+        ]));
+
+        let event = wait_for_event(
+            &mut client,
+            Filter::topic_eq("/tasks/exit"),
+            Duration::from_secs(5),
+            |e| e.container_id() == Some("abc123"),
+        )
+        .await
+        .unwrap();
+        match event {
+            Event::TaskExit(exit) => assert_eq!(exit.exit_status, 137),
+            other => panic!("unexpected event: {:?}", other),
+        }
+
+        let res = wait_for_event(
+            &mut client,
+            Filter::topic_eq("/tasks/exit"),
+            Duration::from_millis(100),
+            |e| e.container_id() == Some("missing"),
+        )
+        .await;
+        match res {
+            Err(StreamError::Timeout(d)) => assert_eq!(d, Duration::from_millis(100)),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,282 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use prost::{DecodeError, Name};
+use prost_types::Any;
+
+use super::{ContainerCreate, ContainerDelete, ContainerUpdate, TaskExit, TaskOom};
+use crate::services::v1::Envelope;
+
+/// An event received from containerd, decoded according to its topic.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// `/containers/create`
+    ContainerCreate(ContainerCreate),
+    /// `/containers/update`
+    ContainerUpdate(ContainerUpdate),
+    /// `/containers/delete`
+    ContainerDelete(ContainerDelete),
+    /// `/tasks/exit`, sent when a process of a task exits.
+    TaskExit(TaskExit),
+    /// `/tasks/oom`, sent when the cgroup of a task hits its memory limit.
+    TaskOom(TaskOom),
+    /// Any other topic, with the payload left as is.
+    Other { topic: String, payload: Any },
+}
+
+impl Event {
+    /// Decode `payload` of an event published on `topic`.
+    pub fn decode(topic: &str, mut payload: Any) -> Result<Self, DecodeError> {
+        if let Some(event) = Self::decode_known(topic, &payload) {
+            return event;
+        }
+        // Containerd doesn't send event payloads with a leading slash on the type URL, which is
+        // required by the `Any` type specification. Add it so that `prost` can decode the payload.
+        if !payload.type_url.starts_with('/') {
+            payload.type_url.insert(0, '/');
+        }
+        Ok(Self::Other {
+            topic: topic.to_string(),
+            payload,
+        })
+    }
+
+    /// Decode `payload` if `topic` is one of the known topics, without copying it.
+    fn decode_known(topic: &str, payload: &Any) -> Option<Result<Self, DecodeError>> {
+        let event = match topic {
+            "/containers/create" => decode_payload(payload).map(Self::ContainerCreate),
+            "/containers/update" => decode_payload(payload).map(Self::ContainerUpdate),
+            "/containers/delete" => decode_payload(payload).map(Self::ContainerDelete),
+            "/tasks/exit" => decode_payload(payload).map(Self::TaskExit),
+            "/tasks/oom" => decode_payload(payload).map(Self::TaskOom),
+            _ => return None,
+        };
+        Some(event)
+    }
+
+    /// Id of the container the event is about, if any.
+    pub fn container_id(&self) -> Option<&str> {
+        match self {
+            Self::ContainerCreate(e) => Some(&e.id),
+            Self::ContainerUpdate(e) => Some(&e.id),
+            Self::ContainerDelete(e) => Some(&e.id),
+            Self::TaskExit(e) => Some(&e.container_id),
+            Self::TaskOom(e) => Some(&e.container_id),
+            Self::Other { .. } => None,
+        }
+    }
+
+    /// Pid of the process the event is about, if any.
+    pub fn pid(&self) -> Option<u32> {
+        match self {
+            Self::TaskExit(e) => Some(e.pid),
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<Envelope> for Event {
+    type Error = DecodeError;
+
+    fn try_from(envelope: Envelope) -> Result<Self, Self::Error> {
+        let payload = envelope
+            .event
+            .ok_or_else(|| DecodeError::new(format!("event {} has no payload", envelope.topic)))?;
+        Self::decode(&envelope.topic, payload)
+    }
+}
+
+/// Decode `payload` as an `M`, checking its type URL like [Any::to_msg] does but with or without
+/// the leading slash.
+fn decode_payload<M: Name + Default>(payload: &Any) -> Result<M, DecodeError> {
+    let full_name = payload.type_url.rsplit('/').next().unwrap_or_default();
+    let name = full_name
+        .strip_prefix(M::PACKAGE)
+        .and_then(|name| name.strip_prefix('.'));
+    if name != Some(M::NAME) {
+        return Err(DecodeError::new(format!(
+            "expected type URL: \"/{}.{}\" (got: \"{}\")",
+            M::PACKAGE,
+            M::NAME,
+            payload.type_url
+        )));
+    }
+    M::decode(payload.value.as_slice())
+}
+
+/// What an [EventDecoder] does with events on topics not known to [Event].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownTopic {
+    /// Drop them.
+    Skip,
+    /// Fail the whole batch.
+    Error,
+    /// Return them as [Event::Other].
+    Collect,
+}
+
+/// Decoder of batches of events, for subscribers receiving many of them.
+///
+/// Known payloads are decoded in place rather than copied first, unknown ones are only copied
+/// when collected, and the decoded events are buffered in a vector reused across batches.
+#[derive(Debug)]
+pub struct EventDecoder {
+    unknown: UnknownTopic,
+    events: Vec<Event>,
+}
+
+impl EventDecoder {
+    pub fn new(unknown: UnknownTopic) -> Self {
+        Self {
+            unknown,
+            events: Vec::new(),
+        }
+    }
+
+    /// Decode `envelopes`, in order.
+    ///
+    /// Fails on the first envelope without payload, with a payload not matching its topic or, with
+    /// [UnknownTopic::Error], with an unknown topic.
+    pub fn decode<'a>(
+        &'a mut self,
+        envelopes: &[Envelope],
+    ) -> Result<std::vec::Drain<'a, Event>, DecodeError> {
+        self.events.clear();
+        self.events.reserve(envelopes.len());
+        for envelope in envelopes {
+            let payload = envelope.event.as_ref().ok_or_else(|| {
+                DecodeError::new(format!("event {} has no payload", envelope.topic))
+            })?;
+            match Event::decode_known(&envelope.topic, payload) {
+                Some(event) => self.events.push(event?),
+                None => match self.unknown {
+                    UnknownTopic::Skip => {}
+                    UnknownTopic::Error => {
+                        return Err(DecodeError::new(format!(
+                            "unknown event topic {}",
+                            envelope.topic
+                        )))
+                    }
+                    UnknownTopic::Collect => self
+                        .events
+                        .push(Event::decode(&envelope.topic, payload.clone())?),
+                },
+            }
+        }
+        Ok(self.events.drain(..))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a payload the way containerd sends it, without a leading slash in the type URL.
+    fn containerd_any<M: Name>(msg: &M) -> Any {
+        let mut any = Any::from_msg(msg).unwrap();
+        any.type_url = any.type_url.trim_start_matches('/').to_string();
+        any
+    }
+
+    #[test]
+    fn decode_task_exit() {
+        let exit = TaskExit {
+            container_id: "abc123".to_string(),
+            id: "abc123".to_string(),
+            pid: 4242,
+            exit_status: 137,
+            exited_at: Some(prost_types::Timestamp {
+                seconds: 1700000000,
+                nanos: 0,
+            }),
+        };
+        let envelope = Envelope {
+            topic: "/tasks/exit".to_string(),
+            namespace: "default".to_string(),
+            event: Some(containerd_any(&exit)),
+            ..Default::default()
+        };
+
+        let event = Event::try_from(envelope).unwrap();
+        assert_eq!(event.container_id(), Some("abc123"));
+        assert_eq!(event.pid(), Some(4242));
+        assert_eq!(event, Event::TaskExit(exit));
+    }
+
+    #[test]
+    fn decode_task_oom() {
+        let oom = TaskOom {
+            container_id: "abc123".to_string(),
+        };
+        let event = Event::decode("/tasks/oom", containerd_any(&oom)).unwrap();
+        assert_eq!(event.container_id(), Some("abc123"));
+        assert_eq!(event.pid(), None);
+        assert_eq!(event, Event::TaskOom(oom));
+
+        // The payload must match the topic.
+        let exit = TaskExit::default();
+        assert!(Event::decode("/tasks/oom", containerd_any(&exit)).is_err());
+    }
+
+    #[test]
+    fn decode_batch() {
+        let exit = TaskExit {
+            container_id: "abc123".to_string(),
+            pid: 4242,
+            ..Default::default()
+        };
+        let oom = TaskOom {
+            container_id: "abc123".to_string(),
+        };
+        let unknown = containerd_any(&TaskOom::default());
+        let envelope = |topic: &str, event: Any| Envelope {
+            topic: topic.to_string(),
+            event: Some(event),
+            ..Default::default()
+        };
+        let envelopes = vec![
+            envelope("/tasks/exit", containerd_any(&exit)),
+            envelope("/tasks/paused", unknown.clone()),
+            envelope("/tasks/oom", containerd_any(&oom)),
+        ];
+
+        let mut decoder = EventDecoder::new(UnknownTopic::Skip);
+        let events: Vec<_> = decoder.decode(&envelopes).unwrap().collect();
+        assert_eq!(
+            events,
+            vec![Event::TaskExit(exit.clone()), Event::TaskOom(oom.clone())]
+        );
+
+        let mut decoder = EventDecoder::new(UnknownTopic::Collect);
+        let events: Vec<_> = decoder.decode(&envelopes).unwrap().collect();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0], Event::TaskExit(exit));
+        assert!(matches!(&events[1], Event::Other { topic, .. } if topic == "/tasks/paused"));
+        assert_eq!(events[2], Event::TaskOom(oom));
+        // The buffer is reused by the next batch.
+        assert_eq!(decoder.decode(&envelopes[1..2]).unwrap().count(), 1);
+
+        let mut decoder = EventDecoder::new(UnknownTopic::Error);
+        assert!(decoder.decode(&envelopes).is_err());
+        assert_eq!(decoder.decode(&envelopes[..1]).unwrap().count(), 1);
+
+        // Payloads must still match their topic.
+        let mismatch = [envelope("/tasks/exit", unknown)];
+        assert!(EventDecoder::new(UnknownTopic::Skip)
+            .decode(&mismatch)
+            .is_err());
+    }
+}
@@ -0,0 +1,238 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Builder for containerd filter expressions, as used by `filters` fields of `List` and
+//! `Subscribe` requests.
+//!
+//! A filter is a conjunction of selectors, `Filter::topic_eq("/tasks/exit")
+//! .and(Filter::namespace_eq("default"))` renders to `topic=="/tasks/exit",namespace=="default"`.
+//! Requests match if any of the filters in their `filters` list matches, so a disjunction is
+//! expressed by passing several filters.
+//!
+//! See <https://github.com/containerd/containerd/blob/main/filters/filter.go>
+
+use std::fmt;
+
+/// Comparison applied by a selector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    /// `==`
+    Equal,
+    /// `!=`
+    NotEqual,
+    /// `~=`, the value is a regular expression.
+    Matches,
+}
+
+impl Operator {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Operator::Equal => "==",
+            Operator::NotEqual => "!=",
+            Operator::Matches => "~=",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Selector {
+    field: Vec<String>,
+    op: Operator,
+    value: String,
+}
+
+/// A conjunction of selectors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    selectors: Vec<Selector>,
+}
+
+impl Filter {
+    /// Compare the field at `path` (e.g. `["labels", "io.containerd.image.name"]`) with `value`.
+    ///
+    /// Path components are quoted as needed, so they may contain dots or other special characters.
+    pub fn new<I, S>(path: I, op: Operator, value: impl Into<String>) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            selectors: vec![Selector {
+                field: path.into_iter().map(Into::into).collect(),
+                op,
+                value: value.into(),
+            }],
+        }
+    }
+
+    /// `field == value`
+    pub fn eq(field: &str, value: impl Into<String>) -> Self {
+        Self::new([field], Operator::Equal, value)
+    }
+
+    /// `field != value`
+    pub fn ne(field: &str, value: impl Into<String>) -> Self {
+        Self::new([field], Operator::NotEqual, value)
+    }
+
+    /// `field ~= regex`
+    pub fn matches(field: &str, regex: impl Into<String>) -> Self {
+        Self::new([field], Operator::Matches, regex)
+    }
+
+    /// Match values of `field` starting with `prefix`, using `~=` with an escaped regular
+    /// expression.
+    pub fn prefix(field: &str, prefix: &str) -> Self {
+        Self::matches(field, format!("^{}", escape_regex(prefix)))
+    }
+
+    /// `topic == topic`, for event subscriptions.
+    pub fn topic_eq(topic: impl Into<String>) -> Self {
+        Self::eq("topic", topic)
+    }
+
+    /// Match event topics starting with `prefix`, e.g. `/tasks/`.
+    pub fn topic_prefix(prefix: &str) -> Self {
+        Self::prefix("topic", prefix)
+    }
+
+    /// `namespace == namespace`, for event subscriptions.
+    pub fn namespace_eq(namespace: impl Into<String>) -> Self {
+        Self::eq("namespace", namespace)
+    }
+
+    /// `id == id`
+    pub fn id_eq(id: impl Into<String>) -> Self {
+        Self::eq("id", id)
+    }
+
+    /// `labels.key == value`
+    pub fn label_eq(key: impl Into<String>, value: impl Into<String>) -> Self {
+        Self::new(["labels".to_string(), key.into()], Operator::Equal, value)
+    }
+
+    /// Require both `self` and `other` to match.
+    pub fn and(mut self, other: Filter) -> Self {
+        self.selectors.extend(other.selectors);
+        self
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, s) in self.selectors.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            for (j, component) in s.field.iter().enumerate() {
+                if j > 0 {
+                    f.write_str(".")?;
+                }
+                if is_plain(component) {
+                    f.write_str(component)?;
+                } else {
+                    write_quoted(f, component)?;
+                }
+            }
+            f.write_str(s.op.as_str())?;
+            write_quoted(f, &s.value)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Filter> for String {
+    fn from(filter: Filter) -> Self {
+        filter.to_string()
+    }
+}
+
+/// Whether `s` can be used as a field path component without quoting.
+fn is_plain(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn write_quoted(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\t' => f.write_str("\\t")?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+fn escape_regex(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_equality() {
+        assert_eq!(
+            Filter::topic_eq("/tasks/exit").to_string(),
+            r#"topic=="/tasks/exit""#
+        );
+        assert_eq!(
+            Filter::ne("image", "busybox").to_string(),
+            r#"image!="busybox""#
+        );
+        assert_eq!(
+            Filter::label_eq("io.containerd.image.name", "say \"hi\"").to_string(),
+            r#"labels."io.containerd.image.name"=="say \"hi\"""#
+        );
+    }
+
+    #[test]
+    fn test_prefix() {
+        assert_eq!(
+            Filter::topic_prefix("/tasks/").to_string(),
+            r#"topic~="^/tasks/""#
+        );
+        assert_eq!(
+            Filter::prefix("name", "docker.io/").to_string(),
+            r#"name~="^docker\\.io/""#
+        );
+    }
+
+    #[test]
+    fn test_conjunction() {
+        let filter = Filter::topic_eq("/tasks/exit")
+            .and(Filter::namespace_eq("default"))
+            .and(Filter::topic_prefix("/tasks/"));
+        let rendered: String = filter.into();
+        assert_eq!(
+            rendered,
+            r#"topic=="/tasks/exit",namespace=="default",topic~="^/tasks/""#
+        );
+    }
+}
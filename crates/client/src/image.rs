@@ -0,0 +1,697 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Helpers to turn an image stored in containerd into a root filesystem, and to read the
+//! configuration of its containers with [image_config].
+//!
+//! [unpack] relies on the following containerd plugins:
+//! - `io.containerd.grpc.v1.images` and `io.containerd.grpc.v1.content`, to resolve the image
+//!   manifest and config,
+//! - `io.containerd.grpc.v1.diff`, with a differ able to apply the layer media types
+//!   (`io.containerd.differ.v1.walking` handles the standard tar and tar+gzip layers),
+//! - `io.containerd.grpc.v1.snapshots`, with the snapshotter passed to [unpack]
+//!   (e.g. `io.containerd.snapshotter.v1.overlayfs`).
+
+use std::collections::HashMap;
+
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+use sha2::{Digest, Sha256};
+use tonic::{
+    body::BoxBody,
+    client::GrpcService,
+    codegen::{Body, Bytes, StdError},
+    Code, Request, Status,
+};
+
+use crate::{
+    error::Error,
+    lease::with_lease,
+    services::v1::{
+        content_client::ContentClient,
+        snapshots::{
+            CommitSnapshotRequest, PrepareSnapshotRequest, RemoveSnapshotRequest,
+            StatSnapshotRequest,
+        },
+        ApplyRequest, GetImageRequest, ReadContentRequest,
+    },
+    types::{Descriptor, Mount},
+    with_namespace, Client,
+};
+
+const MEDIA_TYPE_OCI_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+const MEDIA_TYPE_DOCKER_MANIFEST_LIST: &str =
+    "application/vnd.docker.distribution.manifest.list.v2+json";
+const MEDIA_TYPE_OCI_CONFIG: &str = "application/vnd.oci.image.config.v1+json";
+const MEDIA_TYPE_DOCKER_CONFIG: &str = "application/vnd.docker.container.image.v1+json";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonDescriptor {
+    media_type: String,
+    digest: String,
+    size: i64,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+    #[serde(default)]
+    platform: Option<Platform>,
+}
+
+impl From<JsonDescriptor> for Descriptor {
+    fn from(d: JsonDescriptor) -> Self {
+        Descriptor {
+            media_type: d.media_type,
+            digest: d.digest,
+            size: d.size,
+            annotations: d.annotations,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Platform {
+    architecture: String,
+    os: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Index {
+    manifests: Vec<JsonDescriptor>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    config: JsonDescriptor,
+    layers: Vec<JsonDescriptor>,
+}
+
+/// Configuration of an image, as stored in its config blob.
+///
+/// See <https://github.com/opencontainers/image-spec/blob/main/config.md>.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImageConfig {
+    #[serde(default)]
+    pub architecture: String,
+    #[serde(default)]
+    pub os: String,
+    /// Defaults for the process of the containers run from the image.
+    #[serde(default)]
+    pub config: Option<ContainerConfig>,
+    pub rootfs: RootFs,
+}
+
+/// Layers of an image.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RootFs {
+    /// Digests of the uncompressed layers, from the bottom one.
+    pub diff_ids: Vec<String>,
+}
+
+/// Defaults for the process of the containers run from an image, from which a runtime spec's
+/// `Process` is built.
+///
+/// Fields are empty when the image doesn't set them, including when they are `null`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerConfig {
+    /// User the process runs as: `user`, `uid`, `user:group` or `uid:gid`, root if empty.
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub user: String,
+    /// Environment variables, as `KEY=value`.
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub env: Vec<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub entrypoint: Vec<String>,
+    /// Default arguments, appended to the entrypoint.
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub cmd: Vec<String>,
+    /// Current directory of the process, `/` if empty.
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub working_dir: String,
+    /// Signal to send to stop the process, e.g. `SIGTERM`.
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub stop_signal: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub labels: HashMap<String, String>,
+}
+
+impl ContainerConfig {
+    /// Arguments of the process: the entrypoint followed by the command.
+    pub fn args(&self) -> Vec<String> {
+        [&self.entrypoint[..], &self.cmd[..]].concat()
+    }
+}
+
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Read the whole blob `digest` from the content store.
+pub async fn read_content(
+    client: &Client,
+    namespace: &str,
+    digest: &str,
+) -> Result<Vec<u8>, Error> {
+    Ok(read_blob(&mut client.content(), namespace, digest).await?)
+}
+
+async fn read_blob<T>(
+    client: &mut ContentClient<T>,
+    namespace: &str,
+    digest: &str,
+) -> Result<Vec<u8>, Status>
+where
+    T: GrpcService<BoxBody>,
+    T::Error: Into<StdError>,
+    T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+    let req = ReadContentRequest {
+        digest: digest.to_string(),
+        ..Default::default()
+    };
+    let req = with_namespace!(req, namespace);
+    let mut stream = client.read(req).await?.into_inner();
+    let mut data = Vec::new();
+    while let Some(resp) = stream.message().await? {
+        data.extend_from_slice(&resp.data);
+    }
+    Ok(data)
+}
+
+async fn read_json<T: DeserializeOwned>(
+    client: &Client,
+    namespace: &str,
+    digest: &str,
+) -> Result<T, Status> {
+    let data = read_blob(&mut client.content(), namespace, digest).await?;
+    serde_json::from_slice(&data)
+        .map_err(|e| Status::internal(format!("failed to parse blob {}: {}", digest, e)))
+}
+
+/// Read and decode the image config blob described by `config`, e.g. the config descriptor of
+/// an image's manifest.
+pub async fn read_image_config<T>(
+    client: &mut ContentClient<T>,
+    namespace: &str,
+    config: &Descriptor,
+) -> Result<ImageConfig, Error>
+where
+    T: GrpcService<BoxBody>,
+    T::Error: Into<StdError>,
+    T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+    if config.media_type != MEDIA_TYPE_OCI_CONFIG && config.media_type != MEDIA_TYPE_DOCKER_CONFIG {
+        return Err(Status::invalid_argument(format!(
+            "blob {} of media type {} is not an image config",
+            config.digest, config.media_type
+        ))
+        .into());
+    }
+    let data = read_blob(client, namespace, &config.digest).await?;
+    Ok(serde_json::from_slice(&data)
+        .map_err(|e| Status::internal(format!("failed to parse blob {}: {}", config.digest, e)))?)
+}
+
+/// Read the config of `image`, for the host's platform if it's a multi-platform image.
+pub async fn image_config(
+    client: &Client,
+    namespace: &str,
+    image: &str,
+) -> Result<ImageConfig, Error> {
+    let manifest = read_manifest(client, namespace, image).await?;
+    read_image_config(&mut client.content(), namespace, &manifest.config.into()).await
+}
+
+/// Read the manifest of `image`, selecting the one of the host's platform from an index.
+async fn read_manifest(client: &Client, namespace: &str, image: &str) -> Result<Manifest, Status> {
+    let req = GetImageRequest {
+        name: image.to_string(),
+    };
+    let req = with_namespace!(req, namespace);
+    let target = client
+        .images()
+        .get(req)
+        .await?
+        .into_inner()
+        .image
+        .and_then(|i| i.target)
+        .ok_or_else(|| Status::not_found(format!("image {} has no target", image)))?;
+
+    let mut manifest_digest = target.digest;
+    if target.media_type == MEDIA_TYPE_OCI_INDEX
+        || target.media_type == MEDIA_TYPE_DOCKER_MANIFEST_LIST
+    {
+        let index: Index = read_json(client, namespace, &manifest_digest).await?;
+        manifest_digest = select_manifest(index.manifests)
+            .ok_or_else(|| {
+                Status::not_found(format!(
+                    "image {} has no manifest for linux/{}",
+                    image,
+                    host_arch()
+                ))
+            })?
+            .digest;
+    }
+    read_json(client, namespace, &manifest_digest).await
+}
+
+/// Unpack the layers of `image` with `snapshotter` and prepare an active snapshot `key` on top of
+/// them, returning the mounts of the prepared snapshot.
+///
+/// Layers already unpacked by a previous call (or by containerd itself) are reused. For
+/// multi-platform images, the manifest matching the host's OS and architecture is used.
+///
+/// The snapshots created are attached to `lease`, which must exist (see
+/// [LeaseGuard](crate::lease::LeaseGuard)): nothing references them yet, so containerd's garbage
+/// collector would otherwise remove them, possibly before the unpack completes. Keep the lease
+/// until a container references snapshot `key`.
+pub async fn unpack(
+    client: &Client,
+    namespace: &str,
+    image: &str,
+    snapshotter: &str,
+    key: &str,
+    lease: &str,
+) -> Result<Vec<Mount>, Error> {
+    let manifest = read_manifest(client, namespace, image).await?;
+    let config: ImageConfig = read_json(client, namespace, &manifest.config.digest).await?;
+    if config.rootfs.diff_ids.len() != manifest.layers.len() {
+        return Err(Status::failed_precondition(format!(
+            "image {} has {} layers but {} diff ids",
+            image,
+            manifest.layers.len(),
+            config.rootfs.diff_ids.len()
+        ))
+        .into());
+    }
+
+    let mut parent = String::new();
+    let chain = chain_ids(&config.rootfs.diff_ids);
+    for (layer, chain_id) in manifest.layers.into_iter().zip(chain) {
+        let req = StatSnapshotRequest {
+            snapshotter: snapshotter.to_string(),
+            key: chain_id.clone(),
+        };
+        let req = with_namespace!(req, namespace);
+        match client.snapshots().stat(req).await {
+            Ok(_) => {}
+            Err(s) if s.code() == Code::NotFound => {
+                apply_layer(
+                    client,
+                    namespace,
+                    lease,
+                    snapshotter,
+                    key,
+                    &parent,
+                    &chain_id,
+                    layer.into(),
+                )
+                .await?;
+            }
+            Err(s) => return Err(s.into()),
+        }
+        parent = chain_id;
+    }
+
+    let req = PrepareSnapshotRequest {
+        snapshotter: snapshotter.to_string(),
+        key: key.to_string(),
+        parent,
+        ..Default::default()
+    };
+    let req = with_lease(with_namespace!(req, namespace), lease);
+    Ok(client.snapshots().prepare(req).await?.into_inner().mounts)
+}
+
+/// Apply `layer` on top of `parent` and commit the result as `chain_id`, attaching the snapshots
+/// to `lease`.
+#[allow(clippy::too_many_arguments)]
+async fn apply_layer(
+    client: &Client,
+    namespace: &str,
+    lease: &str,
+    snapshotter: &str,
+    key: &str,
+    parent: &str,
+    chain_id: &str,
+    layer: Descriptor,
+) -> Result<(), Status> {
+    let extract_key = format!("extract-{}-{}", key, chain_id);
+    let req = PrepareSnapshotRequest {
+        snapshotter: snapshotter.to_string(),
+        key: extract_key.clone(),
+        parent: parent.to_string(),
+        ..Default::default()
+    };
+    let req = with_lease(with_namespace!(req, namespace), lease);
+    let mounts = client.snapshots().prepare(req).await?.into_inner().mounts;
+
+    let req = ApplyRequest {
+        diff: Some(layer),
+        mounts,
+        ..Default::default()
+    };
+    let req = with_lease(with_namespace!(req, namespace), lease);
+    let res = match client.diff().apply(req).await {
+        Ok(_) => {
+            let req = CommitSnapshotRequest {
+                snapshotter: snapshotter.to_string(),
+                name: chain_id.to_string(),
+                key: extract_key.clone(),
+                ..Default::default()
+            };
+            let req = with_lease(with_namespace!(req, namespace), lease);
+            match client.snapshots().commit(req).await {
+                // Someone else unpacked the same layer concurrently.
+                Err(s) if s.code() == Code::AlreadyExists => Ok(()),
+                res => res.map(|_| ()),
+            }
+        }
+        Err(s) => Err(s),
+    };
+    if res.is_err() {
+        let req = RemoveSnapshotRequest {
+            snapshotter: snapshotter.to_string(),
+            key: extract_key,
+        };
+        let req = with_namespace!(req, namespace);
+        let _ = client.snapshots().remove(req).await;
+    }
+    res
+}
+
+/// Compute the chain ids of the layers from their uncompressed digests.
+///
+/// See <https://github.com/opencontainers/image-spec/blob/main/config.md#layer-chainid>
+fn chain_ids(diff_ids: &[String]) -> Vec<String> {
+    let mut chain: Vec<String> = Vec::with_capacity(diff_ids.len());
+    for diff_id in diff_ids {
+        let id = match chain.last() {
+            None => diff_id.clone(),
+            Some(parent) => {
+                let digest = Sha256::digest(format!("{} {}", parent, diff_id).as_bytes());
+                let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+                format!("sha256:{}", hex)
+            }
+        };
+        chain.push(id);
+    }
+    chain
+}
+
+fn select_manifest(manifests: Vec<JsonDescriptor>) -> Option<JsonDescriptor> {
+    let arch = host_arch();
+    manifests.into_iter().find(|m| {
+        m.platform
+            .as_ref()
+            .map(|p| p.os == "linux" && p.architecture == arch)
+            .unwrap_or(false)
+    })
+}
+
+/// Return the host architecture with the naming used by OCI images (GOARCH).
+fn host_arch() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "x86" => "386",
+        "aarch64" => "arm64",
+        "powerpc64" => "ppc64le",
+        "loongarch64" => "loong64",
+        arch => arch,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::task::{Context, Poll};
+
+    use tonic::codegen::{http, Service};
+
+    use super::*;
+    use crate::{
+        services::v1::ReadContentResponse,
+        testutil::{grpc_error, grpc_response},
+    };
+
+    const CONFIG_DIGEST: &str =
+        "sha256:46331d942d6350436f64e614d75725f6de3bb5c63e266e236e04389820a234c4";
+
+    /// Config blob of a docker image, which sets the unused fields to `null`.
+    const CONFIG_BLOB: &str = r#"{
+        "architecture": "amd64",
+        "os": "linux",
+        "config": {
+            "User": "nginx:nginx",
+            "Env": ["PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin"],
+            "Entrypoint": ["/docker-entrypoint.sh"],
+            "Cmd": ["nginx", "-g", "daemon off;"],
+            "WorkingDir": "",
+            "Labels": null,
+            "StopSignal": "SIGQUIT",
+            "ExposedPorts": {"80/tcp": {}}
+        },
+        "rootfs": {
+            "type": "layers",
+            "diff_ids": ["sha256:a", "sha256:b"]
+        }
+    }"#;
+
+    /// Fake content service storing the config blob in the "test" namespace.
+    #[derive(Clone)]
+    struct MockContent;
+
+    impl Service<http::Request<BoxBody>> for MockContent {
+        type Response = http::Response<BoxBody>;
+        type Error = Status;
+        type Future = std::future::Ready<Result<Self::Response, Status>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Status>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: http::Request<BoxBody>) -> Self::Future {
+            let ns = req.headers().get("containerd-namespace");
+            if req.uri().path() != "/containerd.services.content.v1.Content/Read"
+                || ns.map(|v| v.as_bytes()) != Some(b"test")
+            {
+                return std::future::ready(Ok(grpc_error(Code::NotFound)));
+            }
+            std::future::ready(Ok(grpc_response(&ReadContentResponse {
+                offset: 0,
+                data: CONFIG_BLOB.as_bytes().to_vec(),
+            })))
+        }
+    }
+
+    #[tokio::test]
+    async fn read_image_config_decodes_blob() {
+        let mut client = ContentClient::new(MockContent);
+        let mut descriptor = Descriptor {
+            media_type: MEDIA_TYPE_DOCKER_CONFIG.to_string(),
+            digest: CONFIG_DIGEST.to_string(),
+            size: CONFIG_BLOB.len() as i64,
+            annotations: HashMap::new(),
+        };
+
+        let image = read_image_config(&mut client, "test", &descriptor)
+            .await
+            .unwrap();
+        assert_eq!(
+            (image.os.as_str(), image.architecture.as_str()),
+            ("linux", "amd64")
+        );
+        assert_eq!(image.rootfs.diff_ids, vec!["sha256:a", "sha256:b"]);
+        let config = image.config.unwrap();
+        assert_eq!(config.user, "nginx:nginx");
+        assert_eq!(
+            config.env,
+            vec!["PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin"]
+        );
+        assert_eq!(
+            config.args(),
+            vec!["/docker-entrypoint.sh", "nginx", "-g", "daemon off;"]
+        );
+        assert_eq!(config.working_dir, "");
+        assert_eq!(config.stop_signal, "SIGQUIT");
+        assert!(config.labels.is_empty());
+
+        let err = read_image_config(&mut client, "other", &descriptor)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::NotFound(_)), "{}", err);
+
+        descriptor.media_type = "application/vnd.oci.image.layer.v1.tar".to_string();
+        let err = read_image_config(&mut client, "test", &descriptor)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidArgument(_)), "{}", err);
+    }
+
+    #[cfg(all(unix, feature = "connect"))]
+    #[tokio::test]
+    async fn unpack_attaches_lease() {
+        use std::sync::{Arc, Mutex};
+
+        use crate::{
+            services::v1::{
+                snapshots::PrepareSnapshotResponse, ApplyResponse, GetImageResponse, Image,
+            },
+            testutil::{grpc_request, serve},
+        };
+
+        const MANIFEST_DIGEST: &str = "sha256:manifest";
+        let manifest = format!(
+            r#"{{
+                "config": {{"mediaType": "{}", "digest": "{}", "size": 0}},
+                "layers": [
+                    {{"mediaType": "application/vnd.oci.image.layer.v1.tar", "digest": "sha256:a", "size": 0}},
+                    {{"mediaType": "application/vnd.oci.image.layer.v1.tar", "digest": "sha256:b", "size": 0}}
+                ]
+            }}"#,
+            MEDIA_TYPE_OCI_CONFIG, CONFIG_DIGEST
+        );
+
+        // Fake containerd recording the calls along with their lease, without layers unpacked.
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+        let path = std::env::temp_dir().join(format!(
+            "containerd-client-unpack-{}.sock",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        let (server, _) = serve(listener, move |req| {
+            let calls = recorded.clone();
+            let manifest = manifest.clone();
+            async move {
+                let method = req.uri().path().rsplit('/').next().unwrap().to_string();
+                let lease = req
+                    .headers()
+                    .get(crate::lease::LEASE_HEADER)
+                    .map(|v| v.to_str().unwrap().to_string());
+                calls.lock().unwrap().push((method.clone(), lease));
+                match method.as_str() {
+                    "Get" => grpc_response(&GetImageResponse {
+                        image: Some(Image {
+                            name: "test".to_string(),
+                            target: Some(Descriptor {
+                                media_type: "application/vnd.oci.image.manifest.v1+json"
+                                    .to_string(),
+                                digest: MANIFEST_DIGEST.to_string(),
+                                ..Default::default()
+                            }),
+                            ..Default::default()
+                        }),
+                    }),
+                    "Read" => {
+                        let req: ReadContentRequest = grpc_request(req.into_body()).await;
+                        let data = match req.digest.as_str() {
+                            MANIFEST_DIGEST => manifest,
+                            _ => CONFIG_BLOB.to_string(),
+                        };
+                        grpc_response(&ReadContentResponse {
+                            offset: 0,
+                            data: data.into_bytes(),
+                        })
+                    }
+                    "Stat" => grpc_error(Code::NotFound),
+                    "Prepare" => grpc_response(&PrepareSnapshotResponse { mounts: vec![] }),
+                    "Apply" => grpc_response(&ApplyResponse { applied: None }),
+                    "Commit" => grpc_response(&()),
+                    _ => grpc_error(Code::Unimplemented),
+                }
+            }
+        });
+
+        let client = Client::from(crate::connect(&path).await.unwrap());
+        unpack(&client, "test", "test", "overlayfs", "key", "unpack-1")
+            .await
+            .unwrap();
+        server.abort();
+        let _ = std::fs::remove_file(&path);
+
+        let calls = calls.lock().unwrap();
+        let leased: Vec<&str> = calls
+            .iter()
+            .filter(|(_, lease)| lease.as_deref() == Some("unpack-1"))
+            .map(|(method, _)| method.as_str())
+            .collect();
+        assert_eq!(
+            leased,
+            vec!["Prepare", "Apply", "Commit", "Prepare", "Apply", "Commit", "Prepare"]
+        );
+        // Reads don't create anything to protect.
+        assert!(calls
+            .iter()
+            .all(|(method, lease)| leased.contains(&method.as_str()) == lease.is_some()));
+    }
+
+    #[test]
+    fn test_chain_ids() {
+        let diff_ids = vec![
+            "sha256:a".to_string(),
+            "sha256:b".to_string(),
+            "sha256:c".to_string(),
+        ];
+        assert_eq!(
+            chain_ids(&diff_ids),
+            vec![
+                "sha256:a".to_string(),
+                "sha256:970a948bffa8de94d6e22d747ba8c95030e6e546909f98f54e99a13005e173a8"
+                    .to_string(),
+                "sha256:dee159b33409cefa3f6eda71539f006014283e9d9130c18a1dea7bd8a33c9b1b"
+                    .to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_select_manifest() {
+        let index: Index = serde_json::from_str(&format!(
+            r#"{{
+                "manifests": [
+                    {{
+                        "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                        "digest": "sha256:other",
+                        "size": 1,
+                        "platform": {{"architecture": "riscv-none", "os": "linux"}}
+                    }},
+                    {{
+                        "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                        "digest": "sha256:windows",
+                        "size": 1,
+                        "platform": {{"architecture": "{arch}", "os": "windows"}}
+                    }},
+                    {{
+                        "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                        "digest": "sha256:host",
+                        "size": 1,
+                        "platform": {{"architecture": "{arch}", "os": "linux"}}
+                    }}
+                ]
+            }}"#,
+            arch = host_arch()
+        ))
+        .unwrap();
+        let m = select_manifest(index.manifests).unwrap();
+        assert_eq!(m.digest, "sha256:host");
+    }
+}
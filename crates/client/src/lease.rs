@@ -0,0 +1,286 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Helpers to hold containerd leases.
+//!
+//! Content and snapshots which are not referenced by an image or a container are removed by
+//! containerd's garbage collector, possibly while they are still being written or unpacked.
+//! Requests carrying a lease id (see [with_lease]) attach the resources they create to that
+//! lease, which protects them until the lease is deleted.
+
+use std::collections::HashMap;
+
+use tonic::{
+    body::BoxBody,
+    client::GrpcService,
+    codegen::{Body, Bytes, StdError},
+    Request, Status,
+};
+
+use crate::{
+    error::Error,
+    services::v1::{leases_client::LeasesClient, CreateRequest, DeleteRequest, Lease},
+    with_namespace,
+};
+
+/// gRPC metadata key containerd reads the lease id of a request from.
+// https://github.com/containerd/containerd/blob/main/leases/grpc.go#L27
+pub const LEASE_HEADER: &str = "containerd-lease";
+
+/// Label telling containerd's garbage collector when to remove the lease, in RFC 3339 format.
+pub const LEASE_EXPIRE_LABEL: &str = "containerd.io/gc.expire";
+
+/// Attach the resources created by `req` to lease `id`.
+///
+/// Panics if `id` is not a valid metadata value, like [with_namespace] does for namespaces.
+pub fn with_lease<T>(mut req: Request<T>, id: &str) -> Request<T> {
+    req.metadata_mut().insert(LEASE_HEADER, id.parse().unwrap());
+    req
+}
+
+/// Create lease `id` in `namespace`.
+pub async fn create_lease<T>(
+    client: &mut LeasesClient<T>,
+    namespace: &str,
+    id: &str,
+    labels: HashMap<String, String>,
+) -> Result<Lease, Error>
+where
+    T: GrpcService<BoxBody>,
+    T::Error: Into<StdError>,
+    T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+    let req = CreateRequest {
+        id: id.to_string(),
+        labels,
+    };
+    let req = with_namespace!(req, namespace);
+    let lease = client
+        .create(req)
+        .await?
+        .into_inner()
+        .lease
+        .ok_or_else(|| Status::internal(format!("lease {} was not returned", id)))?;
+    Ok(lease)
+}
+
+/// Delete lease `id` in `namespace`.
+///
+/// With `sync`, the call returns only after the garbage collection of the released resources.
+pub async fn delete_lease<T>(
+    client: &mut LeasesClient<T>,
+    namespace: &str,
+    id: &str,
+    sync: bool,
+) -> Result<(), Error>
+where
+    T: GrpcService<BoxBody>,
+    T::Error: Into<StdError>,
+    T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+    let req = DeleteRequest {
+        id: id.to_string(),
+        sync,
+    };
+    let req = with_namespace!(req, namespace);
+    client.delete(req).await?;
+    Ok(())
+}
+
+/// A lease deleted when the guard goes out of scope.
+///
+/// Prefer [LeaseGuard::release] to observe the result of the deletion. Dropping the guard
+/// spawns the deletion on the current Tokio runtime, if any, and ignores its result.
+#[cfg(feature = "connect")]
+pub struct LeaseGuard<T>
+where
+    T: GrpcService<BoxBody> + Send + 'static,
+    T::Error: Into<StdError>,
+    T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+    T::Future: Send,
+{
+    client: Option<LeasesClient<T>>,
+    namespace: String,
+    lease: Lease,
+}
+
+#[cfg(feature = "connect")]
+impl<T> LeaseGuard<T>
+where
+    T: GrpcService<BoxBody> + Send + 'static,
+    T::Error: Into<StdError>,
+    T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+    T::Future: Send,
+{
+    /// Create lease `id` in `namespace` and guard it.
+    pub async fn create(
+        mut client: LeasesClient<T>,
+        namespace: &str,
+        id: &str,
+        labels: HashMap<String, String>,
+    ) -> Result<Self, Error> {
+        let lease = create_lease(&mut client, namespace, id, labels).await?;
+        Ok(Self {
+            client: Some(client),
+            namespace: namespace.to_string(),
+            lease,
+        })
+    }
+
+    /// Id of the lease, to pass to [with_lease].
+    pub fn id(&self) -> &str {
+        &self.lease.id
+    }
+
+    /// The lease as returned by containerd.
+    pub fn lease(&self) -> &Lease {
+        &self.lease
+    }
+
+    /// Delete the lease now.
+    pub async fn release(mut self) -> Result<(), Error> {
+        match self.client.take() {
+            Some(mut client) => {
+                delete_lease(&mut client, &self.namespace, &self.lease.id, false).await
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "connect")]
+impl<T> Drop for LeaseGuard<T>
+where
+    T: GrpcService<BoxBody> + Send + 'static,
+    T::Error: Into<StdError>,
+    T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+    T::Future: Send,
+{
+    fn drop(&mut self) {
+        let mut client = match self.client.take() {
+            Some(client) => client,
+            None => return,
+        };
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let namespace = std::mem::take(&mut self.namespace);
+            let id = std::mem::take(&mut self.lease.id);
+            handle.spawn(async move {
+                let _ = delete_lease(&mut client, &namespace, &id, false).await;
+            });
+        }
+    }
+}
+
+#[cfg(all(test, feature = "connect"))]
+mod tests {
+    use std::{
+        future::Future,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll},
+        time::Duration,
+    };
+
+    use tonic::codegen::{http, Service};
+
+    use super::*;
+    use crate::{
+        services::v1::CreateResponse,
+        testutil::{grpc_error, grpc_request, grpc_response},
+    };
+
+    /// Fake lease service recording the ids of the deleted leases.
+    #[derive(Clone, Default)]
+    struct MockLeases {
+        deleted: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Service<http::Request<BoxBody>> for MockLeases {
+        type Response = http::Response<BoxBody>;
+        type Error = Status;
+        type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Status>> + Send>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Status>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: http::Request<BoxBody>) -> Self::Future {
+            let deleted = self.deleted.clone();
+            Box::pin(async move {
+                let path = req.uri().path().to_string();
+                let body = req.into_body();
+                match path.as_str() {
+                    "/containerd.services.leases.v1.Leases/Create" => {
+                        let req: CreateRequest = grpc_request(body).await;
+                        let lease = Lease {
+                            id: req.id,
+                            labels: req.labels,
+                            ..Default::default()
+                        };
+                        Ok(grpc_response(&CreateResponse { lease: Some(lease) }))
+                    }
+                    "/containerd.services.leases.v1.Leases/Delete" => {
+                        let req: DeleteRequest = grpc_request(body).await;
+                        deleted.lock().unwrap().push(req.id);
+                        Ok(grpc_response(&()))
+                    }
+                    _ => Ok(grpc_error(tonic::Code::Unimplemented)),
+                }
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn lease_guard_released_on_drop() {
+        let mock = MockLeases::default();
+        let client = LeasesClient::new(mock.clone());
+
+        let guard = LeaseGuard::create(client.clone(), "test", "pull-1", HashMap::new())
+            .await
+            .unwrap();
+        assert_eq!(guard.id(), "pull-1");
+        assert!(mock.deleted.lock().unwrap().is_empty());
+        drop(guard);
+
+        for _ in 0..100 {
+            if !mock.deleted.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(*mock.deleted.lock().unwrap(), vec!["pull-1".to_string()]);
+
+        let guard = LeaseGuard::create(client, "test", "pull-2", HashMap::new())
+            .await
+            .unwrap();
+        guard.release().await.unwrap();
+        assert_eq!(
+            *mock.deleted.lock().unwrap(),
+            vec!["pull-1".to_string(), "pull-2".to_string()]
+        );
+    }
+
+    #[test]
+    fn with_lease_sets_metadata() {
+        let req = with_lease(Request::new(()), "pull-1");
+        assert_eq!(req.metadata().get(LEASE_HEADER).unwrap(), "pull-1");
+    }
+}
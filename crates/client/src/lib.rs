@@ -73,8 +73,23 @@ pub mod services {
 /// Generated event types.
 pub mod events {
     tonic::include_proto!("containerd.events");
+
+    #[cfg(feature = "connect")]
+    mod stream;
+    mod typed;
+    #[cfg(feature = "connect")]
+    pub use stream::{wait_for_event, EventStream, StreamError};
+    pub use typed::{Event, EventDecoder, UnknownTopic};
 }
 
+pub mod error;
+pub mod filter;
+pub mod image;
+pub mod lease;
+pub mod task;
+#[cfg(test)]
+mod testutil;
+
 /// Connect creates a unix channel to containerd GRPC socket.
 ///
 /// This helper inteded to be used in conjuction with [Tokio](https://tokio.rs) runtime.
@@ -110,6 +125,59 @@ pub async fn connect(
     Ok(channel)
 }
 
+/// Return a channel to containerd GRPC socket, shared with all the other callers using the same path.
+///
+/// Tonic channels are multiplexed, so high-frequency callers don't need to open a new connection
+/// for every operation. The first callers for a path share a single connection attempt, which is
+/// retried by the next caller if it fails. Before a connected channel is handed out again, it's
+/// checked with a `Version` call: a channel failing it, e.g. because containerd restarted, is
+/// dropped and a new one is connected.
+#[cfg(feature = "connect")]
+pub async fn shared_channel(
+    path: impl AsRef<std::path::Path>,
+) -> Result<tonic::transport::Channel, tonic::transport::Error> {
+    use std::{
+        collections::HashMap,
+        path::PathBuf,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use tokio::sync::OnceCell;
+    use tonic::transport::Channel;
+
+    const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+    type Cells = HashMap<PathBuf, Arc<OnceCell<Channel>>>;
+    static CHANNELS: Mutex<Option<Cells>> = Mutex::new(None);
+    let cell = |path: &PathBuf| {
+        CHANNELS
+            .lock()
+            .unwrap()
+            .get_or_insert_with(HashMap::new)
+            .entry(path.clone())
+            .or_default()
+            .clone()
+    };
+
+    let path = path.as_ref().to_path_buf();
+    let mut shared = cell(&path);
+    if let Some(channel) = shared.get() {
+        let mut probe = VersionClient::new(channel.clone());
+        if let Ok(Ok(_)) = tokio::time::timeout(PROBE_TIMEOUT, probe.version(())).await {
+            return Ok(channel.clone());
+        }
+        // Evict the broken channel, unless another caller already replaced it.
+        if let Some(cells) = CHANNELS.lock().unwrap().as_mut() {
+            if cells.get(&path).map_or(false, |c| Arc::ptr_eq(c, &shared)) {
+                cells.remove(&path);
+            }
+        }
+        shared = cell(&path);
+    }
+    shared.get_or_try_init(|| connect(&path)).await.cloned()
+}
+
 /// Help to inject namespace into request.
 ///
 /// To use this macro, the `tonic::Request` is needed.
@@ -277,4 +345,86 @@ mod tests {
 
         assert_eq!(original, decoded)
     }
+
+    /// Serve containerd's version service on a new socket at `path`, see [crate::testutil::serve].
+    #[cfg(all(unix, feature = "connect"))]
+    fn serve_version(
+        path: &std::path::Path,
+    ) -> (
+        tokio::task::JoinHandle<()>,
+        std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    ) {
+        use crate::{services::v1::VersionResponse, testutil::grpc_response};
+
+        let _ = std::fs::remove_file(path);
+        let listener = tokio::net::UnixListener::bind(path).expect("failed to bind socket");
+        crate::testutil::serve(listener, |_| async {
+            grpc_response(&VersionResponse {
+                version: "v1.7.0".to_string(),
+                revision: String::new(),
+            })
+        })
+    }
+
+    #[cfg(all(unix, feature = "connect"))]
+    #[tokio::test]
+    async fn shared_channel_reuse() {
+        use std::sync::atomic::Ordering;
+
+        let path = std::env::temp_dir().join(format!(
+            "containerd-client-shared-{}.sock",
+            std::process::id()
+        ));
+        let (server, accepted) = serve_version(&path);
+
+        // Concurrent first callers share the connection.
+        let (a, b) = tokio::join!(crate::shared_channel(&path), crate::shared_channel(&path));
+        a.expect("failed to connect");
+        b.expect("failed to connect");
+        for _ in 0..3 {
+            crate::shared_channel(&path)
+                .await
+                .expect("failed to connect");
+        }
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+
+        server.abort();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(all(unix, feature = "connect"))]
+    #[tokio::test]
+    async fn shared_channel_rebuilt_after_server_drop() {
+        use std::sync::atomic::Ordering;
+
+        let path = std::env::temp_dir().join(format!(
+            "containerd-client-rebuilt-{}.sock",
+            std::process::id()
+        ));
+        let (server, accepted) = serve_version(&path);
+        crate::shared_channel(&path)
+            .await
+            .expect("failed to connect");
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+
+        // The cached channel fails its check and can't be replaced while the server is gone.
+        server.abort();
+        let _ = server.await;
+        let _ = std::fs::remove_file(&path);
+        assert!(crate::shared_channel(&path).await.is_err());
+
+        // A fresh channel is connected to the new server.
+        let (server, accepted) = serve_version(&path);
+        let channel = crate::shared_channel(&path)
+            .await
+            .expect("failed to connect");
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+        crate::services::v1::version_client::VersionClient::new(channel)
+            .version(())
+            .await
+            .expect("failed to call the new server");
+
+        server.abort();
+        let _ = std::fs::remove_file(&path);
+    }
 }
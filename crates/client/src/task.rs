@@ -0,0 +1,455 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Helpers to drive containerd's task service.
+
+use std::time::SystemTime;
+
+use prost_types::Any;
+use tonic::{
+    body::BoxBody,
+    client::GrpcService,
+    codegen::{Body, Bytes, StdError},
+    Request, Status,
+};
+
+use crate::{
+    error::Error,
+    services::v1::{
+        tasks_client::TasksClient, CreateTaskRequest, PauseTaskRequest, ResumeTaskRequest,
+        WaitRequest,
+    },
+    types::{Descriptor, Mount},
+    with_namespace,
+};
+
+/// Builder for [CreateTaskRequest].
+///
+/// Note that the bundle directory of the task is managed by containerd and the shim, so it's not
+/// part of the request. The rootfs is described by `mounts`, typically the ones returned by the
+/// snapshotter's `Prepare` or `Mounts` calls for the container's snapshot key.
+#[derive(Debug, Clone, Default)]
+pub struct CreateTaskRequestBuilder {
+    req: CreateTaskRequest,
+}
+
+impl CreateTaskRequestBuilder {
+    /// Create a new builder for the task of `container_id`, with `rootfs` as pre-chroot mounts.
+    pub fn new(container_id: impl Into<String>, rootfs: Vec<Mount>) -> Self {
+        Self {
+            req: CreateTaskRequest {
+                container_id: container_id.into(),
+                rootfs,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Set the paths (usually FIFOs) for the task's stdio, empty paths are ignored by containerd.
+    pub fn stdio(
+        mut self,
+        stdin: impl Into<String>,
+        stdout: impl Into<String>,
+        stderr: impl Into<String>,
+    ) -> Self {
+        self.req.stdin = stdin.into();
+        self.req.stdout = stdout.into();
+        self.req.stderr = stderr.into();
+        self
+    }
+
+    /// Allocate a terminal for the task.
+    pub fn terminal(mut self, terminal: bool) -> Self {
+        self.req.terminal = terminal;
+        self
+    }
+
+    /// Restore the task from the given checkpoint.
+    pub fn checkpoint(mut self, checkpoint: Descriptor) -> Self {
+        self.req.checkpoint = Some(checkpoint);
+        self
+    }
+
+    /// Set already packed runtime options.
+    pub fn options_any(mut self, options: Any) -> Self {
+        self.req.options = Some(options);
+        self
+    }
+
+    /// Pack `options` (e.g. runc's `containerd.runc.v1.Options`) into an [Any] and set them as
+    /// runtime options.
+    pub fn options<M: prost::Name>(self, options: &M) -> Result<Self, prost::EncodeError> {
+        let any = Any::from_msg(options)?;
+        Ok(self.options_any(any))
+    }
+
+    /// Override the path of the runtime binary.
+    pub fn runtime_path(mut self, path: impl Into<String>) -> Self {
+        self.req.runtime_path = path.into();
+        self
+    }
+
+    pub fn build(self) -> CreateTaskRequest {
+        self.req
+    }
+}
+
+/// Wait for the init process of task `id` in `namespace` to exit.
+///
+/// Returns the exit code and the time the process exited at. The `Wait` RPC only returns once
+/// the process is gone, so this may block for as long as the task is running.
+pub async fn wait_task<T>(
+    client: &mut TasksClient<T>,
+    namespace: &str,
+    id: &str,
+) -> Result<(u32, SystemTime), Error>
+where
+    T: GrpcService<BoxBody>,
+    T::Error: Into<StdError>,
+    T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+    let req = WaitRequest {
+        container_id: id.to_string(),
+        ..Default::default()
+    };
+    let req = with_namespace!(req, namespace);
+    let resp = client.wait(req).await?.into_inner();
+    let exited_at = resp
+        .exited_at
+        .ok_or_else(|| Status::internal(format!("task {} exited without a timestamp", id)))?;
+    let exited_at = SystemTime::try_from(exited_at)
+        .map_err(|e| Status::internal(format!("invalid exit timestamp of task {}: {}", id, e)))?;
+    Ok((resp.exit_status, exited_at))
+}
+
+/// Readers of the stdout and stderr of a task, see [TaskOutput::open].
+#[cfg(all(unix, feature = "connect"))]
+#[derive(Debug)]
+pub struct TaskOutput {
+    pub stdout: Option<tokio::net::unix::pipe::Receiver>,
+    pub stderr: Option<tokio::net::unix::pipe::Receiver>,
+}
+
+#[cfg(all(unix, feature = "connect"))]
+impl TaskOutput {
+    /// Open the stdout and stderr FIFOs of the task created by `req`, the streams without path
+    /// being `None`.
+    ///
+    /// The FIFOs are created by the caller (e.g. with `mkfifo`) before the task is created, and
+    /// can be removed once they are opened by both sides. They are opened without waiting for
+    /// the shim, which opens their write ends while creating the task: call this before the
+    /// `Create` call. Reading then waits for the shim to write, and reaches EOF once the task
+    /// exited and the shim closed its ends. Dropping the readers before gives up on the output.
+    pub fn open(req: &CreateTaskRequest) -> std::io::Result<Self> {
+        Ok(Self {
+            stdout: open_fifo(&req.stdout)?,
+            stderr: open_fifo(&req.stderr)?,
+        })
+    }
+}
+
+#[cfg(all(unix, feature = "connect"))]
+fn open_fifo(path: &str) -> std::io::Result<Option<tokio::net::unix::pipe::Receiver>> {
+    if path.is_empty() {
+        return Ok(None);
+    }
+    // Opened non-blocking, so that it doesn't wait for the write end to be opened.
+    tokio::net::unix::pipe::OpenOptions::new()
+        .open_receiver(path)
+        .map(Some)
+}
+
+/// Pause all the processes of task `id` in `namespace`, see [resume_task].
+pub async fn pause_task<T>(
+    client: &mut TasksClient<T>,
+    namespace: &str,
+    id: &str,
+) -> Result<(), Error>
+where
+    T: GrpcService<BoxBody>,
+    T::Error: Into<StdError>,
+    T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+    let req = PauseTaskRequest {
+        container_id: id.to_string(),
+    };
+    let req = with_namespace!(req, namespace);
+    client.pause(req).await?;
+    Ok(())
+}
+
+/// Resume the processes of task `id` in `namespace` paused by [pause_task].
+pub async fn resume_task<T>(
+    client: &mut TasksClient<T>,
+    namespace: &str,
+    id: &str,
+) -> Result<(), Error>
+where
+    T: GrpcService<BoxBody>,
+    T::Error: Into<StdError>,
+    T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+    let req = ResumeTaskRequest {
+        container_id: id.to_string(),
+    };
+    let req = with_namespace!(req, namespace);
+    client.resume(req).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll},
+        time::Duration,
+    };
+
+    use tonic::codegen::{http, Service};
+
+    use super::*;
+    use crate::{
+        services::v1::WaitResponse,
+        testutil::{grpc_error, grpc_request, grpc_response},
+    };
+
+    /// Fake task service answering `Wait` calls in the "test" namespace.
+    #[derive(Clone)]
+    struct MockTasks {
+        resp: WaitResponse,
+    }
+
+    impl Service<http::Request<BoxBody>> for MockTasks {
+        type Response = http::Response<BoxBody>;
+        type Error = Status;
+        type Future = std::future::Ready<Result<Self::Response, Status>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Status>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: http::Request<BoxBody>) -> Self::Future {
+            let ns = req.headers().get("containerd-namespace");
+            if req.uri().path() != "/containerd.services.tasks.v1.Tasks/Wait"
+                || ns.map(|v| v.as_bytes()) != Some(b"test")
+            {
+                return std::future::ready(Ok(grpc_error(tonic::Code::NotFound)));
+            }
+            std::future::ready(Ok(grpc_response(&self.resp)))
+        }
+    }
+
+    #[tokio::test]
+    async fn wait_task_returns_exit_status() {
+        let mut client = TasksClient::new(MockTasks {
+            resp: WaitResponse {
+                exit_status: 137,
+                exited_at: Some(prost_types::Timestamp {
+                    seconds: 1700000000,
+                    nanos: 42,
+                }),
+            },
+        });
+
+        let (code, exited_at) = wait_task(&mut client, "test", "abc123").await.unwrap();
+        assert_eq!(code, 137);
+        assert_eq!(
+            exited_at,
+            SystemTime::UNIX_EPOCH + Duration::new(1700000000, 42)
+        );
+
+        let err = wait_task(&mut client, "other", "abc123").await.unwrap_err();
+        assert!(matches!(err, Error::NotFound(_)), "{}", err);
+    }
+
+    #[test]
+    fn create_task_request_builder() {
+        let mounts = vec![Mount {
+            r#type: "overlay".to_string(),
+            source: "overlay".to_string(),
+            target: String::new(),
+            options: vec!["lowerdir=/a:/b".to_string(), "upperdir=/c".to_string()],
+        }];
+        let checkpoint = Descriptor {
+            media_type: "application/vnd.containerd.container.criu.checkpoint.criu.tar".to_string(),
+            digest: "sha256:abc".to_string(),
+            ..Default::default()
+        };
+        let req = CreateTaskRequestBuilder::new("abc123", mounts.clone())
+            .stdio("/tmp/stdin", "/tmp/stdout", "")
+            .terminal(true)
+            .options(&checkpoint)
+            .unwrap()
+            .checkpoint(checkpoint.clone())
+            .build();
+
+        assert_eq!(req.container_id, "abc123");
+        assert_eq!(req.rootfs, mounts);
+        assert_eq!(req.stdin, "/tmp/stdin");
+        assert_eq!(req.stdout, "/tmp/stdout");
+        assert!(req.stderr.is_empty());
+        assert!(req.terminal);
+        assert_eq!(req.checkpoint, Some(checkpoint.clone()));
+        let options: Descriptor = req.options.unwrap().to_msg().unwrap();
+        assert_eq!(options, checkpoint);
+        assert!(req.runtime_path.is_empty());
+    }
+
+    /// Fake task service recording the `Pause` and `Resume` calls on task "abc123".
+    #[derive(Clone, Default)]
+    struct RecordingTasks {
+        calls: Arc<Mutex<Vec<(String, String)>>>,
+    }
+
+    impl Service<http::Request<BoxBody>> for RecordingTasks {
+        type Response = http::Response<BoxBody>;
+        type Error = Status;
+        type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Status>> + Send>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Status>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: http::Request<BoxBody>) -> Self::Future {
+            let calls = self.calls.clone();
+            Box::pin(async move {
+                let path = req.uri().path().to_string();
+                let ns = req
+                    .headers()
+                    .get("containerd-namespace")
+                    .map(|v| v.to_str().unwrap().to_string())
+                    .unwrap_or_default();
+                let id = match path.as_str() {
+                    "/containerd.services.tasks.v1.Tasks/Pause" => {
+                        grpc_request::<PauseTaskRequest>(req.into_body())
+                            .await
+                            .container_id
+                    }
+                    "/containerd.services.tasks.v1.Tasks/Resume" => {
+                        grpc_request::<ResumeTaskRequest>(req.into_body())
+                            .await
+                            .container_id
+                    }
+                    _ => return Ok(grpc_error(tonic::Code::Unimplemented)),
+                };
+                if id != "abc123" {
+                    return Ok(grpc_error(tonic::Code::NotFound));
+                }
+                calls.lock().unwrap().push((path, ns));
+                Ok(grpc_response(&()))
+            })
+        }
+    }
+
+    #[cfg(all(unix, feature = "connect"))]
+    #[tokio::test]
+    async fn task_output_reads_fifos() {
+        use std::io::Write;
+
+        use tokio::io::AsyncReadExt;
+
+        let fifo = |name: &str| {
+            let path = std::env::temp_dir().join(format!(
+                "containerd-client-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_file(&path);
+            let status = std::process::Command::new("mkfifo")
+                .arg(&path)
+                .status()
+                .unwrap();
+            assert!(status.success());
+            path
+        };
+        let (stdout, stderr) = (fifo("stdout"), fifo("stderr"));
+        let req = CreateTaskRequestBuilder::new("abc123", vec![])
+            .stdio("", stdout.to_str().unwrap(), stderr.to_str().unwrap())
+            .build();
+
+        // Opened before the shim opens the write ends, as before creating the task.
+        let output = TaskOutput::open(&req).unwrap();
+        // Stand-in for the shim, opening the write ends once the task is created.
+        let shim = {
+            let (stdout, stderr) = (stdout.clone(), stderr.clone());
+            std::thread::spawn(move || {
+                let mut err = std::fs::OpenOptions::new()
+                    .write(true)
+                    .open(stderr)
+                    .unwrap();
+                let mut out = std::fs::OpenOptions::new()
+                    .write(true)
+                    .open(stdout)
+                    .unwrap();
+                out.write_all(b"output").unwrap();
+                err.write_all(b"error").unwrap();
+            })
+        };
+
+        let mut data = String::new();
+        output
+            .stdout
+            .unwrap()
+            .read_to_string(&mut data)
+            .await
+            .unwrap();
+        assert_eq!(data, "output");
+        data.clear();
+        output
+            .stderr
+            .unwrap()
+            .read_to_string(&mut data)
+            .await
+            .unwrap();
+        assert_eq!(data, "error");
+        shim.join().unwrap();
+
+        let _ = std::fs::remove_file(stdout);
+        let _ = std::fs::remove_file(stderr);
+    }
+
+    #[tokio::test]
+    async fn pause_and_resume_task() {
+        let tasks = RecordingTasks::default();
+        let mut client = TasksClient::new(tasks.clone());
+
+        pause_task(&mut client, "test", "abc123").await.unwrap();
+        resume_task(&mut client, "test", "abc123").await.unwrap();
+        assert_eq!(
+            *tasks.calls.lock().unwrap(),
+            vec![
+                (
+                    "/containerd.services.tasks.v1.Tasks/Pause".to_string(),
+                    "test".to_string()
+                ),
+                (
+                    "/containerd.services.tasks.v1.Tasks/Resume".to_string(),
+                    "test".to_string()
+                ),
+            ]
+        );
+
+        let err = pause_task(&mut client, "test", "missing")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::NotFound(_)), "{}", err);
+    }
+}
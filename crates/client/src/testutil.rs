@@ -0,0 +1,131 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Building blocks for fake containerd services in unit tests.
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use prost::Message;
+use tonic::{
+    body::BoxBody,
+    codegen::{http, Body, Bytes},
+    Code, Status,
+};
+
+/// A response body yielding a single chunk and no trailers.
+pub struct OnceBody(pub Option<Bytes>);
+
+impl Body for OnceBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Status>>> {
+        Poll::Ready(self.0.take().map(Ok))
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<http::HeaderMap>, Status>> {
+        Poll::Ready(Ok(None))
+    }
+}
+
+/// Encode `msg` as a gRPC message frame.
+pub fn grpc_frame<M: Message>(msg: &M) -> Bytes {
+    // gRPC message framing: compression flag, big endian length, then the message.
+    let msg = msg.encode_to_vec();
+    let mut frame = vec![0u8];
+    frame.extend_from_slice(&(msg.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&msg);
+    Bytes::from(frame)
+}
+
+/// Build a successful gRPC response carrying `msg`.
+pub fn grpc_response<M: Message>(msg: &M) -> http::Response<BoxBody> {
+    http::Response::builder()
+        .header("content-type", "application/grpc")
+        .body(BoxBody::new(OnceBody(Some(grpc_frame(msg)))))
+        .unwrap()
+}
+
+/// Build a failed gRPC response with status `code`.
+pub fn grpc_error(code: Code) -> http::Response<BoxBody> {
+    http::Response::builder()
+        .header("content-type", "application/grpc")
+        .header("grpc-status", (code as i32).to_string())
+        .body(BoxBody::new(OnceBody(None)))
+        .unwrap()
+}
+
+/// Decode the single message sent in a gRPC request body.
+pub async fn grpc_request<M: Message + Default>(body: BoxBody) -> M {
+    let mut body = body;
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.data().await {
+        buf.extend_from_slice(&chunk.unwrap());
+    }
+    M::decode(&buf[5..]).unwrap()
+}
+
+/// Serve the gRPC requests of the connections accepted by `listener` with `handler`, like a
+/// containerd listening on a socket, returning the server task along with the number of
+/// connections accepted so far. Aborting the task closes the connections too.
+#[cfg(all(unix, feature = "connect"))]
+pub fn serve<F, Fut>(
+    listener: tokio::net::UnixListener,
+    handler: F,
+) -> (
+    tokio::task::JoinHandle<()>,
+    std::sync::Arc<std::sync::atomic::AtomicUsize>,
+)
+where
+    F: Fn(http::Request<BoxBody>) -> Fut + Clone + Send + 'static,
+    Fut: std::future::Future<Output = http::Response<BoxBody>> + Send + 'static,
+{
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = accepted.clone();
+    let task = tokio::spawn(async move {
+        let mut conns = tokio::task::JoinSet::new();
+        while let Ok((stream, _)) = listener.accept().await {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let handler = handler.clone();
+            let service = hyper::service::service_fn(move |req: http::Request<hyper::Body>| {
+                let req =
+                    req.map(|body| BoxBody::new(body.map_err(|e| Status::from_error(Box::new(e)))));
+                let resp = handler(req);
+                async move { Ok::<_, std::convert::Infallible>(resp.await) }
+            });
+            conns.spawn(
+                hyper::server::conn::Http::new()
+                    .http2_only(true)
+                    .serve_connection(stream, service),
+            );
+        }
+    });
+    (task, accepted)
+}
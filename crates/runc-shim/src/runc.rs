@@ -278,7 +278,7 @@ impl ProcessLifecycle<InitProcess> for RuncInitLifecycle {
             .kill(
                 p.id.as_str(),
                 signal,
-                Some(&runc::options::KillOpts { all }),
+                Some(&runc::options::KillOpts::new().all(all)),
             )
             .await
             .map_err(|e| check_kill_error(e.to_string()))
@@ -289,7 +289,7 @@ impl ProcessLifecycle<InitProcess> for RuncInitLifecycle {
             .runtime
             .delete(
                 p.id.as_str(),
-                Some(&runc::options::DeleteOpts { force: true }),
+                Some(&runc::options::DeleteOpts::new().force(true)),
             )
             .await
         {
@@ -387,6 +387,7 @@ impl ProcessLifecycle<ExecProcess> for RuncExecLifecycle {
             pid_file: Some(pid_path.to_owned()),
             console_socket: None,
             detach: true,
+            ..Default::default()
         };
         let (socket, pio) = if p.stdio.terminal {
             let s = ConsoleSocket::new().await?;
@@ -117,7 +117,7 @@ impl Shim for Service {
             Some(Arc::new(ShimExecutor::default())),
         )?;
 
-        runc.delete(&self.id, Some(&DeleteOpts { force: true }))
+        runc.delete(&self.id, Some(&DeleteOpts::new().force(true)))
             .await
             .unwrap_or_else(|e| warn!("failed to remove runc container: {}", e));
         let mut resp = DeleteResponse::new();
@@ -0,0 +1,286 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Console sockets, through which runc hands over the terminal of a container.
+//!
+//! When a container (or an exec'd process) with `process.terminal` set is created with a console
+//! socket (see [CreateOpts::console_socket](crate::options::CreateOpts::console_socket)), runc
+//! connects to the unix socket at that path and sends a single message: the name of the PTY as
+//! data, and the file descriptor of the PTY master as `SCM_RIGHTS` ancillary data. It then closes
+//! the connection and keeps no reference to the master, so whoever receives it owns the console
+//! of the container: it can [resize](Console::resize) it, and usually [sets it to raw
+//! mode](Console::set_raw) to forward a user's terminal to it.
+
+use std::{
+    fs::File,
+    io::{ErrorKind, IoSliceMut},
+    os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    path::{Path, PathBuf},
+};
+
+use nix::sys::{
+    socket::{recvmsg, ControlMessageOwned, MsgFlags, UnixAddr},
+    termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg},
+};
+use tokio::{io::Interest, net::UnixListener};
+
+use crate::{error::Error, Result};
+
+/// Master end of the PTY of a container, received by [ConsoleSocket::receive].
+#[derive(Debug)]
+pub struct Console {
+    /// Name of the PTY sent by runc, e.g. `/dev/pts/3`.
+    pub name: String,
+    /// PTY master: reading it returns the output of the container, writing to it sends input.
+    pub master: tokio::fs::File,
+}
+
+impl Console {
+    /// Resize the terminal to `width` columns and `height` rows, like a user's terminal window.
+    pub fn resize(&self, width: u16, height: u16) -> Result<()> {
+        let size = libc::winsize {
+            ws_row: height,
+            ws_col: width,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        // Safety: TIOCSWINSZ only reads the winsize it's given.
+        if unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size) } < 0 {
+            return Err(Error::ConsoleFailed(std::io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
+    /// Set the terminal to raw mode: input is available byte by byte, without echo, line
+    /// editing nor signal generation, and output isn't processed.
+    pub fn set_raw(&self) -> Result<()> {
+        let mut termios = tcgetattr(&self.master).map_err(|e| Error::ConsoleFailed(e.into()))?;
+        cfmakeraw(&mut termios);
+        tcsetattr(&self.master, SetArg::TCSANOW, &termios)
+            .map_err(|e| Error::ConsoleFailed(e.into()))
+    }
+}
+
+/// Unix socket to pass as console socket to runc, removed when dropped.
+#[derive(Debug)]
+pub struct ConsoleSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl ConsoleSocket {
+    /// Listen on a new unix socket at `path`, which must not exist yet.
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        let listener = UnixListener::bind(path.as_ref()).map_err(Error::UnixSocketBindFailed)?;
+        Ok(Self {
+            listener,
+            path: path.as_ref().to_path_buf(),
+        })
+    }
+
+    /// Path of the socket, to pass to runc.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Wait for runc to connect and return the console it sends.
+    pub async fn receive(&self) -> Result<Console> {
+        let (stream, _) = self
+            .listener
+            .accept()
+            .await
+            .map_err(Error::UnixSocketConnectionFailed)?;
+        let mut buf = [0u8; 4096];
+        let (len, fd) = loop {
+            stream
+                .readable()
+                .await
+                .map_err(Error::UnixSocketConnectionFailed)?;
+            match stream.try_io(Interest::READABLE, || {
+                receive_fd(stream.as_raw_fd(), &mut buf)
+            }) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+                res => break res.map_err(Error::UnixSocketConnectionFailed)?,
+            }
+        };
+        let master = match fd {
+            Some(fd) => fd,
+            None if len == 0 => return Err(Error::UnixSocketClosed),
+            None => return Err(Error::UnixSocketReceiveMessageFailed),
+        };
+        let name = String::from_utf8_lossy(&buf[..len])
+            .trim_matches(char::from(0))
+            .to_string();
+        Ok(Console {
+            name,
+            master: tokio::fs::File::from_std(File::from(master)),
+        })
+    }
+}
+
+impl Drop for ConsoleSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Receive a message into `buf`, returning its length and the first file descriptor passed
+/// along, if any. Any other file descriptor is closed.
+fn receive_fd(socket: RawFd, buf: &mut [u8]) -> std::io::Result<(usize, Option<OwnedFd>)> {
+    let mut iov = [IoSliceMut::new(buf)];
+    let mut space = nix::cmsg_space!([RawFd; 2]);
+    let msg = recvmsg::<UnixAddr>(
+        socket,
+        &mut iov,
+        Some(&mut space),
+        MsgFlags::MSG_CMSG_CLOEXEC,
+    )?;
+    let fds: Vec<OwnedFd> = msg
+        .cmsgs()
+        .filter_map(|cmsg| match cmsg {
+            ControlMessageOwned::ScmRights(fds) => Some(fds),
+            _ => None,
+        })
+        .flatten()
+        // Safety: the fds were just received, nothing else owns them.
+        .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
+        .collect();
+    Ok((msg.bytes, fds.into_iter().next()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{IoSlice, Write},
+        os::unix::net::UnixStream,
+    };
+
+    use nix::{
+        pty::openpty,
+        sys::{
+            socket::{sendmsg, ControlMessage},
+            termios::LocalFlags,
+        },
+    };
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    /// Send `fd` to `socket` like runc sends the PTY master.
+    async fn send_console(socket: &ConsoleSocket, fd: OwnedFd, name: &'static str) -> Console {
+        let path = socket.path().to_path_buf();
+        let sender = tokio::task::spawn_blocking(move || {
+            let stream = UnixStream::connect(path).unwrap();
+            sendmsg::<UnixAddr>(
+                stream.as_raw_fd(),
+                &[IoSlice::new(name.as_bytes())],
+                &[ControlMessage::ScmRights(&[fd.as_raw_fd()])],
+                MsgFlags::empty(),
+                None,
+            )
+            .unwrap();
+        });
+        let console = socket.receive().await.unwrap();
+        sender.await.unwrap();
+        console
+    }
+
+    #[tokio::test]
+    async fn test_receive_console() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = ConsoleSocket::new(dir.path().join("console.sock")).unwrap();
+
+        // A pipe stands in for the PTY master.
+        let (reader, mut writer) = os_pipe::pipe().unwrap();
+        let mut console = send_console(&socket, reader.into(), "/dev/pts/42").await;
+        assert_eq!(console.name, "/dev/pts/42");
+
+        writer.write_all(b"hello").unwrap();
+        drop(writer);
+        let mut output = String::new();
+        console.master.read_to_string(&mut output).await.unwrap();
+        assert_eq!(output, "hello");
+
+        let path = socket.path().to_path_buf();
+        drop(socket);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_resize_and_raw_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = ConsoleSocket::new(dir.path().join("console.sock")).unwrap();
+        let pty = openpty(None, None).unwrap();
+        let console = send_console(&socket, pty.master, "/dev/pts/42").await;
+
+        console.resize(120, 40).unwrap();
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        assert_eq!(
+            unsafe { libc::ioctl(pty.slave.as_raw_fd(), libc::TIOCGWINSZ, &mut size) },
+            0
+        );
+        assert_eq!((size.ws_col, size.ws_row), (120, 40));
+
+        assert!(tcgetattr(&pty.slave)
+            .unwrap()
+            .local_flags
+            .contains(LocalFlags::ECHO | LocalFlags::ICANON));
+        console.set_raw().unwrap();
+        let flags = tcgetattr(&pty.slave).unwrap().local_flags;
+        assert!(!flags.intersects(LocalFlags::ECHO | LocalFlags::ICANON));
+    }
+
+    #[tokio::test]
+    async fn test_extra_fds_closed() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = ConsoleSocket::new(dir.path().join("console.sock")).unwrap();
+        let (master, _master_writer) = os_pipe::pipe().unwrap();
+        let (extra, mut extra_writer) = os_pipe::pipe().unwrap();
+        let path = socket.path().to_path_buf();
+        let sender = tokio::task::spawn_blocking(move || {
+            let stream = UnixStream::connect(path).unwrap();
+            sendmsg::<UnixAddr>(
+                stream.as_raw_fd(),
+                &[IoSlice::new(b"/dev/pts/42")],
+                &[ControlMessage::ScmRights(&[
+                    master.as_raw_fd(),
+                    extra.as_raw_fd(),
+                ])],
+                MsgFlags::empty(),
+                None,
+            )
+            .unwrap();
+        });
+        let console = socket.receive().await.unwrap();
+        sender.await.unwrap();
+        assert_eq!(console.name, "/dev/pts/42");
+
+        // The read end of the extra pipe is gone with the copy that was received.
+        let err = extra_writer.write_all(b"hello").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+    }
+
+    #[tokio::test]
+    async fn test_receive_closed() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = ConsoleSocket::new(dir.path().join("console.sock")).unwrap();
+        drop(UnixStream::connect(socket.path()).unwrap());
+        assert!(matches!(
+            socket.receive().await,
+            Err(Error::UnixSocketClosed)
+        ));
+    }
+}
@@ -33,24 +33,203 @@
  * limitations under the License.
  */
 
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    time::Duration,
+};
 
-use serde::{Deserialize, Serialize};
-use time::{serde::timestamp, OffsetDateTime};
+use serde::{Deserialize, Deserializer, Serialize};
+use time::{format_description::well_known::Rfc3339, serde::timestamp, OffsetDateTime};
 
 /// Information for runc container
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Container {
     pub id: String,
     pub pid: usize,
+    /// Status as printed by runc, see [Container::status] to match on it.
     pub status: String,
     pub bundle: String,
     pub rootfs: String,
-    #[serde(with = "timestamp")]
+    /// Creation time, deserialized from either a unix timestamp or an RFC 3339 string (as
+    /// printed by `runc state`).
+    #[serde(
+        serialize_with = "timestamp::serialize",
+        deserialize_with = "deserialize_created"
+    )]
     pub created: OffsetDateTime,
+    /// Annotations of the container's spec, empty if it has none (runc omits them then).
+    #[serde(default)]
     pub annotations: HashMap<String, String>,
 }
 
+impl Container {
+    /// Status of the container, parsed from the string printed by runc.
+    pub fn status(&self) -> ContainerStatus {
+        ContainerStatus::from(self.status.clone())
+    }
+
+    /// Time elapsed since the container was created, `None` if `created` is in the future (e.g.
+    /// after the clock was set back).
+    pub fn uptime(&self) -> Option<Duration> {
+        (OffsetDateTime::now_utc() - self.created).try_into().ok()
+    }
+
+    /// Whether the container has the annotation `key` set to `value`.
+    pub fn has_annotation(&self, key: &str, value: &str) -> bool {
+        self.annotations.get(key).map(String::as_str) == Some(value)
+    }
+}
+
+fn deserialize_created<'de, D>(deserializer: D) -> Result<OffsetDateTime, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Created {
+        Timestamp(i64),
+        Rfc3339(String),
+    }
+
+    match Created::deserialize(deserializer)? {
+        Created::Timestamp(t) => {
+            OffsetDateTime::from_unix_timestamp(t).map_err(serde::de::Error::custom)
+        }
+        Created::Rfc3339(s) => {
+            OffsetDateTime::parse(&s, &Rfc3339).map_err(serde::de::Error::custom)
+        }
+    }
+}
+
+/// Status of a container, as reported by `runc state` and `runc list`.
+///
+/// Statuses unknown to this crate are kept as is in [ContainerStatus::Other], so comparing
+/// against strings (e.g. `status == "running"`) keeps working.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum ContainerStatus {
+    /// The container is being created.
+    Creating,
+    /// The container has been created but its process hasn't been started yet.
+    Created,
+    Running,
+    Paused,
+    Stopped,
+    Other(String),
+}
+
+impl ContainerStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ContainerStatus::Creating => "creating",
+            ContainerStatus::Created => "created",
+            ContainerStatus::Running => "running",
+            ContainerStatus::Paused => "paused",
+            ContainerStatus::Stopped => "stopped",
+            ContainerStatus::Other(s) => s,
+        }
+    }
+}
+
+impl From<String> for ContainerStatus {
+    fn from(s: String) -> Self {
+        match s.as_str() {
+            "creating" => ContainerStatus::Creating,
+            "created" => ContainerStatus::Created,
+            "running" => ContainerStatus::Running,
+            "paused" => ContainerStatus::Paused,
+            "stopped" => ContainerStatus::Stopped,
+            _ => ContainerStatus::Other(s),
+        }
+    }
+}
+
+impl From<ContainerStatus> for String {
+    fn from(status: ContainerStatus) -> Self {
+        match status {
+            ContainerStatus::Other(s) => s,
+            status => status.as_str().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ContainerStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl PartialEq<str> for ContainerStatus {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for ContainerStatus {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+/// Container whose status differs from the expected one, see [DriftReport].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusDrift {
+    pub id: String,
+    pub expected: ContainerStatus,
+    /// Status reported by runc.
+    pub actual: ContainerStatus,
+}
+
+/// Differences between the containers a caller expects and the ones runc lists, e.g. to
+/// reconcile them after the caller restarted, see [crate::Runc::drift].
+///
+/// Each list is sorted by container id.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DriftReport {
+    /// Expected containers runc doesn't list.
+    pub missing: Vec<String>,
+    /// Containers runc lists which aren't expected.
+    pub unexpected: Vec<String>,
+    /// Expected containers runc lists with another status.
+    pub status: Vec<StatusDrift>,
+}
+
+impl DriftReport {
+    /// Compare the `expected` ids and statuses of containers to the `containers` listed by runc.
+    pub fn new<I, S>(expected: I, containers: &[Container]) -> Self
+    where
+        I: IntoIterator<Item = (S, ContainerStatus)>,
+        S: Into<String>,
+    {
+        let mut expected: BTreeMap<String, ContainerStatus> = expected
+            .into_iter()
+            .map(|(id, status)| (id.into(), status))
+            .collect();
+        let mut report = Self::default();
+        for container in containers {
+            match expected.remove(&container.id) {
+                Some(status) if status != container.status() => report.status.push(StatusDrift {
+                    id: container.id.clone(),
+                    expected: status,
+                    actual: container.status(),
+                }),
+                Some(_) => {}
+                None => report.unexpected.push(container.id.clone()),
+            }
+        }
+        report.missing = expected.into_keys().collect();
+        report.unexpected.sort();
+        report.status.sort_by(|a, b| a.id.cmp(&b.id));
+        report
+    }
+
+    /// Whether runc's view matches the expected one.
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.unexpected.is_empty() && self.status.is_empty()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -74,6 +253,7 @@ mod tests {
         assert_eq!(c.id, "fake");
         assert_eq!(c.pid, 1000);
         assert_eq!(c.status, "RUNNING");
+        assert_eq!(c.status(), ContainerStatus::Other("RUNNING".to_string()));
         assert_eq!(c.bundle, "/path/to/bundle");
         assert_eq!(c.rootfs, "/path/to/rootfs");
         assert_eq!(
@@ -82,5 +262,112 @@ mod tests {
         );
         assert_eq!(c.annotations.get("foo"), Some(&"bar".to_string()));
         assert_eq!(c.annotations.get("bar"), None);
+        assert!(c.has_annotation("foo", "bar"));
+        assert!(!c.has_annotation("foo", "baz"));
+
+        // runc omits the annotations of the containers which have none.
+        let c: Container = serde_json::from_str(&j.replace(
+            r#",
+                "annotations": {
+                    "foo": "bar"
+                }"#,
+            "",
+        ))
+        .unwrap();
+        assert!(c.annotations.is_empty());
+    }
+
+    #[test]
+    fn uptime_test() {
+        let j = r#"
+            {
+                "id": "fake",
+                "pid": 1000,
+                "status": "running",
+                "bundle": "/path/to/bundle",
+                "rootfs": "/path/to/rootfs",
+                "created": "2015-05-15T10:00:00.123456789Z",
+                "annotations": {}
+            }"#;
+
+        let c: Container = serde_json::from_str(j).unwrap();
+        assert_eq!(
+            c.created,
+            OffsetDateTime::from_unix_timestamp_nanos(1_431_684_000_123_456_789).unwrap()
+        );
+        assert_eq!(c.status(), ContainerStatus::Running);
+        let expected = OffsetDateTime::now_utc() - c.created;
+        let uptime = c.uptime().unwrap();
+        assert!(uptime >= expected && uptime - expected < Duration::from_secs(5));
+
+        let future = Container {
+            created: OffsetDateTime::now_utc() + Duration::from_secs(3600),
+            ..c
+        };
+        assert_eq!(future.uptime(), None);
+
+        let invalid = j.replace("2015-05-15T10:00:00.123456789Z", "yesterday");
+        assert!(serde_json::from_str::<Container>(&invalid).is_err());
+    }
+
+    #[test]
+    fn status_test() {
+        for (raw, status) in [
+            ("creating", ContainerStatus::Creating),
+            ("created", ContainerStatus::Created),
+            ("running", ContainerStatus::Running),
+            ("paused", ContainerStatus::Paused),
+            ("stopped", ContainerStatus::Stopped),
+            ("restoring", ContainerStatus::Other("restoring".to_string())),
+        ] {
+            let json = format!("\"{}\"", raw);
+            let parsed: ContainerStatus = serde_json::from_str(&json).unwrap();
+            assert_eq!(parsed, status);
+            assert_eq!(parsed, raw);
+            assert_eq!(serde_json::to_string(&parsed).unwrap(), json);
+        }
+    }
+
+    #[test]
+    fn drift_test() {
+        let containers: Vec<Container> = serde_json::from_str(
+            r#"[
+                {"id": "web", "pid": 100, "status": "running", "bundle": "/b/web",
+                 "rootfs": "/b/web/rootfs", "created": 1431684000},
+                {"id": "db", "pid": 0, "status": "stopped", "bundle": "/b/db",
+                 "rootfs": "/b/db/rootfs", "created": 1431684000},
+                {"id": "leftover", "pid": 300, "status": "running", "bundle": "/b/leftover",
+                 "rootfs": "/b/leftover/rootfs", "created": 1431684000},
+                {"id": "cache", "pid": 400, "status": "paused", "bundle": "/b/cache",
+                 "rootfs": "/b/cache/rootfs", "created": 1431684000}
+            ]"#,
+        )
+        .unwrap();
+        let expected = [
+            ("web", ContainerStatus::Running),
+            ("db", ContainerStatus::Running),
+            ("queue", ContainerStatus::Running),
+            ("cache", ContainerStatus::Paused),
+            ("batch", ContainerStatus::Created),
+        ];
+
+        let report = DriftReport::new(expected.clone(), &containers);
+        assert_eq!(report.missing, ["batch", "queue"]);
+        assert_eq!(report.unexpected, ["leftover"]);
+        assert_eq!(
+            report.status,
+            [StatusDrift {
+                id: "db".to_string(),
+                expected: ContainerStatus::Running,
+                actual: ContainerStatus::Stopped,
+            }]
+        );
+        assert!(!report.is_empty());
+
+        assert!(DriftReport::new(expected[..1].to_vec(), &containers[..1]).is_empty());
+        assert_eq!(
+            DriftReport::new(Vec::<(String, ContainerStatus)>::new(), &[]),
+            DriftReport::default()
+        );
     }
 }
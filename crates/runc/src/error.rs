@@ -33,7 +33,7 @@
  * limitations under the License.
  */
 
-use std::{env, io, process::ExitStatus};
+use std::{env, io, os::unix::io::RawFd, path::PathBuf, process::ExitStatus};
 
 use thiserror::Error;
 
@@ -57,6 +57,12 @@ pub enum Error {
     #[error("Error occured in runc: {0}")]
     InvalidCommand(io::Error),
 
+    #[error("Invalid oom_score_adj {0}, must be within -1000..=1000")]
+    InvalidOomScoreAdj(i32),
+
+    #[error("Log fd {0} can't be passed to runc: {1}")]
+    LogFdUnavailable(RawFd, &'static str),
+
     #[error("Runc command failed: status={status}, stdout=\"{stdout}\", stderr=\"{stderr}\"")]
     CommandFailed {
         status: ExitStatus,
@@ -64,6 +70,16 @@ pub enum Error {
         stderr: String,
     },
 
+    #[error("Container is not running: stderr=\"{stderr}\"")]
+    ContainerNotRunning { stderr: String },
+
+    #[error("CRIU failed, log={log_path:?}: stderr=\"{stderr}\"")]
+    CriuFailed {
+        /// Log of CRIU, if runc reported it.
+        log_path: Option<PathBuf>,
+        stderr: String,
+    },
+
     #[error("Runc IO unavailable: {0}")]
     UnavailableIO(io::Error),
 
@@ -71,24 +87,116 @@ pub enum Error {
     #[error("Runc command timed out: {0}")]
     CommandTimeout(tokio::time::error::Elapsed),
 
+    #[cfg(not(feature = "async"))]
+    #[error("Runc command killed after timing out in {0:?}")]
+    Timeout(std::time::Duration),
+
+    #[error("Failed to wait for the container's process: {0}")]
+    WaitFailed(io::Error),
+
     #[error("Unable to parse runc version")]
     InvalidVersion,
 
     #[error("Unable to locate the runc")]
     NotFound,
 
+    #[error("Unable to locate the CRIU binary {0:?}")]
+    CriuNotFound(PathBuf),
+
     #[error("Error occurs with fs: {0}")]
     FileSystemError(io::Error),
 
+    #[deprecated(note = "only returned by the deprecated `write_value_to_temp_file`")]
     #[error("Failed to spec file: {0}")]
     SpecFileCreationFailed(io::Error),
 
+    #[error("Failed to write the {context} spec file {path:?}: {source}")]
+    SpecFileWriteFailed {
+        /// What the spec is for, e.g. "exec process".
+        context: &'static str,
+        path: PathBuf,
+        source: io::Error,
+    },
+
     #[error(transparent)]
     SpecFileCleanupFailed(io::Error),
 
     #[error("Failed to find valid path for spec file")]
     SpecFileNotFound,
 
+    #[error("Failed to process OCI spec: {0}")]
+    SpecProcessingFailed(oci_spec::OciSpecError),
+
+    #[error("OCI spec version {version} is not supported by runc, which supports {min} to {max}")]
+    IncompatibleOciVersion {
+        version: String,
+        min: String,
+        max: String,
+    },
+
+    #[error("Invalid seccomp profile: {0}")]
+    InvalidSeccompProfile(String),
+
+    #[error("Root filesystem {0} does not exist or is not a directory")]
+    RootfsNotFound(PathBuf),
+
+    #[error("Network namespace {0} does not exist")]
+    NetnsNotFound(PathBuf),
+
+    #[error("Source {0} of bind mount does not exist")]
+    MountSourceNotFound(PathBuf),
+
+    #[error("Mount option {option} of {destination:?} is not supported: {reason}")]
+    UnsupportedMountOption {
+        destination: PathBuf,
+        option: String,
+        reason: String,
+    },
+
+    #[error("Option {0} is only supported by runc run")]
+    RunOnlyOption(&'static str),
+
+    #[error("Unknown capability {0}")]
+    UnknownCapability(String),
+
+    #[error("Ambient capability {0} is not both permitted and inheritable")]
+    AmbientCapabilityNotPermitted(String),
+
+    #[error("Invalid rlimit {typ}: soft limit {soft} is above the hard limit {hard}")]
+    InvalidRlimit { typ: String, soft: u64, hard: u64 },
+
+    #[error("Invalid container id {id:?}: {reason}")]
+    InvalidContainerId { id: String, reason: &'static str },
+
+    #[error("Invalid hostname {0:?}")]
+    InvalidHostname(String),
+
+    #[error("Id {0} is not mapped in the user namespace")]
+    UnmappedId(u32),
+
+    #[error("Bundle {0} is outside of the bundle root, refusing to remove it")]
+    BundleOutsideRoot(String),
+
+    #[error("Container was deleted but its bundle could not be removed: {0}")]
+    BundleCleanupFailed(io::Error),
+
+    #[error("Spec of bundle {0} has no process")]
+    BundleWithoutProcess(String),
+
+    #[error("{error}, and the rollback failed: {rollback}")]
+    RollbackFailed {
+        /// Error which triggered the rollback.
+        error: Box<Error>,
+        rollback: Box<Error>,
+    },
+
+    #[error("State root {path:?} has {available} bytes available, {required} are required")]
+    StateDirFull {
+        path: PathBuf,
+        available: u64,
+        required: u64,
+    },
+
     #[error("Top command is missing a pid header")]
     TopMissingPidHeader,
 
@@ -107,6 +215,9 @@ pub enum Error {
     #[error("Unix socket unexpectedly closed")]
     UnixSocketClosed,
 
+    #[error("Failed to configure the console: {0}")]
+    ConsoleFailed(io::Error),
+
     #[error("Failed to handle environment variable: {0}")]
     EnvError(env::VarError),
 
@@ -33,7 +33,7 @@
  * limitations under the License.
  */
 
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
 use serde::{Deserialize, Serialize};
 
@@ -56,6 +56,24 @@ pub struct Event {
     pub stats: Option<Stats>,
 }
 
+/// OOM kills of a container, see [crate::Runc::oom_kills].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OomKills {
+    /// Number of processes of the container killed by the OOM killer.
+    pub count: u64,
+    /// Whether `count` is greater than the count of the previous snapshot.
+    pub increased: bool,
+}
+
+/// Memory usage of a container, see [crate::Runc::memory].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemStat {
+    /// Memory used by the container's cgroup, in bytes.
+    pub usage: u64,
+    /// Memory limit of the container's cgroup, `None` if it isn't limited.
+    pub limit: Option<u64>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Stats {
     pub cpu: Cpu,
@@ -67,6 +85,107 @@ pub struct Stats {
     pub huge_tlb: HugeTLB,
 }
 
+/// Memory limits at or above this are the "unlimited" values runc reports: the largest page
+/// aligned `i64` with cgroup v1, `u64::MAX` for "max" with cgroup v2.
+pub(crate) const UNLIMITED_MEMORY: u64 = 0x7FFF_FFFF_FFFF_F000;
+
+impl Stats {
+    /// Limits configured on the container's cgroup, `None` for those which aren't limited.
+    ///
+    /// runc doesn't report the CPU quota and period in its events, they are only in the spec.
+    pub fn limits(&self) -> Limits {
+        let memory_limit = |entry: &Option<MemoryEntry>| {
+            entry
+                .as_ref()
+                .map(|e| e.limit)
+                .filter(|&limit| limit > 0 && limit < UNLIMITED_MEMORY)
+        };
+        Limits {
+            memory: memory_limit(&self.memory.usage),
+            memory_swap: memory_limit(&self.memory.swap),
+            // runc reports 0 when pids.max is "max".
+            pids: self.pids.limit.filter(|&limit| limit > 0),
+        }
+    }
+
+    /// Memory the container can still use before reaching its limit, `None` if it isn't
+    /// limited.
+    pub fn memory_headroom(&self) -> Option<u64> {
+        let limit = self.limits().memory?;
+        let usage = self
+            .memory
+            .usage
+            .as_ref()
+            .and_then(|e| e.usage)
+            .unwrap_or(0);
+        Some(limit.saturating_sub(usage))
+    }
+
+    /// Rates of the cumulative counters between the snapshot `prev`, taken `elapsed` before this
+    /// one, and this snapshot.
+    ///
+    /// A counter which went backwards, e.g. because the container was restarted in between,
+    /// gives no rate rather than a bogus one, like a counter missing from either snapshot.
+    pub fn delta(&self, prev: &Stats, elapsed: Duration) -> StatsRate {
+        let secs = elapsed.as_secs_f64();
+        let rate = |prev: Option<u64>, current: Option<u64>| {
+            let delta = current?.checked_sub(prev?)?;
+            (secs > 0.0).then(|| delta as f64 / secs)
+        };
+        let throttled = |stats: &Stats| stats.cpu.throttling.as_ref()?.throtted_time;
+        StatsRate {
+            cpu: rate(prev.cpu.usage, self.cpu.usage),
+            cpu_throttled: rate(throttled(prev), throttled(self)),
+            io_read_bytes: rate(
+                prev.block_io.service_bytes("read"),
+                self.block_io.service_bytes("read"),
+            ),
+            io_write_bytes: rate(
+                prev.block_io.service_bytes("write"),
+                self.block_io.service_bytes("write"),
+            ),
+            io_read_ops: rate(
+                prev.block_io.serviced("read"),
+                self.block_io.serviced("read"),
+            ),
+            io_write_ops: rate(
+                prev.block_io.serviced("write"),
+                self.block_io.serviced("write"),
+            ),
+        }
+    }
+}
+
+/// Per second rates of the cumulative counters of a container, see [Stats::delta].
+///
+/// Each rate is `None` if it couldn't be computed, e.g. after a counter reset.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StatsRate {
+    /// CPU time used, in nanoseconds per second: 1e9 is one fully used core.
+    pub cpu: Option<f64>,
+    /// Time the container was throttled, in nanoseconds per second.
+    pub cpu_throttled: Option<f64>,
+    /// Bytes read from the block devices per second.
+    pub io_read_bytes: Option<f64>,
+    /// Bytes written to the block devices per second.
+    pub io_write_bytes: Option<f64>,
+    /// Read requests issued to the block devices per second.
+    pub io_read_ops: Option<f64>,
+    /// Write requests issued to the block devices per second.
+    pub io_write_ops: Option<f64>,
+}
+
+/// Resource limits of a container, see [Stats::limits].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Memory limit in bytes, excluding swap.
+    pub memory: Option<u64>,
+    /// Memory limit in bytes, including swap.
+    pub memory_swap: Option<u64>,
+    /// Maximum number of pids.
+    pub pids: Option<u64>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HugeTLB {
     pub usage: Option<u64>,
@@ -111,6 +230,33 @@ pub struct BlkIO {
     pub sectors_recursive: Option<Vec<BlkIOEntry>>,
 }
 
+impl BlkIO {
+    /// Bytes transferred by the `op` operations ("read" or "write"), summed over the devices.
+    pub fn service_bytes(&self, op: &str) -> Option<u64> {
+        sum_op(&self.io_service_bytes_recursive, op)
+    }
+
+    /// Number of `op` requests ("read" or "write"), summed over the devices.
+    pub fn serviced(&self, op: &str) -> Option<u64> {
+        sum_op(&self.io_serviced_recursive, op)
+    }
+}
+
+/// Sum the values of the `entries` of operation `op`, which runc capitalizes.
+fn sum_op(entries: &Option<Vec<BlkIOEntry>>, op: &str) -> Option<u64> {
+    let entries = entries.as_ref()?;
+    Some(
+        entries
+            .iter()
+            .filter(|e| {
+                e.op.as_deref()
+                    .map_or(false, |o| o.eq_ignore_ascii_case(op))
+            })
+            .filter_map(|e| e.value)
+            .sum(),
+    )
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Pids {
     /// Number of pids in the cgroup
@@ -179,3 +325,105 @@ pub struct Memory {
     /// Raw stats of memory
     pub raw: Option<HashMap<String, u64>>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits() {
+        let event = r#"
+            {
+                "type": "stats",
+                "id": "fake",
+                "data": {
+                    "cpu": {"usage": 1000000, "throttling": {"periods": 10, "throttledPeriods": 2}},
+                    "memory": {
+                        "cache": 4096,
+                        "usage": {"limit": 268435456, "usage": 67108864, "max": 100663296, "failcnt": 0},
+                        "swap": {"limit": 9223372036854771712, "usage": 67108864, "failcnt": 0}
+                    },
+                    "pids": {"current": 3, "limit": 100},
+                    "blkio": {},
+                    "hugetlb": {"failcnt": 0}
+                }
+            }"#;
+        let event: Event = serde_json::from_str(event).unwrap();
+        let stats = event.stats.unwrap();
+        assert_eq!(stats.cpu.usage, Some(1000000));
+        assert_eq!(stats.pids.current, Some(3));
+        assert_eq!(
+            stats.limits(),
+            Limits {
+                memory: Some(268435456),
+                memory_swap: None,
+                pids: Some(100),
+            }
+        );
+        assert_eq!(stats.memory_headroom(), Some(268435456 - 67108864));
+
+        let mut unlimited = stats;
+        unlimited.memory.usage.as_mut().unwrap().limit = u64::MAX;
+        unlimited.pids.limit = Some(0);
+        assert_eq!(unlimited.limits(), Limits::default());
+        assert_eq!(unlimited.memory_headroom(), None);
+    }
+
+    #[test]
+    fn test_delta() {
+        let stats = |cpu: u64, throttled: u64, read: u64, written: u64| -> Stats {
+            serde_json::from_value(serde_json::json!({
+                "cpu": {"usage": cpu, "throttling": {"throttledTime": throttled}},
+                "memory": {},
+                "pids": {"current": 3},
+                "blkio": {
+                    "ioServiceBytesRecursive": [
+                        {"major": 8, "minor": 0, "op": "Read", "value": read},
+                        {"major": 8, "minor": 0, "op": "Write", "value": written},
+                        {"major": 8, "minor": 16, "op": "Read", "value": read},
+                        {"major": 8, "minor": 16, "op": "Total", "value": 2 * read + written}
+                    ],
+                    "ioServicedRecursive": [
+                        {"major": 8, "minor": 0, "op": "Read", "value": read / 4096},
+                        {"major": 8, "minor": 0, "op": "Write", "value": written / 4096}
+                    ]
+                },
+                "hugetlb": {"failcnt": 0}
+            }))
+            .unwrap()
+        };
+        let prev = stats(1_000_000_000, 0, 4096, 8192);
+        let current = stats(4_000_000_000, 500_000_000, 3 * 4096, 8 * 8192);
+
+        let rate = current.delta(&prev, Duration::from_secs(2));
+        assert_eq!(
+            rate,
+            StatsRate {
+                cpu: Some(1.5e9),
+                cpu_throttled: Some(2.5e8),
+                io_read_bytes: Some(8192.0),
+                io_write_bytes: Some(28672.0),
+                io_read_ops: Some(1.0),
+                io_write_ops: Some(7.0),
+            }
+        );
+
+        // The container restarted: its counters went backwards.
+        let restarted = stats(500_000_000, 0, 3 * 4096, 0);
+        let rate = restarted.delta(&current, Duration::from_secs(1));
+        assert_eq!(rate.cpu, None);
+        assert_eq!(rate.cpu_throttled, None);
+        assert_eq!(rate.io_read_bytes, Some(0.0));
+        assert_eq!(rate.io_write_bytes, None);
+        assert_eq!(rate.io_write_ops, None);
+
+        // Counters missing from a snapshot, and a zero interval, give no rate.
+        let mut missing = current.clone();
+        missing.cpu.usage = None;
+        missing.block_io.io_serviced_recursive = None;
+        let rate = missing.delta(&prev, Duration::from_secs(1));
+        assert_eq!((rate.cpu, rate.io_read_ops), (None, None));
+        assert_eq!(rate.io_read_bytes, Some(16384.0));
+        assert_eq!(current.delta(&prev, Duration::ZERO), StatsRate::default());
+    }
+}
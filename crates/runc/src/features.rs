@@ -0,0 +1,271 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Output of `runc features`.
+//!
+//! See <https://github.com/opencontainers/runtime-spec/blob/main/features.md>
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Version;
+
+/// Annotation set by runc to "true" when it was built with checkpoint/restore support.
+const CHECKPOINT_ENABLED: &str = "org.opencontainers.runc.checkpoint.enabled";
+
+/// Features supported by the runtime.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Features {
+    /// Minimum OCI runtime spec version recognized by the runtime, e.g. "1.0.0".
+    pub oci_version_min: String,
+    /// Maximum OCI runtime spec version recognized by the runtime, e.g. "1.1.0+dev".
+    pub oci_version_max: String,
+    /// Recognized hook names, `None` if unknown.
+    #[serde(default)]
+    pub hooks: Option<Vec<String>>,
+    /// Recognized mount options, `None` if unknown.
+    #[serde(default)]
+    pub mount_options: Option<Vec<String>>,
+    /// Linux specific features, left as is.
+    #[serde(default)]
+    pub linux: Option<serde_json::Value>,
+    /// Implementation specific annotations, e.g. `io.github.seccomp.libseccomp.version`.
+    #[serde(default)]
+    pub annotations: HashMap<String, String>,
+}
+
+/// What a runc binary supports, probed at runtime.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuncCapabilities {
+    /// Version of runc, e.g. "1.1.12".
+    pub runc_version: Option<String>,
+    /// `runc features` is available (runc 1.1 or later).
+    pub supports_features_cmd: bool,
+    /// `runc checkpoint` and `runc restore` are available.
+    pub supports_checkpoint: bool,
+    /// Containers can run in cgroup v2 hierarchies.
+    pub supports_cgroup_v2: bool,
+}
+
+impl RuncCapabilities {
+    /// Derive the capabilities from the output of `runc --version` and of `runc features`, if
+    /// the latter is available.
+    ///
+    /// Without features, checkpointing is assumed to be supported and cgroup v2 support is
+    /// inferred from the version (runc 1.0 or later).
+    pub fn probe(version: &Version, features: Option<&Features>) -> Self {
+        let major = version
+            .runc_version
+            .as_deref()
+            .and_then(|v| v.split('.').next())
+            .and_then(|major| major.parse::<u64>().ok());
+        match features {
+            Some(features) => Self {
+                runc_version: version.runc_version.clone(),
+                supports_features_cmd: true,
+                supports_checkpoint: features
+                    .annotations
+                    .get(CHECKPOINT_ENABLED)
+                    .map_or(true, |enabled| enabled == "true"),
+                supports_cgroup_v2: features
+                    .linux
+                    .as_ref()
+                    .and_then(|linux| linux.pointer("/cgroup/v2"))
+                    .and_then(serde_json::Value::as_bool)
+                    .unwrap_or(false),
+            },
+            None => Self {
+                runc_version: version.runc_version.clone(),
+                supports_features_cmd: false,
+                supports_checkpoint: true,
+                supports_cgroup_v2: major.map_or(false, |major| major >= 1),
+            },
+        }
+    }
+}
+
+/// What the CRIU binary used by runc supports, probed at runtime, see
+/// [crate::Runc::criu_features].
+///
+/// This tells which checkpoint and restore options are safe to pass, e.g.
+/// [crate::options::CheckpointOpts::tcp_established] only works with `tcp_established`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CriuFeatures {
+    /// Version of CRIU, e.g. "3.17.1".
+    pub version: Option<String>,
+    /// `criu check` passed: the kernel supports the basic checkpoint and restore features.
+    pub check_passed: bool,
+    /// Established TCP connections can be checkpointed, which the TCP repair mode checked by
+    /// `criu check` allows.
+    pub tcp_established: bool,
+    /// Memory can be restored lazily, on page faults (the `uffd-noncoop` feature).
+    pub lazy_pages: bool,
+    /// Dirty memory is tracked for incremental checkpoints (the `mem_dirty_track` feature).
+    pub memory_tracking: bool,
+    /// Pidfds can be stored to detect pid reuse between checkpoints (the `pidfd_store`
+    /// feature).
+    pub pidfd_store: bool,
+    /// The network can be locked with nftables rather than iptables (the
+    /// `network_lock_nftables` feature).
+    pub network_lock_nftables: bool,
+}
+
+impl CriuFeatures {
+    /// Features checked one by one with `criu check --feature <name>`.
+    pub const CHECKED_FEATURES: [&'static str; 4] = [
+        "uffd-noncoop",
+        "mem_dirty_track",
+        "pidfd_store",
+        "network_lock_nftables",
+    ];
+
+    /// Derive the features from the output of `criu --version`, the output of `criu check` if
+    /// it succeeded, and whether `criu check --feature <name>` succeeded for the
+    /// [CriuFeatures::CHECKED_FEATURES].
+    pub fn probe(version: &str, check: Option<&str>, supported: impl Fn(&str) -> bool) -> Self {
+        let check_passed = check.map_or(false, |check| check.contains("Looks good"));
+        Self {
+            version: parse_criu_version(version),
+            check_passed,
+            tcp_established: check_passed,
+            lazy_pages: supported("uffd-noncoop"),
+            memory_tracking: supported("mem_dirty_track"),
+            pidfd_store: supported("pidfd_store"),
+            network_lock_nftables: supported("network_lock_nftables"),
+        }
+    }
+}
+
+/// Parse the output of `criu --version`, e.g. "Version: 3.17.1\nGitID: v3.17.1\n".
+fn parse_criu_version(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Version:"))
+        .map(|version| version.trim().to_string())
+        .filter(|version| !version.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::parse_version;
+
+    #[test]
+    fn test_parse_features() {
+        let output = r#"
+            {
+                "ociVersionMin": "1.0.0",
+                "ociVersionMax": "1.1.0+dev",
+                "hooks": ["prestart", "createRuntime"],
+                "mountOptions": ["bind", "rbind"],
+                "linux": {"namespaces": ["pid", "net"]},
+                "annotations": {"org.opencontainers.runc.version": "1.1.12"}
+            }"#;
+        let features: Features = serde_json::from_str(output).unwrap();
+        assert_eq!(features.oci_version_min, "1.0.0");
+        assert_eq!(features.oci_version_max, "1.1.0+dev");
+        assert_eq!(features.hooks.unwrap().len(), 2);
+        assert_eq!(
+            features.annotations["org.opencontainers.runc.version"],
+            "1.1.12"
+        );
+    }
+
+    #[test]
+    fn test_probe_capabilities() {
+        let version =
+            parse_version("runc version 1.1.12\ncommit: v1.1.12-0-g51d5e946\nspec: 1.0.2-dev\n")
+                .unwrap();
+        let features: Features = serde_json::from_str(
+            r#"
+            {
+                "ociVersionMin": "1.0.0",
+                "ociVersionMax": "1.1.0+dev",
+                "linux": {"cgroup": {"v1": true, "v2": true, "systemd": true}},
+                "annotations": {"org.opencontainers.runc.checkpoint.enabled": "true"}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            RuncCapabilities::probe(&version, Some(&features)),
+            RuncCapabilities {
+                runc_version: Some("1.1.12".to_string()),
+                supports_features_cmd: true,
+                supports_checkpoint: true,
+                supports_cgroup_v2: true,
+            }
+        );
+
+        let features: Features = serde_json::from_str(
+            r#"
+            {
+                "ociVersionMin": "1.0.0",
+                "ociVersionMax": "1.1.0+dev",
+                "linux": {"cgroup": {"v1": true}},
+                "annotations": {"org.opencontainers.runc.checkpoint.enabled": "false"}
+            }"#,
+        )
+        .unwrap();
+        let caps = RuncCapabilities::probe(&version, Some(&features));
+        assert!(!caps.supports_checkpoint);
+        assert!(!caps.supports_cgroup_v2);
+
+        // runc 1.0 has no features command.
+        let version = parse_version("runc version 1.0.3\nspec: 1.0.2-dev\n").unwrap();
+        assert_eq!(
+            RuncCapabilities::probe(&version, None),
+            RuncCapabilities {
+                runc_version: Some("1.0.3".to_string()),
+                supports_features_cmd: false,
+                supports_checkpoint: true,
+                supports_cgroup_v2: true,
+            }
+        );
+        let version = parse_version("runc version 0.1.1\nspec: 1.0.0\n").unwrap();
+        assert!(!RuncCapabilities::probe(&version, None).supports_cgroup_v2);
+    }
+
+    #[test]
+    fn test_probe_criu_features() {
+        let version = "Version: 3.17.1\nGitID: v3.17.1-41-g8f9e1b0\n";
+        let check = "Warn  (criu/kerndat.c:1153): CRIU was built without libnftables support\n\
+                     Looks good.\n";
+        let supported = ["uffd-noncoop", "pidfd_store"];
+        assert_eq!(
+            CriuFeatures::probe(version, Some(check), |f| supported.contains(&f)),
+            CriuFeatures {
+                version: Some("3.17.1".to_string()),
+                check_passed: true,
+                tcp_established: true,
+                lazy_pages: true,
+                memory_tracking: false,
+                pidfd_store: true,
+                network_lock_nftables: false,
+            }
+        );
+
+        // criu check fails without CAP_SYS_ADMIN or CAP_CHECKPOINT_RESTORE.
+        let features = CriuFeatures::probe("Version: 3.15\n", None, |_| false);
+        assert_eq!(features.version.as_deref(), Some("3.15"));
+        assert!(!features.check_passed);
+        assert!(!features.tcp_established);
+        assert!(CriuFeatures::probe("criu\n", None, |_| false)
+            .version
+            .is_none());
+    }
+}
@@ -19,7 +19,10 @@ use std::{
     fmt::Debug,
     fs::{File, OpenOptions},
     io::Result,
-    os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+    os::unix::{
+        fs::OpenOptionsExt,
+        io::{AsRawFd, OwnedFd},
+    },
     process::Stdio,
     sync::Mutex,
 };
@@ -28,7 +31,7 @@ use log::debug;
 use nix::unistd::{Gid, Uid};
 use os_pipe::{PipeReader, PipeWriter};
 #[cfg(feature = "async")]
-use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 use crate::Command;
 
@@ -244,6 +247,122 @@ impl Io for PipedIo {
     }
 }
 
+/// Io driver wiring a readable stream, e.g. the parent's stdin or the read end of a pipe, as the
+/// stdin of the container, with stdout and stderr piped like [PipedIo].
+///
+/// Unlike [PipedIo], the stdin of the container stays open after [Io::close_after_start], which
+/// only drops the copy of the stream held by this driver: the container reads it until the caller
+/// closes the write side. This allows interactive sessions without a console socket.
+#[derive(Debug)]
+pub struct StdinIo {
+    stdin: Mutex<Option<OwnedFd>>,
+    output: PipedIo,
+}
+
+impl StdinIo {
+    /// Use `stdin` as the stdin of the container, `opts.open_stdin` is ignored.
+    pub fn new(
+        uid: u32,
+        gid: u32,
+        opts: &IOOption,
+        stdin: impl Into<OwnedFd>,
+    ) -> std::io::Result<Self> {
+        let opts = IOOption {
+            open_stdin: false,
+            ..opts.clone()
+        };
+        Ok(Self {
+            stdin: Mutex::new(Some(stdin.into())),
+            output: PipedIo::new(uid, gid, &opts)?,
+        })
+    }
+}
+
+impl Io for StdinIo {
+    #[cfg(not(feature = "async"))]
+    fn stdout(&self) -> Option<Box<dyn Read + Send>> {
+        self.output.stdout()
+    }
+
+    #[cfg(feature = "async")]
+    fn stdout(&self) -> Option<Box<dyn AsyncRead + Send + Sync + Unpin>> {
+        self.output.stdout()
+    }
+
+    #[cfg(not(feature = "async"))]
+    fn stderr(&self) -> Option<Box<dyn Read + Send>> {
+        self.output.stderr()
+    }
+
+    #[cfg(feature = "async")]
+    fn stderr(&self) -> Option<Box<dyn AsyncRead + Send + Sync + Unpin>> {
+        self.output.stderr()
+    }
+
+    fn set(&self, cmd: &mut Command) -> std::io::Result<()> {
+        if let Some(stdin) = self.stdin.lock().unwrap().as_ref() {
+            cmd.stdin(stdin.try_clone()?);
+        }
+        self.output.set(cmd)
+    }
+
+    fn close_after_start(&self) {
+        let _ = self.stdin.lock().unwrap().take();
+        self.output.close_after_start();
+    }
+}
+
+/// Copy the stdout and stderr of `io` into `writer` until both reach EOF, returning the number
+/// of bytes copied.
+///
+/// The streams end when the container exits, as long as [Io::close_after_start] was called so
+/// that only the container holds their write ends. Output is read only as fast as `writer`
+/// accepts it, so a slow writer eventually blocks the container on a full pipe.
+#[cfg(feature = "async")]
+pub async fn copy_output<W>(io: &dyn Io, mut writer: W) -> Result<u64>
+where
+    W: AsyncWrite + Unpin,
+{
+    async fn read(
+        stream: &mut Option<Box<dyn AsyncRead + Send + Sync + Unpin>>,
+        buf: &mut [u8],
+    ) -> Result<usize> {
+        match stream {
+            Some(stream) => stream.read(buf).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    let mut stdout = io.stdout();
+    let mut stderr = io.stderr();
+    let mut stdout_buf = vec![0u8; 8192];
+    let mut stderr_buf = vec![0u8; 8192];
+    let mut copied = 0;
+    while stdout.is_some() || stderr.is_some() {
+        let (n, from_stdout) = tokio::select! {
+            n = read(&mut stdout, &mut stdout_buf) => (n?, true),
+            n = read(&mut stderr, &mut stderr_buf) => (n?, false),
+        };
+        if n == 0 {
+            if from_stdout {
+                stdout = None;
+            } else {
+                stderr = None;
+            }
+            continue;
+        }
+        let data = if from_stdout {
+            &stdout_buf[..n]
+        } else {
+            &stderr_buf[..n]
+        };
+        writer.write_all(data).await?;
+        copied += n as u64;
+    }
+    writer.flush().await?;
+    Ok(copied)
+}
+
 /// IO driver to direct output/error messages to /dev/null.
 ///
 /// With this Io driver, all methods of [crate::Runc] can't capture the output/error messages.
@@ -416,6 +535,58 @@ mod tests {
         stderr.read_exact(&mut buf).unwrap_err();
     }
 
+    /// Io serving fixed output, without any pipe.
+    #[cfg(feature = "async")]
+    #[derive(Debug)]
+    struct FixedIo {
+        stdout: Vec<u8>,
+        stderr: Vec<u8>,
+    }
+
+    #[cfg(feature = "async")]
+    impl Io for FixedIo {
+        fn stdout(&self) -> Option<Box<dyn AsyncRead + Send + Sync + Unpin>> {
+            Some(Box::new(std::io::Cursor::new(self.stdout.clone())))
+        }
+
+        fn stderr(&self) -> Option<Box<dyn AsyncRead + Send + Sync + Unpin>> {
+            Some(Box::new(std::io::Cursor::new(self.stderr.clone())))
+        }
+
+        fn set(&self, _cmd: &mut Command) -> Result<()> {
+            Ok(())
+        }
+
+        fn close_after_start(&self) {}
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_copy_output() {
+        let stdout = "a line on stdout\n".repeat(1000);
+        let io = FixedIo {
+            stdout: stdout.clone().into_bytes(),
+            stderr: b"an error\n".to_vec(),
+        };
+        let mut buf = Vec::new();
+        let copied = copy_output(&io, &mut buf).await.unwrap();
+        assert_eq!(copied as usize, stdout.len() + 9);
+        assert_eq!(buf.len(), copied as usize);
+
+        // Chunks of both streams may interleave, but each one keeps its order.
+        let output = String::from_utf8(buf).unwrap();
+        assert_eq!(output.replacen("an error\n", "", 1), stdout);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_copy_output_null_io() {
+        let io = NullIo::new().unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(copy_output(&io, &mut buf).await.unwrap(), 0);
+        assert!(buf.is_empty());
+    }
+
     #[test]
     fn test_null_io() {
         let io = NullIo::new().unwrap();
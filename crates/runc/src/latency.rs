@@ -0,0 +1,178 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Latency histograms of the runc commands, see [crate::options::GlobalOpts::record_latency].
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
+
+/// Upper bounds of the buckets, in milliseconds: powers of 2 from 1ms to about 32s, the last
+/// bucket holding the slower commands.
+const BUCKET_COUNT: usize = 17;
+
+fn bucket_bound(index: usize) -> Duration {
+    match index {
+        i if i + 1 < BUCKET_COUNT => Duration::from_millis(1 << i),
+        _ => Duration::MAX,
+    }
+}
+
+/// Histogram of command latencies, updated without locking.
+#[derive(Debug, Default)]
+pub(crate) struct Histogram {
+    buckets: [AtomicU64; BUCKET_COUNT],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+    max_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn record(&self, latency: Duration) {
+        let index = (0..BUCKET_COUNT)
+            .find(|&i| latency <= bucket_bound(i))
+            .unwrap_or(BUCKET_COUNT - 1);
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> LatencySnapshot {
+        LatencySnapshot {
+            count: self.count.load(Ordering::Relaxed),
+            sum: Duration::from_micros(self.sum_micros.load(Ordering::Relaxed)),
+            max: Duration::from_micros(self.max_micros.load(Ordering::Relaxed)),
+            buckets: self
+                .buckets
+                .iter()
+                .enumerate()
+                .map(|(i, count)| (bucket_bound(i), count.load(Ordering::Relaxed)))
+                .collect(),
+        }
+    }
+}
+
+/// Latency histograms per subcommand, shared by the clones of a [crate::Runc].
+///
+/// The map is only write-locked the first time a subcommand completes.
+#[derive(Debug, Default)]
+pub(crate) struct Latencies(RwLock<HashMap<String, Arc<Histogram>>>);
+
+impl Latencies {
+    pub(crate) fn record(&self, subcommand: &str, latency: Duration) {
+        let histogram = self.0.read().unwrap().get(subcommand).cloned();
+        let histogram = match histogram {
+            Some(histogram) => histogram,
+            None => self
+                .0
+                .write()
+                .unwrap()
+                .entry(subcommand.to_string())
+                .or_default()
+                .clone(),
+        };
+        histogram.record(latency);
+    }
+
+    pub(crate) fn snapshot(&self) -> HashMap<String, LatencySnapshot> {
+        self.0
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(subcommand, histogram)| (subcommand.clone(), histogram.snapshot()))
+            .collect()
+    }
+}
+
+/// Latencies of a subcommand recorded so far, see [crate::Runc::latency_snapshot].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencySnapshot {
+    /// Number of commands recorded.
+    pub count: u64,
+    /// Total latency of the commands.
+    pub sum: Duration,
+    /// Highest latency.
+    pub max: Duration,
+    /// Upper bound of each bucket and how many commands it holds, by increasing bound. The last
+    /// bound is `Duration::MAX`.
+    pub buckets: Vec<(Duration, u64)>,
+}
+
+impl LatencySnapshot {
+    /// Average latency, `None` if nothing was recorded.
+    pub fn mean(&self) -> Option<Duration> {
+        let count = u32::try_from(self.count).ok().filter(|&c| c > 0)?;
+        Some(self.sum / count)
+    }
+
+    /// Upper bound of the bucket holding the `q` quantile (e.g. 0.99), capped by the highest
+    /// latency, `None` if nothing was recorded.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        self.buckets
+            .iter()
+            .find(|(_, count)| {
+                seen += count;
+                seen >= rank
+            })
+            .map(|(bound, _)| (*bound).min(self.max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let latencies = Latencies::default();
+        for millis in [0, 1, 3, 3, 40, 100_000] {
+            latencies.record("create", Duration::from_millis(millis));
+        }
+        latencies.record("start", Duration::from_micros(1500));
+
+        let snapshot = latencies.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        let create = &snapshot["create"];
+        assert_eq!(create.count, 6);
+        assert_eq!(create.sum, Duration::from_millis(100_047));
+        assert_eq!(create.max, Duration::from_secs(100));
+        assert_eq!(create.buckets.len(), BUCKET_COUNT);
+        let count = |bound: Duration| create.buckets.iter().find(|(b, _)| *b == bound).unwrap().1;
+        assert_eq!(count(Duration::from_millis(1)), 2);
+        assert_eq!(count(Duration::from_millis(4)), 2);
+        assert_eq!(count(Duration::from_millis(64)), 1);
+        assert_eq!(count(Duration::MAX), 1);
+        assert_eq!(create.mean(), Some(Duration::from_micros(16_674_500)));
+        assert_eq!(create.quantile(0.5), Some(Duration::from_millis(4)));
+        assert_eq!(create.quantile(1.0), Some(Duration::from_secs(100)));
+
+        let start = &snapshot["start"];
+        assert_eq!(start.count, 1);
+        assert_eq!(start.quantile(0.99), Some(Duration::from_micros(1500)));
+    }
+}
@@ -37,38 +37,84 @@
 
 //! A crate for consuming the runc binary in your Rust applications, similar to
 //! [go-runc](https://github.com/containerd/go-runc) for Go.
+#[cfg(not(feature = "async"))]
+use std::os::unix::process::CommandExt;
 use std::{
+    borrow::Cow,
     fmt::{self, Debug, Display},
+    os::unix::{io::RawFd, process::ExitStatusExt},
     path::{Path, PathBuf},
     process::{ExitStatus, Stdio},
     sync::Arc,
+    time::{Duration, Instant},
 };
+#[cfg(feature = "async")]
+use std::{collections::HashMap, sync::Weak};
 
 #[cfg(feature = "async")]
 use async_trait::async_trait;
-#[cfg(feature = "async")]
 use log::debug;
-use oci_spec::runtime::{LinuxResources, Process};
+use oci_spec::runtime::{LinuxResources, Process, Spec};
+#[cfg(feature = "async")]
+use tokio::sync::OwnedMutexGuard;
 
-use crate::{container::Container, error::Error, options::*, utils::write_value_to_temp_file};
+use crate::{
+    container::{Container, ContainerStatus, DriftReport},
+    error::Error,
+    features::Features,
+    options::*,
+    utils::write_value_to_temp_file_with_context,
+};
 
+#[cfg(feature = "async")]
+pub mod console;
 pub mod container;
 pub mod error;
 pub mod events;
+pub mod features;
 pub mod io;
 #[cfg(feature = "async")]
+pub mod latency;
+pub mod logs;
+#[cfg(feature = "async")]
 pub mod monitor;
 pub mod options;
+pub mod spec;
+pub mod stats;
+#[cfg(feature = "async")]
+pub mod tail;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
 pub mod utils;
 
 pub type Result<T> = std::result::Result<T, crate::error::Error>;
 
 /// Response is for (pid, exit status, outputs).
+///
+/// More fields may be added, build one with [Response::new] outside of this crate, e.g. in the
+/// fakes of tests.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct Response {
+    /// Pid of the runc process, or of the container's process when a pid file was requested.
     pub pid: u32,
     pub status: ExitStatus,
     pub output: String,
+    /// Absolute path of the bundle passed to runc, for the commands taking one (create, run
+    /// and restore).
+    pub bundle: Option<PathBuf>,
+}
+
+impl Response {
+    /// Response of a command which took no bundle.
+    pub fn new(pid: u32, status: ExitStatus, output: String) -> Self {
+        Self {
+            pid,
+            status,
+            output,
+            bundle: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -76,6 +122,8 @@ pub struct Version {
     pub runc_version: Option<String>,
     pub spec_version: Option<String>,
     pub commit: Option<String>,
+    /// The whole "runc version" line as printed by runc.
+    pub raw: String,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -104,13 +152,215 @@ pub type Command = tokio::process::Command;
 pub struct Runc {
     command: PathBuf,
     args: Vec<String>,
+    /// CRIU binary passed to runc, probed by [Runc::criu_features].
+    criu: PathBuf,
     spawner: Arc<dyn Spawner + Send + Sync>,
+    #[cfg(feature = "async")]
+    timeout: Duration,
+    #[cfg(not(feature = "async"))]
+    timeout: Option<Duration>,
+    /// Check container ids before running runc, see [GlobalOpts::validate_ids].
+    validate_ids: bool,
+    /// Append stderr to the output of successful commands, see [GlobalOpts::combine_output].
+    combine_output: bool,
+    validate_seccomp: bool,
+    validate_rootfs: bool,
+    validate_mounts: bool,
+    check_oci_version: bool,
+    working_dir: Option<PathBuf>,
+    launcher: Option<Launcher>,
+    bundle_root: Option<PathBuf>,
+    /// Log file descriptor inherited by runc.
+    log_fd: Option<RawFd>,
+    /// Directory runc keeps the state of the containers in.
+    state_root: PathBuf,
+    /// Free space the state root must have before creating a container.
+    min_root_free: Option<u64>,
+    #[cfg(feature = "async")]
+    container_locks: Option<Arc<ContainerLocks>>,
+    /// Capabilities probed by [Runc::capabilities], shared by the clones of this instance.
+    #[cfg(feature = "async")]
+    capabilities: Arc<tokio::sync::OnceCell<features::RuncCapabilities>>,
+    /// Latencies recorded if [GlobalOpts::record_latency] is set, shared by the clones of this
+    /// instance.
+    #[cfg(feature = "async")]
+    latencies: Option<Arc<latency::Latencies>>,
+    on_command_complete: Option<CommandCallback>,
+    correlation_id: Option<String>,
+    oom_score_adj: Option<i32>,
+    pre_exec: Vec<PreExecHook>,
+}
+
+/// Exit code and output of a container run by [Runc::run_captured].
+#[cfg(feature = "async")]
+#[derive(Debug, Clone)]
+pub struct RunOutput {
+    /// Exit code of the container's process, 128 + the signal number if it was killed.
+    pub exit_code: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Container run in the background by [Runc::spawn_run].
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct RunHandle {
+    id: String,
+    runc: Runc,
+    /// Taken once waited for.
+    task: Option<tokio::task::JoinHandle<Result<Response>>>,
+    kill_on_drop: Option<KillOnDrop>,
+}
+
+/// How the container of a dropped [RunHandle] is killed, see [RunHandle::kill_on_drop].
+#[cfg(feature = "async")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KillOnDrop {
+    /// Signal sent to the container, `SIGKILL` by default.
+    pub signal: u32,
+    /// Time the container has to exit after `signal` before it's sent `SIGKILL`, e.g. for a
+    /// stateful workload to flush its data on `SIGTERM`. Without one, only `signal` is sent.
+    pub grace_period: Option<Duration>,
+}
+
+#[cfg(feature = "async")]
+impl Default for KillOnDrop {
+    fn default() -> Self {
+        Self {
+            signal: libc::SIGKILL as u32,
+            grace_period: None,
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl RunHandle {
+    /// Id of the container.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Kill the container as set by `kill` if the handle is dropped while it still runs, instead
+    /// of leaving it running in the background.
+    ///
+    /// The container is killed by a task spawned on the current tokio runtime, if any, and the
+    /// handle being dropped doesn't wait for it.
+    pub fn kill_on_drop(mut self, kill: KillOnDrop) -> Self {
+        self.kill_on_drop = Some(kill);
+        self
+    }
+
+    /// Send the specified signal to processes inside the container while it runs.
+    pub async fn kill(&self, sig: u32, opts: Option<&KillOpts>) -> Result<()> {
+        self.runc.kill(&self.id, sig, opts).await
+    }
+
+    /// Wait for the container to exit and return the result of the run.
+    pub async fn wait(mut self) -> Result<Response> {
+        let task = self.task.take().expect("run task is only taken once");
+        task.await.map_err(|e| Error::Other(Box::new(e)))?
+    }
+}
+
+#[cfg(feature = "async")]
+impl Drop for RunHandle {
+    fn drop(&mut self) {
+        let (kill, task) = match (self.kill_on_drop, self.task.take()) {
+            (Some(kill), Some(task)) if !task.is_finished() => (kill, task),
+            _ => return,
+        };
+        let runtime = match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => runtime,
+            Err(_) => return,
+        };
+        let (runc, id) = (self.runc.clone(), std::mem::take(&mut self.id));
+        runtime.spawn(async move {
+            if let Err(e) = runc.kill(&id, kill.signal, None).await {
+                debug!("failed to kill container {} on drop: {}", id, e);
+            }
+            let sigkill = libc::SIGKILL as u32;
+            if let (Some(grace_period), false) = (kill.grace_period, kill.signal == sigkill) {
+                if tokio::time::timeout(grace_period, task).await.is_err() {
+                    let _ = runc.kill(&id, sigkill, None).await;
+                }
+            }
+        });
+    }
+}
+
+/// Per-container locks, see [GlobalOpts::lock_per_container].
+#[cfg(feature = "async")]
+#[derive(Debug, Default)]
+pub(crate) struct ContainerLocks(std::sync::Mutex<HashMap<String, Weak<tokio::sync::Mutex<()>>>>);
+
+#[cfg(feature = "async")]
+impl ContainerLocks {
+    async fn lock(&self, id: &str) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.0.lock().unwrap();
+            // Forget the locks nobody holds or waits for anymore.
+            locks.retain(|_, lock| lock.strong_count() > 0);
+            match locks.get(id).and_then(Weak::upgrade) {
+                Some(lock) => lock,
+                None => {
+                    let lock = Arc::new(tokio::sync::Mutex::new(()));
+                    locks.insert(id.to_string(), Arc::downgrade(&lock));
+                    lock
+                }
+            }
+        };
+        lock.lock_owned().await
+    }
+}
+
+/// Callback invoked after each runc command, see [GlobalOpts::on_command_complete].
+#[derive(Clone)]
+pub(crate) struct CommandCallback(pub(crate) Arc<CommandCallbackFn>);
+
+type CommandCallbackFn = dyn Fn(&str, Duration, bool) + Send + Sync;
+
+impl Debug for CommandCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CommandCallback")
+    }
+}
+
+/// Hook run in the runc child before exec, see [GlobalOpts::pre_exec].
+#[derive(Clone)]
+pub(crate) struct PreExecHook(pub(crate) Arc<PreExecHookFn>);
+
+type PreExecHookFn = dyn Fn() -> std::io::Result<()> + Send + Sync;
+
+impl Debug for PreExecHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PreExecHook")
+    }
+}
+
+/// Return the runc subcommand in the arguments `args` of runc, e.g. "create".
+pub(crate) fn subcommand_of(mut args: impl Iterator<Item = String>) -> String {
+    // The global flags, which may be overridden per call, come first.
+    while let Some(arg) = args.next() {
+        if [ROOT, LOG, LOG_FORMAT, CRIU].contains(&arg.as_str()) {
+            args.next();
+        } else if !arg.starts_with('-') {
+            return arg;
+        }
+    }
+    String::new()
 }
 
 impl Runc {
     fn command(&self, args: &[String]) -> Result<Command> {
         let args = [&self.args, args].concat();
-        let mut cmd = Command::new(&self.command);
+        let mut cmd = match &self.launcher {
+            Some(launcher) => {
+                let mut cmd = Command::new(launcher.program());
+                cmd.args(launcher.args()).arg(&self.command);
+                cmd
+            }
+            None => Command::new(&self.command),
+        };
 
         // Default to piped stdio, and they may be override by command options.
         cmd.stdin(Stdio::null())
@@ -119,26 +369,229 @@ impl Runc {
 
         // NOTIFY_SOCKET introduces a special behavior in runc but should only be set if invoked from systemd
         cmd.args(&args).env_remove("NOTIFY_SOCKET");
+        if let Some(dir) = &self.working_dir {
+            cmd.current_dir(dir);
+        }
+        if let Some(fd) = self.log_fd {
+            // The fd is passed to runc as /proc/self/fd/<fd>, keep it open across exec.
+            unsafe {
+                cmd.pre_exec(move || {
+                    nix::fcntl::fcntl(
+                        fd,
+                        nix::fcntl::FcntlArg::F_SETFD(nix::fcntl::FdFlag::empty()),
+                    )?;
+                    Ok(())
+                });
+            }
+        }
+        if let Some(score) = self.oom_score_adj {
+            // Set before exec rather than after spawn, so that runc's children inherit it. The
+            // value is formatted before the fork, the hook only makes raw syscalls.
+            let score = score.to_string().into_bytes();
+            unsafe {
+                cmd.pre_exec(move || utils::write_oom_score_adj(&score));
+            }
+        }
+        for PreExecHook(hook) in &self.pre_exec {
+            let hook = hook.clone();
+            // Safety: the caller of GlobalOpts::pre_exec vouched for the hook.
+            unsafe {
+                cmd.pre_exec(move || hook());
+            }
+        }
+
+        Ok(cmd)
+    }
+
+    /// Check that `id` is a valid container id, unless disabled with [GlobalOpts::validate_ids].
+    fn check_id(&self, id: &str) -> Result<()> {
+        if self.validate_ids {
+            utils::validate_id(id)?;
+        }
+        Ok(())
+    }
 
+    /// Build a `criu` command with `args`, using the CRIU binary passed to runc.
+    fn criu_command(&self, args: &[&str]) -> Result<Command> {
+        let criu =
+            utils::binary_path(&self.criu).ok_or_else(|| Error::CriuNotFound(self.criu.clone()))?;
+        let mut cmd = Command::new(criu);
+        cmd.args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
         Ok(cmd)
     }
+
+    /// Like [Runc::command] for a create or run with `opts`, which may override the global
+    /// `--systemd-cgroup` and `--rootless` flags.
+    fn create_command(&self, args: &[String], opts: Option<&CreateOpts>) -> Result<Command> {
+        self.command_with_overrides(
+            args,
+            opts.and_then(|o| o.systemd_cgroup),
+            opts.and_then(|o| o.rootless),
+        )
+    }
+
+    /// Like [Runc::command], replacing the global `--systemd-cgroup` and `--rootless` flags for
+    /// this call with the overrides which are set.
+    fn command_with_overrides(
+        &self,
+        args: &[String],
+        systemd_cgroup: Option<bool>,
+        rootless: Option<Option<bool>>,
+    ) -> Result<Command> {
+        if systemd_cgroup.is_none() && rootless.is_none() {
+            return self.command(args);
+        }
+        let mut runc = self.clone();
+        if let Some(systemd_cgroup) = systemd_cgroup {
+            runc.args.retain(|a| a != SYSTEMD_CGROUP);
+            if systemd_cgroup {
+                runc.args.push(SYSTEMD_CGROUP.to_string());
+            }
+        }
+        if let Some(rootless) = rootless {
+            let prefix = format!("{}=", ROOTLESS);
+            runc.args.retain(|a| !a.starts_with(&prefix));
+            if let Some(rootless) = rootless {
+                runc.args.push(format!("{}{}", prefix, rootless));
+            }
+        }
+        runc.command(args)
+    }
+
+    /// Whether the process spec of an exec with `opts` is passed to runc on its stdin, see
+    /// [ExecOpts::process_via_stdin].
+    ///
+    /// A launcher may not pass stdin on, or run runc where `/dev/stdin` doesn't lead to it, so the
+    /// temp file is used with one, and when `/dev/stdin` can't be resolved here.
+    fn exec_via_stdin(&self, opts: Option<&ExecOpts>) -> bool {
+        opts.map_or(false, |o| o.process_via_stdin && o.io.is_none())
+            && self.launcher.is_none()
+            && utils::dev_stdin_available()
+    }
+
+    /// Return the runc subcommand run by `cmd`, e.g. "create".
+    fn subcommand(&self, cmd: &Command) -> String {
+        #[cfg(feature = "async")]
+        let cmd = cmd.as_std();
+        let launcher_args = self.launcher.as_ref().map_or(0, |l| l.args().len() + 1);
+        subcommand_of(
+            cmd.get_args()
+                .skip(launcher_args)
+                .map(|a| a.to_string_lossy().to_string()),
+        )
+    }
+
+    /// Span covering the run of `cmd`, carrying the correlation id of the instance, if any.
+    #[cfg(feature = "tracing")]
+    fn command_span(&self, cmd: &Command) -> tracing::Span {
+        tracing::debug_span!(
+            "runc",
+            subcommand = %self.subcommand(cmd),
+            correlation_id = self.correlation_id.as_deref(),
+        )
+    }
+
+    /// Log the outcome of a command and report it to the `on_command_complete` callback, if any.
+    fn command_complete(&self, subcommand: &str, started: Instant, succeeded: bool) {
+        let elapsed = started.elapsed();
+        let outcome = if succeeded { "succeeded" } else { "failed" };
+        match &self.correlation_id {
+            Some(id) => debug!(
+                correlation_id = id.as_str();
+                "runc {} {} after {:?}", subcommand, outcome, elapsed
+            ),
+            None => debug!("runc {} {} after {:?}", subcommand, outcome, elapsed),
+        }
+        if let Some(CommandCallback(callback)) = &self.on_command_complete {
+            callback(subcommand, elapsed, succeeded);
+        }
+        #[cfg(feature = "async")]
+        if let Some(latencies) = &self.latencies {
+            latencies.record(subcommand, elapsed);
+        }
+    }
+
+    /// Check that `bundle` may be removed, i.e. that it's inside the configured bundle root.
+    fn check_bundle_removal(&self, bundle: &str) -> Result<()> {
+        let inside = match &self.bundle_root {
+            Some(root) => match (Path::new(bundle).canonicalize(), root.canonicalize()) {
+                (Ok(bundle), Ok(root)) => bundle != root && bundle.starts_with(root),
+                _ => false,
+            },
+            None => false,
+        };
+        if !inside {
+            return Err(Error::BundleOutsideRoot(bundle.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Return those of the state directories `dirs` which aren't the state of one of
+    /// `containers`.
+    fn orphans(dirs: Vec<PathBuf>, containers: &[Container]) -> Vec<PathBuf> {
+        dirs.into_iter()
+            .filter(|dir| {
+                let id = dir.file_name().map(|n| n.to_string_lossy());
+                !containers
+                    .iter()
+                    .any(|c| Some(c.id.as_str()) == id.as_deref())
+            })
+            .collect()
+    }
+
+    /// Remove the orphaned state directory `dir`, which may be gone already.
+    fn remove_orphan(dir: &Path) -> Result<()> {
+        match std::fs::remove_dir_all(dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Error::FileSystemError(e)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Run the opt-in checks on the bundle and the state root before handing them to runc.
+    fn check_bundle(&self, bundle: &Path) -> Result<()> {
+        if let Some(min_free) = self.min_root_free {
+            utils::check_state_root(&self.state_root, min_free)?;
+        }
+        if self.validate_seccomp {
+            spec::validate_seccomp(bundle)?;
+        }
+        if self.validate_rootfs {
+            spec::validate_rootfs(bundle)?;
+        }
+        if self.validate_mounts {
+            spec::validate_mounts(&spec::load(bundle)?, &spec::HostMountSupport::probe()?)?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(not(feature = "async"))]
 impl Runc {
     fn launch(&self, cmd: Command, combined_output: bool) -> Result<Response> {
-        let (status, pid, stdout, stderr) = self.spawner.execute(cmd)?;
+        let subcommand = self.subcommand(&cmd);
+        #[cfg(feature = "tracing")]
+        let _span = self.command_span(&cmd).entered();
+        let started = Instant::now();
+        let res = self.execute(cmd, combined_output);
+        self.command_complete(&subcommand, started, res.is_ok());
+        res
+    }
+
+    fn execute(&self, cmd: Command, combined_output: bool) -> Result<Response> {
+        let (status, pid, stdout, stderr) = match self.timeout {
+            Some(timeout) => self.spawner.execute_with_timeout(cmd, timeout)?,
+            None => self.spawner.execute(cmd)?,
+        };
         if status.success() {
-            let output = if combined_output {
+            let output = if combined_output && self.combine_output {
                 stdout + stderr.as_str()
             } else {
                 stdout
             };
-            Ok(Response {
-                pid,
-                status,
-                output,
-            })
+            Ok(Response::new(pid, status, output))
         } else {
             Err(Error::CommandFailed {
                 status,
@@ -153,29 +606,67 @@ impl Runc {
     where
         P: AsRef<Path>,
     {
-        let mut args = vec![
-            "create".to_string(),
-            "--bundle".to_string(),
-            utils::abs_string(bundle)?,
-        ];
+        self.check_id(id)?;
+        self.check_bundle(bundle.as_ref())?;
+        self.check_oci_version(bundle.as_ref())?;
+        let bundle = utils::abs_string(bundle)?;
+        let mut args = vec!["create".to_string(), "--bundle".to_string(), bundle.clone()];
         if let Some(opts) = opts {
-            args.append(&mut opts.args()?);
+            args.append(&mut opts.create_args()?);
         }
         args.push(id.to_string());
-        let mut cmd = self.command(&args)?;
-        match opts {
+        let mut cmd = self.create_command(&args, opts)?;
+        let res = match opts {
             Some(CreateOpts { io: Some(io), .. }) => {
                 io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string()))?;
                 let res = self.launch(cmd, true)?;
                 io.close_after_start();
-                Ok(res)
+                res
             }
-            _ => self.launch(cmd, true),
+            _ => self.launch(cmd, true)?,
+        };
+        with_init_pid(res, opts.and_then(|o| o.pid_file.as_ref())).map(|res| Response {
+            bundle: Some(bundle.into()),
+            ..res
+        })
+    }
+
+    /// Create a new container, tolerating a container `id` which already exists with the same
+    /// bundle.
+    ///
+    /// This gives at-least-once semantics to create, e.g. when it's retried after a crash. If
+    /// the existing container was created from another bundle, the error of runc is returned.
+    /// On success, the pid of the returned [Response] is the one of the existing container.
+    pub fn create_idempotent<P>(
+        &self,
+        id: &str,
+        bundle: P,
+        opts: Option<&CreateOpts>,
+    ) -> Result<Response>
+    where
+        P: AsRef<Path>,
+    {
+        let bundle = utils::abs_string(bundle)?;
+        match self.create(id, &bundle, opts) {
+            Err(err) if utils::is_already_exists(&err) => {
+                let container = self.state(id)?;
+                if container.bundle != bundle {
+                    return Err(err);
+                }
+                Ok(Response {
+                    pid: container.pid as u32,
+                    status: ExitStatus::from_raw(0),
+                    output: String::new(),
+                    bundle: Some(bundle.into()),
+                })
+            }
+            res => res,
         }
     }
 
     /// Delete a container
     pub fn delete(&self, id: &str, opts: Option<&DeleteOpts>) -> Result<()> {
+        self.check_id(id)?;
         let mut args = vec!["delete".to_string()];
         if let Some(opts) = opts {
             args.append(&mut opts.args());
@@ -185,56 +676,255 @@ impl Runc {
         Ok(())
     }
 
+    /// Delete a container and remove its bundle directory.
+    ///
+    /// The bundle must be inside [GlobalOpts::bundle_root], otherwise nothing is deleted and
+    /// [Error::BundleOutsideRoot] is returned. If the container is deleted but its bundle can't
+    /// be removed, [Error::BundleCleanupFailed] is returned.
+    pub fn delete_and_cleanup(&self, id: &str, opts: Option<&DeleteOpts>) -> Result<()> {
+        let container = self.state(id)?;
+        self.check_bundle_removal(&container.bundle)?;
+        self.delete(id, opts)?;
+        match std::fs::remove_dir_all(&container.bundle) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(Error::BundleCleanupFailed(e))
+            }
+            _ => Ok(()),
+        }
+    }
+
     /// Execute an additional process inside the container
     pub fn exec(&self, id: &str, spec: &Process, opts: Option<&ExecOpts>) -> Result<()> {
-        let (_temp_file, filename) = write_value_to_temp_file(spec)?;
-        let mut args = vec!["exec".to_string(), "--process".to_string(), filename];
+        self.exec_with_response(id, spec, opts).map(|_| ())
+    }
+
+    /// Execute an additional process inside the container like [Runc::exec], and return the
+    /// [Response] of runc.
+    ///
+    /// If `opts` has a pid file, the pid of the returned [Response] is the one of the new process
+    /// instead of runc's. The pid file of a detached process is removed once read.
+    pub fn exec_with_response(
+        &self,
+        id: &str,
+        spec: &Process,
+        opts: Option<&ExecOpts>,
+    ) -> Result<Response> {
+        self.check_id(id)?;
+        let spec = match opts {
+            Some(opts) => opts.process(spec)?,
+            None => Cow::Borrowed(spec),
+        };
+        let process_pipe = if self.exec_via_stdin(opts) {
+            utils::write_value_to_pipe(&spec, "exec process")?
+        } else {
+            None
+        };
+        let process_file = match process_pipe {
+            Some(_) => None,
+            None => Some(write_value_to_temp_file_with_context(
+                &spec,
+                "exec process",
+            )?),
+        };
+        let mut args = vec![
+            "exec".to_string(),
+            "--process".to_string(),
+            match &process_file {
+                Some(file) => file.path_string()?,
+                None => "/dev/stdin".to_string(),
+            },
+        ];
         if let Some(opts) = opts {
             args.append(&mut opts.args()?);
         }
         args.push(id.to_string());
-        let mut cmd = self.command(&args)?;
-        match opts {
+        let mut cmd = self.command_with_overrides(&args, None, opts.and_then(|o| o.rootless))?;
+        if let Some(pipe) = process_pipe {
+            cmd.stdin(pipe);
+        }
+        let res = match opts {
             Some(ExecOpts { io: Some(io), .. }) => {
                 io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string()))?;
-                self.launch(cmd, true)?;
+                let res = self.launch(cmd, true).map_err(utils::map_exec_error)?;
                 io.close_after_start();
+                res
             }
-            _ => {
-                self.launch(cmd, true)?;
-            }
+            _ => self.launch(cmd, true).map_err(utils::map_exec_error)?,
+        };
+        let pid_file = opts.and_then(|o| o.pid_file.as_ref());
+        let res = with_init_pid(res, pid_file);
+        if let (Some(pid_file), true) = (pid_file, opts.map_or(false, |o| o.detach)) {
+            let _ = std::fs::remove_file(pid_file);
         }
-        Ok(())
+        res
+    }
+
+    /// Execute `argv` in the container, without having to build a full [Process].
+    ///
+    /// The command inherits the working directory and the user of the container's process, read
+    /// from its bundle: it fails if the spec can't be loaded or has no process, rather than
+    /// running as root. See [spec::exec_process] for how `env` is applied.
+    pub fn exec_command(
+        &self,
+        id: &str,
+        argv: &[String],
+        env: &[(String, String)],
+        opts: Option<&ExecOpts>,
+    ) -> Result<()> {
+        let container = self.state(id)?;
+        let base = spec::load(&container.bundle)?
+            .process()
+            .clone()
+            .ok_or_else(|| Error::BundleWithoutProcess(container.bundle.clone()))?;
+        let process = spec::exec_process(Some(&base), argv, env);
+        self.exec(id, &process, opts)
     }
 
     /// Send the specified signal to processes inside the container
+    ///
+    /// Succeeds if the init process has already exited, unless [KillOpts::strict] is set.
     pub fn kill(&self, id: &str, sig: u32, opts: Option<&KillOpts>) -> Result<()> {
+        self.check_id(id)?;
         let mut args = vec!["kill".to_string()];
         if let Some(opts) = opts {
             args.append(&mut opts.args());
         }
         args.push(id.to_string());
         args.push(sig.to_string());
-        let _ = self.launch(self.command(&args)?, true)?;
-        Ok(())
+        match self.launch(self.command(&args)?, true) {
+            Err(e) if !opts.map_or(false, |o| o.strict) && utils::is_process_done(&e) => Ok(()),
+            res => res.map(|_| ()),
+        }
     }
 
     /// List all containers associated with this runc instance
     pub fn list(&self) -> Result<Vec<Container>> {
         let args = ["list".to_string(), "--format=json".to_string()];
         let res = self.launch(self.command(&args)?, true)?;
-        let output = res.output.trim();
+        utils::parse_json_or_null(&res.output)
+    }
 
-        // Ugly hack to work around golang
-        Ok(if output == "null" {
-            Vec::new()
-        } else {
-            serde_json::from_str(output).map_err(Error::JsonDeserializationFailed)?
+    /// List the containers which have the annotation `key` set to `value`, e.g. the containers
+    /// of a pod.
+    pub fn list_by_annotation(&self, key: &str, value: &str) -> Result<Vec<Container>> {
+        let mut containers = self.list()?;
+        containers.retain(|c| c.has_annotation(key, value));
+        Ok(containers)
+    }
+
+    /// Compare the `expected` ids and statuses of containers to the containers runc lists, e.g.
+    /// to recover from a crash of the caller. See [DriftReport].
+    pub fn drift<I, S>(&self, expected: I) -> Result<DriftReport>
+    where
+        I: IntoIterator<Item = (S, ContainerStatus)>,
+        S: Into<String>,
+    {
+        Ok(DriftReport::new(expected, &self.list()?))
+    }
+
+    /// Return the directories of the state root which are the state of no container runc
+    /// lists, e.g. left behind by a crash.
+    ///
+    /// Only directories holding runc's `state.json` are considered, see [utils::state_dirs].
+    /// They are read before listing the containers, so that containers created meanwhile aren't
+    /// taken for orphans.
+    pub fn find_orphans(&self) -> Result<Vec<PathBuf>> {
+        let dirs = utils::state_dirs(&self.state_root)?;
+        Ok(Self::orphans(dirs, &self.list()?))
+    }
+
+    /// Remove the directories found by [Runc::find_orphans], and return them.
+    pub fn clean_orphans(&self) -> Result<Vec<PathBuf>> {
+        let orphans = self.find_orphans()?;
+        for dir in &orphans {
+            Self::remove_orphan(dir)?;
+        }
+        Ok(orphans)
+    }
+
+    /// Return the pids of the processes in the container's cgroup.
+    ///
+    /// The pids are read from the `cgroup.procs` file of the cgroup of the container's init
+    /// process, which doesn't depend on the `ps` binary. Falls back to [Runc::ps] when the cgroup
+    /// can't be found.
+    pub fn pids(&self, id: &str) -> Result<Vec<usize>> {
+        let container = self.state(id)?;
+        let procs = std::fs::read_to_string(format!("/proc/{}/cgroup", container.pid))
+            .ok()
+            .and_then(|cgroup| utils::cgroup_procs_path(&cgroup))
+            .and_then(|path| std::fs::read_to_string(path).ok());
+        match procs {
+            Some(procs) => Ok(utils::parse_pids(&procs)),
+            None => self.ps(id),
+        }
+    }
+
+    /// Return how many processes of container `id` the OOM killer killed, and whether the count
+    /// increased since `previous`, a count returned by an earlier call.
+    ///
+    /// The count is read from the memory cgroup of the container's init process, which is cheaper
+    /// than watching OOM events and doesn't miss the kills happening while nobody listens.
+    pub fn oom_kills(&self, id: &str, previous: Option<u64>) -> Result<events::OomKills> {
+        let container = self.state(id)?;
+        let not_found = || {
+            Error::FileSystemError(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no OOM kill counter for container {}", id),
+            ))
+        };
+        let cgroup = std::fs::read_to_string(format!("/proc/{}/cgroup", container.pid))
+            .map_err(Error::FileSystemError)?;
+        let path = utils::oom_events_path(&cgroup).ok_or_else(not_found)?;
+        let events = std::fs::read_to_string(path).map_err(Error::FileSystemError)?;
+        let count = utils::parse_oom_kill(&events).ok_or_else(not_found)?;
+        Ok(events::OomKills {
+            count,
+            increased: previous.map_or(false, |previous| count > previous),
         })
     }
 
+    /// Return the pid of the init process of container `id`, `None` if the container was
+    /// created but not started, or stopped.
+    ///
+    /// Unlike [Runc::state], this doesn't run runc nor parse the whole state of the container,
+    /// which suits hot paths such as signaling it. See [utils::read_running_init_pid].
+    pub fn init_pid(&self, id: &str) -> Result<Option<i32>> {
+        self.check_id(id)?;
+        utils::read_running_init_pid(&self.state_root, id)
+    }
+
+    /// Return the memory usage and limit of container `id`, read from its memory cgroup.
+    ///
+    /// Unlike [Runc::stats], this doesn't run runc: the pid of the init process is read from the
+    /// state runc keeps in its root, which suits high-frequency monitoring. The usage includes
+    /// the page cache, like the usage reported by [Runc::stats].
+    ///
+    /// Fails with [Error::ContainerNotRunning] if the container was created but not started, or
+    /// stopped: its init pid may belong to another process by now.
+    pub fn memory(&self, id: &str) -> Result<events::MemStat> {
+        self.check_id(id)?;
+        let pid = utils::read_running_init_pid(&self.state_root, id)?.ok_or_else(|| {
+            Error::ContainerNotRunning {
+                stderr: String::new(),
+            }
+        })?;
+        utils::read_memory_stat(pid)
+    }
+
+    /// Return the absolute path of the cgroup runc placed container `id` in, under
+    /// `/sys/fs/cgroup`.
+    ///
+    /// The path is read from the state runc keeps in its root, or derived from the container's
+    /// cgroup config and whether it uses the systemd driver. On cgroup v1 hosts, that's the path
+    /// in the `pids` hierarchy; the container has the same path in the other hierarchies.
+    pub fn cgroup_path(&self, id: &str) -> Result<PathBuf> {
+        self.check_id(id)?;
+        utils::read_cgroup_path(&self.state_root, id)
+    }
+
     /// Pause a container
     pub fn pause(&self, id: &str) -> Result<()> {
+        self.check_id(id)?;
         let args = ["pause".to_string(), id.to_string()];
         let _ = self.launch(self.command(&args)?, true)?;
         Ok(())
@@ -242,35 +932,84 @@ impl Runc {
 
     /// Resume a container
     pub fn resume(&self, id: &str) -> Result<()> {
+        self.check_id(id)?;
         let args = ["resume".to_string(), id.to_string()];
         let _ = self.launch(self.command(&args)?, true)?;
         Ok(())
     }
 
-    pub fn checkpoint(&self) -> Result<()> {
-        Err(Error::Unimplemented("checkpoint".to_string()))
+    /// Checkpoint container `id` with CRIU, so that it can be restored by [Runc::restore].
+    ///
+    /// The container is stopped once checkpointed, unless [CheckpointOpts::leave_running] or
+    /// [CheckpointOpts::pre_dump] is set. Failures of CRIU are reported as [Error::CriuFailed],
+    /// the partial images being left in place unless [CheckpointOpts::cleanup_on_failure] is set.
+    pub fn checkpoint(&self, id: &str, opts: Option<&CheckpointOpts>) -> Result<()> {
+        self.check_id(id)?;
+        let mut args = vec!["checkpoint".to_string()];
+        if let Some(opts) = opts {
+            args.append(&mut opts.args()?);
+        }
+        args.push(id.to_string());
+        let res = self.launch(self.command(&args)?, true);
+        if let (Err(_), Some(path)) = (&res, opts.and_then(CheckpointOpts::failed_image_path)) {
+            let _ = std::fs::remove_dir_all(path);
+        }
+        res.map_err(utils::map_criu_error)?;
+        Ok(())
     }
 
-    pub fn restore(&self) -> Result<()> {
-        Err(Error::Unimplemented("restore".to_string()))
+    /// Restore container `id` from a checkpoint, with the spec of `bundle`.
+    ///
+    /// With [RestoreOpts::detach], this returns once the container is restored, and the pid of
+    /// the returned [Response] is the one of the restored process if `opts` has a pid file.
+    /// Otherwise it waits for the container to exit: a non-zero exit status is reported as
+    /// [Error::CommandFailed], like a failure of runc. Failures of CRIU are reported as
+    /// [Error::CriuFailed].
+    ///
+    /// The bundle is checked like for [Runc::create].
+    pub fn restore<P>(&self, id: &str, bundle: P, opts: Option<&RestoreOpts>) -> Result<Response>
+    where
+        P: AsRef<Path>,
+    {
+        self.check_id(id)?;
+        self.check_bundle(bundle.as_ref())?;
+        let bundle = utils::abs_string(bundle)?;
+        let mut args = vec![
+            "restore".to_string(),
+            "--bundle".to_string(),
+            bundle.clone(),
+        ];
+        if let Some(opts) = opts {
+            args.append(&mut opts.args()?);
+        }
+        args.push(id.to_string());
+        let mut cmd = self.command(&args)?;
+        let res = match opts {
+            Some(RestoreOpts { io: Some(io), .. }) => {
+                io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string()))?;
+                let res = self.launch(cmd, true);
+                io.close_after_start();
+                res
+            }
+            _ => self.launch(cmd, true),
+        }
+        .map_err(utils::map_criu_error)?;
+        with_init_pid(res, opts.and_then(|o| o.pid_file.as_ref())).map(|res| Response {
+            bundle: Some(bundle.into()),
+            ..res
+        })
     }
 
     /// List all the processes inside the container, returning their pids
     pub fn ps(&self, id: &str) -> Result<Vec<usize>> {
+        self.check_id(id)?;
         let args = [
             "ps".to_string(),
             "--format=json".to_string(),
             id.to_string(),
         ];
         let res = self.launch(self.command(&args)?, false)?;
-        let output = res.output.trim();
-
-        // Ugly hack to work around golang
-        Ok(if output == "null" {
-            Vec::new()
-        } else {
-            serde_json::from_str(output).map_err(Error::JsonDeserializationFailed)?
-        })
+        utils::parse_json_or_null(&res.output)
     }
 
     /// Run the create, start, delete lifecycle of the container and return its exit status
@@ -278,39 +1017,107 @@ impl Runc {
     where
         P: AsRef<Path>,
     {
-        let mut args = vec![
-            "run".to_string(),
-            "--bundle".to_string(),
-            utils::abs_string(bundle)?,
-        ];
+        self.check_id(id)?;
+        self.check_bundle(bundle.as_ref())?;
+        self.check_oci_version(bundle.as_ref())?;
+        let bundle = utils::abs_string(bundle)?;
+        let mut args = vec!["run".to_string(), "--bundle".to_string(), bundle.clone()];
         if let Some(opts) = opts {
-            args.append(&mut opts.args()?);
+            args.append(&mut opts.run_args()?);
         }
         args.push(id.to_string());
-        let mut cmd = self.command(&args)?;
+        let mut cmd = self.create_command(&args, opts)?;
         if let Some(CreateOpts { io: Some(io), .. }) = opts {
             io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string()))?;
         };
-        self.launch(cmd, true)
-    }
-
-    /// Start an already created container
-    pub fn start(&self, id: &str) -> Result<Response> {
-        let args = ["start".to_string(), id.to_string()];
-        self.launch(self.command(&args)?, true)
+        let res = self.launch(cmd, true)?;
+        with_init_pid(res, opts.and_then(|o| o.pid_file.as_ref())).map(|res| Response {
+            bundle: Some(bundle.into()),
+            ..res
+        })
     }
 
-    /// Return the state of a container
-    pub fn state(&self, id: &str) -> Result<Container> {
-        let args = ["state".to_string(), id.to_string()];
-        let res = self.launch(self.command(&args)?, true)?;
-        serde_json::from_str(&res.output).map_err(Error::JsonDeserializationFailed)
+    /// Create container `id` and start it, with all-or-nothing semantics: if it fails to start,
+    /// the created container is force deleted before the error of `start` is returned.
+    ///
+    /// The rollback is best-effort, if the delete fails too [Error::RollbackFailed] reports
+    /// both errors and the container may be left behind. On success, the [Response] of the
+    /// create is returned.
+    pub fn create_and_start<P>(
+        &self,
+        id: &str,
+        bundle: P,
+        opts: Option<&CreateOpts>,
+    ) -> Result<Response>
+    where
+        P: AsRef<Path>,
+    {
+        let res = self.create(id, bundle, opts)?;
+        if let Err(error) = self.start(id) {
+            let rollback = self.delete(id, Some(&DeleteOpts::new().force(true)));
+            return Err(match rollback {
+                Ok(()) => error,
+                Err(rollback) => Error::RollbackFailed {
+                    error: Box::new(error),
+                    rollback: Box::new(rollback),
+                },
+            });
+        }
+        Ok(res)
+    }
+
+    /// Start an already created container
+    pub fn start(&self, id: &str) -> Result<Response> {
+        self.check_id(id)?;
+        let args = ["start".to_string(), id.to_string()];
+        self.launch(self.command(&args)?, true)
+    }
+
+    /// Return the state of a container
+    pub fn state(&self, id: &str) -> Result<Container> {
+        self.check_id(id)?;
+        let args = ["state".to_string(), id.to_string()];
+        let res = self.launch(self.command(&args)?, true)?;
+        serde_json::from_str(&res.output).map_err(Error::JsonDeserializationFailed)
+    }
+
+    /// Return the OCI spec the container was created with, read from its bundle.
+    ///
+    /// Fails with [Error::SpecFileNotFound] if the bundle has been removed since.
+    pub fn container_spec(&self, id: &str) -> Result<Spec> {
+        let container = self.state(id)?;
+        if !spec::config_path(&container.bundle).exists() {
+            return Err(Error::SpecFileNotFound);
+        }
+        spec::load(&container.bundle)
+    }
+
+    /// Write the bundle of container `id` as a tar archive to `writer`, e.g. to attach it to a
+    /// bug report. See [spec::export_bundle] for the layout of the archive.
+    ///
+    /// Fails with [Error::SpecFileNotFound] if the bundle has been removed since.
+    pub fn export_bundle<W: std::io::Write>(
+        &self,
+        id: &str,
+        writer: W,
+        include_rootfs: bool,
+    ) -> Result<W> {
+        let container = self.state(id)?;
+        if !spec::config_path(&container.bundle).exists() {
+            return Err(Error::SpecFileNotFound);
+        }
+        spec::export_bundle(&container.bundle, writer, include_rootfs)
     }
 
     /// Return the latest statistics for a container
+    ///
+    /// This is a single sample, taken right away: with `--stats`, `runc events` prints one stats
+    /// event and exits, instead of printing one every `--interval` until the container exits.
+    /// Only its stdout is parsed, so that warnings runc logs to stderr don't break the parsing.
     pub fn stats(&self, id: &str) -> Result<events::Stats> {
+        self.check_id(id)?;
         let args = vec!["events".to_string(), "--stats".to_string(), id.to_string()];
-        let res = self.launch(self.command(&args)?, true)?;
+        let res = self.launch(self.command(&args)?, false)?;
         let event: events::Event =
             serde_json::from_str(&res.output).map_err(Error::JsonDeserializationFailed)?;
         if let Some(stats) = event.stats {
@@ -322,38 +1129,112 @@ impl Runc {
 
     /// Update a container with the provided resource spec
     pub fn update(&self, id: &str, resources: &LinuxResources) -> Result<()> {
-        let (_temp_file, filename) = write_value_to_temp_file(resources)?;
+        self.check_id(id)?;
+        let resources_file = write_value_to_temp_file_with_context(resources, "update resources")?;
         let args = [
             "update".to_string(),
             "--resources".to_string(),
-            filename,
+            resources_file.path_string()?,
             id.to_string(),
         ];
         self.launch(self.command(&args)?, true)?;
         Ok(())
     }
-}
 
-// a macro tool to cleanup the file with name $filename,
-// there is no async drop in async rust, so we have to call remove_file everytime
-// after a temp file created, before return of a function.
-// with this macro we don't have to write the match case codes everytime.
-#[cfg(feature = "async")]
-macro_rules! tc {
-    ($b:expr, $filename: expr) => {
-        match $b {
-            Ok(r) => r,
-            Err(e) => {
-                let _ = tokio::fs::remove_file($filename).await;
-                return Err(e);
+    /// Run the runc command `args` (e.g. `["ps", "--format=json", id]`, the global options being
+    /// prepended) with its stdout and stderr written to the given files, and return its exit
+    /// status.
+    ///
+    /// Unlike the other commands, the output isn't buffered in memory, which suits large outputs
+    /// such as the logs of a verbose checkpoint. A non-zero exit status isn't an error, runc's
+    /// error message being in `stderr`.
+    pub fn launch_to_files(
+        &self,
+        args: &[String],
+        stdout: std::fs::File,
+        stderr: std::fs::File,
+    ) -> Result<ExitStatus> {
+        let mut cmd = self.command(args)?;
+        cmd.stdout(stdout).stderr(stderr);
+        let subcommand = self.subcommand(&cmd);
+        #[cfg(feature = "tracing")]
+        let _span = self.command_span(&cmd).entered();
+        let started = Instant::now();
+        let res = match self.timeout {
+            Some(timeout) => self.spawner.execute_with_timeout(cmd, timeout),
+            None => self.spawner.execute(cmd),
+        }
+        .map(|(status, ..)| status);
+        let succeeded = matches!(&res, Ok(status) if status.success());
+        self.command_complete(&subcommand, started, succeeded);
+        res
+    }
+
+    /// Return the version of the runc binary
+    pub fn version(&self) -> Result<Version> {
+        let args = ["--version".to_string()];
+        let res = self.launch(self.command(&args)?, true)?;
+        utils::parse_version(&res.output)
+    }
+
+    /// Return the features supported by runc, requires runc 1.1 or later.
+    pub fn features(&self) -> Result<Features> {
+        let args = ["features".to_string()];
+        let res = self.launch(self.command(&args)?, false)?;
+        serde_json::from_str(&res.output).map_err(Error::JsonDeserializationFailed)
+    }
+
+    /// Return what the CRIU binary used for checkpoint and restore supports, probed with
+    /// `criu --version`, `criu check` and `criu check --feature` (see [GlobalOpts::criu]).
+    ///
+    /// A missing CRIU binary is reported as [Error::CriuNotFound]. `criu check` needs
+    /// `CAP_SYS_ADMIN` or `CAP_CHECKPOINT_RESTORE`, without which no feature is reported.
+    pub fn criu_features(&self) -> Result<features::CriuFeatures> {
+        let (_, version) = self.run_criu(&["--version"])?;
+        let (passed, check) = self.run_criu(&["check"])?;
+        let mut supported = Vec::new();
+        for feature in features::CriuFeatures::CHECKED_FEATURES {
+            if self.run_criu(&["check", "--feature", feature])?.0 {
+                supported.push(feature);
             }
         }
-    };
+        Ok(features::CriuFeatures::probe(
+            &version,
+            passed.then_some(check.as_str()),
+            |feature| supported.contains(&feature),
+        ))
+    }
+
+    /// Run `criu` with `args`, and return whether it succeeded and its combined output.
+    fn run_criu(&self, args: &[&str]) -> Result<(bool, String)> {
+        let (status, _, stdout, stderr) = self.spawner.execute(self.criu_command(args)?)?;
+        Ok((status.success(), stdout + stderr.as_str()))
+    }
+
+    fn check_oci_version(&self, bundle: &Path) -> Result<()> {
+        if self.check_oci_version {
+            spec::check_oci_version(&spec::load(bundle)?, &self.features()?)?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(not(feature = "async"))]
 pub trait Spawner: Debug {
     fn execute(&self, cmd: Command) -> Result<(ExitStatus, u32, String, String)>;
+
+    /// Like [Spawner::execute], but kill the command and fail with [Error::Timeout] if it's
+    /// still running after `timeout`, see [GlobalOpts::blocking_timeout].
+    ///
+    /// The default implementation ignores the timeout.
+    fn execute_with_timeout(
+        &self,
+        cmd: Command,
+        timeout: Duration,
+    ) -> Result<(ExitStatus, u32, String, String)> {
+        let _ = timeout;
+        self.execute(cmd)
+    }
 }
 
 #[cfg(feature = "async")]
@@ -368,7 +1249,72 @@ pub trait Spawner: Debug {
 /// and some other utilities.
 #[cfg(feature = "async")]
 impl Runc {
-    async fn launch(&self, mut cmd: Command, combined_output: bool) -> Result<Response> {
+    async fn launch(&self, cmd: Command, combined_output: bool) -> Result<Response> {
+        self.launch_with_timeout(cmd, combined_output, None).await
+    }
+
+    /// Serialize mutating operations on container `id`, if enabled with
+    /// [GlobalOpts::lock_per_container].
+    async fn lock_container(&self, id: &str) -> Option<OwnedMutexGuard<()>> {
+        match &self.container_locks {
+            Some(locks) => Some(locks.lock(id).await),
+            None => None,
+        }
+    }
+
+    async fn lock_container_if(&self, id: &str, lock: bool) -> Option<OwnedMutexGuard<()>> {
+        if lock {
+            self.lock_container(id).await
+        } else {
+            None
+        }
+    }
+
+    /// Launch the command, overriding the global timeout with `timeout` if it is set.
+    ///
+    /// A zero timeout means to wait for the command forever.
+    async fn launch_with_timeout(
+        &self,
+        cmd: Command,
+        combined_output: bool,
+        timeout: Option<Duration>,
+    ) -> Result<Response> {
+        let subcommand = self.subcommand(&cmd);
+        let started = Instant::now();
+        let res = self.execute(cmd, combined_output, timeout).await;
+        self.command_complete(&subcommand, started, res.is_ok());
+        res
+    }
+
+    async fn execute(
+        &self,
+        cmd: Command,
+        combined_output: bool,
+        timeout: Option<Duration>,
+    ) -> Result<Response> {
+        let (status, pid, stdout, stderr) = self.spawn_and_wait(cmd, timeout).await?;
+        if status.success() {
+            let output = if combined_output && self.combine_output {
+                stdout + stderr.as_str()
+            } else {
+                stdout
+            };
+            Ok(Response::new(pid, status, output))
+        } else {
+            Err(Error::CommandFailed {
+                status,
+                stdout,
+                stderr,
+            })
+        }
+    }
+
+    /// Run the command to completion and return its exit status, pid, stdout and stderr.
+    async fn spawn_and_wait(
+        &self,
+        mut cmd: Command,
+        timeout: Option<Duration>,
+    ) -> Result<(ExitStatus, u32, String, String)> {
         debug!("Execute command {:?}", cmd);
         unsafe {
             cmd.pre_exec(move || {
@@ -384,25 +1330,24 @@ impl Runc {
             });
         }
 
-        let (status, pid, stdout, stderr) = self.spawner.execute(cmd).await?;
-        if status.success() {
-            let output = if combined_output {
-                stdout + stderr.as_str()
+        #[cfg(feature = "tracing")]
+        let span = self.command_span(&cmd);
+        let timeout = timeout.unwrap_or(self.timeout);
+        let wait = async move {
+            if timeout.is_zero() {
+                self.spawner.execute(cmd).await
             } else {
-                stdout
-            };
-            Ok(Response {
-                pid,
-                status,
-                output,
-            })
-        } else {
-            Err(Error::CommandFailed {
-                status,
-                stdout,
-                stderr,
-            })
-        }
+                // The child is dropped together with the execute future when the timeout fires,
+                // make sure it doesn't outlive us.
+                cmd.kill_on_drop(true);
+                tokio::time::timeout(timeout, self.spawner.execute(cmd))
+                    .await
+                    .map_err(Error::CommandTimeout)?
+            }
+        };
+        #[cfg(feature = "tracing")]
+        let wait = tracing::Instrument::instrument(wait, span);
+        wait.await
     }
 
     /// Create a new container
@@ -415,97 +1360,473 @@ impl Runc {
     where
         P: AsRef<Path>,
     {
-        let mut args = vec![
-            "create".to_string(),
-            "--bundle".to_string(),
-            utils::abs_string(bundle)?,
-        ];
+        self.check_id(id)?;
+        let _guard = self.lock_container(id).await;
+        self.check_bundle(bundle.as_ref())?;
+        self.check_oci_version(bundle.as_ref()).await?;
+        let bundle = utils::abs_string(bundle)?;
+        let mut args = vec!["create".to_string(), "--bundle".to_string(), bundle.clone()];
         if let Some(opts) = opts {
-            args.append(&mut opts.args()?);
+            args.append(&mut opts.create_args()?);
         }
         args.push(id.to_string());
-        let mut cmd = self.command(&args)?;
-        match opts {
+        let mut cmd = self.create_command(&args, opts)?;
+        let timeout = opts.and_then(|o| o.timeout);
+        let res = match opts {
             Some(CreateOpts { io: Some(io), .. }) => {
                 io.set(&mut cmd).map_err(Error::UnavailableIO)?;
-                let res = self.launch(cmd, true).await?;
+                let res = self.launch_with_timeout(cmd, true, timeout).await?;
                 io.close_after_start();
-                Ok(res)
+                res
+            }
+            _ => self.launch_with_timeout(cmd, true, timeout).await?,
+        };
+        with_init_pid(res, opts.and_then(|o| o.pid_file.as_ref()))
+            .await
+            .map(|res| Response {
+                bundle: Some(bundle.into()),
+                ..res
+            })
+    }
+
+    /// Create a new container like [Runc::create], with debug logging enabled and the log entries
+    /// of runc sent to `logs` while the create is in flight, e.g. to report the progress of slow
+    /// creates.
+    ///
+    /// runc logs to a pipe in JSON format for this create, overriding the global log options.
+    /// `logs` is a broadcast channel so that sending never blocks: when a receiver doesn't keep
+    /// up, it loses the oldest entries (and gets [RecvError::Lagged] instead). Entries written by
+    /// runc after the create returned are not sent.
+    ///
+    /// [RecvError::Lagged]: tokio::sync::broadcast::error::RecvError::Lagged
+    pub async fn create_with_logs<P>(
+        &self,
+        id: &str,
+        bundle: P,
+        opts: Option<&CreateOpts>,
+        logs: tokio::sync::broadcast::Sender<logs::LogEntry>,
+    ) -> Result<Response>
+    where
+        P: AsRef<Path>,
+    {
+        self.with_logs(
+            logs,
+            |runc| async move { runc.create(id, bundle, opts).await },
+        )
+        .await
+    }
+
+    /// Checkpoint container `id` like [Runc::checkpoint], with debug logging enabled and the log
+    /// entries of runc, which include the progress of CRIU, sent to `logs` while the checkpoint
+    /// is in flight, e.g. to show the progress of a migration or detect a stalled checkpoint.
+    ///
+    /// `logs` is dropped once the checkpoint completes or fails, which ends the stream of the
+    /// receivers once they have received the remaining entries (unless other senders are left).
+    /// Like for [Runc::create_with_logs], a receiver which doesn't keep up loses the oldest
+    /// entries rather than blocking the checkpoint.
+    pub async fn checkpoint_with_logs(
+        &self,
+        id: &str,
+        opts: Option<&CheckpointOpts>,
+        logs: tokio::sync::broadcast::Sender<logs::LogEntry>,
+    ) -> Result<()> {
+        self.with_logs(logs, |runc| async move { runc.checkpoint(id, opts).await })
+            .await
+    }
+
+    /// Run `op` with a clone of this instance logging to a pipe in JSON format, with debug
+    /// logging enabled, and send the entries read from the pipe to `logs` while `op` runs.
+    async fn with_logs<F, Fut, T>(
+        &self,
+        logs: tokio::sync::broadcast::Sender<logs::LogEntry>,
+        op: F,
+    ) -> Result<T>
+    where
+        F: FnOnce(Runc) -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        use std::os::unix::io::AsRawFd;
+
+        use futures::FutureExt;
+        use tokio::io::AsyncBufReadExt;
+
+        let (reader, writer) = os_pipe::pipe().map_err(Error::UnavailableIO)?;
+        let reader = tokio::net::unix::pipe::Receiver::from_owned_fd(reader.into())
+            .map_err(Error::UnavailableIO)?;
+        let mut runc = self.clone();
+        // The last occurrence of a flag wins, and runc inherits the write end like a log fd.
+        runc.args.extend([
+            "--debug".to_string(),
+            "--log".to_string(),
+            format!("/proc/self/fd/{}", writer.as_raw_fd()),
+            "--log-format".to_string(),
+            LogFormat::Json.to_string(),
+        ]);
+        runc.log_fd = Some(writer.as_raw_fd());
+
+        let send = |line: &str| match logs::LogEntry::parse(line) {
+            // Nobody listening isn't an error.
+            Some(mut entry) => {
+                if let Some(id) = &self.correlation_id {
+                    entry.set_correlation_id(id);
+                }
+                drop(logs.send(entry))
+            }
+            None => debug!("ignoring runc log line {:?}", line),
+        };
+        let mut lines = tokio::io::BufReader::new(reader).lines();
+        let op = op(runc);
+        tokio::pin!(op);
+        let res = loop {
+            tokio::select! {
+                res = &mut op => break res,
+                line = lines.next_line() => match line {
+                    Ok(Some(line)) => send(&line),
+                    _ => break op.await,
+                },
+            }
+        };
+        drop(writer);
+
+        // runc init inherits the write end and keeps it open until the container starts, so
+        // don't wait for the end of the pipe: only forward what runc has already written.
+        while let Some(Ok(Some(line))) = lines.next_line().now_or_never() {
+            send(&line);
+        }
+        res
+    }
+
+    /// Create a new container, tolerating a container `id` which already exists with the same
+    /// bundle.
+    ///
+    /// This gives at-least-once semantics to create, e.g. when it's retried after a crash. If
+    /// the existing container was created from another bundle, the error of runc is returned.
+    /// On success, the pid of the returned [Response] is the one of the existing container.
+    pub async fn create_idempotent<P>(
+        &self,
+        id: &str,
+        bundle: P,
+        opts: Option<&CreateOpts>,
+    ) -> Result<Response>
+    where
+        P: AsRef<Path>,
+    {
+        let bundle = utils::abs_string(bundle)?;
+        match self.create(id, &bundle, opts).await {
+            Err(err) if utils::is_already_exists(&err) => {
+                let container = self.state(id).await?;
+                if container.bundle != bundle {
+                    return Err(err);
+                }
+                Ok(Response {
+                    pid: container.pid as u32,
+                    status: ExitStatus::from_raw(0),
+                    output: String::new(),
+                    bundle: Some(bundle.into()),
+                })
             }
-            _ => self.launch(cmd, true).await,
+            res => res,
         }
     }
 
     /// Delete a container
     pub async fn delete(&self, id: &str, opts: Option<&DeleteOpts>) -> Result<()> {
+        self.check_id(id)?;
+        let _guard = self.lock_container(id).await;
         let mut args = vec!["delete".to_string()];
         if let Some(opts) = opts {
             args.append(&mut opts.args());
         }
         args.push(id.to_string());
-        let _ = self.launch(self.command(&args)?, true).await?;
+        let timeout = opts.and_then(|o| o.timeout);
+        let _ = self
+            .launch_with_timeout(self.command(&args)?, true, timeout)
+            .await?;
         Ok(())
     }
 
+    /// Delete a container and remove its bundle directory.
+    ///
+    /// The bundle must be inside [GlobalOpts::bundle_root], otherwise nothing is deleted and
+    /// [Error::BundleOutsideRoot] is returned. If the container is deleted but its bundle can't
+    /// be removed, [Error::BundleCleanupFailed] is returned.
+    pub async fn delete_and_cleanup(&self, id: &str, opts: Option<&DeleteOpts>) -> Result<()> {
+        let container = self.state(id).await?;
+        self.check_bundle_removal(&container.bundle)?;
+        self.delete(id, opts).await?;
+        match std::fs::remove_dir_all(&container.bundle) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(Error::BundleCleanupFailed(e))
+            }
+            _ => Ok(()),
+        }
+    }
+
     /// Return an event stream of container notifications
+    ///
+    /// Unlike [Runc::stats], this would follow `runc events` until the container exits.
     pub async fn events(&self, _id: &str, _interval: &std::time::Duration) -> Result<()> {
         Err(Error::Unimplemented("events".to_string()))
     }
 
     /// Execute an additional process inside the container
     pub async fn exec(&self, id: &str, spec: &Process, opts: Option<&ExecOpts>) -> Result<()> {
-        let f = write_value_to_temp_file(spec).await?;
-        let mut args = vec!["exec".to_string(), "--process".to_string(), f.clone()];
+        self.exec_with_response(id, spec, opts).await.map(|_| ())
+    }
+
+    /// Execute an additional process inside the container like [Runc::exec], and return the
+    /// [Response] of runc.
+    ///
+    /// If `opts` has a pid file, the pid of the returned [Response] is the one of the new process
+    /// instead of runc's. The pid file of a detached process is removed once read.
+    pub async fn exec_with_response(
+        &self,
+        id: &str,
+        spec: &Process,
+        opts: Option<&ExecOpts>,
+    ) -> Result<Response> {
+        self.check_id(id)?;
+        let _guard = self
+            .lock_container_if(id, opts.map_or(false, |o| o.detach))
+            .await;
+        let spec = match opts {
+            Some(opts) => opts.process(spec)?,
+            None => Cow::Borrowed(spec),
+        };
+        let process_pipe = if self.exec_via_stdin(opts) {
+            utils::write_value_to_pipe(&spec, "exec process")?
+        } else {
+            None
+        };
+        let process_file = match process_pipe {
+            Some(_) => None,
+            None => Some(write_value_to_temp_file_with_context(&spec, "exec process").await?),
+        };
+        let mut args = vec![
+            "exec".to_string(),
+            "--process".to_string(),
+            match &process_file {
+                Some(file) => file.path_string()?,
+                None => "/dev/stdin".to_string(),
+            },
+        ];
         if let Some(opts) = opts {
-            args.append(&mut tc!(opts.args(), &f));
+            args.append(&mut opts.args()?);
         }
         args.push(id.to_string());
-        let mut cmd = self.command(&args)?;
-        match opts {
+        let mut cmd = self.command_with_overrides(&args, None, opts.and_then(|o| o.rootless))?;
+        if let Some(pipe) = process_pipe {
+            cmd.stdin(pipe);
+        }
+        let timeout = opts.and_then(|o| o.timeout);
+        let res = match opts {
             Some(ExecOpts { io: Some(io), .. }) => {
-                tc!(
-                    io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string())),
-                    &f
-                );
-                tc!(self.launch(cmd, true).await, &f);
+                io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string()))?;
+                let res = self
+                    .launch_with_timeout(cmd, true, timeout)
+                    .await
+                    .map_err(utils::map_exec_error)?;
                 io.close_after_start();
+                res
             }
-            _ => {
-                tc!(self.launch(cmd, true).await, &f);
-            }
+            _ => self
+                .launch_with_timeout(cmd, true, timeout)
+                .await
+                .map_err(utils::map_exec_error)?,
+        };
+        drop(process_file);
+        let pid_file = opts.and_then(|o| o.pid_file.as_ref());
+        let res = with_init_pid(res, pid_file).await;
+        if let (Some(pid_file), true) = (pid_file, opts.map_or(false, |o| o.detach)) {
+            let _ = tokio::fs::remove_file(pid_file).await;
         }
-        let _ = tokio::fs::remove_file(&f).await;
-        Ok(())
+        res
+    }
+
+    /// Execute `argv` in the container, without having to build a full [Process].
+    ///
+    /// The command inherits the working directory and the user of the container's process, read
+    /// from its bundle: it fails if the spec can't be loaded or has no process, rather than
+    /// running as root. See [spec::exec_process] for how `env` is applied.
+    pub async fn exec_command(
+        &self,
+        id: &str,
+        argv: &[String],
+        env: &[(String, String)],
+        opts: Option<&ExecOpts>,
+    ) -> Result<()> {
+        let container = self.state(id).await?;
+        let base = spec::load(&container.bundle)?
+            .process()
+            .clone()
+            .ok_or_else(|| Error::BundleWithoutProcess(container.bundle.clone()))?;
+        let process = spec::exec_process(Some(&base), argv, env);
+        self.exec(id, &process, opts).await
     }
 
     /// Send the specified signal to processes inside the container
+    ///
+    /// Succeeds if the init process has already exited, unless [KillOpts::strict] is set.
     pub async fn kill(&self, id: &str, sig: u32, opts: Option<&KillOpts>) -> Result<()> {
+        self.check_id(id)?;
+        let _guard = self.lock_container(id).await;
         let mut args = vec!["kill".to_string()];
         if let Some(opts) = opts {
             args.append(&mut opts.args());
         }
         args.push(id.to_string());
         args.push(sig.to_string());
-        let _ = self.launch(self.command(&args)?, true).await?;
-        Ok(())
+        let timeout = opts.and_then(|o| o.timeout);
+        match self
+            .launch_with_timeout(self.command(&args)?, true, timeout)
+            .await
+        {
+            Err(e) if !opts.map_or(false, |o| o.strict) && utils::is_process_done(&e) => Ok(()),
+            res => res.map(|_| ()),
+        }
     }
 
     /// List all containers associated with this runc instance
     pub async fn list(&self) -> Result<Vec<Container>> {
         let args = ["list".to_string(), "--format=json".to_string()];
         let res = self.launch(self.command(&args)?, true).await?;
-        let output = res.output.trim();
+        utils::parse_json_or_null(&res.output)
+    }
 
-        // Ugly hack to work around golang
-        Ok(if output == "null" {
-            Vec::new()
-        } else {
-            serde_json::from_str(output).map_err(Error::JsonDeserializationFailed)?
+    /// List the containers which have the annotation `key` set to `value`, e.g. the containers
+    /// of a pod.
+    pub async fn list_by_annotation(&self, key: &str, value: &str) -> Result<Vec<Container>> {
+        let mut containers = self.list().await?;
+        containers.retain(|c| c.has_annotation(key, value));
+        Ok(containers)
+    }
+
+    /// Compare the `expected` ids and statuses of containers to the containers runc lists, e.g.
+    /// to recover from a crash of the caller. See [DriftReport].
+    pub async fn drift<I, S>(&self, expected: I) -> Result<DriftReport>
+    where
+        I: IntoIterator<Item = (S, ContainerStatus)>,
+        S: Into<String>,
+    {
+        Ok(DriftReport::new(expected, &self.list().await?))
+    }
+
+    /// Return the directories of the state root which are the state of no container runc
+    /// lists, e.g. left behind by a crash.
+    ///
+    /// Only directories holding runc's `state.json` are considered, see [utils::state_dirs].
+    /// They are read before listing the containers, so that containers created meanwhile aren't
+    /// taken for orphans.
+    pub async fn find_orphans(&self) -> Result<Vec<PathBuf>> {
+        let dirs = utils::state_dirs(&self.state_root)?;
+        Ok(Self::orphans(dirs, &self.list().await?))
+    }
+
+    /// Remove the directories found by [Runc::find_orphans], and return them.
+    ///
+    /// Each removal holds the lock of the container id, if enabled with
+    /// [GlobalOpts::lock_per_container].
+    pub async fn clean_orphans(&self) -> Result<Vec<PathBuf>> {
+        let orphans = self.find_orphans().await?;
+        for dir in &orphans {
+            let id = dir.file_name().unwrap_or_default().to_string_lossy();
+            let _guard = self.lock_container(&id).await;
+            Self::remove_orphan(dir)?;
+        }
+        Ok(orphans)
+    }
+
+    /// Return the pids of the processes in the container's cgroup.
+    ///
+    /// The pids are read from the `cgroup.procs` file of the cgroup of the container's init
+    /// process, which doesn't depend on the `ps` binary. Falls back to [Runc::ps] when the cgroup
+    /// can't be found.
+    pub async fn pids(&self, id: &str) -> Result<Vec<usize>> {
+        let container = self.state(id).await?;
+        let path = tokio::fs::read_to_string(format!("/proc/{}/cgroup", container.pid))
+            .await
+            .ok()
+            .and_then(|cgroup| utils::cgroup_procs_path(&cgroup));
+        let procs = match path {
+            Some(path) => tokio::fs::read_to_string(path).await.ok(),
+            None => None,
+        };
+        match procs {
+            Some(procs) => Ok(utils::parse_pids(&procs)),
+            None => self.ps(id).await,
+        }
+    }
+
+    /// Return how many processes of container `id` the OOM killer killed, and whether the count
+    /// increased since `previous`, a count returned by an earlier call.
+    ///
+    /// The count is read from the memory cgroup of the container's init process, which is cheaper
+    /// than watching OOM events and doesn't miss the kills happening while nobody listens.
+    pub async fn oom_kills(&self, id: &str, previous: Option<u64>) -> Result<events::OomKills> {
+        let container = self.state(id).await?;
+        let not_found = || {
+            Error::FileSystemError(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no OOM kill counter for container {}", id),
+            ))
+        };
+        let cgroup = tokio::fs::read_to_string(format!("/proc/{}/cgroup", container.pid))
+            .await
+            .map_err(Error::FileSystemError)?;
+        let path = utils::oom_events_path(&cgroup).ok_or_else(not_found)?;
+        let events = tokio::fs::read_to_string(path)
+            .await
+            .map_err(Error::FileSystemError)?;
+        let count = utils::parse_oom_kill(&events).ok_or_else(not_found)?;
+        Ok(events::OomKills {
+            count,
+            increased: previous.map_or(false, |previous| count > previous),
         })
     }
 
+    /// Return the pid of the init process of container `id`, `None` if the container was
+    /// created but not started, or stopped.
+    ///
+    /// Unlike [Runc::state], this doesn't run runc nor parse the whole state of the container,
+    /// which suits hot paths such as signaling it. See [utils::read_running_init_pid].
+    pub async fn init_pid(&self, id: &str) -> Result<Option<i32>> {
+        self.check_id(id)?;
+        utils::read_running_init_pid(&self.state_root, id)
+    }
+
+    /// Return the memory usage and limit of container `id`, read from its memory cgroup.
+    ///
+    /// Unlike [Runc::stats], this doesn't run runc: the pid of the init process is read from the
+    /// state runc keeps in its root, which suits high-frequency monitoring. The usage includes
+    /// the page cache, like the usage reported by [Runc::stats].
+    ///
+    /// Fails with [Error::ContainerNotRunning] if the container was created but not started, or
+    /// stopped: its init pid may belong to another process by now.
+    pub async fn memory(&self, id: &str) -> Result<events::MemStat> {
+        self.check_id(id)?;
+        let pid = utils::read_running_init_pid(&self.state_root, id)?.ok_or_else(|| {
+            Error::ContainerNotRunning {
+                stderr: String::new(),
+            }
+        })?;
+        utils::read_memory_stat(pid)
+    }
+
+    /// Return the absolute path of the cgroup runc placed container `id` in, under
+    /// `/sys/fs/cgroup`.
+    ///
+    /// The path is read from the state runc keeps in its root, or derived from the container's
+    /// cgroup config and whether it uses the systemd driver. On cgroup v1 hosts, that's the path
+    /// in the `pids` hierarchy; the container has the same path in the other hierarchies.
+    pub async fn cgroup_path(&self, id: &str) -> Result<PathBuf> {
+        self.check_id(id)?;
+        utils::read_cgroup_path(&self.state_root, id)
+    }
+
     /// Pause a container
     pub async fn pause(&self, id: &str) -> Result<()> {
+        self.check_id(id)?;
+        let _guard = self.lock_container(id).await;
         let args = ["pause".to_string(), id.to_string()];
         let _ = self.launch(self.command(&args)?, true).await?;
         Ok(())
@@ -513,35 +1834,100 @@ impl Runc {
 
     /// Resume a container
     pub async fn resume(&self, id: &str) -> Result<()> {
+        self.check_id(id)?;
+        let _guard = self.lock_container(id).await;
         let args = ["resume".to_string(), id.to_string()];
         let _ = self.launch(self.command(&args)?, true).await?;
         Ok(())
     }
 
-    pub async fn checkpoint(&self) -> Result<()> {
-        Err(Error::Unimplemented("checkpoint".to_string()))
+    /// Checkpoint container `id` with CRIU, so that it can be restored by [Runc::restore].
+    ///
+    /// The container is stopped once checkpointed, unless [CheckpointOpts::leave_running] or
+    /// [CheckpointOpts::pre_dump] is set. Failures of CRIU are reported as [Error::CriuFailed],
+    /// the partial images being left in place unless [CheckpointOpts::cleanup_on_failure] is set.
+    pub async fn checkpoint(&self, id: &str, opts: Option<&CheckpointOpts>) -> Result<()> {
+        self.check_id(id)?;
+        let _guard = self.lock_container(id).await;
+        let mut args = vec!["checkpoint".to_string()];
+        if let Some(opts) = opts {
+            args.append(&mut opts.args()?);
+        }
+        args.push(id.to_string());
+        let timeout = opts.and_then(|o| o.timeout);
+        let res = self
+            .launch_with_timeout(self.command(&args)?, true, timeout)
+            .await;
+        if let (Err(_), Some(path)) = (&res, opts.and_then(CheckpointOpts::failed_image_path)) {
+            let _ = tokio::fs::remove_dir_all(path).await;
+        }
+        res.map_err(utils::map_criu_error)?;
+        Ok(())
     }
 
-    pub async fn restore(&self) -> Result<()> {
-        Err(Error::Unimplemented("restore".to_string()))
+    /// Restore container `id` from a checkpoint, with the spec of `bundle`.
+    ///
+    /// With [RestoreOpts::detach], this returns once the container is restored, and the pid of
+    /// the returned [Response] is the one of the restored process if `opts` has a pid file.
+    /// Otherwise it waits for the container to exit: a non-zero exit status is reported as
+    /// [Error::CommandFailed], like a failure of runc. Failures of CRIU are reported as
+    /// [Error::CriuFailed].
+    ///
+    /// The bundle is checked like for [Runc::create].
+    pub async fn restore<P>(
+        &self,
+        id: &str,
+        bundle: P,
+        opts: Option<&RestoreOpts>,
+    ) -> Result<Response>
+    where
+        P: AsRef<Path>,
+    {
+        self.check_id(id)?;
+        self.check_bundle(bundle.as_ref())?;
+        let _guard = self
+            .lock_container_if(id, opts.map_or(false, |o| o.detach))
+            .await;
+        let bundle = utils::abs_string(bundle)?;
+        let mut args = vec![
+            "restore".to_string(),
+            "--bundle".to_string(),
+            bundle.clone(),
+        ];
+        if let Some(opts) = opts {
+            args.append(&mut opts.args()?);
+        }
+        args.push(id.to_string());
+        let mut cmd = self.command(&args)?;
+        let timeout = opts.and_then(|o| o.timeout);
+        let res = match opts {
+            Some(RestoreOpts { io: Some(io), .. }) => {
+                io.set(&mut cmd).map_err(Error::UnavailableIO)?;
+                let res = self.launch_with_timeout(cmd, true, timeout).await;
+                io.close_after_start();
+                res
+            }
+            _ => self.launch_with_timeout(cmd, true, timeout).await,
+        }
+        .map_err(utils::map_criu_error)?;
+        with_init_pid(res, opts.and_then(|o| o.pid_file.as_ref()))
+            .await
+            .map(|res| Response {
+                bundle: Some(bundle.into()),
+                ..res
+            })
     }
 
     /// List all the processes inside the container, returning their pids
     pub async fn ps(&self, id: &str) -> Result<Vec<usize>> {
+        self.check_id(id)?;
         let args = [
             "ps".to_string(),
             "--format=json".to_string(),
             id.to_string(),
         ];
         let res = self.launch(self.command(&args)?, true).await?;
-        let output = res.output.trim();
-
-        // Ugly hack to work around golang
-        Ok(if output == "null" {
-            Vec::new()
-        } else {
-            serde_json::from_str(output).map_err(Error::JsonDeserializationFailed)?
-        })
+        utils::parse_json_or_null(&res.output)
     }
 
     /// Run the create, start, delete lifecycle of the container and return its exit status
@@ -549,25 +1935,156 @@ impl Runc {
     where
         P: AsRef<Path>,
     {
-        let mut args = vec![
-            "run".to_string(),
-            "--bundle".to_string(),
-            utils::abs_string(bundle)?,
-        ];
-        if let Some(opts) = opts {
-            args.append(&mut opts.args()?);
-        }
-        args.push(id.to_string());
-        let mut cmd = self.command(&args)?;
+        self.run_with_response(id, bundle, opts).await.map(|_| ())
+    }
+
+    /// Run the container like [Runc::run], and return the [Response] of runc.
+    ///
+    /// If `opts` has a pid file, the pid of the returned [Response] is the one of the container's
+    /// init process instead of runc's.
+    pub async fn run_with_response<P>(
+        &self,
+        id: &str,
+        bundle: P,
+        opts: Option<&CreateOpts>,
+    ) -> Result<Response>
+    where
+        P: AsRef<Path>,
+    {
+        self.check_id(id)?;
+        let _guard = self
+            .lock_container_if(id, opts.map_or(false, |o| o.detach))
+            .await;
+        let bundle = utils::abs_path_buf(bundle)?;
+        let cmd = self.run_command(id, &bundle, opts).await?;
+        let timeout = opts.and_then(|o| o.timeout);
+        let res = self.launch_with_timeout(cmd, true, timeout).await?;
+        with_init_pid(res, opts.and_then(|o| o.pid_file.as_ref()))
+            .await
+            .map(|res| Response {
+                bundle: Some(bundle),
+                ..res
+            })
+    }
+
+    /// Run the container to completion like [Runc::run], and return its exit code and output.
+    ///
+    /// The stdout and stderr of the container are captured separately, any [CreateOpts::io] or
+    /// [CreateOpts::detach] in `opts` is ignored. A non-zero exit code isn't an error, but if
+    /// runc itself fails (e.g. on timeout) the container is force deleted.
+    pub async fn run_captured<P>(
+        &self,
+        id: &str,
+        bundle: P,
+        opts: Option<&CreateOpts>,
+    ) -> Result<RunOutput>
+    where
+        P: AsRef<Path>,
+    {
+        let mut opts = opts.cloned().unwrap_or_default();
+        opts.io = None;
+        opts.detach = false;
+        let cmd = self.run_command(id, bundle.as_ref(), Some(&opts)).await?;
+        let started = Instant::now();
+        let res = self.spawn_and_wait(cmd, opts.timeout).await;
+        let succeeded = matches!(&res, Ok((status, ..)) if status.success());
+        self.command_complete("run", started, succeeded);
+        match res {
+            Ok((status, _, stdout, stderr)) => Ok(RunOutput {
+                exit_code: status
+                    .code()
+                    .unwrap_or_else(|| 128 + status.signal().unwrap_or_default()),
+                stdout,
+                stderr,
+            }),
+            Err(e) => {
+                let _ = self.delete(id, Some(&DeleteOpts::new().force(true))).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Build the `runc run` command of container `id`, checking `bundle` first.
+    async fn run_command(
+        &self,
+        id: &str,
+        bundle: &Path,
+        opts: Option<&CreateOpts>,
+    ) -> Result<Command> {
+        self.check_id(id)?;
+        self.check_bundle(bundle)?;
+        self.check_oci_version(bundle).await?;
+        let mut args = vec![
+            "run".to_string(),
+            "--bundle".to_string(),
+            utils::abs_string(bundle)?,
+        ];
+        if let Some(opts) = opts {
+            args.append(&mut opts.run_args()?);
+        }
+        args.push(id.to_string());
+        let mut cmd = self.create_command(&args, opts)?;
         if let Some(CreateOpts { io: Some(io), .. }) = opts {
             io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string()))?;
         };
-        let _ = self.launch(cmd, true).await?;
-        Ok(())
+        Ok(cmd)
+    }
+
+    /// Run the container like [Runc::run] in a background task, and return a handle to kill it
+    /// while it runs or wait for it to exit.
+    ///
+    /// Must be called from a tokio runtime.
+    pub fn spawn_run<P>(&self, id: &str, bundle: P, opts: Option<CreateOpts>) -> RunHandle
+    where
+        P: AsRef<Path>,
+    {
+        let runc = self.clone();
+        let (task_id, bundle) = (id.to_string(), bundle.as_ref().to_path_buf());
+        let task = tokio::spawn(async move {
+            runc.run_with_response(&task_id, bundle, opts.as_ref())
+                .await
+        });
+        RunHandle {
+            id: id.to_string(),
+            runc: self.clone(),
+            task: Some(task),
+            kill_on_drop: None,
+        }
+    }
+
+    /// Create container `id` and start it, with all-or-nothing semantics: if it fails to start,
+    /// the created container is force deleted before the error of `start` is returned.
+    ///
+    /// The rollback is best-effort, if the delete fails too [Error::RollbackFailed] reports
+    /// both errors and the container may be left behind. On success, the [Response] of the
+    /// create is returned.
+    pub async fn create_and_start<P>(
+        &self,
+        id: &str,
+        bundle: P,
+        opts: Option<&CreateOpts>,
+    ) -> Result<Response>
+    where
+        P: AsRef<Path>,
+    {
+        let res = self.create(id, bundle, opts).await?;
+        if let Err(error) = self.start(id).await {
+            let rollback = self.delete(id, Some(&DeleteOpts::new().force(true))).await;
+            return Err(match rollback {
+                Ok(()) => error,
+                Err(rollback) => Error::RollbackFailed {
+                    error: Box::new(error),
+                    rollback: Box::new(rollback),
+                },
+            });
+        }
+        Ok(res)
     }
 
     /// Start an already created container
     pub async fn start(&self, id: &str) -> Result<()> {
+        self.check_id(id)?;
+        let _guard = self.lock_container(id).await;
         let args = vec!["start".to_string(), id.to_string()];
         let _ = self.launch(self.command(&args)?, true).await?;
         Ok(())
@@ -575,15 +2092,68 @@ impl Runc {
 
     /// Return the state of a container
     pub async fn state(&self, id: &str) -> Result<Container> {
+        self.check_id(id)?;
         let args = vec!["state".to_string(), id.to_string()];
         let res = self.launch(self.command(&args)?, true).await?;
         serde_json::from_str(&res.output).map_err(Error::JsonDeserializationFailed)
     }
 
+    /// Wait for the init process of the container to exit and return its exit status, e.g.
+    /// after starting it, see [monitor::wait_pid].
+    ///
+    /// The caller must be the parent or a subreaper of the init process, like a shim is. A
+    /// container which already stopped is handled as long as its init process hasn't been
+    /// reaped: its pid, which runc doesn't report anymore, is read from the container's state.
+    pub async fn wait(&self, id: &str) -> Result<ExitStatus> {
+        let pid = match self.state(id).await?.pid {
+            0 => utils::read_init_pid(&self.state_root, id)?,
+            pid => pid as i32,
+        };
+        monitor::wait_pid(pid).await.map_err(Error::WaitFailed)
+    }
+
+    /// Return the OCI spec the container was created with, read from its bundle.
+    ///
+    /// Fails with [Error::SpecFileNotFound] if the bundle has been removed since.
+    pub async fn container_spec(&self, id: &str) -> Result<Spec> {
+        let container = self.state(id).await?;
+        if !spec::config_path(&container.bundle).exists() {
+            return Err(Error::SpecFileNotFound);
+        }
+        spec::load(&container.bundle)
+    }
+
+    /// Write the bundle of container `id` as a tar archive to `writer`, e.g. to attach it to a
+    /// bug report. See [spec::export_bundle] for the layout of the archive.
+    ///
+    /// Fails with [Error::SpecFileNotFound] if the bundle has been removed since.
+    pub async fn export_bundle<W: std::io::Write + Send + 'static>(
+        &self,
+        id: &str,
+        writer: W,
+        include_rootfs: bool,
+    ) -> Result<W> {
+        let container = self.state(id).await?;
+        if !spec::config_path(&container.bundle).exists() {
+            return Err(Error::SpecFileNotFound);
+        }
+        // Archiving a whole rootfs takes a while, keep it off the runtime.
+        tokio::task::spawn_blocking(move || {
+            spec::export_bundle(&container.bundle, writer, include_rootfs)
+        })
+        .await
+        .map_err(|e| Error::FileSystemError(std::io::Error::new(std::io::ErrorKind::Other, e)))?
+    }
+
     /// Return the latest statistics for a container
+    ///
+    /// This is a single sample, taken right away: with `--stats`, `runc events` prints one stats
+    /// event and exits, instead of printing one every `--interval` until the container exits.
+    /// Only its stdout is parsed, so that warnings runc logs to stderr don't break the parsing.
     pub async fn stats(&self, id: &str) -> Result<events::Stats> {
+        self.check_id(id)?;
         let args = vec!["events".to_string(), "--stats".to_string(), id.to_string()];
-        let res = self.launch(self.command(&args)?, true).await?;
+        let res = self.launch(self.command(&args)?, false).await?;
         let event: events::Event =
             serde_json::from_str(&res.output).map_err(Error::JsonDeserializationFailed)?;
         if let Some(stats) = event.stats {
@@ -593,21 +2163,170 @@ impl Runc {
         }
     }
 
+    /// Return the latest statistics of each container of `ids`, querying at most `concurrency`
+    /// containers at the same time.
+    ///
+    /// Failing to get the statistics of a container, e.g. because it was deleted, doesn't affect
+    /// the others.
+    pub async fn stats_many<I, S>(
+        &self,
+        ids: I,
+        concurrency: usize,
+    ) -> HashMap<String, Result<events::Stats>>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        use futures::StreamExt;
+
+        futures::stream::iter(ids)
+            .map(|id| async move {
+                let id = id.into();
+                let stats = self.stats(&id).await;
+                (id, stats)
+            })
+            .buffer_unordered(concurrency.max(1))
+            .collect()
+            .await
+    }
+
     /// Update a container with the provided resource spec
     pub async fn update(&self, id: &str, resources: &LinuxResources) -> Result<()> {
-        let f = write_value_to_temp_file(resources).await?;
+        self.check_id(id)?;
+        let _guard = self.lock_container(id).await;
+        let resources_file =
+            write_value_to_temp_file_with_context(resources, "update resources").await?;
         let args = [
             "update".to_string(),
             "--resources".to_string(),
-            f.to_string(),
+            resources_file.path_string()?,
             id.to_string(),
         ];
-        let _ = tc!(self.launch(self.command(&args)?, true).await, &f);
-        let _ = tokio::fs::remove_file(&f).await;
+        self.launch(self.command(&args)?, true).await?;
+        Ok(())
+    }
+
+    /// Run the runc command `args` (e.g. `["ps", "--format=json", id]`, the global options being
+    /// prepended) with its stdout and stderr written to the given files, and return its exit
+    /// status.
+    ///
+    /// Unlike the other commands, the output isn't buffered in memory, which suits large outputs
+    /// such as the logs of a verbose checkpoint. A non-zero exit status isn't an error, runc's
+    /// error message being in `stderr`.
+    pub async fn launch_to_files(
+        &self,
+        args: &[String],
+        stdout: std::fs::File,
+        stderr: std::fs::File,
+    ) -> Result<ExitStatus> {
+        let mut cmd = self.command(args)?;
+        cmd.stdout(stdout).stderr(stderr);
+        let subcommand = self.subcommand(&cmd);
+        let started = Instant::now();
+        let res = self
+            .spawn_and_wait(cmd, None)
+            .await
+            .map(|(status, ..)| status);
+        let succeeded = matches!(&res, Ok(status) if status.success());
+        self.command_complete(&subcommand, started, succeeded);
+        res
+    }
+
+    /// Return the version of the runc binary
+    pub async fn version(&self) -> Result<Version> {
+        let args = ["--version".to_string()];
+        let res = self.launch(self.command(&args)?, true).await?;
+        utils::parse_version(&res.output)
+    }
+
+    /// Return the features supported by runc, requires runc 1.1 or later.
+    pub async fn features(&self) -> Result<Features> {
+        let args = ["features".to_string()];
+        let res = self.launch(self.command(&args)?, false).await?;
+        serde_json::from_str(&res.output).map_err(Error::JsonDeserializationFailed)
+    }
+
+    /// Return the latency histograms of the runc commands run so far by this instance and its
+    /// clones, per subcommand (e.g. "create").
+    ///
+    /// Latencies are only recorded with [GlobalOpts::record_latency], the snapshot is empty
+    /// otherwise.
+    pub fn latency_snapshot(&self) -> HashMap<String, latency::LatencySnapshot> {
+        self.latencies
+            .as_ref()
+            .map_or_else(HashMap::new, |latencies| latencies.snapshot())
+    }
+
+    /// Return what this runc binary supports, probed with `runc --version` and `runc features`.
+    ///
+    /// The result is cached, the binary is only probed by the first successful call.
+    pub async fn capabilities(&self) -> Result<features::RuncCapabilities> {
+        self.capabilities
+            .get_or_try_init(|| async {
+                let version = self.version().await?;
+                let features = self.features().await.ok();
+                Ok(features::RuncCapabilities::probe(
+                    &version,
+                    features.as_ref(),
+                ))
+            })
+            .await
+            .cloned()
+    }
+
+    /// Return what the CRIU binary used for checkpoint and restore supports, probed with
+    /// `criu --version`, `criu check` and `criu check --feature` (see [GlobalOpts::criu]).
+    ///
+    /// A missing CRIU binary is reported as [Error::CriuNotFound]. `criu check` needs
+    /// `CAP_SYS_ADMIN` or `CAP_CHECKPOINT_RESTORE`, without which no feature is reported.
+    pub async fn criu_features(&self) -> Result<features::CriuFeatures> {
+        let (_, version) = self.run_criu(&["--version"]).await?;
+        let (passed, check) = self.run_criu(&["check"]).await?;
+        let mut supported = Vec::new();
+        for feature in features::CriuFeatures::CHECKED_FEATURES {
+            if self.run_criu(&["check", "--feature", feature]).await?.0 {
+                supported.push(feature);
+            }
+        }
+        Ok(features::CriuFeatures::probe(
+            &version,
+            passed.then_some(check.as_str()),
+            |feature| supported.contains(&feature),
+        ))
+    }
+
+    /// Run `criu` with `args`, and return whether it succeeded and its combined output.
+    async fn run_criu(&self, args: &[&str]) -> Result<(bool, String)> {
+        let (status, _, stdout, stderr) = self.spawner.execute(self.criu_command(args)?).await?;
+        Ok((status.success(), stdout + stderr.as_str()))
+    }
+
+    async fn check_oci_version(&self, bundle: &Path) -> Result<()> {
+        if self.check_oci_version {
+            spec::check_oci_version(&spec::load(bundle)?, &self.features().await?)?;
+        }
         Ok(())
     }
 }
 
+/// Replace the pid of `res`, which is runc's own pid, with the one runc wrote to `pid_file`.
+#[cfg(not(feature = "async"))]
+fn with_init_pid(mut res: Response, pid_file: Option<&PathBuf>) -> Result<Response> {
+    if let Some(pid_file) = pid_file {
+        res.pid = utils::read_pid_file(pid_file)?;
+    }
+    Ok(res)
+}
+
+/// Replace the pid of `res`, which is runc's own pid, with the one runc wrote to `pid_file`.
+#[cfg(feature = "async")]
+async fn with_init_pid(mut res: Response, pid_file: Option<&PathBuf>) -> Result<Response> {
+    if let Some(pid_file) = pid_file {
+        res.pid = utils::read_pid_file(pid_file).await?;
+    }
+    Ok(res)
+}
+
 #[derive(Debug)]
 pub struct DefaultExecutor {}
 
@@ -641,15 +2360,78 @@ impl Spawner for DefaultExecutor {
         let stderr = String::from_utf8_lossy(&result.stderr).to_string();
         Ok((status, pid, stdout, stderr))
     }
+
+    fn execute_with_timeout(
+        &self,
+        cmd: Command,
+        timeout: Duration,
+    ) -> Result<(ExitStatus, u32, String, String)> {
+        use std::sync::{atomic::AtomicBool, mpsc};
+
+        let deadline = Instant::now() + timeout;
+        let mut cmd = cmd;
+        // In its own process group, so that what it forks is killed along with it on timeout.
+        cmd.process_group(0);
+        let mut child = cmd.spawn().map_err(Error::ProcessSpawnFailed)?;
+        let pid = child.id();
+        // The output is drained on threads, as runc's children may keep the pipes open after it
+        // exited, and they can be told to give up.
+        let stop = Arc::new(AtomicBool::new(false));
+        let (done, drained) = mpsc::channel();
+        let stdout = child
+            .stdout
+            .take()
+            .map(|pipe| utils::drain_pipe(pipe, stop.clone(), done.clone()));
+        let stderr = child
+            .stderr
+            .take()
+            .map(|pipe| utils::drain_pipe(pipe, stop.clone(), done));
+        let readers = stdout.iter().chain(&stderr).count();
+
+        // runc isn't reaped until then, so that neither its pid nor the id of its process group
+        // can be reused while it may still be killed.
+        let mut timed_out = (0..readers).any(|_| {
+            let left = deadline.saturating_duration_since(Instant::now());
+            drained.recv_timeout(left).is_err()
+        });
+        let mut delay = Duration::from_millis(1);
+        while !timed_out && !utils::has_exited(pid).map_err(Error::InvalidCommand)? {
+            timed_out = Instant::now() >= deadline;
+            std::thread::sleep(delay);
+            delay = (delay * 2).min(Duration::from_millis(10));
+        }
+        if timed_out {
+            unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGKILL) };
+            let _ = child.kill();
+            stop.store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+        let status = child.wait().map_err(Error::InvalidCommand)?;
+        let output = |reader: Option<std::thread::JoinHandle<std::io::Result<Vec<u8>>>>| {
+            let data = match reader {
+                Some(reader) => reader
+                    .join()
+                    .expect("output reader panicked")
+                    .map_err(Error::InvalidCommand)?,
+                None => Vec::new(),
+            };
+            Ok::<_, Error>(String::from_utf8_lossy(&data).to_string())
+        };
+        let (stdout, stderr) = (output(stdout), output(stderr));
+        if timed_out {
+            return Err(Error::Timeout(timeout));
+        }
+        Ok((status, pid, stdout?, stderr?))
+    }
 }
 
 #[cfg(test)]
 #[cfg(all(target_os = "linux", not(feature = "async")))]
 mod tests {
-    use std::sync::Arc;
+    use std::{io::Read, os::unix::io::AsRawFd, sync::Arc};
 
     use super::{
-        io::{InheritedStdIo, PipedStdIo},
+        io::{IOOption, InheritedStdIo, PipedStdIo, StdinIo},
+        test_util::{fake_runc_script, FakeResponse, FakeRunc},
         *,
     };
 
@@ -746,6 +2528,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_no_subreaper_run_only() {
+        let opts = CreateOpts::new().no_subreaper(true);
+        let response = echo_client()
+            .run("fake-id", "fake-bundle", Some(&opts))
+            .expect("echo failed.");
+        assert!(response.output.contains("--no-subreaper"));
+
+        match echo_client().create("fake-id", "fake-bundle", Some(&opts)) {
+            Err(Error::RunOnlyOption(opt)) => assert_eq!(opt, "--no-subreaper"),
+            res => panic!("create accepted --no-subreaper: {:?}", res),
+        }
+    }
+
     #[test]
     fn test_exec() {
         let opts = ExecOpts::new();
@@ -774,234 +2570,3808 @@ mod tests {
         }
     }
 
-    #[test]
-    fn test_delete() {
-        let opts = DeleteOpts::new();
-        let ok_runc = ok_client();
-        ok_runc
-            .delete("fake-id", Some(&opts))
-            .expect("true failed.");
-        eprintln!("ok_runc succeeded.");
+    const KILL_STOPPED_STDERR: &str =
+        "time=\"2024-01-01T00:00:00Z\" level=error msg=\"container not running\"\n";
+    const EXEC_STOPPED_STDERR: &str = "time=\"2024-01-01T00:00:00Z\" level=error msg=\"exec failed: cannot exec in a stopped container\"\n";
 
-        let fail_runc = fail_client();
-        match fail_runc.delete("fake-id", Some(&opts)) {
-            Ok(_) => panic!("fail_runc returned exit status 0."),
-            Err(Error::CommandFailed {
-                status,
-                stdout,
-                stderr,
-            }) => {
-                if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
-                    eprintln!("fail_runc succeeded.");
-                } else {
-                    panic!("unexpected outputs from fail_runc.")
-                }
+    const CRIU_FAILED_STDERR: &str = "time=\"2024-01-01T00:00:00Z\" level=error msg=\"criu failed: type RESTORE errno 0\\nlog file: /run/runc/fake-id/criu.work/restore.log\"\n";
+
+    /// [FakeRunc] answering `state` with the running container "fake-id" of `pid` and `bundle`.
+    fn running_runc(pid: u32, bundle: &Path) -> Arc<FakeRunc> {
+        let fake = Arc::new(FakeRunc::new());
+        fake.respond(
+            "state",
+            FakeResponse::success(format!(
+                r#"{{"id": "fake-id", "pid": {}, "status": "running", "bundle": "{}", "rootfs": "", "created": 0, "annotations": {{}}}}"#,
+                pid,
+                bundle.display()
+            )),
+        );
+        fake
+    }
+
+    /// [FakeRunc] failing `subcommand` with `stderr`, like a failed runc invocation.
+    fn failing_runc(subcommand: &str, stderr: &str) -> Arc<FakeRunc> {
+        let fake = Arc::new(FakeRunc::new());
+        fake.respond(subcommand, FakeResponse::failure(1, stderr));
+        fake
+    }
+
+    /// Save the process passed to each `exec` answered by `fake`, to be taken from the returned
+    /// slot.
+    fn record_exec(fake: &FakeRunc) -> Arc<std::sync::Mutex<Option<Process>>> {
+        let process = Arc::new(std::sync::Mutex::new(None));
+        let recorded = process.clone();
+        fake.respond_with("exec", move |args| {
+            if let Some(i) = args.iter().position(|a| a == "--process") {
+                let data = std::fs::read(&args[i + 1]).unwrap();
+                *recorded.lock().unwrap() = Some(serde_json::from_slice(&data).unwrap());
             }
-            Err(e) => panic!("unexpected error from fail_runc: {:?}", e),
+            FakeResponse::default()
+        });
+        process
+    }
+
+    #[test]
+    fn test_exec_not_running() {
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(failing_runc("exec", EXEC_STOPPED_STDERR));
+        let runc = opts.build().expect("unable to create runc instance");
+        match runc.exec("fake-id", &dummy_process(), None) {
+            Err(Error::ContainerNotRunning { stderr }) => assert_eq!(stderr, EXEC_STOPPED_STDERR),
+            other => panic!("unexpected result from exec: {:?}", other),
         }
+
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(failing_runc("exec", "exec failed: permission denied"));
+        let runc = opts.build().expect("unable to create runc instance");
+        assert!(matches!(
+            runc.exec("fake-id", &dummy_process(), None),
+            Err(Error::CommandFailed { .. })
+        ));
     }
 
     #[test]
-    fn test_output() {
-        // test create cmd with inherit Io, expect empty cmd output
-        let mut opts = CreateOpts::new();
-        opts.io = Some(Arc::new(InheritedStdIo::new().unwrap()));
-        let echo_runc = echo_client();
-        let response = echo_runc
-            .create("fake-id", "fake-bundle", Some(&opts))
-            .expect("echo failed.");
-        assert_ne!(response.pid, 0);
-        assert!(response.status.success());
-        assert!(response.output.is_empty());
+    fn test_exec_command() {
+        let bundle = tempfile::tempdir().unwrap();
+        std::fs::write(
+            spec::config_path(bundle.path()),
+            r#"{"ociVersion": "1.0.2", "process": {"user": {"uid": 1000, "gid": 1000}, "cwd": "/srv", "args": ["sleep", "inf"]}}"#,
+        )
+        .unwrap();
+        let fake = running_runc(1000, bundle.path());
+        let exec_process = record_exec(&fake);
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(fake.clone());
+        let runc = opts.build().expect("unable to create runc instance");
 
-        // test create cmd with pipe Io, expect nonempty cmd output
-        let mut opts = CreateOpts::new();
-        opts.io = Some(Arc::new(PipedStdIo::new().unwrap()));
-        let echo_runc = echo_client();
-        let response = echo_runc
-            .create("fake-id", "fake-bundle", Some(&opts))
-            .expect("echo failed.");
-        assert_ne!(response.pid, 0);
-        assert!(response.status.success());
-        assert!(!response.output.is_empty());
+        let argv = vec!["ls".to_string(), "/".to_string()];
+        let env = vec![("FOO".to_string(), "bar".to_string())];
+        runc.exec_command("fake-id", &argv, &env, None)
+            .expect("exec_command failed");
+
+        let process = exec_process.lock().unwrap().take().unwrap();
+        assert_eq!(process.args().as_ref().unwrap(), &argv);
+        assert_eq!(process.cwd(), Path::new("/srv"));
+        assert_eq!(process.user().uid(), 1000);
+        assert!(process
+            .env()
+            .as_ref()
+            .unwrap()
+            .contains(&"FOO=bar".to_string()));
+
+        // Without a process to inherit the user from, nothing is run as root.
+        std::fs::write(
+            spec::config_path(bundle.path()),
+            r#"{"ociVersion": "1.0.2"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            runc.exec_command("fake-id", &argv, &env, None),
+            Err(Error::BundleWithoutProcess(_))
+        ));
+        std::fs::remove_file(spec::config_path(bundle.path())).unwrap();
+        assert!(matches!(
+            runc.exec_command("fake-id", &argv, &env, None),
+            Err(Error::SpecProcessingFailed(_))
+        ));
+        assert!(exec_process.lock().unwrap().is_none());
     }
-}
 
-/// Tokio tests
-#[cfg(test)]
-#[cfg(all(target_os = "linux", feature = "async"))]
-mod tests {
-    use std::sync::Arc;
+    #[test]
+    fn test_working_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(dir.path(), "pwd\n");
+        let work = dir.path().join("work");
+        std::fs::create_dir(&work).unwrap();
 
-    use super::{
-        io::{InheritedStdIo, PipedStdIo},
-        *,
-    };
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .working_dir(&work)
+            .build()
+            .expect("unable to create runc instance");
+        let response = runc
+            .create("fake-id", "fake-bundle", None)
+            .expect("pwd failed.");
+        assert_eq!(
+            Path::new(response.output.trim()).canonicalize().unwrap(),
+            work.canonicalize().unwrap()
+        );
+    }
 
-    fn ok_client() -> Runc {
-        GlobalOpts::new()
-            .command("/bin/true")
+    #[test]
+    fn test_container_spec() {
+        let bundle = tempfile::tempdir().unwrap();
+        std::fs::write(
+            spec::config_path(bundle.path()),
+            r#"{"ociVersion": "1.0.2", "hostname": "fake-host"}"#,
+        )
+        .unwrap();
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(running_runc(1000, bundle.path()));
+        let runc = opts.build().expect("unable to create runc instance");
+
+        let spec = runc.container_spec("fake-id").expect("failed to read spec");
+        assert_eq!(spec.hostname().as_deref(), Some("fake-host"));
+
+        bundle.close().unwrap();
+        assert!(matches!(
+            runc.container_spec("fake-id"),
+            Err(Error::SpecFileNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_pid_file() {
+        // Fake runc writing a well-known pid to the pid file, like runc does with the pid of
+        // the container's process.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(dir.path(), "while [ $# -gt 0 ]; do\n  [ \"$1\" = --pid-file ] && echo 424242 > \"$2\"\n  shift\ndone\n");
+        let runc = GlobalOpts::new()
+            .command(&script)
             .build()
-            .expect("unable to create runc instance")
+            .expect("unable to create runc instance");
+
+        let opts = CreateOpts::new().pid_file(dir.path().join("init.pid"));
+        let response = runc
+            .create("fake-id", "fake-bundle", Some(&opts))
+            .expect("create failed.");
+        assert_eq!(response.pid, 424242);
+
+        let response = runc
+            .create("fake-id", "fake-bundle", None)
+            .expect("create failed.");
+        assert_ne!(response.pid, 424242);
+
+        let opts = ExecOpts::new().pid_file(dir.path().join("exec.pid"));
+        let response = runc
+            .exec_with_response("fake-id", &dummy_process(), Some(&opts))
+            .expect("exec failed.");
+        assert_eq!(response.pid, 424242);
     }
 
-    fn fail_client() -> Runc {
-        GlobalOpts::new()
-            .command("/bin/false")
+    #[test]
+    fn test_create_stdin() {
+        use std::io::Write;
+
+        // Fake runc leaving a process reading its stdin in the background, like a container.
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("output");
+        let script = fake_runc_script(
+            dir.path(),
+            &format!("exec 3<&0\ncat <&3 > {} 2>&1 &\n", output.display()),
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
             .build()
-            .expect("unable to create runc instance")
+            .expect("unable to create runc instance");
+
+        let (reader, mut writer) = os_pipe::pipe().unwrap();
+        let uid = nix::unistd::getuid().as_raw();
+        let gid = nix::unistd::getgid().as_raw();
+        let opts = IOOption {
+            open_stdin: false,
+            open_stdout: false,
+            open_stderr: false,
+        };
+        let io = StdinIo::new(uid, gid, &opts, reader).unwrap();
+        let opts = CreateOpts::new().io(Arc::new(io));
+        runc.create("fake-id", "fake-bundle", Some(&opts))
+            .expect("create failed.");
+
+        // The container still reads its stdin after create returned, until it's closed.
+        writer.write_all(b"hello\n").unwrap();
+        drop(writer);
+        for _ in 0..100 {
+            if std::fs::read_to_string(&output).unwrap_or_default() == "hello\n" {
+                return;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        panic!("the container didn't read its stdin");
     }
 
-    fn echo_client() -> Runc {
-        GlobalOpts::new()
-            .command("/bin/echo")
+    #[test]
+    fn test_combine_output() {
+        // Fake runc printing the state of the container, and a warning on stderr.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(dir.path(), "echo '{\"id\": \"fake-id\", \"pid\": 42, \"status\": \"running\", \"bundle\": \"/bundle\", \"rootfs\": \"/bundle/rootfs\", \"created\": \"2023-01-01T00:00:00Z\"}'\necho 'level=warning msg=\"cgroup is not accessible\"' >&2\n");
+
+        let runc = GlobalOpts::new()
+            .command(&script)
             .build()
-            .expect("unable to create runc instance")
+            .expect("unable to create runc instance");
+        assert!(matches!(
+            runc.state("fake-id"),
+            Err(Error::JsonDeserializationFailed(_))
+        ));
+
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .combine_output(false)
+            .build()
+            .expect("unable to create runc instance");
+        let container = runc.state("fake-id").expect("state failed.");
+        assert_eq!(container.id, "fake-id");
+        assert_eq!(container.pid, 42);
+        let response = runc.start("fake-id").expect("start failed.");
+        assert!(!response.output.contains("warning"));
     }
 
-    #[tokio::test]
-    async fn test_async_create() {
-        let opts = CreateOpts::new();
-        let ok_runc = ok_client();
-        let ok_task = tokio::spawn(async move {
-            let response = ok_runc
-                .create("fake-id", "fake-bundle", Some(&opts))
-                .await
-                .expect("true failed.");
-            assert_ne!(response.pid, 0);
-            assert!(response.status.success());
-            assert!(response.output.is_empty());
-        });
+    #[test]
+    fn test_pids() {
+        // Stand-in for the container's processes, in the same cgroup as the test.
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(running_runc(child.id(), Path::new("")));
+        let runc = opts.build().expect("unable to create runc instance");
+
+        let pids = runc.pids("fake-id").expect("failed to list pids");
+        let _ = child.kill();
+        let _ = child.wait();
+        assert!(pids.contains(&(child.id() as usize)));
+        assert!(pids.contains(&(std::process::id() as usize)));
+    }
+
+    #[test]
+    fn test_on_command_complete() {
+        let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorder = |command: &str| {
+            let calls = calls.clone();
+            GlobalOpts::new()
+                .command(command)
+                .on_command_complete(move |subcommand, _, success| {
+                    calls
+                        .lock()
+                        .unwrap()
+                        .push((subcommand.to_string(), success))
+                })
+                .build()
+                .expect("unable to create runc instance")
+        };
+
+        recorder("/bin/true")
+            .start("fake-id")
+            .expect("true failed.");
+        assert!(recorder("/bin/false").start("fake-id").is_err());
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![("start".to_string(), true), ("start".to_string(), false)]
+        );
+    }
+
+    #[test]
+    fn test_validate_ids() {
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .build()
+            .expect("unable to create runc instance");
+        for id in ["", "a/b", "../fake-id"] {
+            assert!(matches!(
+                runc.start(id),
+                Err(Error::InvalidContainerId { .. })
+            ));
+            assert!(matches!(
+                runc.kill(id, 9, None),
+                Err(Error::InvalidContainerId { .. })
+            ));
+        }
+        runc.start("fake-id").expect("true failed.");
+
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .validate_ids(false)
+            .build()
+            .expect("unable to create runc instance");
+        runc.start("a/b").expect("true failed.");
+    }
+
+    #[test]
+    fn test_create_idempotent() {
+        let bundle = tempfile::tempdir().unwrap();
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        let fake = running_runc(1000, bundle.path());
+        fake.respond(
+            "create",
+            FakeResponse::failure(1, "container with id exists: fake-id"),
+        );
+        opts.custom_spawner(fake);
+        let runc = opts.build().expect("unable to create runc instance");
+
+        let response = runc
+            .create_idempotent("fake-id", bundle.path(), None)
+            .expect("create of the same container failed");
+        assert_eq!(response.pid, 1000);
+
+        let other = tempfile::tempdir().unwrap();
+        match runc.create_idempotent("fake-id", other.path(), None) {
+            Err(Error::CommandFailed { stderr, .. }) => {
+                assert_eq!(stderr, "container with id exists: fake-id")
+            }
+            other => panic!("unexpected result from create: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_restore() {
+        // Fake runc writing a well-known pid to the pid file, like runc does with the pid of
+        // the restored process.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(dir.path(), "while [ $# -gt 0 ]; do\n  [ \"$1\" = --pid-file ] && echo 424242 > \"$2\"\n  shift\ndone\n");
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+
+        let opts = RestoreOpts::new()
+            .image_path(dir.path().join("checkpoint"))
+            .pid_file(dir.path().join("restore.pid"))
+            .detach(true);
+        let response = runc
+            .restore("fake-id", "fake-bundle", Some(&opts))
+            .expect("restore failed.");
+        assert_eq!(response.pid, 424242);
+
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(failing_runc("restore", CRIU_FAILED_STDERR));
+        let runc = opts.build().expect("unable to create runc instance");
+        match runc.restore("fake-id", "fake-bundle", None) {
+            Err(Error::CriuFailed { log_path, .. }) => assert_eq!(
+                log_path.as_deref(),
+                Some(Path::new("/run/runc/fake-id/criu.work/restore.log"))
+            ),
+            other => panic!("unexpected result from restore: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_exec_caps() {
+        use oci_spec::runtime::Capability;
+
+        let fake = running_runc(1000, Path::new(""));
+        let exec_process = record_exec(&fake);
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(fake.clone());
+        let runc = opts.build().expect("unable to create runc instance");
+
+        let opts = ExecOpts::new()
+            .caps(vec![Capability::SysPtrace])
+            .process_label("system_u:system_r:container_t:s0");
+        runc.exec("fake-id", &dummy_process(), Some(&opts))
+            .expect("exec failed");
+
+        let process = exec_process.lock().unwrap().take().unwrap();
+        let caps = process.capabilities().as_ref().unwrap();
+        for set in [caps.bounding(), caps.effective(), caps.permitted()] {
+            assert!(set.as_ref().unwrap().contains(&Capability::SysPtrace));
+        }
+        assert!(!caps
+            .ambient()
+            .as_ref()
+            .map_or(false, |s| s.contains(&Capability::SysPtrace)));
+        assert_eq!(
+            process.selinux_label().as_deref(),
+            Some("system_u:system_r:container_t:s0")
+        );
+    }
+
+    #[test]
+    fn test_exec_rlimits() {
+        use oci_spec::runtime::{PosixRlimitBuilder, PosixRlimitType};
+
+        let fake = running_runc(1000, Path::new(""));
+        let exec_process = record_exec(&fake);
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(fake.clone());
+        let runc = opts.build().expect("unable to create runc instance");
+        let rlimit = |typ, soft: u64, hard: u64| {
+            PosixRlimitBuilder::default()
+                .typ(typ)
+                .soft(soft)
+                .hard(hard)
+                .build()
+                .unwrap()
+        };
+        let mut process = dummy_process();
+        process.set_rlimits(Some(vec![
+            rlimit(PosixRlimitType::RlimitNofile, 1024, 1024),
+            rlimit(PosixRlimitType::RlimitCore, 0, 0),
+        ]));
+
+        let opts =
+            ExecOpts::new().rlimits(vec![rlimit(PosixRlimitType::RlimitNofile, 65536, 65536)]);
+        runc.exec("fake-id", &process, Some(&opts))
+            .expect("exec failed");
+        let written = exec_process.lock().unwrap().take().unwrap();
+        assert_eq!(
+            written.rlimits().as_deref(),
+            Some(
+                &[
+                    rlimit(PosixRlimitType::RlimitCore, 0, 0),
+                    rlimit(PosixRlimitType::RlimitNofile, 65536, 65536),
+                ][..]
+            )
+        );
+
+        let opts = ExecOpts::new().rlimits(vec![rlimit(PosixRlimitType::RlimitNofile, 4096, 1024)]);
+        match runc.exec("fake-id", &process, Some(&opts)) {
+            Err(Error::InvalidRlimit { typ, soft, hard }) => {
+                assert_eq!((typ.as_str(), soft, hard), ("RLIMIT_NOFILE", 4096, 1024))
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(exec_process.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_exec_process_via_stdin() {
+        // Fake runc saving the path and the content of its process spec.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(
+            dir.path(),
+            r#"dir="$(dirname "$0")"
+while [ $# -gt 0 ]; do
+  if [ "$1" = --process ]; then echo "$2" > "$dir/path"; cat "$2" > "$dir/spec"; fi
+  shift
+done
+"#,
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+        // A launcher may not pass stdin on.
+        let launched = GlobalOpts::new()
+            .command(&script)
+            .launcher(Launcher::new("/usr/bin/env"))
+            .build()
+            .expect("unable to create runc instance");
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+
+        let opts = ExecOpts::new().process_via_stdin(true);
+        for (runc, opts, via_stdin) in [
+            (&runc, opts.clone(), true),
+            // An io may provide stdin to the process, the spec falls back to a temp file.
+            (
+                &runc,
+                opts.clone().io(Arc::new(crate::io::NullIo::new().unwrap())),
+                false,
+            ),
+            (&launched, opts, false),
+        ] {
+            runc.exec("fake-id", &dummy_process(), Some(&opts))
+                .expect("exec failed");
+            let path = read("path");
+            let spec: Process = serde_json::from_str(&read("spec")).unwrap();
+            assert_eq!(spec, dummy_process());
+            if via_stdin {
+                assert_eq!(path.trim(), "/dev/stdin");
+            } else {
+                assert_ne!(path.trim(), "/dev/stdin");
+                assert!(!Path::new(path.trim()).exists());
+            }
+        }
+    }
+
+    #[test]
+    fn test_launcher() {
+        let subcommands = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = subcommands.clone();
+        // echo as a launcher prints the command line it would run.
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .launcher(Launcher::new("/bin/echo").arg("--scope"))
+            .on_command_complete(move |subcommand, _, _| {
+                recorded.lock().unwrap().push(subcommand.to_string())
+            })
+            .build()
+            .expect("unable to create runc instance");
+
+        let response = runc
+            .create("fake-id", "fake-bundle", None)
+            .expect("echo failed.");
+        assert_eq!(
+            response.output.trim(),
+            format!(
+                "--scope {} --log-format text create --bundle {} fake-id",
+                runc.command.display(),
+                utils::abs_string("fake-bundle").unwrap()
+            )
+        );
+        assert_eq!(*subcommands.lock().unwrap(), vec!["create".to_string()]);
+    }
+
+    #[test]
+    fn test_min_root_free() {
+        let root = tempfile::tempdir().unwrap();
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .root(root.path())
+            .min_root_free(u64::MAX)
+            .build()
+            .expect("unable to create runc instance");
+        assert!(matches!(
+            runc.create("fake-id", "fake-bundle", None),
+            Err(Error::StateDirFull { .. })
+        ));
+        assert!(matches!(
+            runc.restore("fake-id", "fake-bundle", None),
+            Err(Error::StateDirFull { .. })
+        ));
+
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .root(root.path())
+            .min_root_free(1)
+            .build()
+            .expect("unable to create runc instance");
+        runc.create("fake-id", "fake-bundle", None)
+            .expect("true failed.");
+    }
+
+    #[test]
+    fn test_oom_kills() {
+        // The test process stands in for the container's init process.
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(running_runc(std::process::id(), Path::new("")));
+        let runc = opts.build().expect("unable to create runc instance");
+
+        let first = runc
+            .oom_kills("fake-id", None)
+            .expect("failed to read OOM kills");
+        assert!(!first.increased);
+        let second = runc
+            .oom_kills("fake-id", Some(first.count))
+            .expect("failed to read OOM kills");
+        assert!(second.count >= first.count);
+        assert_eq!(second.increased, second.count > first.count);
+    }
+
+    #[test]
+    fn test_init_pid() {
+        // The test process stands in for the init process of a running container.
+        let stat = std::fs::read_to_string("/proc/self/stat").unwrap();
+        let start: u64 = stat[stat.rfind(')').unwrap() + 2..]
+            .split(' ')
+            .nth(19)
+            .unwrap()
+            .parse()
+            .unwrap();
+        let mut exited = std::process::Command::new("/bin/true").spawn().unwrap();
+        exited.wait().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let write_state = |id: &str, pid: u32, start: u64| {
+            std::fs::create_dir(root.path().join(id)).unwrap();
+            std::fs::write(
+                root.path().join(id).join("state.json"),
+                serde_json::json!({
+                    "id": id,
+                    "init_process_pid": pid,
+                    "init_process_start": start,
+                    "config": {"rootfs": "/bundle/rootfs"},
+                })
+                .to_string(),
+            )
+            .unwrap();
+        };
+        write_state("running", std::process::id(), start);
+        write_state("created", std::process::id(), start);
+        std::fs::write(root.path().join("created/exec.fifo"), "").unwrap();
+        write_state("stopped", exited.id(), 0);
+        write_state("reused", std::process::id(), start + 1);
+        let runc = GlobalOpts::new()
+            .command("/bin/false")
+            .root(root.path())
+            .build()
+            .expect("unable to create runc instance");
+
+        assert_eq!(
+            runc.init_pid("running").unwrap(),
+            Some(std::process::id() as i32)
+        );
+        assert_eq!(runc.init_pid("created").unwrap(), None);
+        assert_eq!(runc.init_pid("stopped").unwrap(), None);
+        assert_eq!(runc.init_pid("reused").unwrap(), None);
+        assert!(runc.init_pid("other-id").is_err());
+    }
+
+    #[test]
+    fn test_memory() {
+        // The test process stands in for the container's init process.
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("fake-id")).unwrap();
+        std::fs::write(
+            root.path().join("fake-id/state.json"),
+            format!(r#"{{"init_process_pid": {}}}"#, std::process::id()),
+        )
+        .unwrap();
+        let mut exited = std::process::Command::new("/bin/true").spawn().unwrap();
+        exited.wait().unwrap();
+        std::fs::create_dir(root.path().join("stopped")).unwrap();
+        std::fs::write(
+            root.path().join("stopped/state.json"),
+            format!(r#"{{"init_process_pid": {}}}"#, exited.id()),
+        )
+        .unwrap();
+
+        // Fake runc reporting the stats of the same cgroup.
+        let cgroup = std::fs::read_to_string("/proc/self/cgroup").unwrap();
+        let (usage, limit) = utils::memory_stat_paths(&cgroup).unwrap();
+        let script = fake_runc_script(
+            root.path(),
+            &format!(
+                r#"usage=$(cat {})
+limit=$(cat {})
+[ "$limit" = max ] && limit=18446744073709551615
+echo '{{"type":"stats","id":"fake-id","data":{{"cpu":{{"usage":0}},"memory":{{"usage":{{"limit":'$limit',"usage":'$usage',"failcnt":0}}}},"pids":{{}},"blkio":{{}},"hugetlb":{{"failcnt":0}}}}}}'
+"#,
+                usage.display(),
+                limit.display()
+            ),
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .root(root.path())
+            .build()
+            .expect("unable to create runc instance");
+
+        let memory = runc.memory("fake-id").expect("failed to read memory");
+        let stats = runc.stats("fake-id").expect("failed to read stats");
+        let usage = stats.memory.usage.as_ref().and_then(|e| e.usage).unwrap();
+        // The usage moves, but not by more than a few MiB between the reads.
+        assert!(memory.usage > 0);
+        assert!(memory.usage.abs_diff(usage) < 64 << 20);
+        assert_eq!(memory.limit, stats.limits().memory);
+        assert!(matches!(
+            runc.memory("stopped"),
+            Err(Error::ContainerNotRunning { .. })
+        ));
+        assert!(runc.memory("other-id").is_err());
+    }
+
+    #[test]
+    fn test_cgroup_path() {
+        // The test process stands in for the container's init process.
+        let cgroup = std::fs::read_to_string("/proc/self/cgroup").unwrap();
+        let own = utils::cgroup_procs_path(&cgroup).unwrap();
+        let own = own.parent().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("fake-id")).unwrap();
+        std::fs::write(
+            root.path().join("fake-id/state.json"),
+            serde_json::json!({
+                "cgroup_paths": {"pids": own, "memory": own, "": own},
+                "config": {"cgroups": {"path": "/elsewhere"}},
+            })
+            .to_string(),
+        )
+        .unwrap();
+        std::fs::create_dir(root.path().join("derived-id")).unwrap();
+        std::fs::write(
+            root.path().join("derived-id/state.json"),
+            r#"{"config": {"cgroups": {"name": "derived-id", "parent": "runc"}}}"#,
+        )
+        .unwrap();
+        let runc = GlobalOpts::new()
+            .command("/bin/false")
+            .root(root.path())
+            .build()
+            .expect("unable to create runc instance");
+
+        let path = runc
+            .cgroup_path("fake-id")
+            .expect("failed to get cgroup path");
+        assert_eq!(path, own);
+        assert!(path.exists());
+        let derived = runc.cgroup_path("derived-id").unwrap();
+        assert!(derived.starts_with("/sys/fs/cgroup"));
+        assert!(derived.ends_with("runc/derived-id"));
+        assert!(runc.cgroup_path("other-id").is_err());
+    }
+
+    #[test]
+    fn test_criu_features() {
+        // Fake CRIU 3.17 supporting lazy pages only.
+        let dir = tempfile::tempdir().unwrap();
+        let criu = fake_runc_script(
+            dir.path(),
+            r#"case "$*" in
+  --version) printf 'Version: 3.17.1\nGitID: v3.17.1\n' ;;
+  check) echo 'Looks good.' ;;
+  "check --feature uffd-noncoop") echo 'uffd-noncoop is supported' ;;
+  *) echo "$3 is not supported" >&2; exit 1 ;;
+esac
+"#,
+        );
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .criu(&criu)
+            .build()
+            .expect("unable to create runc instance");
+
+        assert_eq!(
+            runc.criu_features().unwrap(),
+            features::CriuFeatures {
+                version: Some("3.17.1".to_string()),
+                check_passed: true,
+                tcp_established: true,
+                lazy_pages: true,
+                ..Default::default()
+            }
+        );
+
+        let missing = dir.path().join("missing-criu");
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .criu(&missing)
+            .build()
+            .expect("unable to create runc instance");
+        match runc.criu_features() {
+            Err(Error::CriuNotFound(path)) => assert_eq!(path, missing),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_export_bundle() {
+        let bundle = tempfile::tempdir().unwrap();
+        std::fs::write(
+            spec::config_path(bundle.path()),
+            r#"{"ociVersion": "1.0.2", "root": {"path": "rootfs"}}"#,
+        )
+        .unwrap();
+        std::fs::write(bundle.path().join("annotations.json"), "{}").unwrap();
+        std::fs::create_dir_all(bundle.path().join("rootfs/bin")).unwrap();
+        std::fs::write(bundle.path().join("rootfs/bin/sh"), "").unwrap();
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(running_runc(1000, bundle.path()));
+        let runc = opts.build().expect("unable to create runc instance");
+
+        let entries = |archive: Vec<u8>| {
+            let mut names: Vec<String> = tar::Archive::new(archive.as_slice())
+                .entries()
+                .unwrap()
+                .map(|e| {
+                    let path = e.unwrap().path().unwrap().display().to_string();
+                    path.trim_end_matches('/').to_string()
+                })
+                .collect();
+            names.sort();
+            names
+        };
+        let archive = runc
+            .export_bundle("fake-id", Vec::new(), false)
+            .expect("export failed");
+        assert_eq!(entries(archive), vec!["annotations.json", "config.json"]);
+        let archive = runc
+            .export_bundle("fake-id", Vec::new(), true)
+            .expect("export failed");
+        assert_eq!(
+            entries(archive),
+            vec![
+                "annotations.json",
+                "config.json",
+                "rootfs",
+                "rootfs/bin",
+                "rootfs/bin/sh"
+            ]
+        );
+
+        bundle.close().unwrap();
+        assert!(matches!(
+            runc.export_bundle("fake-id", Vec::new(), false),
+            Err(Error::SpecFileNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_delete_and_cleanup() {
+        let root = tempfile::tempdir().unwrap();
+        let bundle = root.path().join("fake-id");
+        std::fs::create_dir(&bundle).unwrap();
+        std::fs::write(spec::config_path(&bundle), "{}").unwrap();
+        let runc = |bundle_root: &Path| {
+            let mut opts = GlobalOpts::new()
+                .command("/bin/true")
+                .bundle_root(bundle_root);
+            opts.custom_spawner(running_runc(1000, &bundle));
+            opts.build().expect("unable to create runc instance")
+        };
+
+        let elsewhere = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            runc(elsewhere.path()).delete_and_cleanup("fake-id", None),
+            Err(Error::BundleOutsideRoot(_))
+        ));
+        assert!(bundle.exists());
+
+        runc(root.path())
+            .delete_and_cleanup("fake-id", None)
+            .expect("delete_and_cleanup failed");
+        assert!(!bundle.exists());
+    }
+
+    #[test]
+    fn test_log_fd() {
+        let log = tempfile::tempfile().unwrap();
+        // Fake runc writing to the path following --log, which only works if the fd is
+        // inherited.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(
+            dir.path(),
+            "while [ \"$1\" != --log ]; do shift; done\necho logged > \"$2\"\n",
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .log_fd(log.as_raw_fd())
+            .build()
+            .expect("unable to create runc instance");
+
+        runc.pause("fake-id").expect("pause failed");
+        let mut logged = String::new();
+        (&log).read_to_string(&mut logged).unwrap();
+        assert_eq!(logged, "logged\n");
+    }
+
+    #[test]
+    fn test_kill_stopped() {
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(failing_runc("kill", KILL_STOPPED_STDERR));
+        let runc = opts.build().expect("unable to create runc instance");
+        runc.kill("fake-id", 9, None)
+            .expect("kill of a stopped container failed");
+
+        let strict = KillOpts::new().strict(true);
+        match runc.kill("fake-id", 9, Some(&strict)) {
+            Err(Error::CommandFailed { stderr, .. }) => assert_eq!(stderr, KILL_STOPPED_STDERR),
+            other => panic!("unexpected result from kill: {:?}", other),
+        }
+
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(failing_runc("kill", EXEC_STOPPED_STDERR));
+        let runc = opts.build().expect("unable to create runc instance");
+        assert!(runc.kill("fake-id", 9, None).is_err());
+    }
+
+    /// Messages of the log records carrying the `correlation_id` key-value `id`.
+    fn correlated_logs(id: &str) -> Vec<String> {
+        struct Capture;
+        static RECORDS: std::sync::Mutex<Vec<(String, String)>> = std::sync::Mutex::new(Vec::new());
+
+        impl log::Log for Capture {
+            fn enabled(&self, _: &log::Metadata) -> bool {
+                true
+            }
+
+            fn log(&self, record: &log::Record) {
+                if let Some(id) = record.key_values().get("correlation_id".into()) {
+                    let entry = (id.to_string(), record.args().to_string());
+                    RECORDS.lock().unwrap().push(entry);
+                }
+            }
+
+            fn flush(&self) {}
+        }
+
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&Capture).unwrap();
+            log::set_max_level(log::LevelFilter::Debug);
+        });
+        RECORDS
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(i, _)| i == id)
+            .map(|(_, message)| message.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_correlation_id() {
+        // Install the logger first.
+        assert!(correlated_logs("request-42").is_empty());
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .correlation_id("request-42")
+            .build()
+            .expect("unable to create runc instance");
+        runc.pause("fake-id").expect("true failed");
+        runc.resume("fake-id").expect("true failed");
+        let logs = correlated_logs("request-42");
+        assert_eq!(logs.len(), 2);
+        assert!(logs[0].starts_with("runc pause succeeded after"));
+        assert!(logs[1].starts_with("runc resume succeeded after"));
+    }
+
+    /// Subscriber recording the subcommand and correlation id of the `runc` spans.
+    #[cfg(feature = "tracing")]
+    #[derive(Clone, Default)]
+    struct SpanCapture(Arc<std::sync::Mutex<Vec<(String, String)>>>);
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for SpanCapture {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            #[derive(Default)]
+            struct Fields(std::collections::HashMap<&'static str, String>);
+
+            impl tracing::field::Visit for Fields {
+                fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn Debug) {
+                    self.0.insert(field.name(), format!("{:?}", value));
+                }
+
+                fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+                    self.0.insert(field.name(), value.to_string());
+                }
+            }
+
+            let mut fields = Fields::default();
+            span.record(&mut fields);
+            let mut spans = self.0.lock().unwrap();
+            if span.metadata().name() == "runc" {
+                let field = |name| fields.0.get(name).cloned().unwrap_or_default();
+                spans.push((field("subcommand"), field("correlation_id")));
+            }
+            tracing::span::Id::from_u64(spans.len() as u64 + 1)
+        }
+
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, _: &tracing::Event<'_>) {}
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_correlation_id_span() {
+        let capture = SpanCapture::default();
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .correlation_id("request-42")
+            .build()
+            .expect("unable to create runc instance");
+        tracing::subscriber::with_default(capture.clone(), || {
+            runc.pause("fake-id").expect("true failed");
+        });
+        assert_eq!(
+            *capture.0.lock().unwrap(),
+            [("pause".to_string(), "request-42".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_exec_cap_names() {
+        use oci_spec::runtime::Capability;
+
+        let fake = running_runc(1000, Path::new(""));
+        let exec_process = record_exec(&fake);
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(fake.clone());
+        let runc = opts.build().expect("unable to create runc instance");
+
+        let opts = ExecOpts::new().cap_names(["CAP_NET_ADMN"]);
+        match runc.exec("fake-id", &dummy_process(), Some(&opts)) {
+            Err(Error::UnknownCapability(name)) => assert_eq!(name, "CAP_NET_ADMN"),
+            other => panic!("unexpected result from exec: {:?}", other),
+        }
+        assert!(exec_process.lock().unwrap().is_none());
+
+        let opts = ExecOpts::new().cap_names(["CAP_NET_ADMIN"]);
+        runc.exec("fake-id", &dummy_process(), Some(&opts))
+            .expect("exec failed");
+        let process = exec_process.lock().unwrap().take().unwrap();
+        let caps = process.capabilities().as_ref().unwrap();
+        assert!(caps
+            .effective()
+            .as_ref()
+            .unwrap()
+            .contains(&Capability::NetAdmin));
+    }
+
+    #[test]
+    fn test_checkpoint_restore() {
+        // Fake runc dumping an image on checkpoint, and restoring only from an existing image.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(
+            dir.path(),
+            r#"while [ $# -gt 0 ]; do
+  case "$1" in
+    checkpoint|restore) cmd=$1 ;;
+    --image-path) images=$2 ;;
+    --pid-file) pid_file=$2 ;;
+  esac
+  shift
+done
+case $cmd in
+  checkpoint) mkdir -p "$images" && echo 424242 > "$images/inventory.img" ;;
+  restore) cp "$images/inventory.img" "$pid_file" ;;
+esac
+"#,
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+
+        let images = dir.path().join("checkpoint");
+        let restore = RestoreOpts::new()
+            .image_path(&images)
+            .pid_file(dir.path().join("restore.pid"))
+            .detach(true);
+        assert!(runc
+            .restore("fake-id", "fake-bundle", Some(&restore))
+            .is_err());
+
+        let checkpoint = CheckpointOpts::new().image_path(&images);
+        runc.checkpoint("fake-id", Some(&checkpoint))
+            .expect("checkpoint failed");
+        let response = runc
+            .restore("fake-id", "fake-bundle", Some(&restore))
+            .expect("restore failed");
+        assert_eq!(response.pid, 424242);
+    }
+
+    #[test]
+    fn test_exec_detached() {
+        // Fake runc starting the process in the background when detached, like runc does.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(dir.path(), "while [ $# -gt 0 ]; do\n  [ \"$1\" = --pid-file ] && pid_file=$2\n  [ \"$1\" = --detach ] && detach=1\n  shift\ndone\n[ -n \"$detach\" ] || exit 1\nsleep 10 > /dev/null 2>&1 &\necho $! > \"$pid_file\"\n");
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+
+        let pid_file = dir.path().join("exec.pid");
+        let opts = ExecOpts::new().detach(true).pid_file(&pid_file);
+        let response = runc
+            .exec_with_response("fake-id", &dummy_process(), Some(&opts))
+            .expect("exec failed.");
+        assert!(std::path::Path::new(&format!("/proc/{}", response.pid)).exists());
+        assert!(!pid_file.exists());
+        unsafe { libc::kill(response.pid as i32, libc::SIGKILL) };
+    }
+
+    #[test]
+    fn test_oom_score_adj() {
+        let dir = tempfile::tempdir().unwrap();
+        let adj = dir.path().join("oom_score_adj");
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .oom_score_adj(900)
+            .launcher(
+                Launcher::new("/bin/sh")
+                    .arg("-c")
+                    .arg(format!("cat /proc/self/oom_score_adj > {}", adj.display())),
+            )
+            .build()
+            .expect("unable to create runc instance");
+
+        runc.pause("fake-id").expect("launcher failed");
+        assert_eq!(std::fs::read_to_string(&adj).unwrap(), "900\n");
+    }
+
+    #[test]
+    fn test_pre_exec() {
+        let dir = tempfile::tempdir().unwrap();
+        let limit = dir.path().join("nofile");
+        // Safety: setrlimit is async-signal-safe.
+        let opts = unsafe {
+            GlobalOpts::new().command("/bin/true").pre_exec(|| {
+                let rlim = libc::rlimit {
+                    rlim_cur: 123,
+                    rlim_max: 123,
+                };
+                if libc::setrlimit(libc::RLIMIT_NOFILE, &rlim) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            })
+        };
+        let runc = opts
+            .launcher(
+                Launcher::new("/bin/sh")
+                    .arg("-c")
+                    .arg(format!("ulimit -n > {}", limit.display())),
+            )
+            .build()
+            .expect("unable to create runc instance");
+
+        runc.pause("fake-id").expect("launcher failed");
+        assert_eq!(std::fs::read_to_string(&limit).unwrap(), "123\n");
+
+        // A failing hook fails the command before anything is executed.
+        std::fs::remove_file(&limit).unwrap();
+        let runc = unsafe {
+            GlobalOpts::new()
+                .command("/bin/true")
+                .pre_exec(|| Err(std::io::Error::from_raw_os_error(libc::EPERM)))
+        }
+        .launcher(
+            Launcher::new("/bin/sh")
+                .arg("-c")
+                .arg(format!("ulimit -n > {}", limit.display())),
+        )
+        .build()
+        .expect("unable to create runc instance");
+        assert!(runc.pause("fake-id").is_err());
+        assert!(!limit.exists());
+    }
+
+    #[test]
+    fn test_launch_to_files() {
+        // Fake runc printing a large output before failing.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(
+            dir.path(),
+            "seq 1 200000\necho \"checkpoint failed\" >&2\nexit 3\n",
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+
+        let stdout = dir.path().join("stdout");
+        let stderr = dir.path().join("stderr");
+        let status = runc
+            .launch_to_files(
+                &["checkpoint".to_string(), "fake-id".to_string()],
+                std::fs::File::create(&stdout).unwrap(),
+                std::fs::File::create(&stderr).unwrap(),
+            )
+            .expect("launch failed");
+        assert_eq!(status.code(), Some(3));
+        let output = std::fs::read_to_string(&stdout).unwrap();
+        assert_eq!(output.lines().count(), 200000);
+        assert_eq!(output.lines().last(), Some("200000"));
+        assert_eq!(
+            std::fs::read_to_string(&stderr).unwrap(),
+            "checkpoint failed\n"
+        );
+    }
+
+    #[test]
+    fn test_response_bundle() {
+        // Fake runc printing its arguments.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(dir.path(), "echo \"$@\"\n");
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+
+        let bundle = std::env::current_dir().unwrap().join("bundles/fake-id");
+        for res in [
+            runc.create("fake-id", "bundles/./fake-id", None),
+            runc.run("fake-id", "bundles/../bundles/fake-id", None),
+        ] {
+            let res = res.expect("runc failed");
+            assert_eq!(res.bundle.as_deref(), Some(bundle.as_path()));
+            assert!(res
+                .output
+                .contains(&format!("--bundle {} ", bundle.display())));
+        }
+        assert_eq!(runc.start("fake-id").unwrap().bundle, None);
+    }
+
+    #[test]
+    fn test_create_and_start_rollback() {
+        // Fake runc failing to start, and to delete if "fail-delete" exists.
+        let dir = tempfile::tempdir().unwrap();
+        let calls = dir.path().join("calls");
+        let fail_delete = dir.path().join("fail-delete");
+        let script = fake_runc_script(
+            dir.path(),
+            &format!(
+                "echo \"$@\" >> {calls}\n\
+                 case \"$*\" in\n\
+                 *start*) echo \"start failed\" >&2; exit 1;;\n\
+                 *delete*) if [ -e {fail} ]; then echo \"delete failed\" >&2; exit 1; fi;;\n\
+                 esac\nexit 0\n",
+                calls = calls.display(),
+                fail = fail_delete.display()
+            ),
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+
+        let err = runc
+            .create_and_start("fake-id", dir.path(), None)
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::CommandFailed { ref stderr, .. } if stderr == "start failed\n")
+        );
+        // The global options come first.
+        let calls_made = std::fs::read_to_string(&calls).unwrap();
+        let calls_made: Vec<&str> = calls_made.lines().collect();
+        assert_eq!(calls_made.len(), 3);
+        assert!(calls_made[0].contains(" create --bundle "));
+        assert!(calls_made[1].ends_with(" start fake-id"));
+        assert!(calls_made[2].ends_with(" delete --force fake-id"));
+
+        std::fs::write(&fail_delete, "").unwrap();
+        match runc.create_and_start("fake-id", dir.path(), None) {
+            Err(Error::RollbackFailed { error, rollback }) => {
+                assert!(error.to_string().contains("start failed"));
+                assert!(rollback.to_string().contains("delete failed"));
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_cleanup_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let image_path = dir.path().join("checkpoint");
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(failing_runc("checkpoint", CRIU_FAILED_STDERR));
+        let runc = opts.build().expect("unable to create runc instance");
+
+        // Partial images are left by default.
+        std::fs::create_dir(&image_path).unwrap();
+        std::fs::write(image_path.join("inventory.img"), "partial").unwrap();
+        let opts = CheckpointOpts::new().image_path(&image_path);
+        assert!(matches!(
+            runc.checkpoint("fake-id", Some(&opts)),
+            Err(Error::CriuFailed { .. })
+        ));
+        assert!(image_path.join("inventory.img").exists());
+
+        let opts = opts.cleanup_on_failure(true);
+        assert!(matches!(
+            runc.checkpoint("fake-id", Some(&opts)),
+            Err(Error::CriuFailed { .. })
+        ));
+        assert!(!image_path.exists());
+    }
+
+    #[test]
+    fn test_blocking_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let dead = |pid_file: &Path| {
+            let pid = std::fs::read_to_string(pid_file).unwrap();
+            std::fs::read_to_string(format!("/proc/{}/stat", pid.trim()))
+                .map_or(true, |stat| stat.contains(") Z "))
+        };
+        for (name, body) in [
+            // Hanging, with a child holding its stdout.
+            ("hanging", "sleep 30 &\necho $! > {dir}/child\nwait\n"),
+            // Exiting, leaving behind a child holding its stdout.
+            ("exiting", "sleep 30 &\necho $! > {dir}/child\n"),
+        ] {
+            let script = fake_runc_script(
+                dir.path(),
+                &format!(
+                    "echo $$ > {dir}/pid\n{}",
+                    body.replace("{dir}", &dir.path().display().to_string()),
+                    dir = dir.path().display()
+                ),
+            );
+            let runc = GlobalOpts::new()
+                .command(&script)
+                .blocking_timeout(Duration::from_millis(200))
+                .build()
+                .expect("unable to create runc instance");
+
+            let started = Instant::now();
+            assert!(matches!(runc.start("fake-id"), Err(Error::Timeout(_))));
+            assert!(started.elapsed() < Duration::from_secs(2));
+            // runc is reaped, and its child killed along with it.
+            assert!(dead(&dir.path().join("pid")), "{} runc survived", name);
+            let child = dir.path().join("child");
+            let deadline = Instant::now() + Duration::from_secs(1);
+            while !dead(&child) && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            assert!(dead(&child), "child of {} runc survived", name);
+        }
+
+        // Commands completing in time aren't affected.
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .blocking_timeout(Duration::from_secs(5))
+            .build()
+            .expect("unable to create runc instance");
+        runc.start("fake-id").unwrap();
+    }
+
+    #[test]
+    fn test_list_by_annotation() {
+        // Fake runc listing containers of two pods, and one without annotations.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(
+            dir.path(),
+            r#"cat <<EOF
+[
+  {"id": "a", "pid": 10, "status": "running", "bundle": "/b/a", "rootfs": "/b/a/rootfs", "created": "2023-06-01T10:00:00Z", "annotations": {"io.kubernetes.cri.sandbox-id": "pod1"}},
+  {"id": "b", "pid": 11, "status": "running", "bundle": "/b/b", "rootfs": "/b/b/rootfs", "created": "2023-06-01T10:00:00Z", "annotations": {"io.kubernetes.cri.sandbox-id": "pod2"}},
+  {"id": "c", "pid": 12, "status": "created", "bundle": "/b/c", "rootfs": "/b/c/rootfs", "created": "2023-06-01T10:00:00Z"},
+  {"id": "d", "pid": 13, "status": "running", "bundle": "/b/d", "rootfs": "/b/d/rootfs", "created": "2023-06-01T10:00:00Z", "annotations": {"io.kubernetes.cri.sandbox-id": "pod1", "foo": "bar"}}
+]
+EOF
+"#,
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+
+        let ids =
+            |containers: Vec<Container>| containers.into_iter().map(|c| c.id).collect::<Vec<_>>();
+        let pod1 = runc
+            .list_by_annotation("io.kubernetes.cri.sandbox-id", "pod1")
+            .unwrap();
+        assert_eq!(ids(pod1), ["a", "d"]);
+        let none = runc.list_by_annotation("foo", "baz").unwrap();
+        assert!(none.is_empty());
+    }
+
+    #[test]
+    fn test_systemd_cgroup_per_call() {
+        let fake = Arc::new(FakeRunc::new());
+        let subcommands = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = subcommands.clone();
+        let mut opts = GlobalOpts::new()
+            .command("/bin/true")
+            .systemd_cgroup(true)
+            .on_command_complete(move |subcommand, _, _| {
+                recorded.lock().unwrap().push(subcommand.to_string())
+            });
+        opts.custom_spawner(fake.clone());
+        let runc = opts.build().expect("unable to create runc instance");
+
+        let enabled = CreateOpts::new().systemd_cgroup(true);
+        let disabled = CreateOpts::new().systemd_cgroup(false);
+        runc.create("fake-id", "fake-bundle", None).unwrap();
+        runc.create("fake-id", "fake-bundle", Some(&disabled))
+            .unwrap();
+        runc.run("fake-id", "fake-bundle", Some(&disabled)).unwrap();
+        runc.create("fake-id", "fake-bundle", Some(&enabled))
+            .unwrap();
+
+        let systemd_cgroup: Vec<usize> = fake
+            .calls()
+            .iter()
+            .map(|args| args.iter().filter(|a| *a == "--systemd-cgroup").count())
+            .collect();
+        assert_eq!(systemd_cgroup, [1, 0, 0, 1]);
+        assert_eq!(
+            *subcommands.lock().unwrap(),
+            ["create", "create", "run", "create"]
+        );
+
+        // Enabled for a single call only.
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        let fake = Arc::new(FakeRunc::new());
+        opts.custom_spawner(fake.clone());
+        let runc = opts.build().expect("unable to create runc instance");
+        runc.create("fake-id", "fake-bundle", Some(&enabled))
+            .unwrap();
+        runc.create("fake-id", "fake-bundle", None).unwrap();
+        let args = fake.calls();
+        assert!(args[0].contains(&"--systemd-cgroup".to_string()));
+        assert!(!args[1].contains(&"--systemd-cgroup".to_string()));
+    }
+
+    #[test]
+    fn test_rootless_per_call() {
+        let fake = Arc::new(FakeRunc::new());
+        let mut opts = GlobalOpts::new().command("/bin/true").rootless(false);
+        opts.custom_spawner(fake.clone());
+        let runc = opts.build().expect("unable to create runc instance");
+
+        runc.create("fake-id", "fake-bundle", None).unwrap();
+        runc.create(
+            "fake-id",
+            "fake-bundle",
+            Some(&CreateOpts::new().rootless(true)),
+        )
+        .unwrap();
+        runc.run(
+            "fake-id",
+            "fake-bundle",
+            Some(&CreateOpts::new().rootless_auto()),
+        )
+        .unwrap();
+        runc.exec(
+            "fake-id",
+            &dummy_process(),
+            Some(&ExecOpts::new().rootless(true)),
+        )
+        .unwrap();
+        runc.exec("fake-id", &dummy_process(), None).unwrap();
+
+        let rootless: Vec<Vec<String>> = fake
+            .calls()
+            .iter()
+            .map(|args| {
+                args.iter()
+                    .filter(|a| a.starts_with("--rootless"))
+                    .cloned()
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            rootless,
+            [
+                vec!["--rootless=false"],
+                vec!["--rootless=true"],
+                vec![],
+                vec!["--rootless=true"],
+                vec!["--rootless=false"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_clean_orphans() {
+        // Fake runc listing container "a" only.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(
+            dir.path(),
+            r#"echo '[{"id": "a", "pid": 10, "status": "running", "bundle": "/b/a", "rootfs": "/b/a/rootfs", "created": 0}]'
+"#,
+        );
+        let root = dir.path().join("root");
+        for id in ["a", "b", "not-a-container"] {
+            std::fs::create_dir_all(root.join(id)).unwrap();
+        }
+        std::fs::write(root.join("a").join("state.json"), "{}").unwrap();
+        std::fs::write(root.join("b").join("state.json"), "{}").unwrap();
+        std::fs::write(root.join("file"), "").unwrap();
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .root(&root)
+            .build()
+            .expect("unable to create runc instance");
+
+        assert_eq!(runc.find_orphans().unwrap(), [root.join("b")]);
+        assert_eq!(runc.clean_orphans().unwrap(), [root.join("b")]);
+        assert!(!root.join("b").exists());
+        assert!(root.join("a").join("state.json").exists());
+        assert!(root.join("not-a-container").exists());
+        assert!(runc.find_orphans().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_stats_single_sample() {
+        // Fake runc printing a single event with --stats, and streaming them otherwise.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(
+            dir.path(),
+            r#"event='{"type":"stats","id":"fake-id","data":{"cpu":{"usage":42},"memory":{},"pids":{"current":3},"blkio":{},"hugetlb":{"failcnt":0}}}'
+echo 'level=warning msg="cgroup v1 is deprecated"' >&2
+for arg in "$@"; do
+  [ "$arg" = --stats ] && echo "$event" && exit 0
+done
+while true; do echo "$event"; sleep 1; done
+"#,
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .blocking_timeout(Duration::from_secs(5))
+            .build()
+            .expect("unable to create runc instance");
+
+        let started = Instant::now();
+        let stats = runc.stats("fake-id").unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(stats.cpu.usage, Some(42));
+        assert_eq!(stats.pids.current, Some(3));
+    }
+
+    #[test]
+    fn test_delete() {
+        let opts = DeleteOpts::new();
+        let ok_runc = ok_client();
+        ok_runc
+            .delete("fake-id", Some(&opts))
+            .expect("true failed.");
+        eprintln!("ok_runc succeeded.");
+
+        let fail_runc = fail_client();
+        match fail_runc.delete("fake-id", Some(&opts)) {
+            Ok(_) => panic!("fail_runc returned exit status 0."),
+            Err(Error::CommandFailed {
+                status,
+                stdout,
+                stderr,
+            }) => {
+                if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
+                    eprintln!("fail_runc succeeded.");
+                } else {
+                    panic!("unexpected outputs from fail_runc.")
+                }
+            }
+            Err(e) => panic!("unexpected error from fail_runc: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_output() {
+        // test create cmd with inherit Io, expect empty cmd output
+        let mut opts = CreateOpts::new();
+        opts.io = Some(Arc::new(InheritedStdIo::new().unwrap()));
+        let echo_runc = echo_client();
+        let response = echo_runc
+            .create("fake-id", "fake-bundle", Some(&opts))
+            .expect("echo failed.");
+        assert_ne!(response.pid, 0);
+        assert!(response.status.success());
+        assert!(response.output.is_empty());
+
+        // test create cmd with pipe Io, expect nonempty cmd output
+        let mut opts = CreateOpts::new();
+        opts.io = Some(Arc::new(PipedStdIo::new().unwrap()));
+        let echo_runc = echo_client();
+        let response = echo_runc
+            .create("fake-id", "fake-bundle", Some(&opts))
+            .expect("echo failed.");
+        assert_ne!(response.pid, 0);
+        assert!(response.status.success());
+        assert!(!response.output.is_empty());
+    }
+}
+
+/// Tokio tests
+#[cfg(test)]
+#[cfg(all(target_os = "linux", feature = "async"))]
+mod tests {
+    use std::{io::Read, os::unix::io::AsRawFd, sync::Arc};
+
+    use super::{
+        io::{IOOption, InheritedStdIo, PipedStdIo, StdinIo},
+        test_util::{fake_runc_script, FakeResponse, FakeRunc},
+        *,
+    };
+
+    fn ok_client() -> Runc {
+        GlobalOpts::new()
+            .command("/bin/true")
+            .build()
+            .expect("unable to create runc instance")
+    }
+
+    fn fail_client() -> Runc {
+        GlobalOpts::new()
+            .command("/bin/false")
+            .build()
+            .expect("unable to create runc instance")
+    }
+
+    fn echo_client() -> Runc {
+        GlobalOpts::new()
+            .command("/bin/echo")
+            .build()
+            .expect("unable to create runc instance")
+    }
+
+    fn dummy_process() -> Process {
+        serde_json::from_str(
+            "
+            {
+                \"user\": {
+                    \"uid\": 1000,
+                    \"gid\": 1000
+                },
+                \"cwd\": \"/path/to/dir\"
+            }",
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_async_create() {
+        let opts = CreateOpts::new();
+        let ok_runc = ok_client();
+        let ok_task = tokio::spawn(async move {
+            let response = ok_runc
+                .create("fake-id", "fake-bundle", Some(&opts))
+                .await
+                .expect("true failed.");
+            assert_ne!(response.pid, 0);
+            assert!(response.status.success());
+            assert!(response.output.is_empty());
+        });
+
+        let opts = CreateOpts::new();
+        let fail_runc = fail_client();
+        let fail_task = tokio::spawn(async move {
+            match fail_runc
+                .create("fake-id", "fake-bundle", Some(&opts))
+                .await
+            {
+                Ok(_) => panic!("fail_runc returned exit status 0."),
+                Err(Error::CommandFailed {
+                    status,
+                    stdout,
+                    stderr,
+                }) => {
+                    if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
+                        eprintln!("fail_runc succeeded.");
+                    } else {
+                        panic!("unexpected outputs from fail_runc.")
+                    }
+                }
+                Err(e) => panic!("unexpected error from fail_runc: {:?}", e),
+            }
+        });
+
+        ok_task.await.expect("ok_task failed.");
+        fail_task.await.expect("fail_task unexpectedly succeeded.");
+    }
+
+    #[tokio::test]
+    async fn test_async_start() {
+        let ok_runc = ok_client();
+        let ok_task = tokio::spawn(async move {
+            ok_runc.start("fake-id").await.expect("true failed.");
+            eprintln!("ok_runc succeeded.");
+        });
 
-        let opts = CreateOpts::new();
         let fail_runc = fail_client();
         let fail_task = tokio::spawn(async move {
+            match fail_runc.start("fake-id").await {
+                Ok(_) => panic!("fail_runc returned exit status 0."),
+                Err(Error::CommandFailed {
+                    status,
+                    stdout,
+                    stderr,
+                }) => {
+                    if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
+                        eprintln!("fail_runc succeeded.");
+                    } else {
+                        panic!("unexpected outputs from fail_runc.")
+                    }
+                }
+                Err(e) => panic!("unexpected error from fail_runc: {:?}", e),
+            }
+        });
+
+        ok_task.await.expect("ok_task failed.");
+        fail_task.await.expect("fail_task unexpectedly succeeded.");
+    }
+
+    #[tokio::test]
+    async fn test_async_run() {
+        let opts = CreateOpts::new();
+        let ok_runc = ok_client();
+        tokio::spawn(async move {
+            ok_runc
+                .create("fake-id", "fake-bundle", Some(&opts))
+                .await
+                .expect("true failed.");
+            eprintln!("ok_runc succeeded.");
+        });
+
+        let opts = CreateOpts::new();
+        let fail_runc = fail_client();
+        tokio::spawn(async move {
             match fail_runc
                 .create("fake-id", "fake-bundle", Some(&opts))
                 .await
-            {
-                Ok(_) => panic!("fail_runc returned exit status 0."),
-                Err(Error::CommandFailed {
-                    status,
-                    stdout,
-                    stderr,
-                }) => {
-                    if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
-                        eprintln!("fail_runc succeeded.");
-                    } else {
-                        panic!("unexpected outputs from fail_runc.")
-                    }
+            {
+                Ok(_) => panic!("fail_runc returned exit status 0."),
+                Err(Error::CommandFailed {
+                    status,
+                    stdout,
+                    stderr,
+                }) => {
+                    if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
+                        eprintln!("fail_runc succeeded.");
+                    } else {
+                        panic!("unexpected outputs from fail_runc.")
+                    }
+                }
+                Err(e) => panic!("unexpected error from fail_runc: {:?}", e),
+            }
+        })
+        .await
+        .expect("tokio spawn falied.");
+    }
+
+    #[tokio::test]
+    async fn test_async_delete() {
+        let opts = DeleteOpts::new();
+        let ok_runc = ok_client();
+        tokio::spawn(async move {
+            ok_runc
+                .delete("fake-id", Some(&opts))
+                .await
+                .expect("true failed.");
+            eprintln!("ok_runc succeeded.");
+        });
+
+        let opts = DeleteOpts::new();
+        let fail_runc = fail_client();
+        tokio::spawn(async move {
+            match fail_runc.delete("fake-id", Some(&opts)).await {
+                Ok(_) => panic!("fail_runc returned exit status 0."),
+                Err(Error::CommandFailed {
+                    status,
+                    stdout,
+                    stderr,
+                }) => {
+                    if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
+                        eprintln!("fail_runc succeeded.");
+                    } else {
+                        panic!("unexpected outputs from fail_runc.")
+                    }
+                }
+                Err(e) => panic!("unexpected error from fail_runc: {:?}", e),
+            }
+        })
+        .await
+        .expect("tokio spawn falied.");
+    }
+
+    #[tokio::test]
+    async fn test_async_timeout_override() {
+        let fake = Arc::new(FakeRunc::new());
+        fake.respond(
+            "create",
+            FakeResponse::default().delay(Duration::from_millis(500)),
+        );
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.timeout(100).custom_spawner(fake);
+        let slow_runc = opts.build().expect("unable to create runc instance");
+
+        match slow_runc.create("fake-id", "fake-bundle", None).await {
+            Err(Error::CommandTimeout(_)) => {}
+            Ok(_) => panic!("slow_runc should time out with the global timeout."),
+            Err(e) => panic!("unexpected error from slow_runc: {:?}", e),
+        }
+
+        let opts = CreateOpts::new().timeout(Duration::from_secs(10));
+        let response = slow_runc
+            .create("fake-id", "fake-bundle", Some(&opts))
+            .await
+            .expect("slow_runc should succeed with a generous timeout.");
+        assert!(response.status.success());
+    }
+
+    #[tokio::test]
+    async fn test_async_timeout_kills_command() {
+        // Fake runc recording its pid and hanging.
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        let script = fake_runc_script(
+            dir.path(),
+            &format!("echo $$ > {}\nexec sleep 30\n", pid_file.display()),
+        );
+
+        let mut opts = GlobalOpts::new().command(&script);
+        opts.timeout(200);
+        let runc = opts.build().expect("unable to create runc instance");
+        match runc.create("fake-id", "fake-bundle", None).await {
+            Err(Error::CommandTimeout(_)) => {}
+            Ok(_) => panic!("the hanging runc should time out."),
+            Err(e) => panic!("unexpected error from the hanging runc: {:?}", e),
+        }
+
+        let pid = std::fs::read_to_string(&pid_file).expect("runc didn't record its pid");
+        let stat = format!("/proc/{}/stat", pid.trim());
+        for _ in 0..100 {
+            // Killed, and either reaped or waiting to be.
+            match std::fs::read_to_string(&stat) {
+                Err(_) => return,
+                Ok(stat)
+                    if stat
+                        .rsplit(')')
+                        .next()
+                        .unwrap()
+                        .trim_start()
+                        .starts_with('Z') =>
+                {
+                    return
+                }
+                Ok(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+        }
+        panic!("the runc command outlived its timeout");
+    }
+
+    const KILL_STOPPED_STDERR: &str =
+        "time=\"2024-01-01T00:00:00Z\" level=error msg=\"container not running\"\n";
+    const EXEC_STOPPED_STDERR: &str = "time=\"2024-01-01T00:00:00Z\" level=error msg=\"exec failed: cannot exec in a stopped container\"\n";
+
+    const CRIU_FAILED_STDERR: &str = "time=\"2024-01-01T00:00:00Z\" level=error msg=\"criu failed: type RESTORE errno 0\\nlog file: /run/runc/fake-id/criu.work/restore.log\"\n";
+
+    /// [FakeRunc] answering `state` with the running container "fake-id" of `pid` and `bundle`.
+    fn running_runc(pid: u32, bundle: &Path) -> Arc<FakeRunc> {
+        let fake = Arc::new(FakeRunc::new());
+        fake.respond(
+            "state",
+            FakeResponse::success(format!(
+                r#"{{"id": "fake-id", "pid": {}, "status": "running", "bundle": "{}", "rootfs": "", "created": 0, "annotations": {{}}}}"#,
+                pid,
+                bundle.display()
+            )),
+        );
+        fake
+    }
+
+    /// [FakeRunc] failing `subcommand` with `stderr`, like a failed runc invocation.
+    fn failing_runc(subcommand: &str, stderr: &str) -> Arc<FakeRunc> {
+        let fake = Arc::new(FakeRunc::new());
+        fake.respond(subcommand, FakeResponse::failure(1, stderr));
+        fake
+    }
+
+    /// Save the process passed to each `exec` answered by `fake`, to be taken from the returned
+    /// slot.
+    fn record_exec(fake: &FakeRunc) -> Arc<std::sync::Mutex<Option<Process>>> {
+        let process = Arc::new(std::sync::Mutex::new(None));
+        let recorded = process.clone();
+        fake.respond_with("exec", move |args| {
+            if let Some(i) = args.iter().position(|a| a == "--process") {
+                let data = std::fs::read(&args[i + 1]).unwrap();
+                *recorded.lock().unwrap() = Some(serde_json::from_slice(&data).unwrap());
+            }
+            FakeResponse::default()
+        });
+        process
+    }
+
+    #[tokio::test]
+    async fn test_async_exec_not_running() {
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(failing_runc("exec", EXEC_STOPPED_STDERR));
+        let runc = opts.build().expect("unable to create runc instance");
+        match runc.exec("fake-id", &dummy_process(), None).await {
+            Err(Error::ContainerNotRunning { stderr }) => assert_eq!(stderr, EXEC_STOPPED_STDERR),
+            other => panic!("unexpected result from exec: {:?}", other),
+        }
+
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(failing_runc("exec", "exec failed: permission denied"));
+        let runc = opts.build().expect("unable to create runc instance");
+        assert!(matches!(
+            runc.exec("fake-id", &dummy_process(), None).await,
+            Err(Error::CommandFailed { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_async_exec_command() {
+        let bundle = tempfile::tempdir().unwrap();
+        std::fs::write(
+            spec::config_path(bundle.path()),
+            r#"{"ociVersion": "1.0.2", "process": {"user": {"uid": 1000, "gid": 1000}, "cwd": "/srv", "args": ["sleep", "inf"]}}"#,
+        )
+        .unwrap();
+        let fake = running_runc(1000, bundle.path());
+        let exec_process = record_exec(&fake);
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(fake.clone());
+        let runc = opts.build().expect("unable to create runc instance");
+
+        let argv = vec!["ls".to_string(), "/".to_string()];
+        let env = vec![("FOO".to_string(), "bar".to_string())];
+        runc.exec_command("fake-id", &argv, &env, None)
+            .await
+            .expect("exec_command failed");
+
+        let process = exec_process.lock().unwrap().take().unwrap();
+        assert_eq!(process.args().as_ref().unwrap(), &argv);
+        assert_eq!(process.cwd(), Path::new("/srv"));
+        assert_eq!(process.user().uid(), 1000);
+        assert!(process
+            .env()
+            .as_ref()
+            .unwrap()
+            .contains(&"FOO=bar".to_string()));
+
+        // Without a process to inherit the user from, nothing is run as root.
+        std::fs::write(
+            spec::config_path(bundle.path()),
+            r#"{"ociVersion": "1.0.2"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            runc.exec_command("fake-id", &argv, &env, None).await,
+            Err(Error::BundleWithoutProcess(_))
+        ));
+        std::fs::remove_file(spec::config_path(bundle.path())).unwrap();
+        assert!(matches!(
+            runc.exec_command("fake-id", &argv, &env, None).await,
+            Err(Error::SpecProcessingFailed(_))
+        ));
+        assert!(exec_process.lock().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_async_working_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(dir.path(), "pwd\n");
+        let work = dir.path().join("work");
+        std::fs::create_dir(&work).unwrap();
+
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .working_dir(&work)
+            .build()
+            .expect("unable to create runc instance");
+        let response = runc
+            .create("fake-id", "fake-bundle", None)
+            .await
+            .expect("pwd failed.");
+        assert_eq!(
+            Path::new(response.output.trim()).canonicalize().unwrap(),
+            work.canonicalize().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_async_container_spec() {
+        let bundle = tempfile::tempdir().unwrap();
+        std::fs::write(
+            spec::config_path(bundle.path()),
+            r#"{"ociVersion": "1.0.2", "hostname": "fake-host"}"#,
+        )
+        .unwrap();
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(running_runc(1000, bundle.path()));
+        let runc = opts.build().expect("unable to create runc instance");
+
+        let spec = runc
+            .container_spec("fake-id")
+            .await
+            .expect("failed to read spec");
+        assert_eq!(spec.hostname().as_deref(), Some("fake-host"));
+
+        bundle.close().unwrap();
+        assert!(matches!(
+            runc.container_spec("fake-id").await,
+            Err(Error::SpecFileNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_async_create_stdin() {
+        use tokio::io::AsyncWriteExt;
+
+        // Fake runc leaving a process reading its stdin in the background, like a container.
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("output");
+        let script = fake_runc_script(
+            dir.path(),
+            &format!("exec 3<&0\ncat <&3 > {} 2>&1 &\n", output.display()),
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+
+        let (reader, writer) = os_pipe::pipe().unwrap();
+        let uid = nix::unistd::getuid().as_raw();
+        let gid = nix::unistd::getgid().as_raw();
+        let opts = IOOption {
+            open_stdin: false,
+            open_stdout: false,
+            open_stderr: false,
+        };
+        let io = StdinIo::new(uid, gid, &opts, reader).unwrap();
+        let opts = CreateOpts::new().io(Arc::new(io));
+        runc.create("fake-id", "fake-bundle", Some(&opts))
+            .await
+            .expect("create failed.");
+
+        // The container still reads its stdin after create returned, until it's closed.
+        let mut writer = tokio::net::unix::pipe::Sender::from_owned_fd(writer.into()).unwrap();
+        writer.write_all(b"hello\n").await.unwrap();
+        drop(writer);
+        for _ in 0..100 {
+            if tokio::fs::read_to_string(&output).await.unwrap_or_default() == "hello\n" {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        panic!("the container didn't read its stdin");
+    }
+
+    #[tokio::test]
+    async fn test_async_combine_output() {
+        // Fake runc printing the state of the container, and a warning on stderr.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(dir.path(), "echo '{\"id\": \"fake-id\", \"pid\": 42, \"status\": \"running\", \"bundle\": \"/bundle\", \"rootfs\": \"/bundle/rootfs\", \"created\": \"2023-01-01T00:00:00Z\"}'\necho 'level=warning msg=\"cgroup is not accessible\"' >&2\n");
+
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+        assert!(matches!(
+            runc.state("fake-id").await,
+            Err(Error::JsonDeserializationFailed(_))
+        ));
+
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .combine_output(false)
+            .build()
+            .expect("unable to create runc instance");
+        let container = runc.state("fake-id").await.expect("state failed.");
+        assert_eq!(container.id, "fake-id");
+        assert_eq!(container.pid, 42);
+    }
+
+    #[tokio::test]
+    async fn test_async_pid_file() {
+        // Fake runc writing a well-known pid to the pid file, like runc does with the pid of
+        // the container's process.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(dir.path(), "while [ $# -gt 0 ]; do\n  [ \"$1\" = --pid-file ] && echo 424242 > \"$2\"\n  shift\ndone\n");
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+
+        let opts = CreateOpts::new().pid_file(dir.path().join("init.pid"));
+        let response = runc
+            .create("fake-id", "fake-bundle", Some(&opts))
+            .await
+            .expect("create failed.");
+        assert_eq!(response.pid, 424242);
+
+        let response = runc
+            .create("fake-id", "fake-bundle", None)
+            .await
+            .expect("create failed.");
+        assert_ne!(response.pid, 424242);
+
+        let opts = ExecOpts::new().pid_file(dir.path().join("exec.pid"));
+        let response = runc
+            .exec_with_response("fake-id", &dummy_process(), Some(&opts))
+            .await
+            .expect("exec failed.");
+        assert_eq!(response.pid, 424242);
+    }
+
+    #[tokio::test]
+    async fn test_async_pids() {
+        // Stand-in for the container's processes, in the same cgroup as the test.
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(running_runc(child.id(), Path::new("")));
+        let runc = opts.build().expect("unable to create runc instance");
+
+        let pids = runc.pids("fake-id").await.expect("failed to list pids");
+        let _ = child.kill();
+        let _ = child.wait();
+        assert!(pids.contains(&(child.id() as usize)));
+        assert!(pids.contains(&(std::process::id() as usize)));
+    }
+
+    #[tokio::test]
+    async fn test_async_on_command_complete() {
+        let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorder = |command: &str| {
+            let calls = calls.clone();
+            GlobalOpts::new()
+                .command(command)
+                .on_command_complete(move |subcommand, _, success| {
+                    calls
+                        .lock()
+                        .unwrap()
+                        .push((subcommand.to_string(), success))
+                })
+                .build()
+                .expect("unable to create runc instance")
+        };
+
+        recorder("/bin/true")
+            .start("fake-id")
+            .await
+            .expect("true failed.");
+        assert!(recorder("/bin/false").start("fake-id").await.is_err());
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![("start".to_string(), true), ("start".to_string(), false)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_async_validate_ids() {
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .build()
+            .expect("unable to create runc instance");
+        for id in ["", "a/b", "../fake-id"] {
+            assert!(matches!(
+                runc.start(id).await,
+                Err(Error::InvalidContainerId { .. })
+            ));
+            assert!(matches!(
+                runc.kill(id, 9, None).await,
+                Err(Error::InvalidContainerId { .. })
+            ));
+        }
+        runc.start("fake-id").await.expect("true failed.");
+
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .validate_ids(false)
+            .build()
+            .expect("unable to create runc instance");
+        runc.start("a/b").await.expect("true failed.");
+    }
+
+    #[tokio::test]
+    async fn test_async_latency_snapshot() {
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .record_latency(true)
+            .build()
+            .expect("unable to create runc instance");
+        for _ in 0..3 {
+            runc.start("fake-id").await.expect("true failed.");
+        }
+        runc.clone().pause("fake-id").await.expect("true failed.");
+
+        let snapshot = runc.latency_snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot["start"].count, 3);
+        assert_eq!(
+            snapshot["start"].buckets.iter().map(|b| b.1).sum::<u64>(),
+            3
+        );
+        assert!(snapshot["start"].max > Duration::ZERO);
+        assert_eq!(snapshot["pause"].count, 1);
+
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .build()
+            .expect("unable to create runc instance");
+        runc.start("fake-id").await.expect("true failed.");
+        assert!(runc.latency_snapshot().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_async_create_idempotent() {
+        let bundle = tempfile::tempdir().unwrap();
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        let fake = running_runc(1000, bundle.path());
+        fake.respond(
+            "create",
+            FakeResponse::failure(1, "container with id exists: fake-id"),
+        );
+        opts.custom_spawner(fake);
+        let runc = opts.build().expect("unable to create runc instance");
+
+        let response = runc
+            .create_idempotent("fake-id", bundle.path(), None)
+            .await
+            .expect("create of the same container failed");
+        assert_eq!(response.pid, 1000);
+
+        let other = tempfile::tempdir().unwrap();
+        match runc.create_idempotent("fake-id", other.path(), None).await {
+            Err(Error::CommandFailed { stderr, .. }) => {
+                assert_eq!(stderr, "container with id exists: fake-id")
+            }
+            other => panic!("unexpected result from create: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_restore() {
+        // Fake runc writing a well-known pid to the pid file, like runc does with the pid of
+        // the restored process.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(dir.path(), "while [ $# -gt 0 ]; do\n  [ \"$1\" = --pid-file ] && echo 424242 > \"$2\"\n  shift\ndone\n");
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+
+        let opts = RestoreOpts::new()
+            .image_path(dir.path().join("checkpoint"))
+            .pid_file(dir.path().join("restore.pid"))
+            .detach(true);
+        let response = runc
+            .restore("fake-id", "fake-bundle", Some(&opts))
+            .await
+            .expect("restore failed.");
+        assert_eq!(response.pid, 424242);
+
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(failing_runc("restore", CRIU_FAILED_STDERR));
+        let runc = opts.build().expect("unable to create runc instance");
+        match runc.restore("fake-id", "fake-bundle", None).await {
+            Err(Error::CriuFailed { log_path, .. }) => assert_eq!(
+                log_path.as_deref(),
+                Some(Path::new("/run/runc/fake-id/criu.work/restore.log"))
+            ),
+            other => panic!("unexpected result from restore: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_exec_caps() {
+        use oci_spec::runtime::Capability;
+
+        let fake = running_runc(1000, Path::new(""));
+        let exec_process = record_exec(&fake);
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(fake.clone());
+        let runc = opts.build().expect("unable to create runc instance");
+
+        let opts = ExecOpts::new()
+            .caps(vec![Capability::SysPtrace])
+            .process_label("system_u:system_r:container_t:s0");
+        runc.exec("fake-id", &dummy_process(), Some(&opts))
+            .await
+            .expect("exec failed");
+
+        let process = exec_process.lock().unwrap().take().unwrap();
+        let caps = process.capabilities().as_ref().unwrap();
+        for set in [caps.bounding(), caps.effective(), caps.permitted()] {
+            assert!(set.as_ref().unwrap().contains(&Capability::SysPtrace));
+        }
+        assert!(!caps
+            .ambient()
+            .as_ref()
+            .map_or(false, |s| s.contains(&Capability::SysPtrace)));
+        assert_eq!(
+            process.selinux_label().as_deref(),
+            Some("system_u:system_r:container_t:s0")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_async_exec_rlimits() {
+        use oci_spec::runtime::{PosixRlimitBuilder, PosixRlimitType};
+
+        let fake = running_runc(1000, Path::new(""));
+        let exec_process = record_exec(&fake);
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(fake.clone());
+        let runc = opts.build().expect("unable to create runc instance");
+        let rlimit = |typ, soft: u64, hard: u64| {
+            PosixRlimitBuilder::default()
+                .typ(typ)
+                .soft(soft)
+                .hard(hard)
+                .build()
+                .unwrap()
+        };
+        let mut process = dummy_process();
+        process.set_rlimits(Some(vec![
+            rlimit(PosixRlimitType::RlimitNofile, 1024, 1024),
+            rlimit(PosixRlimitType::RlimitCore, 0, 0),
+        ]));
+
+        let opts =
+            ExecOpts::new().rlimits(vec![rlimit(PosixRlimitType::RlimitNofile, 65536, 65536)]);
+        runc.exec("fake-id", &process, Some(&opts))
+            .await
+            .expect("exec failed");
+        let written = exec_process.lock().unwrap().take().unwrap();
+        assert_eq!(
+            written.rlimits().as_deref(),
+            Some(
+                &[
+                    rlimit(PosixRlimitType::RlimitCore, 0, 0),
+                    rlimit(PosixRlimitType::RlimitNofile, 65536, 65536),
+                ][..]
+            )
+        );
+
+        let opts = ExecOpts::new().rlimits(vec![rlimit(PosixRlimitType::RlimitNofile, 4096, 1024)]);
+        match runc.exec("fake-id", &process, Some(&opts)).await {
+            Err(Error::InvalidRlimit { typ, soft, hard }) => {
+                assert_eq!((typ.as_str(), soft, hard), ("RLIMIT_NOFILE", 4096, 1024))
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(exec_process.lock().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_async_exec_process_via_stdin() {
+        // Fake runc saving the path and the content of its process spec.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(
+            dir.path(),
+            r#"dir="$(dirname "$0")"
+while [ $# -gt 0 ]; do
+  if [ "$1" = --process ]; then echo "$2" > "$dir/path"; cat "$2" > "$dir/spec"; fi
+  shift
+done
+"#,
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+        // A launcher may not pass stdin on.
+        let launched = GlobalOpts::new()
+            .command(&script)
+            .launcher(Launcher::new("/usr/bin/env"))
+            .build()
+            .expect("unable to create runc instance");
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+
+        let opts = ExecOpts::new().process_via_stdin(true);
+        for (runc, opts, via_stdin) in [
+            (&runc, opts.clone(), true),
+            // An io may provide stdin to the process, the spec falls back to a temp file.
+            (
+                &runc,
+                opts.clone().io(Arc::new(crate::io::NullIo::new().unwrap())),
+                false,
+            ),
+            (&launched, opts, false),
+        ] {
+            runc.exec("fake-id", &dummy_process(), Some(&opts))
+                .await
+                .expect("exec failed");
+            let path = read("path");
+            let spec: Process = serde_json::from_str(&read("spec")).unwrap();
+            assert_eq!(spec, dummy_process());
+            if via_stdin {
+                assert_eq!(path.trim(), "/dev/stdin");
+            } else {
+                assert_ne!(path.trim(), "/dev/stdin");
+                assert!(!Path::new(path.trim()).exists());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_launcher() {
+        let subcommands = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = subcommands.clone();
+        // echo as a launcher prints the command line it would run.
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .launcher(Launcher::new("/bin/echo").arg("--scope"))
+            .on_command_complete(move |subcommand, _, _| {
+                recorded.lock().unwrap().push(subcommand.to_string())
+            })
+            .build()
+            .expect("unable to create runc instance");
+
+        let response = runc
+            .create("fake-id", "fake-bundle", None)
+            .await
+            .expect("echo failed.");
+        assert_eq!(
+            response.output.trim(),
+            format!(
+                "--scope {} --log-format text create --bundle {} fake-id",
+                runc.command.display(),
+                utils::abs_string("fake-bundle").unwrap()
+            )
+        );
+        assert_eq!(*subcommands.lock().unwrap(), vec!["create".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_async_min_root_free() {
+        let root = tempfile::tempdir().unwrap();
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .root(root.path())
+            .min_root_free(u64::MAX)
+            .build()
+            .expect("unable to create runc instance");
+        assert!(matches!(
+            runc.create("fake-id", "fake-bundle", None).await,
+            Err(Error::StateDirFull { .. })
+        ));
+        assert!(matches!(
+            runc.restore("fake-id", "fake-bundle", None).await,
+            Err(Error::StateDirFull { .. })
+        ));
+
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .root(root.path())
+            .min_root_free(1)
+            .build()
+            .expect("unable to create runc instance");
+        runc.create("fake-id", "fake-bundle", None)
+            .await
+            .expect("true failed.");
+    }
+
+    #[tokio::test]
+    async fn test_async_lock_per_container() {
+        let max_running = |lock: bool, ids: [&'static str; 2]| async move {
+            let slow = Arc::new(FakeRunc::new());
+            let delay = FakeResponse::default().delay(Duration::from_millis(50));
+            slow.respond("kill", delay.clone()).respond("delete", delay);
+            let mut opts = GlobalOpts::new()
+                .command("/bin/true")
+                .lock_per_container(lock);
+            opts.custom_spawner(slow.clone());
+            let runc = opts.build().expect("unable to create runc instance");
+            let (kill, delete) =
+                tokio::join!(runc.kill(ids[0], 9, None), runc.delete(ids[1], None));
+            kill.expect("kill failed");
+            delete.expect("delete failed");
+            slow.max_running()
+        };
+
+        assert_eq!(max_running(true, ["a", "a"]).await, 1);
+        assert_eq!(max_running(true, ["a", "b"]).await, 2);
+        assert_eq!(max_running(false, ["a", "a"]).await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_async_oom_kills() {
+        // The test process stands in for the container's init process.
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(running_runc(std::process::id(), Path::new("")));
+        let runc = opts.build().expect("unable to create runc instance");
+
+        let first = runc
+            .oom_kills("fake-id", None)
+            .await
+            .expect("failed to read OOM kills");
+        assert!(!first.increased);
+        let second = runc
+            .oom_kills("fake-id", Some(first.count))
+            .await
+            .expect("failed to read OOM kills");
+        assert!(second.count >= first.count);
+        assert_eq!(second.increased, second.count > first.count);
+    }
+
+    #[tokio::test]
+    async fn test_async_init_pid() {
+        // The test process stands in for the init process of a running container.
+        let stat = std::fs::read_to_string("/proc/self/stat").unwrap();
+        let start: u64 = stat[stat.rfind(')').unwrap() + 2..]
+            .split(' ')
+            .nth(19)
+            .unwrap()
+            .parse()
+            .unwrap();
+        let mut exited = std::process::Command::new("/bin/true").spawn().unwrap();
+        exited.wait().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let write_state = |id: &str, pid: u32, start: u64| {
+            std::fs::create_dir(root.path().join(id)).unwrap();
+            std::fs::write(
+                root.path().join(id).join("state.json"),
+                serde_json::json!({
+                    "id": id,
+                    "init_process_pid": pid,
+                    "init_process_start": start,
+                    "config": {"rootfs": "/bundle/rootfs"},
+                })
+                .to_string(),
+            )
+            .unwrap();
+        };
+        write_state("running", std::process::id(), start);
+        write_state("created", std::process::id(), start);
+        std::fs::write(root.path().join("created/exec.fifo"), "").unwrap();
+        write_state("stopped", exited.id(), 0);
+        write_state("reused", std::process::id(), start + 1);
+        let runc = GlobalOpts::new()
+            .command("/bin/false")
+            .root(root.path())
+            .build()
+            .expect("unable to create runc instance");
+
+        assert_eq!(
+            runc.init_pid("running").await.unwrap(),
+            Some(std::process::id() as i32)
+        );
+        assert_eq!(runc.init_pid("created").await.unwrap(), None);
+        assert_eq!(runc.init_pid("stopped").await.unwrap(), None);
+        assert_eq!(runc.init_pid("reused").await.unwrap(), None);
+        assert!(runc.init_pid("other-id").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_async_memory() {
+        // The test process stands in for the container's init process.
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("fake-id")).unwrap();
+        std::fs::write(
+            root.path().join("fake-id/state.json"),
+            format!(r#"{{"init_process_pid": {}}}"#, std::process::id()),
+        )
+        .unwrap();
+        let mut exited = std::process::Command::new("/bin/true").spawn().unwrap();
+        exited.wait().unwrap();
+        std::fs::create_dir(root.path().join("stopped")).unwrap();
+        std::fs::write(
+            root.path().join("stopped/state.json"),
+            format!(r#"{{"init_process_pid": {}}}"#, exited.id()),
+        )
+        .unwrap();
+
+        // Fake runc reporting the stats of the same cgroup.
+        let cgroup = std::fs::read_to_string("/proc/self/cgroup").unwrap();
+        let (usage, limit) = utils::memory_stat_paths(&cgroup).unwrap();
+        let script = fake_runc_script(
+            root.path(),
+            &format!(
+                r#"usage=$(cat {})
+limit=$(cat {})
+[ "$limit" = max ] && limit=18446744073709551615
+echo '{{"type":"stats","id":"fake-id","data":{{"cpu":{{"usage":0}},"memory":{{"usage":{{"limit":'$limit',"usage":'$usage',"failcnt":0}}}},"pids":{{}},"blkio":{{}},"hugetlb":{{"failcnt":0}}}}}}'
+"#,
+                usage.display(),
+                limit.display()
+            ),
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .root(root.path())
+            .build()
+            .expect("unable to create runc instance");
+
+        let memory = runc.memory("fake-id").await.expect("failed to read memory");
+        let stats = runc.stats("fake-id").await.expect("failed to read stats");
+        let usage = stats.memory.usage.as_ref().and_then(|e| e.usage).unwrap();
+        // The usage moves, but not by more than a few MiB between the reads.
+        assert!(memory.usage > 0);
+        assert!(memory.usage.abs_diff(usage) < 64 << 20);
+        assert_eq!(memory.limit, stats.limits().memory);
+        assert!(matches!(
+            runc.memory("stopped").await,
+            Err(Error::ContainerNotRunning { .. })
+        ));
+        assert!(runc.memory("other-id").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_async_cgroup_path() {
+        // The test process stands in for the container's init process.
+        let cgroup = std::fs::read_to_string("/proc/self/cgroup").unwrap();
+        let own = utils::cgroup_procs_path(&cgroup).unwrap();
+        let own = own.parent().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("fake-id")).unwrap();
+        std::fs::write(
+            root.path().join("fake-id/state.json"),
+            serde_json::json!({
+                "cgroup_paths": {"pids": own, "memory": own, "": own},
+                "config": {"cgroups": {"path": "/elsewhere"}},
+            })
+            .to_string(),
+        )
+        .unwrap();
+        std::fs::create_dir(root.path().join("derived-id")).unwrap();
+        std::fs::write(
+            root.path().join("derived-id/state.json"),
+            r#"{"config": {"cgroups": {"name": "derived-id", "parent": "runc"}}}"#,
+        )
+        .unwrap();
+        let runc = GlobalOpts::new()
+            .command("/bin/false")
+            .root(root.path())
+            .build()
+            .expect("unable to create runc instance");
+
+        let path = runc
+            .cgroup_path("fake-id")
+            .await
+            .expect("failed to get cgroup path");
+        assert_eq!(path, own);
+        assert!(path.exists());
+        let derived = runc.cgroup_path("derived-id").await.unwrap();
+        assert!(derived.starts_with("/sys/fs/cgroup"));
+        assert!(derived.ends_with("runc/derived-id"));
+        assert!(runc.cgroup_path("other-id").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_async_export_bundle() {
+        let bundle = tempfile::tempdir().unwrap();
+        std::fs::write(
+            spec::config_path(bundle.path()),
+            r#"{"ociVersion": "1.0.2", "root": {"path": "rootfs"}}"#,
+        )
+        .unwrap();
+        std::fs::write(bundle.path().join("annotations.json"), "{}").unwrap();
+        std::fs::create_dir_all(bundle.path().join("rootfs/bin")).unwrap();
+        std::fs::write(bundle.path().join("rootfs/bin/sh"), "").unwrap();
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(running_runc(1000, bundle.path()));
+        let runc = opts.build().expect("unable to create runc instance");
+
+        let entries = |archive: Vec<u8>| {
+            let mut names: Vec<String> = tar::Archive::new(archive.as_slice())
+                .entries()
+                .unwrap()
+                .map(|e| {
+                    let path = e.unwrap().path().unwrap().display().to_string();
+                    path.trim_end_matches('/').to_string()
+                })
+                .collect();
+            names.sort();
+            names
+        };
+        let archive = runc
+            .export_bundle("fake-id", Vec::new(), false)
+            .await
+            .expect("export failed");
+        assert_eq!(entries(archive), vec!["annotations.json", "config.json"]);
+        let archive = runc
+            .export_bundle("fake-id", Vec::new(), true)
+            .await
+            .expect("export failed");
+        assert_eq!(
+            entries(archive),
+            vec![
+                "annotations.json",
+                "config.json",
+                "rootfs",
+                "rootfs/bin",
+                "rootfs/bin/sh"
+            ]
+        );
+
+        bundle.close().unwrap();
+        assert!(matches!(
+            runc.export_bundle("fake-id", Vec::new(), false).await,
+            Err(Error::SpecFileNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_async_delete_and_cleanup() {
+        let root = tempfile::tempdir().unwrap();
+        let bundle = root.path().join("fake-id");
+        std::fs::create_dir(&bundle).unwrap();
+        std::fs::write(spec::config_path(&bundle), "{}").unwrap();
+        let runc = |bundle_root: &Path| {
+            let mut opts = GlobalOpts::new()
+                .command("/bin/true")
+                .bundle_root(bundle_root);
+            opts.custom_spawner(running_runc(1000, &bundle));
+            opts.build().expect("unable to create runc instance")
+        };
+
+        let elsewhere = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            runc(elsewhere.path())
+                .delete_and_cleanup("fake-id", None)
+                .await,
+            Err(Error::BundleOutsideRoot(_))
+        ));
+        assert!(bundle.exists());
+
+        runc(root.path())
+            .delete_and_cleanup("fake-id", None)
+            .await
+            .expect("delete_and_cleanup failed");
+        assert!(!bundle.exists());
+    }
+
+    #[tokio::test]
+    async fn test_async_log_fd() {
+        let log = tempfile::tempfile().unwrap();
+        // Fake runc writing to the path following --log, which only works if the fd is
+        // inherited.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(
+            dir.path(),
+            "while [ \"$1\" != --log ]; do shift; done\necho logged > \"$2\"\n",
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .log_fd(log.as_raw_fd())
+            .build()
+            .expect("unable to create runc instance");
+
+        runc.pause("fake-id").await.expect("pause failed");
+        let mut logged = String::new();
+        (&log).read_to_string(&mut logged).unwrap();
+        assert_eq!(logged, "logged\n");
+    }
+
+    #[tokio::test]
+    async fn test_async_kill_stopped() {
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(failing_runc("kill", KILL_STOPPED_STDERR));
+        let runc = opts.build().expect("unable to create runc instance");
+        runc.kill("fake-id", 9, None)
+            .await
+            .expect("kill of a stopped container failed");
+
+        let strict = KillOpts::new().strict(true);
+        match runc.kill("fake-id", 9, Some(&strict)).await {
+            Err(Error::CommandFailed { stderr, .. }) => assert_eq!(stderr, KILL_STOPPED_STDERR),
+            other => panic!("unexpected result from kill: {:?}", other),
+        }
+
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(failing_runc("kill", EXEC_STOPPED_STDERR));
+        let runc = opts.build().expect("unable to create runc instance");
+        assert!(runc.kill("fake-id", 9, None).await.is_err());
+    }
+
+    /// Messages of the log records carrying the `correlation_id` key-value `id`.
+    fn correlated_logs(id: &str) -> Vec<String> {
+        struct Capture;
+        static RECORDS: std::sync::Mutex<Vec<(String, String)>> = std::sync::Mutex::new(Vec::new());
+
+        impl log::Log for Capture {
+            fn enabled(&self, _: &log::Metadata) -> bool {
+                true
+            }
+
+            fn log(&self, record: &log::Record) {
+                if let Some(id) = record.key_values().get("correlation_id".into()) {
+                    let entry = (id.to_string(), record.args().to_string());
+                    RECORDS.lock().unwrap().push(entry);
                 }
-                Err(e) => panic!("unexpected error from fail_runc: {:?}", e),
             }
+
+            fn flush(&self) {}
+        }
+
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&Capture).unwrap();
+            log::set_max_level(log::LevelFilter::Debug);
         });
+        RECORDS
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(i, _)| i == id)
+            .map(|(_, message)| message.clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_async_correlation_id() {
+        // Install the logger first.
+        assert!(correlated_logs("request-42").is_empty());
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .correlation_id("request-42")
+            .build()
+            .expect("unable to create runc instance");
+        runc.pause("fake-id").await.expect("true failed");
+        runc.resume("fake-id").await.expect("true failed");
+        let logs = correlated_logs("request-42");
+        assert_eq!(logs.len(), 2);
+        assert!(logs[0].starts_with("runc pause succeeded after"));
+        assert!(logs[1].starts_with("runc resume succeeded after"));
+    }
+
+    /// Subscriber recording the subcommand and correlation id of the `runc` spans.
+    #[cfg(feature = "tracing")]
+    #[derive(Clone, Default)]
+    struct SpanCapture(Arc<std::sync::Mutex<Vec<(String, String)>>>);
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for SpanCapture {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            #[derive(Default)]
+            struct Fields(std::collections::HashMap<&'static str, String>);
+
+            impl tracing::field::Visit for Fields {
+                fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn Debug) {
+                    self.0.insert(field.name(), format!("{:?}", value));
+                }
+
+                fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+                    self.0.insert(field.name(), value.to_string());
+                }
+            }
+
+            let mut fields = Fields::default();
+            span.record(&mut fields);
+            let mut spans = self.0.lock().unwrap();
+            if span.metadata().name() == "runc" {
+                let field = |name| fields.0.get(name).cloned().unwrap_or_default();
+                spans.push((field("subcommand"), field("correlation_id")));
+            }
+            tracing::span::Id::from_u64(spans.len() as u64 + 1)
+        }
+
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, _: &tracing::Event<'_>) {}
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn test_async_correlation_id_span() {
+        let capture = SpanCapture::default();
+        let _guard = tracing::subscriber::set_default(capture.clone());
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .correlation_id("request-42")
+            .build()
+            .expect("unable to create runc instance");
+        runc.pause("fake-id").await.expect("true failed");
+        assert_eq!(
+            *capture.0.lock().unwrap(),
+            [("pause".to_string(), "request-42".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_async_correlation_id_logs() {
+        use tokio::sync::broadcast;
+
+        // Fake runc logging an entry to --log.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(
+            dir.path(),
+            r#"while [ "$1" != --log ]; do shift; done
+echo '{"level":"debug","msg":"one"}' >> "$2"
+"#,
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .correlation_id("request-42")
+            .build()
+            .expect("unable to create runc instance");
 
-        ok_task.await.expect("ok_task failed.");
-        fail_task.await.expect("fail_task unexpectedly succeeded.");
+        let (tx, mut rx) = broadcast::channel(16);
+        runc.create_with_logs("fake-id", dir.path(), None, tx)
+            .await
+            .unwrap();
+        let entry = rx.recv().await.unwrap();
+        assert_eq!(entry.msg, "one");
+        assert_eq!(entry.correlation_id(), Some("request-42"));
     }
 
     #[tokio::test]
-    async fn test_async_start() {
-        let ok_runc = ok_client();
-        let ok_task = tokio::spawn(async move {
-            ok_runc.start("fake-id").await.expect("true failed.");
-            eprintln!("ok_runc succeeded.");
+    async fn test_async_stats_many() {
+        // Stats of a container are given after a delay, the container "dead" doesn't exist.
+        let fake = Arc::new(FakeRunc::new());
+        fake.respond_with("events", |args| {
+            let id = args.last().unwrap();
+            let response = if id == "dead" {
+                FakeResponse::failure(1, "container does not exist")
+            } else {
+                FakeResponse::success(format!(
+                    r#"{{"type":"stats","id":"{}","data":{{"cpu":{{"usage":{}}},"memory":{{}},"pids":{{}},"blkio":{{}},"hugetlb":{{"failcnt":0}}}}}}"#,
+                    id,
+                    id.len()
+                ))
+            };
+            response.delay(Duration::from_millis(50))
         });
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(fake.clone());
+        let runc = opts.build().expect("unable to create runc instance");
 
-        let fail_runc = fail_client();
-        let fail_task = tokio::spawn(async move {
-            match fail_runc.start("fake-id").await {
-                Ok(_) => panic!("fail_runc returned exit status 0."),
-                Err(Error::CommandFailed {
-                    status,
-                    stdout,
-                    stderr,
-                }) => {
-                    if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
-                        eprintln!("fail_runc succeeded.");
-                    } else {
-                        panic!("unexpected outputs from fail_runc.")
+        let stats = runc.stats_many(["a", "bb", "dead", "dddd"], 2).await;
+        assert_eq!(stats.len(), 4);
+        for id in ["a", "bb", "dddd"] {
+            let usage = stats[id].as_ref().expect("stats failed").cpu.usage;
+            assert_eq!(usage, Some(id.len() as u64));
+        }
+        assert!(matches!(stats["dead"], Err(Error::CommandFailed { .. })));
+        assert_eq!(fake.max_running(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_async_exec_cap_names() {
+        use oci_spec::runtime::Capability;
+
+        let fake = running_runc(1000, Path::new(""));
+        let exec_process = record_exec(&fake);
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(fake.clone());
+        let runc = opts.build().expect("unable to create runc instance");
+
+        let opts = ExecOpts::new().cap_names(["CAP_NET_ADMN"]);
+        match runc.exec("fake-id", &dummy_process(), Some(&opts)).await {
+            Err(Error::UnknownCapability(name)) => assert_eq!(name, "CAP_NET_ADMN"),
+            other => panic!("unexpected result from exec: {:?}", other),
+        }
+        assert!(exec_process.lock().unwrap().is_none());
+
+        let opts = ExecOpts::new().cap_names(["CAP_NET_ADMIN"]);
+        runc.exec("fake-id", &dummy_process(), Some(&opts))
+            .await
+            .expect("exec failed");
+        let process = exec_process.lock().unwrap().take().unwrap();
+        let caps = process.capabilities().as_ref().unwrap();
+        assert!(caps
+            .effective()
+            .as_ref()
+            .unwrap()
+            .contains(&Capability::NetAdmin));
+    }
+
+    #[tokio::test]
+    async fn test_async_checkpoint_restore() {
+        // Fake runc dumping an image on checkpoint, and restoring only from an existing image.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(
+            dir.path(),
+            r#"while [ $# -gt 0 ]; do
+  case "$1" in
+    checkpoint|restore) cmd=$1 ;;
+    --image-path) images=$2 ;;
+    --pid-file) pid_file=$2 ;;
+  esac
+  shift
+done
+case $cmd in
+  checkpoint) mkdir -p "$images" && echo 424242 > "$images/inventory.img" ;;
+  restore) cp "$images/inventory.img" "$pid_file" ;;
+esac
+"#,
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+
+        let images = dir.path().join("checkpoint");
+        let restore = RestoreOpts::new()
+            .image_path(&images)
+            .pid_file(dir.path().join("restore.pid"))
+            .detach(true);
+        assert!(runc
+            .restore("fake-id", "fake-bundle", Some(&restore))
+            .await
+            .is_err());
+
+        let checkpoint = CheckpointOpts::new().image_path(&images);
+        runc.checkpoint("fake-id", Some(&checkpoint))
+            .await
+            .expect("checkpoint failed");
+        let response = runc
+            .restore("fake-id", "fake-bundle", Some(&restore))
+            .await
+            .expect("restore failed");
+        assert_eq!(response.pid, 424242);
+    }
+
+    #[tokio::test]
+    async fn test_async_spawn_run() {
+        // Fake runc whose run lasts until the container is killed.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(
+            dir.path(),
+            r#"killed="$(dirname "$0")/killed"
+for arg; do
+  case $arg in
+    run) while [ ! -f "$killed" ]; do sleep 0.01; done ;;
+    kill) touch "$killed" ;;
+  esac
+done
+"#,
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+
+        let handle = runc.spawn_run("fake-id", "fake-bundle", None);
+        assert_eq!(handle.id(), "fake-id");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!dir.path().join("killed").exists());
+
+        handle.kill(9, None).await.expect("kill failed");
+        let response = tokio::time::timeout(Duration::from_secs(5), handle.wait())
+            .await
+            .expect("run didn't exit")
+            .expect("run failed");
+        assert!(response.status.success());
+    }
+
+    #[tokio::test]
+    async fn test_async_run_handle_kill_on_drop() {
+        // Fake runc whose run lasts until the container is sent SIGKILL, recording the signals.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(
+            dir.path(),
+            r#"dir="$(dirname "$0")"
+case "$*" in
+  *" run "*) while [ ! -f "$dir/killed" ]; do sleep 0.01; done ;;
+  *" kill "*)
+    for sig; do :; done
+    if [ "$sig" = 9 ]; then touch "$dir/killed"; fi
+    echo "$sig" >> "$dir/signals" ;;
+esac
+"#,
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+        let signals = dir.path().join("signals");
+        let wait_signals = |n: usize| {
+            let signals = signals.clone();
+            async move {
+                for _ in 0..500 {
+                    let sent = std::fs::read_to_string(&signals).unwrap_or_default();
+                    if sent.lines().count() >= n {
+                        return sent;
                     }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
                 }
-                Err(e) => panic!("unexpected error from fail_runc: {:?}", e),
+                panic!("signals weren't sent");
             }
-        });
+        };
 
-        ok_task.await.expect("ok_task failed.");
-        fail_task.await.expect("fail_task unexpectedly succeeded.");
+        // The container is killed with SIGKILL by default.
+        let handle = runc
+            .spawn_run("fake-id", "fake-bundle", None)
+            .kill_on_drop(KillOnDrop::default());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drop(handle);
+        assert_eq!(wait_signals(1).await, "9\n");
+
+        // SIGTERM first, then SIGKILL as the container is still running after the grace period.
+        std::fs::remove_file(dir.path().join("killed")).unwrap();
+        std::fs::remove_file(&signals).unwrap();
+        let handle = runc
+            .spawn_run("fake-id", "fake-bundle", None)
+            .kill_on_drop(KillOnDrop {
+                signal: libc::SIGTERM as u32,
+                grace_period: Some(Duration::from_millis(100)),
+            });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drop(handle);
+        assert_eq!(wait_signals(2).await, "15\n9\n");
+
+        // Nothing is sent once the run is over.
+        std::fs::remove_file(dir.path().join("killed")).unwrap();
+        std::fs::remove_file(&signals).unwrap();
+        let handle = runc
+            .spawn_run("fake-id", "fake-bundle", None)
+            .kill_on_drop(KillOnDrop::default());
+        handle.kill(9, None).await.unwrap();
+        std::fs::remove_file(&signals).unwrap();
+        handle.wait().await.expect("run failed");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!signals.exists());
     }
 
     #[tokio::test]
-    async fn test_async_run() {
-        let opts = CreateOpts::new();
-        let ok_runc = ok_client();
-        tokio::spawn(async move {
-            ok_runc
-                .create("fake-id", "fake-bundle", Some(&opts))
-                .await
-                .expect("true failed.");
-            eprintln!("ok_runc succeeded.");
-        });
+    async fn test_async_exec_detached() {
+        // Fake runc starting the process in the background when detached, like runc does.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(dir.path(), "while [ $# -gt 0 ]; do\n  [ \"$1\" = --pid-file ] && pid_file=$2\n  [ \"$1\" = --detach ] && detach=1\n  shift\ndone\n[ -n \"$detach\" ] || exit 1\nsleep 10 > /dev/null 2>&1 &\necho $! > \"$pid_file\"\n");
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
 
-        let opts = CreateOpts::new();
-        let fail_runc = fail_client();
-        tokio::spawn(async move {
-            match fail_runc
-                .create("fake-id", "fake-bundle", Some(&opts))
-                .await
-            {
-                Ok(_) => panic!("fail_runc returned exit status 0."),
-                Err(Error::CommandFailed {
-                    status,
-                    stdout,
-                    stderr,
-                }) => {
-                    if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
-                        eprintln!("fail_runc succeeded.");
-                    } else {
-                        panic!("unexpected outputs from fail_runc.")
-                    }
+        let pid_file = dir.path().join("exec.pid");
+        let opts = ExecOpts::new().detach(true).pid_file(&pid_file);
+        let response = runc
+            .exec_with_response("fake-id", &dummy_process(), Some(&opts))
+            .await
+            .expect("exec failed.");
+        assert!(std::path::Path::new(&format!("/proc/{}", response.pid)).exists());
+        assert!(!pid_file.exists());
+        unsafe { libc::kill(response.pid as i32, libc::SIGKILL) };
+    }
+
+    #[tokio::test]
+    async fn test_async_oom_score_adj() {
+        let dir = tempfile::tempdir().unwrap();
+        let adj = dir.path().join("oom_score_adj");
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .oom_score_adj(900)
+            .launcher(
+                Launcher::new("/bin/sh")
+                    .arg("-c")
+                    .arg(format!("cat /proc/self/oom_score_adj > {}", adj.display())),
+            )
+            .build()
+            .expect("unable to create runc instance");
+
+        runc.pause("fake-id").await.expect("launcher failed");
+        assert_eq!(std::fs::read_to_string(&adj).unwrap(), "900\n");
+    }
+
+    #[tokio::test]
+    async fn test_async_pre_exec() {
+        let dir = tempfile::tempdir().unwrap();
+        let limit = dir.path().join("nofile");
+        // Safety: setrlimit is async-signal-safe.
+        let opts = unsafe {
+            GlobalOpts::new().command("/bin/true").pre_exec(|| {
+                let rlim = libc::rlimit {
+                    rlim_cur: 123,
+                    rlim_max: 123,
+                };
+                if libc::setrlimit(libc::RLIMIT_NOFILE, &rlim) < 0 {
+                    return Err(std::io::Error::last_os_error());
                 }
-                Err(e) => panic!("unexpected error from fail_runc: {:?}", e),
+                Ok(())
+            })
+        };
+        let runc = opts
+            .launcher(
+                Launcher::new("/bin/sh")
+                    .arg("-c")
+                    .arg(format!("ulimit -n > {}", limit.display())),
+            )
+            .build()
+            .expect("unable to create runc instance");
+
+        runc.pause("fake-id").await.expect("launcher failed");
+        assert_eq!(std::fs::read_to_string(&limit).unwrap(), "123\n");
+
+        // A failing hook fails the command before anything is executed.
+        std::fs::remove_file(&limit).unwrap();
+        let runc = unsafe {
+            GlobalOpts::new()
+                .command("/bin/true")
+                .pre_exec(|| Err(std::io::Error::from_raw_os_error(libc::EPERM)))
+        }
+        .launcher(
+            Launcher::new("/bin/sh")
+                .arg("-c")
+                .arg(format!("ulimit -n > {}", limit.display())),
+        )
+        .build()
+        .expect("unable to create runc instance");
+        assert!(runc.pause("fake-id").await.is_err());
+        assert!(!limit.exists());
+    }
+
+    #[tokio::test]
+    async fn test_async_launch_to_files() {
+        // Fake runc printing a large output before failing.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(
+            dir.path(),
+            "seq 1 200000\necho \"checkpoint failed\" >&2\nexit 3\n",
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+
+        let stdout = dir.path().join("stdout");
+        let stderr = dir.path().join("stderr");
+        let status = runc
+            .launch_to_files(
+                &["checkpoint".to_string(), "fake-id".to_string()],
+                std::fs::File::create(&stdout).unwrap(),
+                std::fs::File::create(&stderr).unwrap(),
+            )
+            .await
+            .expect("launch failed");
+        assert_eq!(status.code(), Some(3));
+        let output = std::fs::read_to_string(&stdout).unwrap();
+        assert_eq!(output.lines().count(), 200000);
+        assert_eq!(output.lines().last(), Some("200000"));
+        assert_eq!(
+            std::fs::read_to_string(&stderr).unwrap(),
+            "checkpoint failed\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_async_response_bundle() {
+        // Fake runc printing its arguments.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(dir.path(), "echo \"$@\"\n");
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+
+        let bundle = std::env::current_dir().unwrap().join("bundles/fake-id");
+        for res in [
+            runc.create("fake-id", "bundles/./fake-id", None).await,
+            runc.run_with_response("fake-id", "bundles/../bundles/fake-id", None)
+                .await,
+        ] {
+            let res = res.expect("runc failed");
+            assert_eq!(res.bundle.as_deref(), Some(bundle.as_path()));
+            assert!(res
+                .output
+                .contains(&format!("--bundle {} ", bundle.display())));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_create_and_start_rollback() {
+        // Fake runc failing to start, and to delete if "fail-delete" exists.
+        let dir = tempfile::tempdir().unwrap();
+        let calls = dir.path().join("calls");
+        let fail_delete = dir.path().join("fail-delete");
+        let script = fake_runc_script(
+            dir.path(),
+            &format!(
+                "echo \"$@\" >> {calls}\n\
+                 case \"$*\" in\n\
+                 *start*) echo \"start failed\" >&2; exit 1;;\n\
+                 *delete*) if [ -e {fail} ]; then echo \"delete failed\" >&2; exit 1; fi;;\n\
+                 esac\nexit 0\n",
+                calls = calls.display(),
+                fail = fail_delete.display()
+            ),
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+
+        let err = runc
+            .create_and_start("fake-id", dir.path(), None)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::CommandFailed { ref stderr, .. } if stderr == "start failed\n")
+        );
+        // The global options come first.
+        let calls_made = std::fs::read_to_string(&calls).unwrap();
+        let calls_made: Vec<&str> = calls_made.lines().collect();
+        assert_eq!(calls_made.len(), 3);
+        assert!(calls_made[0].contains(" create --bundle "));
+        assert!(calls_made[1].ends_with(" start fake-id"));
+        assert!(calls_made[2].ends_with(" delete --force fake-id"));
+
+        std::fs::write(&fail_delete, "").unwrap();
+        match runc.create_and_start("fake-id", dir.path(), None).await {
+            Err(Error::RollbackFailed { error, rollback }) => {
+                assert!(error.to_string().contains("start failed"));
+                assert!(rollback.to_string().contains("delete failed"));
             }
-        })
-        .await
-        .expect("tokio spawn falied.");
+            res => panic!("unexpected result {:?}", res),
+        }
     }
 
     #[tokio::test]
-    async fn test_async_delete() {
-        let opts = DeleteOpts::new();
-        let ok_runc = ok_client();
-        tokio::spawn(async move {
-            ok_runc
-                .delete("fake-id", Some(&opts))
-                .await
-                .expect("true failed.");
-            eprintln!("ok_runc succeeded.");
-        });
+    async fn test_async_capabilities() {
+        // Fake runc 1.1 recording how many times it's probed.
+        let dir = tempfile::tempdir().unwrap();
+        let calls = dir.path().join("calls");
+        let script = fake_runc_script(
+            dir.path(),
+            &format!(
+                r#"for arg in "$@"; do
+  case "$arg" in
+    --version) printf 'runc version 1.1.12\nspec: 1.0.2-dev\n' ;;
+    features) echo '{{"ociVersionMin": "1.0.0", "ociVersionMax": "1.1.0", "linux": {{"cgroup": {{"v2": true}}}}}}' ;;
+    *) continue ;;
+  esac
+  echo "$arg" >> {}
+done
+"#,
+                calls.display()
+            ),
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
 
-        let opts = DeleteOpts::new();
-        let fail_runc = fail_client();
-        tokio::spawn(async move {
-            match fail_runc.delete("fake-id", Some(&opts)).await {
-                Ok(_) => panic!("fail_runc returned exit status 0."),
-                Err(Error::CommandFailed {
-                    status,
-                    stdout,
-                    stderr,
-                }) => {
-                    if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
-                        eprintln!("fail_runc succeeded.");
-                    } else {
-                        panic!("unexpected outputs from fail_runc.")
-                    }
-                }
-                Err(e) => panic!("unexpected error from fail_runc: {:?}", e),
+        let caps = runc.capabilities().await.unwrap();
+        assert_eq!(caps.runc_version.as_deref(), Some("1.1.12"));
+        assert!(caps.supports_features_cmd);
+        assert!(caps.supports_checkpoint);
+        assert!(caps.supports_cgroup_v2);
+        assert_eq!(runc.clone().capabilities().await.unwrap(), caps);
+        let calls = std::fs::read_to_string(calls).unwrap();
+        assert_eq!(calls.lines().collect::<Vec<_>>(), ["--version", "features"]);
+    }
+
+    #[tokio::test]
+    async fn test_async_criu_features() {
+        // Fake CRIU 3.17 supporting lazy pages only.
+        let dir = tempfile::tempdir().unwrap();
+        let criu = fake_runc_script(
+            dir.path(),
+            r#"case "$*" in
+  --version) printf 'Version: 3.17.1\nGitID: v3.17.1\n' ;;
+  check) echo 'Looks good.' ;;
+  "check --feature uffd-noncoop") echo 'uffd-noncoop is supported' ;;
+  *) echo "$3 is not supported" >&2; exit 1 ;;
+esac
+"#,
+        );
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .criu(&criu)
+            .build()
+            .expect("unable to create runc instance");
+
+        assert_eq!(
+            runc.criu_features().await.unwrap(),
+            features::CriuFeatures {
+                version: Some("3.17.1".to_string()),
+                check_passed: true,
+                tcp_established: true,
+                lazy_pages: true,
+                ..Default::default()
             }
-        })
+        );
+
+        let missing = dir.path().join("missing-criu");
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .criu(&missing)
+            .build()
+            .expect("unable to create runc instance");
+        match runc.criu_features().await {
+            Err(Error::CriuNotFound(path)) => assert_eq!(path, missing),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_checkpoint_cleanup_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let image_path = dir.path().join("checkpoint");
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(failing_runc("checkpoint", CRIU_FAILED_STDERR));
+        let runc = opts.build().expect("unable to create runc instance");
+
+        // Partial images are left by default.
+        std::fs::create_dir(&image_path).unwrap();
+        std::fs::write(image_path.join("inventory.img"), "partial").unwrap();
+        let opts = CheckpointOpts::new().image_path(&image_path);
+        assert!(matches!(
+            runc.checkpoint("fake-id", Some(&opts)).await,
+            Err(Error::CriuFailed { .. })
+        ));
+        assert!(image_path.join("inventory.img").exists());
+
+        let opts = opts.cleanup_on_failure(true);
+        assert!(matches!(
+            runc.checkpoint("fake-id", Some(&opts)).await,
+            Err(Error::CriuFailed { .. })
+        ));
+        assert!(!image_path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_async_run_captured() {
+        // Stand-in for a container printing to both streams and exiting with an error.
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .launcher(
+                Launcher::new("/bin/sh")
+                    .arg("-c")
+                    .arg("echo output; echo error >&2; exit 3"),
+            )
+            .build()
+            .expect("unable to create runc instance");
+
+        let output = runc
+            .run_captured("fake-id", "fake-bundle", None)
+            .await
+            .expect("run_captured failed");
+        assert_eq!(output.exit_code, 3);
+        assert_eq!(output.stdout, "output\n");
+        assert_eq!(output.stderr, "error\n");
+
+        // The container is deleted when runc doesn't complete.
+        let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .launcher(
+                Launcher::new("/bin/sh")
+                    .arg("-c")
+                    .arg(r#"case "$*" in *delete*) ;; *) sleep 10 ;; esac"#),
+            )
+            .on_command_complete(move |subcommand, _, _| {
+                recorded.lock().unwrap().push(subcommand.to_string())
+            })
+            .build()
+            .expect("unable to create runc instance");
+        let opts = CreateOpts::new().timeout(Duration::from_millis(50));
+        assert!(matches!(
+            runc.run_captured("fake-id", "fake-bundle", Some(&opts))
+                .await,
+            Err(Error::CommandTimeout(_))
+        ));
+        assert_eq!(*calls.lock().unwrap(), ["run", "delete"]);
+    }
+
+    #[tokio::test]
+    async fn test_async_create_with_logs() {
+        use tokio::sync::broadcast::{self, error::RecvError};
+
+        // Fake runc logging to --log, and waiting for the first entry to be received.
+        let dir = tempfile::tempdir().unwrap();
+        let seen = dir.path().join("seen");
+        let script = fake_runc_script(
+            dir.path(),
+            &format!(
+                r#"while [ $# -gt 0 ]; do
+  case "$1" in
+    --log) log="$2"; shift ;;
+  esac
+  shift
+done
+echo '{{"level":"debug","msg":"one"}}' >> "$log"
+i=0
+while [ ! -e {seen} ] && [ $i -lt 500 ]; do sleep 0.01; i=$((i+1)); done
+echo '{{"level":"debug","msg":"two"}}' >> "$log"
+echo 'not json' >> "$log"
+echo '{{"level":"info","msg":"three"}}' >> "$log"
+"#,
+                seen = seen.display()
+            ),
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+        let msg = |res: std::result::Result<logs::LogEntry, RecvError>| res.unwrap().msg;
+
+        // Entries are received while the create is in flight.
+        let (tx, mut rx) = broadcast::channel(16);
+        let create = runc.create_with_logs("fake-id", dir.path(), None, tx);
+        let receive = async {
+            let first = msg(rx.recv().await);
+            std::fs::File::create(&seen).unwrap();
+            first
+        };
+        let (res, first) = tokio::join!(create, receive);
+        res.unwrap();
+        assert_eq!(first, "one");
+        assert_eq!(msg(rx.recv().await), "two");
+        assert_eq!(msg(rx.recv().await), "three");
+
+        // A receiver not keeping up loses the oldest entries, without blocking the create.
+        let (tx, mut rx) = broadcast::channel(1);
+        runc.create_with_logs("fake-id", dir.path(), None, tx)
+            .await
+            .unwrap();
+        assert!(matches!(rx.recv().await, Err(RecvError::Lagged(2))));
+        assert_eq!(msg(rx.recv().await), "three");
+    }
+
+    #[tokio::test]
+    async fn test_async_checkpoint_with_logs() {
+        use tokio::sync::broadcast::{self, error::RecvError};
+
+        // Fake runc logging the progress of CRIU to --log, and failing the checkpoint of
+        // "fail-id" after it.
+        let dir = tempfile::tempdir().unwrap();
+        let seen = dir.path().join("seen");
+        let script = fake_runc_script(
+            dir.path(),
+            &format!(
+                r#"for id; do :; done
+while [ $# -gt 0 ]; do
+  case "$1" in
+    --log) log="$2"; shift ;;
+  esac
+  shift
+done
+echo '{{"level":"debug","msg":"criu: Dumping processes"}}' >> "$log"
+i=0
+while [ ! -e {seen} ] && [ $i -lt 500 ]; do sleep 0.01; i=$((i+1)); done
+echo '{{"level":"debug","msg":"criu: Dumping pages"}}' >> "$log"
+[ "$id" = fail-id ] && echo 'criu failed: type NOTIFY errno 0' >&2 && exit 1
+echo '{{"level":"info","msg":"checkpointed"}}' >> "$log"
+"#,
+                seen = seen.display()
+            ),
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+        let opts = CheckpointOpts::new().image_path(dir.path().join("checkpoint"));
+
+        // Progress is received while the checkpoint is in flight, and the stream ends with it.
+        let (tx, mut rx) = broadcast::channel(16);
+        let checkpoint = runc.checkpoint_with_logs("fake-id", Some(&opts), tx);
+        let receive = async {
+            let first = rx.recv().await.unwrap().msg;
+            std::fs::File::create(&seen).unwrap();
+            first
+        };
+        let (res, first) = tokio::join!(checkpoint, receive);
+        res.unwrap();
+        assert_eq!(first, "criu: Dumping processes");
+        assert_eq!(rx.recv().await.unwrap().msg, "criu: Dumping pages");
+        assert_eq!(rx.recv().await.unwrap().msg, "checkpointed");
+        assert!(matches!(rx.recv().await, Err(RecvError::Closed)));
+
+        // The stream also ends when the checkpoint fails.
+        let (tx, mut rx) = broadcast::channel(16);
+        assert!(runc
+            .checkpoint_with_logs("fail-id", Some(&opts), tx)
+            .await
+            .is_err());
+        assert_eq!(rx.recv().await.unwrap().msg, "criu: Dumping processes");
+        assert_eq!(rx.recv().await.unwrap().msg, "criu: Dumping pages");
+        assert!(matches!(rx.recv().await, Err(RecvError::Closed)));
+    }
+
+    #[tokio::test]
+    async fn test_async_list_by_annotation() {
+        // Fake runc listing containers of two pods, and one without annotations.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(
+            dir.path(),
+            r#"cat <<EOF
+[
+  {"id": "a", "pid": 10, "status": "running", "bundle": "/b/a", "rootfs": "/b/a/rootfs", "created": "2023-06-01T10:00:00Z", "annotations": {"io.kubernetes.cri.sandbox-id": "pod1"}},
+  {"id": "b", "pid": 11, "status": "running", "bundle": "/b/b", "rootfs": "/b/b/rootfs", "created": "2023-06-01T10:00:00Z", "annotations": {"io.kubernetes.cri.sandbox-id": "pod2"}},
+  {"id": "c", "pid": 12, "status": "created", "bundle": "/b/c", "rootfs": "/b/c/rootfs", "created": "2023-06-01T10:00:00Z"},
+  {"id": "d", "pid": 13, "status": "running", "bundle": "/b/d", "rootfs": "/b/d/rootfs", "created": "2023-06-01T10:00:00Z", "annotations": {"io.kubernetes.cri.sandbox-id": "pod1", "foo": "bar"}}
+]
+EOF
+"#,
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+
+        let ids =
+            |containers: Vec<Container>| containers.into_iter().map(|c| c.id).collect::<Vec<_>>();
+        let pod1 = runc
+            .list_by_annotation("io.kubernetes.cri.sandbox-id", "pod1")
+            .await
+            .unwrap();
+        assert_eq!(ids(pod1), ["a", "d"]);
+        let none = runc.list_by_annotation("foo", "baz").await.unwrap();
+        assert!(none.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_async_wait() {
+        // Fake runc reporting the state of a container whose init process is a child of ours.
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("state");
+        let script = fake_runc_script(dir.path(), &format!("cat {}\n", state.display()));
+        let root = dir.path().join("root");
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .root(&root)
+            .build()
+            .expect("unable to create runc instance");
+        let report = |status: &str, pid: u32| {
+            std::fs::write(
+                &state,
+                format!(
+                    r#"{{"id": "fake-id", "pid": {}, "status": "{}", "bundle": "/b", "rootfs": "/b/rootfs", "created": "2023-06-01T10:00:00Z"}}"#,
+                    pid, status
+                ),
+            )
+            .unwrap();
+        };
+        // Spawned without tokio, which would reap it itself.
+        let spawn = |script: &str| {
+            std::process::Command::new("/bin/sh")
+                .arg("-c")
+                .arg(script)
+                .spawn()
+                .unwrap()
+                .id()
+        };
+
+        let pid = spawn("sleep 0.2; exit 7");
+        report("running", pid);
+        assert_eq!(runc.wait("fake-id").await.unwrap().code(), Some(7));
+
+        // Exited before the wait: runc reports no pid, it's read from the container's state.
+        let pid = spawn("exit 8");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        report("stopped", 0);
+        std::fs::create_dir_all(root.join("fake-id")).unwrap();
+        std::fs::write(
+            root.join("fake-id").join("state.json"),
+            format!(r#"{{"id": "fake-id", "init_process_pid": {}}}"#, pid),
+        )
+        .unwrap();
+        assert_eq!(runc.wait("fake-id").await.unwrap().code(), Some(8));
+    }
+
+    #[tokio::test]
+    async fn test_async_systemd_cgroup_per_call() {
+        let fake = Arc::new(FakeRunc::new());
+        let subcommands = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = subcommands.clone();
+        let mut opts = GlobalOpts::new()
+            .command("/bin/true")
+            .systemd_cgroup(true)
+            .on_command_complete(move |subcommand, _, _| {
+                recorded.lock().unwrap().push(subcommand.to_string())
+            });
+        opts.custom_spawner(fake.clone());
+        let runc = opts.build().expect("unable to create runc instance");
+
+        let enabled = CreateOpts::new().systemd_cgroup(true);
+        let disabled = CreateOpts::new().systemd_cgroup(false);
+        runc.create("fake-id", "fake-bundle", None).await.unwrap();
+        runc.create("fake-id", "fake-bundle", Some(&disabled))
+            .await
+            .unwrap();
+        runc.run("fake-id", "fake-bundle", Some(&disabled))
+            .await
+            .unwrap();
+        runc.create("fake-id", "fake-bundle", Some(&enabled))
+            .await
+            .unwrap();
+
+        let systemd_cgroup: Vec<usize> = fake
+            .calls()
+            .iter()
+            .map(|args| args.iter().filter(|a| *a == "--systemd-cgroup").count())
+            .collect();
+        assert_eq!(systemd_cgroup, [1, 0, 0, 1]);
+        assert_eq!(
+            *subcommands.lock().unwrap(),
+            ["create", "create", "run", "create"]
+        );
+
+        // Enabled for a single call only.
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        let fake = Arc::new(FakeRunc::new());
+        opts.custom_spawner(fake.clone());
+        let runc = opts.build().expect("unable to create runc instance");
+        runc.create("fake-id", "fake-bundle", Some(&enabled))
+            .await
+            .unwrap();
+        runc.create("fake-id", "fake-bundle", None).await.unwrap();
+        let args = fake.calls();
+        assert!(args[0].contains(&"--systemd-cgroup".to_string()));
+        assert!(!args[1].contains(&"--systemd-cgroup".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_async_rootless_per_call() {
+        let fake = Arc::new(FakeRunc::new());
+        let mut opts = GlobalOpts::new().command("/bin/true").rootless(false);
+        opts.custom_spawner(fake.clone());
+        let runc = opts.build().expect("unable to create runc instance");
+
+        runc.create("fake-id", "fake-bundle", None).await.unwrap();
+        runc.create(
+            "fake-id",
+            "fake-bundle",
+            Some(&CreateOpts::new().rootless(true)),
+        )
         .await
-        .expect("tokio spawn falied.");
+        .unwrap();
+        runc.run(
+            "fake-id",
+            "fake-bundle",
+            Some(&CreateOpts::new().rootless_auto()),
+        )
+        .await
+        .unwrap();
+        runc.exec(
+            "fake-id",
+            &dummy_process(),
+            Some(&ExecOpts::new().rootless(true)),
+        )
+        .await
+        .unwrap();
+        runc.exec("fake-id", &dummy_process(), None).await.unwrap();
+
+        let rootless: Vec<Vec<String>> = fake
+            .calls()
+            .iter()
+            .map(|args| {
+                args.iter()
+                    .filter(|a| a.starts_with("--rootless"))
+                    .cloned()
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            rootless,
+            [
+                vec!["--rootless=false"],
+                vec!["--rootless=true"],
+                vec![],
+                vec!["--rootless=true"],
+                vec!["--rootless=false"],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_async_clean_orphans() {
+        // Fake runc listing container "a" only.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(
+            dir.path(),
+            r#"echo '[{"id": "a", "pid": 10, "status": "running", "bundle": "/b/a", "rootfs": "/b/a/rootfs", "created": 0}]'
+"#,
+        );
+        let root = dir.path().join("root");
+        for id in ["a", "b", "not-a-container"] {
+            std::fs::create_dir_all(root.join(id)).unwrap();
+        }
+        std::fs::write(root.join("a").join("state.json"), "{}").unwrap();
+        std::fs::write(root.join("b").join("state.json"), "{}").unwrap();
+        std::fs::write(root.join("file"), "").unwrap();
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .root(&root)
+            .build()
+            .expect("unable to create runc instance");
+
+        assert_eq!(runc.find_orphans().await.unwrap(), [root.join("b")]);
+        assert_eq!(runc.clean_orphans().await.unwrap(), [root.join("b")]);
+        assert!(!root.join("b").exists());
+        assert!(root.join("a").join("state.json").exists());
+        assert!(root.join("not-a-container").exists());
+        assert!(runc.find_orphans().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_async_stats_single_sample() {
+        // Fake runc printing a single event with --stats, and streaming them otherwise.
+        let dir = tempfile::tempdir().unwrap();
+        let script = fake_runc_script(
+            dir.path(),
+            r#"event='{"type":"stats","id":"fake-id","data":{"cpu":{"usage":42},"memory":{},"pids":{"current":3},"blkio":{},"hugetlb":{"failcnt":0}}}'
+echo 'level=warning msg="cgroup v1 is deprecated"' >&2
+for arg in "$@"; do
+  [ "$arg" = --stats ] && echo "$event" && exit 0
+done
+while true; do echo "$event"; sleep 1; done
+"#,
+        );
+        let runc = GlobalOpts::new()
+            .command(&script)
+            .build()
+            .expect("unable to create runc instance");
+
+        let stats = tokio::time::timeout(Duration::from_secs(1), runc.stats("fake-id"))
+            .await
+            .expect("stats didn't return promptly")
+            .unwrap();
+        assert_eq!(stats.cpu.usage, Some(42));
+        assert_eq!(stats.pids.current, Some(3));
     }
 
     #[tokio::test]
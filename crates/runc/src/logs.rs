@@ -0,0 +1,84 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Entries of the logs runc writes with `--log-format json`.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Line of a runc JSON log, e.g.
+/// `{"level":"debug","msg":"nsexec[12]: update /proc/12/uid_map","time":"2023-06-01T10:00:00Z"}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogEntry {
+    /// Level of the entry: "debug", "info", "warning", "error"...
+    pub level: String,
+    pub msg: String,
+    /// Time of the entry, RFC3339 formatted.
+    #[serde(default)]
+    pub time: Option<String>,
+    /// Other fields of the entry, e.g. the `error` some entries carry.
+    #[serde(flatten)]
+    pub fields: HashMap<String, serde_json::Value>,
+}
+
+impl LogEntry {
+    /// Field of the correlation id stamped on the entries, see
+    /// [crate::options::GlobalOpts::correlation_id].
+    pub const CORRELATION_ID: &'static str = "correlation_id";
+
+    /// Parse a line of a runc JSON log, `None` if it isn't a JSON entry (e.g. a line written by
+    /// a runc hook to the log).
+    pub fn parse(line: &str) -> Option<Self> {
+        serde_json::from_str(line.trim()).ok()
+    }
+
+    /// Return the correlation id of the runc command which logged the entry, if any.
+    pub fn correlation_id(&self) -> Option<&str> {
+        self.fields
+            .get(Self::CORRELATION_ID)
+            .and_then(serde_json::Value::as_str)
+    }
+
+    /// Stamp the entry with the correlation id `id`, kept among its [LogEntry::fields].
+    pub fn set_correlation_id(&mut self, id: &str) {
+        self.fields
+            .insert(Self::CORRELATION_ID.to_string(), id.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let entry = LogEntry::parse(
+            r#"{"level":"error","msg":"container_linux.go:380: starting container process caused","error":"exit status 1","time":"2023-06-01T10:00:00Z"}"#,
+        )
+        .unwrap();
+        assert_eq!(entry.level, "error");
+        assert_eq!(entry.time.as_deref(), Some("2023-06-01T10:00:00Z"));
+        assert_eq!(entry.fields["error"], "exit status 1");
+
+        assert_eq!(entry.correlation_id(), None);
+        let mut entry = entry;
+        entry.set_correlation_id("request-42");
+        assert_eq!(entry.correlation_id(), Some("request-42"));
+
+        assert!(LogEntry::parse("not json").is_none());
+    }
+}
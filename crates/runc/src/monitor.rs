@@ -14,17 +14,25 @@
    limitations under the License.
 */
 
-use std::process::{ExitStatus, Output};
+use std::{
+    io,
+    os::unix::{
+        io::{AsRawFd, FromRawFd, OwnedFd},
+        process::ExitStatusExt,
+    },
+    process::{ExitStatus, Output},
+};
 
 use async_trait::async_trait;
 use log::error;
 use time::OffsetDateTime;
 use tokio::{
-    process::Command,
+    io::{unix::AsyncFd, AsyncRead, AsyncReadExt, Interest},
+    process::{ChildStderr, ChildStdout, Command},
     sync::oneshot::{channel, Receiver, Sender},
 };
 
-use crate::error::Error;
+use crate::{error::Error, Spawner};
 
 /// A trait for spawning and waiting for a process.
 ///
@@ -49,17 +57,7 @@ pub trait ProcessMonitor {
             .id()
             .expect("failed to take pid of the container process.");
         let out = chi.wait_with_output().await?;
-        let ts = OffsetDateTime::now_utc();
-        // On Unix, out.status.code() will return None if the process was terminated by a signal.
-        let status = out.status.code().unwrap_or(-1);
-        match tx.send(Exit { ts, pid, status }) {
-            Ok(_) => Ok(out),
-            Err(e) => {
-                error!("command {:?} exited but receiver dropped.", cmd);
-                error!("couldn't send messages: {:?}", e);
-                Err(std::io::ErrorKind::ConnectionRefused.into())
-            }
-        }
+        send_exit(&cmd, tx, pid, out)
     }
 
     /// Wait for the spawned process to exit and return the exit status.
@@ -83,6 +81,97 @@ impl DefaultMonitor {
     }
 }
 
+/// A [ProcessMonitor] learning of the exit of the processes it spawns through a pidfd, on Linux
+/// 5.3 and later, and reaping them itself.
+///
+/// Unlike [DefaultMonitor], it doesn't rely on tokio's `SIGCHLD` handling: a pidfd can't refer
+/// to another process after its pid is reused, and it signals the exit even when the process is
+/// reaped by a reaper calling `waitpid(-1)` concurrently, which then fails with `ECHILD`
+/// instead of hanging. The processes are spawned without tokio, which never tries to reap them.
+/// On older kernels, they're waited for by a blocking thread, see [wait_pid].
+///
+/// The pidfd is opened right after the process is spawned, from its pid. A reaper calling
+/// `waitpid(-1)` in between may still reap a process exiting that quickly, in which case
+/// waiting for it fails instead of waiting for another process reusing the pid: a pidfd of a
+/// process which isn't our child can't be waited for. Closing that window would take spawning
+/// with `clone3(CLONE_PIDFD)`, which std doesn't expose.
+///
+/// It's also a [Spawner](crate::Spawner), so that runc commands are run with it when it's passed
+/// to [GlobalOpts::custom_spawner](crate::options::GlobalOpts::custom_spawner).
+#[derive(Debug, Clone, Default)]
+pub struct PidfdMonitor {}
+
+impl PidfdMonitor {
+    pub const fn new() -> Self {
+        Self {}
+    }
+}
+
+#[async_trait]
+impl ProcessMonitor for PidfdMonitor {
+    async fn start(&self, mut cmd: Command, tx: Sender<Exit>) -> std::io::Result<Output> {
+        // Spawned without tokio, which would otherwise try to reap the process again once the
+        // child is dropped, possibly reaping another process which reused the pid by then.
+        let mut chi = cmd.as_std_mut().spawn()?;
+        let pid = chi.id();
+        // Open the pidfd before anything else, see the window documented on PidfdMonitor.
+        let pidfd = try_pidfd_open(pid as i32)?;
+        let read_to_end = |pipe: Option<Box<dyn AsyncRead + Send + Unpin>>| async move {
+            let mut buf = Vec::new();
+            if let Some(mut pipe) = pipe {
+                pipe.read_to_end(&mut buf).await?;
+            }
+            Ok::<_, io::Error>(buf)
+        };
+        let stdout = match chi.stdout.take() {
+            Some(pipe) => Some(Box::new(ChildStdout::from_std(pipe)?) as _),
+            None => None,
+        };
+        let stderr = match chi.stderr.take() {
+            Some(pipe) => Some(Box::new(ChildStderr::from_std(pipe)?) as _),
+            None => None,
+        };
+        let (stdout, stderr, status) = tokio::try_join!(
+            read_to_end(stdout),
+            read_to_end(stderr),
+            wait_child(pid as i32, pidfd)
+        )?;
+        send_exit(
+            &cmd,
+            tx,
+            pid,
+            Output {
+                status,
+                stdout,
+                stderr,
+            },
+        )
+    }
+}
+
+#[async_trait]
+impl Spawner for PidfdMonitor {
+    async fn execute(&self, cmd: Command) -> Result<(ExitStatus, u32, String, String), Error> {
+        let result = execute(self, cmd).await?;
+        Ok((result.status, result.exit.pid, result.stdout, result.stderr))
+    }
+}
+
+/// Send the [Exit] of the process `pid`, spawned by `cmd`, to `tx` and return its output.
+fn send_exit(cmd: &Command, tx: Sender<Exit>, pid: u32, out: Output) -> std::io::Result<Output> {
+    let ts = OffsetDateTime::now_utc();
+    // On Unix, out.status.code() will return None if the process was terminated by a signal.
+    let status = out.status.code().unwrap_or(-1);
+    match tx.send(Exit { ts, pid, status }) {
+        Ok(_) => Ok(out),
+        Err(e) => {
+            error!("command {:?} exited but receiver dropped.", cmd);
+            error!("couldn't send messages: {:?}", e);
+            Err(std::io::ErrorKind::ConnectionRefused.into())
+        }
+    }
+}
+
 /// Process exit status returned by [ProcessMonitor::wait()].
 #[derive(Debug)]
 pub struct Exit {
@@ -130,6 +219,78 @@ pub async fn execute<T: ProcessMonitor + Send + Sync>(
     })
 }
 
+/// Wait for the process `pid` to exit, without polling, and return its exit status.
+///
+/// Unlike [ProcessMonitor], the process doesn't have to be spawned by us: it must only be a child
+/// of the calling process, or of a subreaper it is (see `PR_SET_CHILD_SUBREAPER`), which nobody
+/// else reaps, e.g. the init process of a container. A process which already exited is reaped
+/// right away. Its exit is signalled by a pidfd, or waited for by a blocking thread on kernels
+/// before 5.3.
+pub async fn wait_pid(pid: i32) -> io::Result<ExitStatus> {
+    let pidfd = try_pidfd_open(pid)?;
+    wait_child(pid, pidfd).await
+}
+
+/// Wait for the child process `pid` to exit through `pidfd`, or by a blocking thread when
+/// there's none, and reap it.
+async fn wait_child(pid: i32, pidfd: Option<OwnedFd>) -> io::Result<ExitStatus> {
+    match pidfd {
+        Some(pidfd) => wait_pidfd(pidfd).await,
+        None => tokio::task::spawn_blocking(move || waitid(libc::P_PID, pid as libc::id_t))
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?,
+    }
+}
+
+/// Open a pidfd referring to the process `pid`, `None` before Linux 5.3.
+fn try_pidfd_open(pid: i32) -> io::Result<Option<OwnedFd>> {
+    match pidfd_open(pid) {
+        Ok(pidfd) => Ok(Some(pidfd)),
+        Err(e) if e.raw_os_error() == Some(libc::ENOSYS) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Open a pidfd referring to the process `pid`, failing with `ENOSYS` before Linux 5.3.
+fn pidfd_open(pid: i32) -> io::Result<OwnedFd> {
+    // Safety: pidfd_open only takes integers.
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Safety: the fd was just opened, nothing else owns it.
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) })
+}
+
+/// Wait for the child process `pidfd` refers to to exit, and reap it.
+async fn wait_pidfd(pidfd: OwnedFd) -> io::Result<ExitStatus> {
+    let pidfd = AsyncFd::with_interest(pidfd, Interest::READABLE)?;
+    // A pidfd becomes readable once the process exited, and stays so.
+    let _ready = pidfd.readable().await?;
+    waitid(libc::P_PIDFD, pidfd.as_raw_fd() as libc::id_t)
+}
+
+/// Reap the child process identified by `id`, blocking until it exits.
+fn waitid(idtype: libc::idtype_t, id: libc::id_t) -> io::Result<ExitStatus> {
+    // Safety: siginfo_t is plain old data.
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    // Safety: waitid only writes to info.
+    while unsafe { libc::waitid(idtype, id, &mut info, libc::WEXITED) } < 0 {
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+    // Safety: si_status is set for the SIGCHLD info filled by waitid.
+    let status = unsafe { info.si_status() };
+    // Encode it like the status returned by waitpid.
+    Ok(ExitStatus::from_raw(match info.si_code {
+        libc::CLD_EXITED => status << 8,
+        libc::CLD_DUMPED => status | 0x80,
+        _ => status,
+    }))
+}
+
 #[cfg(test)]
 mod tests {
     use std::process::Stdio;
@@ -177,4 +338,89 @@ mod tests {
         assert!(!result.stdout.is_empty());
         assert_eq!(result.stderr.len(), 0);
     }
+
+    #[tokio::test]
+    async fn test_wait_pid() {
+        // Spawned without tokio, which would reap them itself.
+        let spawn = |script: &str| {
+            std::process::Command::new("/bin/sh")
+                .arg("-c")
+                .arg(script)
+                .spawn()
+                .unwrap()
+                .id() as i32
+        };
+
+        let status = wait_pid(spawn("sleep 0.1; exit 3")).await.unwrap();
+        assert_eq!(status.code(), Some(3));
+
+        let status = wait_pid(spawn("kill -9 $$")).await.unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGKILL));
+
+        // Already exited, but not reaped yet.
+        let pid = spawn("exit 4");
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert_eq!(wait_pid(pid).await.unwrap().code(), Some(4));
+        // Reaped.
+        assert!(wait_pid(pid).await.is_err());
+    }
+
+    /// Whether pidfd_open is available, i.e. the kernel is Linux 5.3 or later.
+    fn pidfd_supported() -> bool {
+        let release = std::fs::read_to_string("/proc/sys/kernel/osrelease").unwrap();
+        let mut version = release
+            .split(|c: char| !c.is_ascii_digit())
+            .map(|n| n.parse::<u32>().unwrap_or(0));
+        (version.next().unwrap_or(0), version.next().unwrap_or(0)) >= (5, 3)
+    }
+
+    #[tokio::test]
+    async fn test_pidfd_monitor() {
+        if !pidfd_supported() {
+            return;
+        }
+        let monitor = PidfdMonitor::new();
+        let mut cmd = Command::new("/bin/sh");
+        cmd.arg("-c")
+            .arg("echo out; echo err >&2; exit 3")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let result = execute(&monitor, cmd).await.unwrap();
+        assert_eq!(result.exit.status, 3);
+        assert_eq!(result.status.code(), Some(3));
+        assert_eq!(result.stdout, "out\n");
+        assert_eq!(result.stderr, "err\n");
+
+        let mut cmd = Command::new("/bin/sh");
+        cmd.arg("-c").arg("kill -9 $$");
+        let (status, pid, _, _) = Spawner::execute(&monitor, cmd).await.unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGKILL));
+        assert!(pid > 0);
+    }
+
+    #[tokio::test]
+    async fn test_pidfd_monitor_other_children() {
+        if !pidfd_supported() {
+            return;
+        }
+        // A child not spawned by tokio, exiting while the monitor runs a command.
+        let other = std::process::Command::new("/bin/sh")
+            .arg("-c")
+            .arg("exit 7")
+            .spawn()
+            .unwrap()
+            .id();
+        let monitor = PidfdMonitor::new();
+        let (status, _, _, _) = Spawner::execute(&monitor, Command::new("/bin/true"))
+            .await
+            .unwrap();
+        assert!(status.success());
+
+        // Let tokio handle the SIGCHLD of the exits, and reap its orphans.
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        Command::new("/bin/true").status().await.unwrap();
+
+        // Neither the monitor nor tokio reaped the other child.
+        assert_eq!(wait_pid(other as i32).await.unwrap().code(), Some(7));
+    }
 }
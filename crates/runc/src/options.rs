@@ -34,24 +34,32 @@
  */
 
 use std::{
+    borrow::Cow,
+    os::unix::io::RawFd,
     path::{Path, PathBuf},
     sync::Arc,
     time::Duration,
 };
 
-use crate::{error::Error, io::Io, utils, DefaultExecutor, LogFormat, Runc, Spawner};
+use oci_spec::runtime::{Capability, PosixRlimit, Process};
+
+use crate::{
+    error::Error, io::Io, utils, CommandCallback, DefaultExecutor, LogFormat, PreExecHook, Runc,
+    Spawner,
+};
 
 // constants for log format
 pub const JSON: &str = "json";
 pub const TEXT: &str = "text";
 
 // constants for runc global flags
+pub(crate) const CRIU: &str = "--criu";
 const DEBUG: &str = "--debug";
-const LOG: &str = "--log";
-const LOG_FORMAT: &str = "--log-format";
-const ROOT: &str = "--root";
-const ROOTLESS: &str = "--rootless";
-const SYSTEMD_CGROUP: &str = "--systemd-cgroup";
+pub(crate) const LOG: &str = "--log";
+pub(crate) const LOG_FORMAT: &str = "--log-format";
+pub(crate) const ROOT: &str = "--root";
+pub(crate) const ROOTLESS: &str = "--rootless";
+pub(crate) const SYSTEMD_CGROUP: &str = "--systemd-cgroup";
 
 // constants for runc-create/runc-exec flags
 const CONSOLE_SOCKET: &str = "--console-socket";
@@ -60,6 +68,18 @@ const NO_NEW_KEYRING: &str = "--no-new-keyring";
 const NO_PIVOT: &str = "--no-pivot";
 const PID_FILE: &str = "--pid-file";
 
+// constants for runc-checkpoint/runc-restore flags
+const IMAGE_PATH: &str = "--image-path";
+const WORK_PATH: &str = "--work-path";
+const PARENT_PATH: &str = "--parent-path";
+const LEAVE_RUNNING: &str = "--leave-running";
+const PRE_DUMP: &str = "--pre-dump";
+const NO_SUBREAPER: &str = "--no-subreaper";
+const TCP_ESTABLISHED: &str = "--tcp-established";
+const EXT_UNIX_SK: &str = "--ext-unix-sk";
+const SHELL_JOB: &str = "--shell-job";
+const FILE_LOCKS: &str = "--file-locks";
+
 // constants for runc-kill flags
 const ALL: &str = "--all";
 
@@ -68,6 +88,7 @@ const FORCE: &str = "--force";
 
 // constant for command
 pub const DEFAULT_COMMAND: &str = "runc";
+pub const DEFAULT_CRIU: &str = "criu";
 
 pub trait Args {
     type Output;
@@ -83,12 +104,16 @@ pub trait Args {
 pub struct GlobalOpts {
     /// Override the name of the runc binary. If [`None`], `runc` is used.
     command: Option<PathBuf>,
+    /// Path of the CRIU binary used for checkpoint and restore. If [`None`], `criu` is used.
+    criu: Option<PathBuf>,
     /// Debug logging.
     ///
     /// If true, debug level logs are emitted.
     debug: bool,
     /// Path to log file.
     log: Option<PathBuf>,
+    /// Already open log file descriptor, takes precedence over `log`.
+    log_fd: Option<RawFd>,
     /// Log format to use.
     log_format: LogFormat,
     /// Path to root directory of container rootfs.
@@ -104,9 +129,47 @@ pub struct GlobalOpts {
     systemd_cgroup: bool,
     /// Timeout settings for runc command.
     ///
-    /// Default is 5 seconds.
+    /// Default is zero, which means to wait for the command forever.
     /// This will be used only in AsyncClient.
     timeout: Duration,
+    /// Timeout after which runc commands are killed.
+    /// This will be used only in the sync client.
+    blocking_timeout: Option<Duration>,
+    /// Don't check container ids before running runc.
+    skip_id_validation: bool,
+    /// Keep the stderr of runc out of the output of successful commands.
+    separate_output: bool,
+    /// Validate the seccomp profile of the bundle before creating a container.
+    validate_seccomp: bool,
+    /// Check the root filesystem of the bundle exists before creating a container.
+    validate_rootfs: bool,
+    /// Check the mounts of the bundle are supported by the host before creating a container.
+    validate_mounts: bool,
+    /// Check the bundle's OCI spec version against the versions supported by runc before
+    /// creating a container.
+    check_oci_version: bool,
+    /// Working directory of the runc process.
+    working_dir: Option<PathBuf>,
+    /// Directory the bundles removed by `delete_and_cleanup` must be in.
+    bundle_root: Option<PathBuf>,
+    /// Minimum free space of the state root before creating a container.
+    min_root_free: Option<u64>,
+    /// Serialize mutating operations per container id.
+    /// This will be used only in AsyncClient.
+    lock_per_container: bool,
+    /// Record a latency histogram per subcommand.
+    /// This will be used only in AsyncClient.
+    record_latency: bool,
+    /// Command wrapping runc, if any.
+    launcher: Option<Launcher>,
+    /// Callback invoked after each runc command.
+    on_command_complete: Option<CommandCallback>,
+    /// Id attached to the log records of each runc command.
+    correlation_id: Option<String>,
+    /// OOM score adjustment of the runc process.
+    oom_score_adj: Option<i32>,
+    /// Hooks run in the runc child before exec.
+    pre_exec: Vec<PreExecHook>,
     /// executor that runs the commands
     executor: Option<Arc<dyn Spawner + Send + Sync>>,
 }
@@ -122,6 +185,14 @@ impl GlobalOpts {
         self
     }
 
+    /// Set the path of the CRIU binary runc uses to checkpoint and restore containers.
+    ///
+    /// The default is `criu`, looked up in `PATH`. [Runc::criu_features] probes the same binary.
+    pub fn criu(mut self, criu: impl AsRef<Path>) -> Self {
+        self.criu = Some(criu.as_ref().to_path_buf());
+        self
+    }
+
     /// Set the root directory to store containers' state.
     ///
     /// The path should be located on tmpfs.
@@ -145,6 +216,20 @@ impl GlobalOpts {
         self
     }
 
+    /// Set the log destination to the already open file descriptor `fd`, instead of a path.
+    ///
+    /// runc only accepts a log path, so `fd` is inherited by runc and passed as
+    /// `/proc/self/fd/<fd>`, which works with every runc version. `fd` must stay open as long as
+    /// the [Runc] instance is used. Takes precedence over [GlobalOpts::log].
+    ///
+    /// [GlobalOpts::build] fails with [Error::LogFdUnavailable] when `fd` can't be reached this
+    /// way: without procfs, or with a [GlobalOpts::launcher], which may not pass inherited fds
+    /// on to runc (e.g. `systemd-run`). Use [GlobalOpts::log] then.
+    pub fn log_fd(mut self, fd: RawFd) -> Self {
+        self.log_fd = Some(fd);
+        self
+    }
+
     /// Set the log format (default is text).
     pub fn log_format(mut self, log_format: LogFormat) -> Self {
         self.log_format = log_format;
@@ -172,7 +257,9 @@ impl GlobalOpts {
 
     /// Enable or disable rootless mode.
     ///
-    // Default is auto, meaning to auto-detect whether rootless should be enabled.
+    /// Default is auto, meaning to auto-detect whether rootless should be enabled. When enabled
+    /// and [GlobalOpts::root] isn't set, the state root defaults to
+    /// [utils::default_root(true)](utils::default_root).
     pub fn rootless(mut self, rootless: bool) -> Self {
         self.rootless = Some(rootless);
         self
@@ -189,11 +276,203 @@ impl GlobalOpts {
         self
     }
 
+    /// Check container ids with [utils::validate_id] before running runc, which is the default.
+    ///
+    /// An invalid id is then reported as [Error::InvalidContainerId] instead of an opaque runc
+    /// failure, and can't be used to reach outside of the state root.
+    pub fn validate_ids(mut self, validate: bool) -> Self {
+        self.skip_id_validation = !validate;
+        self
+    }
+
+    /// Append the stderr of runc to its stdout in the output of successful commands, which is the
+    /// default.
+    ///
+    /// When disabled, the output is only the stdout of runc, so that warnings runc logs to
+    /// stderr don't break the parsing of the JSON returned by e.g. [Runc::list] and
+    /// [Runc::state].
+    pub fn combine_output(mut self, combine: bool) -> Self {
+        self.separate_output = !combine;
+        self
+    }
+
+    /// Check the seccomp profile in the bundle's `config.json` before `create` and `run`.
+    ///
+    /// A malformed profile is then reported as [Error::InvalidSeccompProfile] instead of a
+    /// failure deep inside runc. See [crate::spec::validate_seccomp].
+    pub fn validate_seccomp(mut self, validate: bool) -> Self {
+        self.validate_seccomp = validate;
+        self
+    }
+
+    /// Check the root filesystem of the bundle exists before `create` and `run`.
+    ///
+    /// A missing rootfs is then reported as [Error::RootfsNotFound]. See
+    /// [crate::spec::validate_rootfs].
+    pub fn validate_rootfs(mut self, validate: bool) -> Self {
+        self.validate_rootfs = validate;
+        self
+    }
+
+    /// Check the filesystem types and options of the bundle's mounts are supported by the host
+    /// before `create` and `run`.
+    ///
+    /// An unsupported mount is then reported as [Error::UnsupportedMountOption]. See
+    /// [crate::spec::validate_mounts].
+    pub fn validate_mounts(mut self, validate: bool) -> Self {
+        self.validate_mounts = validate;
+        self
+    }
+
+    /// Check the `ociVersion` of the bundle's spec against the range supported by runc (see
+    /// [Runc::features]) before `create` and `run`.
+    ///
+    /// This costs an extra runc invocation per container, and an incompatible spec is reported
+    /// as [Error::IncompatibleOciVersion] instead of an obscure runc failure.
+    pub fn check_oci_version(mut self, check: bool) -> Self {
+        self.check_oci_version = check;
+        self
+    }
+
+    /// Set the working directory of the runc process itself.
+    ///
+    /// By default runc inherits the working directory of the caller. This is independent of the
+    /// container's working directory, but matters e.g. for relative paths and core dumps of runc.
+    pub fn working_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.working_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Directory containing the bundles, which [Runc::delete_and_cleanup] is allowed to remove.
+    ///
+    /// Without it, [Runc::delete_and_cleanup] refuses to remove any bundle.
+    pub fn bundle_root(mut self, root: impl AsRef<Path>) -> Self {
+        self.bundle_root = Some(root.as_ref().to_path_buf());
+        self
+    }
+
+    /// Check that the filesystem of the state root (see [GlobalOpts::root]) has at least `bytes`
+    /// available before `create` and `run`.
+    ///
+    /// The state root is usually a small tmpfs, and a full one makes runc fail with ENOSPC deep
+    /// in container creation. It's reported as [Error::StateDirFull] instead.
+    pub fn min_root_free(mut self, bytes: u64) -> Self {
+        self.min_root_free = Some(bytes);
+        self
+    }
+
+    /// Serialize the operations changing the state of a container (create, start, kill,
+    /// delete...) per container id, while different containers are still handled concurrently.
+    ///
+    /// This prevents e.g. a kill from racing a delete of the same container. Operations waiting
+    /// for the container's process (`run`, `exec` and `restore` without detach) aren't
+    /// serialized, as they would block the others until the process exits.
+    /// This will be used only in AsyncClient.
+    pub fn lock_per_container(mut self, lock: bool) -> Self {
+        self.lock_per_container = lock;
+        self
+    }
+
+    /// Record a histogram of the wall-clock latency of each subcommand, read with
+    /// [Runc::latency_snapshot].
+    ///
+    /// Unlike [GlobalOpts::on_command_complete], this needs no external metrics system, which
+    /// suits quick introspection. Recording only costs a few atomic additions per command.
+    /// This will be used only in AsyncClient.
+    pub fn record_latency(mut self, record: bool) -> Self {
+        self.record_latency = record;
+        self
+    }
+
+    /// Launch runc through `launcher`, e.g. in a transient systemd scope with
+    /// [Launcher::systemd_scope].
+    ///
+    /// By default runc is executed directly. Unlike [GlobalOpts::systemd_cgroup], which tells
+    /// runc to manage the container's cgroup through systemd, this only affects where the runc
+    /// process itself runs.
+    pub fn launcher(mut self, launcher: Launcher) -> Self {
+        self.launcher = Some(launcher);
+        self
+    }
+
+    /// Call `callback` after each runc command with the subcommand (e.g. "create"), how long it
+    /// took, and whether it succeeded.
+    ///
+    /// This allows to feed any metrics system, e.g. with counters and histograms per subcommand.
+    pub fn on_command_complete<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&str, Duration, bool) + Send + Sync + 'static,
+    {
+        self.on_command_complete = Some(CommandCallback(Arc::new(callback)));
+        self
+    }
+
+    /// Run `hook` in the runc child process, after fork and before runc is executed, e.g. to
+    /// set resource limits or enter namespaces for runc itself.
+    ///
+    /// Hooks run in the order they were added, after the ones the crate sets up (e.g. for
+    /// [GlobalOpts::oom_score_adj]). An error returned by a hook aborts the command, which fails
+    /// to spawn with that error. With a [GlobalOpts::launcher], the hooks run before the
+    /// launcher is executed.
+    ///
+    /// # Safety
+    ///
+    /// The hook runs in a child forked from a possibly multi-threaded process, in which only the
+    /// forking thread survives: locks held by other threads at the time of the fork stay locked
+    /// forever. It must therefore only call async-signal-safe functions (see `signal-safety(7)`),
+    /// e.g. raw syscalls like `setrlimit` or `setns`, and must not allocate, lock a mutex, log,
+    /// nor access anything which may be in use by another thread. The requirements of
+    /// [std::os::unix::process::CommandExt::pre_exec] apply, for the sync and async clients
+    /// alike.
+    pub unsafe fn pre_exec<F>(mut self, hook: F) -> Self
+    where
+        F: Fn() -> std::io::Result<()> + Send + Sync + 'static,
+    {
+        self.pre_exec.push(PreExecHook(Arc::new(hook)));
+        self
+    }
+
+    /// Attach `id` as the `correlation_id` key-value to the log records of each runc command,
+    /// so that they can be tied back to the request which triggered them.
+    ///
+    /// The id is also stamped on the runc log entries streamed by the async client, e.g. by
+    /// `Runc::create_with_logs`, see [crate::logs::LogEntry::correlation_id]. With the `tracing`
+    /// feature, it's also a field of the `runc` span covering each command.
+    pub fn correlation_id(mut self, id: impl Into<String>) -> Self {
+        self.correlation_id = Some(id.into());
+        self
+    }
+
+    /// Set the `oom_score_adj` of the runc process, inherited by the container's processes.
+    ///
+    /// A positive value makes the OOM killer pick the containers before the calling manager.
+    /// Values outside of -1000..=1000 make [GlobalOpts::build] fail with
+    /// [Error::InvalidOomScoreAdj], and lowering the score below the caller's requires
+    /// `CAP_SYS_RESOURCE`.
+    pub fn oom_score_adj(mut self, score: i32) -> Self {
+        self.oom_score_adj = Some(score);
+        self
+    }
+
     pub fn timeout(&mut self, millis: u64) -> &mut Self {
         self.timeout = Duration::from_millis(millis);
         self
     }
 
+    /// Kill the runc commands still running after `timeout`, failing them with
+    /// `Error::Timeout`, instead of waiting for them forever.
+    ///
+    /// runc is run in its own process group, which is killed with `SIGKILL`, so a container
+    /// being created may be left behind. Its output is read until the timeout even if runc
+    /// exited, as the processes it forked may hold it. The timeout is applied by
+    /// `Spawner::execute_with_timeout`, which custom spawners must implement for it to have an
+    /// effect.
+    /// This will be used only in the sync client, see [GlobalOpts::timeout] for the async one.
+    pub fn blocking_timeout(mut self, timeout: Duration) -> Self {
+        self.blocking_timeout = Some(timeout);
+        self
+    }
+
     pub fn custom_spawner(&mut self, executor: Arc<dyn Spawner + Send + Sync>) -> &mut Self {
         self.executor = Some(executor);
         self
@@ -203,6 +482,18 @@ impl GlobalOpts {
         self.args()
     }
 
+    /// State root passed to runc, if any.
+    ///
+    /// In auto rootless mode runc picks the right default itself, it's only set explicitly when
+    /// rootless mode is forced.
+    fn state_root(&self) -> Option<PathBuf> {
+        match (&self.root, self.rootless) {
+            (Some(root), _) => Some(root.clone()),
+            (None, Some(true)) => Some(utils::default_root(true)),
+            (None, _) => None,
+        }
+    }
+
     fn output(&self) -> Result<(PathBuf, Vec<String>), Error> {
         let path = self
             .command
@@ -214,18 +505,27 @@ impl GlobalOpts {
         let mut args = Vec::new();
 
         // --root path : Set the root directory to store containers' state.
-        if let Some(root) = &self.root {
+        if let Some(root) = self.state_root() {
             args.push(ROOT.into());
             args.push(utils::abs_string(root)?);
         }
 
+        // --criu path : Set the path of the CRIU binary.
+        if let Some(criu) = &self.criu {
+            args.push(CRIU.into());
+            args.push(criu.to_string_lossy().to_string());
+        }
+
         // --debug : Enable debug logging.
         if self.debug {
             args.push(DEBUG.into());
         }
 
         // --log path : Set the log destination to path. The default is to log to stderr.
-        if let Some(log_path) = &self.log {
+        if let Some(fd) = self.log_fd {
+            args.push(LOG.into());
+            args.push(format!("/proc/self/fd/{}", fd));
+        } else if let Some(log_path) = &self.log {
             args.push(LOG.into());
             args.push(utils::abs_string(log_path)?);
         }
@@ -252,6 +552,20 @@ impl Args for GlobalOpts {
     type Output = Result<Runc, Error>;
 
     fn args(&self) -> Self::Output {
+        if let Some(score) = self.oom_score_adj.filter(|s| !(-1000..=1000).contains(s)) {
+            return Err(Error::InvalidOomScoreAdj(score));
+        }
+        if let Some(fd) = self.log_fd {
+            if self.launcher.is_some() {
+                return Err(Error::LogFdUnavailable(fd, "a launcher may not pass it on"));
+            }
+            if std::fs::symlink_metadata(format!("/proc/self/fd/{}", fd)).is_err() {
+                return Err(Error::LogFdUnavailable(
+                    fd,
+                    "it isn't open or procfs is missing",
+                ));
+            }
+        }
         let (command, args) = self.output()?;
         let executor = if let Some(exec) = self.executor.clone() {
             exec
@@ -261,11 +575,82 @@ impl Args for GlobalOpts {
         Ok(Runc {
             command,
             args,
+            criu: self
+                .criu
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_CRIU)),
             spawner: executor,
+            #[cfg(feature = "async")]
+            timeout: self.timeout,
+            #[cfg(not(feature = "async"))]
+            timeout: self.blocking_timeout,
+            validate_ids: !self.skip_id_validation,
+            combine_output: !self.separate_output,
+            validate_seccomp: self.validate_seccomp,
+            validate_rootfs: self.validate_rootfs,
+            validate_mounts: self.validate_mounts,
+            check_oci_version: self.check_oci_version,
+            working_dir: self.working_dir.clone(),
+            launcher: self.launcher.clone(),
+            #[cfg(feature = "async")]
+            container_locks: self.lock_per_container.then(Default::default),
+            #[cfg(feature = "async")]
+            capabilities: Default::default(),
+            #[cfg(feature = "async")]
+            latencies: self.record_latency.then(Default::default),
+            bundle_root: self.bundle_root.clone(),
+            log_fd: self.log_fd,
+            state_root: self.state_root().unwrap_or_else(utils::default_state_root),
+            min_root_free: self.min_root_free,
+            on_command_complete: self.on_command_complete.clone(),
+            correlation_id: self.correlation_id.clone(),
+            oom_score_adj: self.oom_score_adj,
+            pre_exec: self.pre_exec.clone(),
         })
     }
 }
 
+/// Command runc is launched through, receiving the runc command line as its arguments.
+///
+/// For instance `Launcher::new("systemd-run").arg("--scope")` runs
+/// `systemd-run --scope runc [args...]`.
+#[derive(Debug, Clone)]
+pub struct Launcher {
+    program: PathBuf,
+    args: Vec<String>,
+}
+
+impl Launcher {
+    pub fn new(program: impl AsRef<Path>) -> Self {
+        Self {
+            program: program.as_ref().to_path_buf(),
+            args: Vec::new(),
+        }
+    }
+
+    /// Launch runc in a transient systemd scope, collected once runc exits.
+    pub fn systemd_scope() -> Self {
+        Self::new("systemd-run")
+            .arg("--scope")
+            .arg("--quiet")
+            .arg("--collect")
+    }
+
+    /// Append an argument, passed before the runc command line.
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn program(&self) -> &Path {
+        &self.program
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+}
+
 #[derive(Clone, Default)]
 pub struct CreateOpts {
     pub io: Option<Arc<dyn Io>>,
@@ -279,6 +664,15 @@ pub struct CreateOpts {
     pub no_pivot: bool,
     /// A new session keyring for the container will not be created.
     pub no_new_keyring: bool,
+    /// Don't make runc the subreaper of the container's process (only available for run, create
+    /// fails when it is set)
+    pub no_subreaper: bool,
+    /// Override the global timeout for this call, only used by the async client.
+    pub timeout: Option<Duration>,
+    /// Override the global systemd cgroup setting for this call.
+    pub systemd_cgroup: Option<bool>,
+    /// Override the global rootless mode for this call, `Some(None)` meaning auto.
+    pub rootless: Option<Option<bool>>,
 }
 
 impl Args for CreateOpts {
@@ -312,6 +706,23 @@ impl CreateOpts {
         Self::default()
     }
 
+    /// Arguments of `runc create`, which has no `--no-subreaper` flag.
+    pub(crate) fn create_args(&self) -> Result<Vec<String>, Error> {
+        if self.no_subreaper {
+            return Err(Error::RunOnlyOption(NO_SUBREAPER));
+        }
+        self.args()
+    }
+
+    /// Arguments of `runc run`, which also takes `--no-subreaper`.
+    pub(crate) fn run_args(&self) -> Result<Vec<String>, Error> {
+        let mut args = self.args()?;
+        if self.no_subreaper {
+            args.push(NO_SUBREAPER.to_string());
+        }
+        Ok(args)
+    }
+
     pub fn io(mut self, io: Arc<dyn Io>) -> Self {
         self.io = Some(io);
         self
@@ -347,6 +758,51 @@ impl CreateOpts {
         self.no_new_keyring = no_new_keyring;
         self
     }
+
+    /// Don't make runc the subreaper of the container's process, e.g. because the caller
+    /// already is one. Only `runc run` supports it, [Runc::create] fails with
+    /// [Error::RunOnlyOption] when it is set.
+    /// Orphaned descendants of the container's process are then reparented to the caller's
+    /// subreaper instead of runc, so the caller must reap them, typically from the reaper feeding
+    /// its `ProcessMonitor`, and must not assume every exit it sees is the one of a process it
+    /// spawned.
+    pub fn no_subreaper(mut self, no_subreaper: bool) -> Self {
+        self.no_subreaper = no_subreaper;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Let systemd manage the cgroup of this container or not, whatever
+    /// [GlobalOpts::systemd_cgroup] is, e.g. for a node running both kinds of containers.
+    ///
+    /// This takes precedence over the global setting for this create or run only: `true` passes
+    /// the global `--systemd-cgroup` flag to runc, `false` leaves it out. Unless this is called,
+    /// the global setting applies.
+    pub fn systemd_cgroup(mut self, systemd_cgroup: bool) -> Self {
+        self.systemd_cgroup = Some(systemd_cgroup);
+        self
+    }
+
+    /// Enable or disable rootless mode for this call only, whatever [GlobalOpts::rootless] is,
+    /// e.g. for a process managing both rootful and rootless containers.
+    ///
+    /// The global `--rootless` flag is replaced by this one. Note that the state root is still
+    /// the global one.
+    pub fn rootless(mut self, rootless: bool) -> Self {
+        self.rootless = Some(Some(rootless));
+        self
+    }
+
+    /// Let runc auto-detect rootless mode for this call only, leaving out the global
+    /// `--rootless` flag.
+    pub fn rootless_auto(mut self) -> Self {
+        self.rootless = Some(None);
+        self
+    }
 }
 
 /// Container execution options
@@ -357,8 +813,22 @@ pub struct ExecOpts {
     pub pid_file: Option<PathBuf>,
     /// Path to where a console socket should be created.
     pub console_socket: Option<PathBuf>,
-    /// Detach from the container's process (only available for run)
+    /// Return once the process is started instead of waiting for it to exit.
     pub detach: bool,
+    /// Capabilities added to the process, on top of the ones of its spec.
+    pub caps: Vec<Capability>,
+    /// Names of capabilities added to the process, validated before runc is spawned.
+    pub cap_names: Vec<String>,
+    /// SELinux label of the process, overriding the one of its spec.
+    pub process_label: Option<String>,
+    /// Override the global timeout for this call, only used by the async client.
+    pub timeout: Option<Duration>,
+    /// Pass the process spec to runc through its stdin rather than a temporary file.
+    pub process_via_stdin: bool,
+    /// Resource limits of the process, overriding the ones of its spec.
+    pub rlimits: Vec<PosixRlimit>,
+    /// Override the global rootless mode for this call, `Some(None)` meaning auto.
+    pub rootless: Option<Option<bool>>,
 }
 
 impl Args for ExecOpts {
@@ -386,11 +856,400 @@ impl ExecOpts {
         Self::default()
     }
 
+    /// Add `caps` to the bounding, effective and permitted capabilities of the process.
+    ///
+    /// runc ignores its `--cap` flag when the process is given as a spec, so the capabilities are
+    /// added to the spec written for runc instead. This grants the exec'd process privileges the
+    /// container's own processes may not have, e.g. `CAP_SYS_PTRACE` lets it inspect and alter
+    /// every process of the container: only use it for trusted debugging tools.
+    pub fn caps(mut self, caps: Vec<Capability>) -> Self {
+        self.caps = caps;
+        self
+    }
+
+    /// Like [ExecOpts::caps], with capabilities given by name, e.g. from user input.
+    ///
+    /// The names are checked against the known Linux capabilities before runc is spawned, an
+    /// unknown name making exec fail with [Error::UnknownCapability].
+    pub fn cap_names<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.cap_names = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Pass the process spec to runc through a pipe on its stdin, read as `--process /dev/stdin`,
+    /// instead of writing it to a temporary file, which saves the filesystem churn of frequent
+    /// execs.
+    ///
+    /// This works with any runc version, but stdin is then not available to the process: the
+    /// temporary file is still used when an [ExecOpts::io] is set, as it may provide stdin, or
+    /// when the spec is too large to be held by a pipe. It's also used when runc is run by a
+    /// [Launcher], which may not pass stdin on or run runc in another
+    /// mount namespace, and when `/dev/stdin` or `/proc` are missing.
+    pub fn process_via_stdin(mut self, via_stdin: bool) -> Self {
+        self.process_via_stdin = via_stdin;
+        self
+    }
+
+    /// Run the process with the SELinux label `label`, like `runc exec --process-label`.
+    pub fn process_label(mut self, label: impl Into<String>) -> Self {
+        self.process_label = Some(label.into());
+        self
+    }
+
+    /// Set the resource limits of the process, e.g. a higher `RLIMIT_NOFILE` for a debug shell,
+    /// replacing the limits of the same types in its spec, usually the container init's.
+    ///
+    /// A soft limit above its hard limit makes exec fail with [Error::InvalidRlimit] before runc
+    /// is spawned.
+    pub fn rlimits(mut self, rlimits: Vec<PosixRlimit>) -> Self {
+        self.rlimits = rlimits;
+        self
+    }
+
+    /// Enable or disable rootless mode for this exec only, see [CreateOpts::rootless].
+    pub fn rootless(mut self, rootless: bool) -> Self {
+        self.rootless = Some(Some(rootless));
+        self
+    }
+
+    /// Let runc auto-detect rootless mode for this exec only, see [CreateOpts::rootless_auto].
+    pub fn rootless_auto(mut self) -> Self {
+        self.rootless = Some(None);
+        self
+    }
+
+    /// Return `spec` with the options which runc only takes through the process spec applied.
+    pub(crate) fn process<'a>(&self, spec: &'a Process) -> Result<Cow<'a, Process>, Error> {
+        if self.caps.is_empty()
+            && self.cap_names.is_empty()
+            && self.process_label.is_none()
+            && self.rlimits.is_empty()
+        {
+            return Ok(Cow::Borrowed(spec));
+        }
+        let named = self
+            .cap_names
+            .iter()
+            .map(|name| crate::spec::parse_capability(name))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut spec = spec.clone();
+        crate::spec::add_capabilities(&mut spec, &self.caps);
+        crate::spec::add_capabilities(&mut spec, &named);
+        if let Some(label) = &self.process_label {
+            spec.set_selinux_label(Some(label.clone()));
+        }
+        crate::spec::merge_rlimits(&mut spec, &self.rlimits)?;
+        Ok(Cow::Owned(spec))
+    }
+
+    pub fn io(mut self, io: Arc<dyn Io>) -> Self {
+        self.io = Some(io);
+        self
+    }
+
+    pub fn pid_file<P>(mut self, pid_file: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.pid_file = Some(pid_file.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn console_socket<P>(mut self, console_socket: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.console_socket = Some(console_socket.as_ref().to_path_buf());
+        self
+    }
+
+    /// Run the process in the background, like `runc exec --detach`.
+    ///
+    /// Combined with a [pid file](ExecOpts::pid_file), exec returns the pid of the process and
+    /// removes the pid file once read.
+    pub fn detach(mut self, detach: bool) -> Self {
+        self.detach = detach;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
+/// Flags locating the CRIU images, shared by [CheckpointOpts] and [RestoreOpts].
+fn criu_paths(
+    image_path: &Option<PathBuf>,
+    work_path: &Option<PathBuf>,
+) -> Result<Vec<String>, Error> {
+    let mut args = vec![];
+    if let Some(image_path) = image_path {
+        args.push(IMAGE_PATH.to_string());
+        args.push(utils::abs_string(image_path)?);
+    }
+    if let Some(work_path) = work_path {
+        args.push(WORK_PATH.to_string());
+        args.push(utils::abs_string(work_path)?);
+    }
+    Ok(args)
+}
+
+/// Flags of the resources CRIU handles, shared by [CheckpointOpts] and [RestoreOpts] as both
+/// sides must agree on them.
+fn criu_flags(
+    tcp_established: bool,
+    ext_unix_sk: bool,
+    shell_job: bool,
+    file_locks: bool,
+) -> Vec<String> {
+    [
+        (tcp_established, TCP_ESTABLISHED),
+        (ext_unix_sk, EXT_UNIX_SK),
+        (shell_job, SHELL_JOB),
+        (file_locks, FILE_LOCKS),
+    ]
+    .iter()
+    .filter(|(set, _)| *set)
+    .map(|(_, flag)| flag.to_string())
+    .collect()
+}
+
+/// Container checkpoint options
+#[derive(Debug, Clone, Default)]
+pub struct CheckpointOpts {
+    /// Path to write the checkpoint images to, runc defaults to `checkpoint` in its working
+    /// directory.
+    pub image_path: Option<PathBuf>,
+    /// Path where CRIU writes its work files and logs, defaults to the image path.
+    pub work_path: Option<PathBuf>,
+    /// Path to the images of a previous checkpoint, to only dump the memory changed since.
+    pub parent_path: Option<PathBuf>,
+    /// Leave the container running after the checkpoint, instead of stopping it.
+    pub leave_running: bool,
+    /// Only dump the memory of the container, which keeps running, see
+    /// [CheckpointOpts::pre_dump].
+    pub pre_dump: bool,
+    /// Checkpoint established TCP connections.
+    pub tcp_established: bool,
+    /// Checkpoint external unix sockets.
+    pub ext_unix_sk: bool,
+    /// Checkpoint a shell job, i.e. a process attached to a terminal.
+    pub shell_job: bool,
+    /// Checkpoint file locks.
+    pub file_locks: bool,
+    /// Remove the image path when the checkpoint fails, instead of leaving it for debugging.
+    pub cleanup_on_failure: bool,
+    /// Override the global timeout for this call, only used by the async client.
+    pub timeout: Option<Duration>,
+}
+
+impl Args for CheckpointOpts {
+    type Output = Result<Vec<String>, Error>;
+
+    fn args(&self) -> Self::Output {
+        let mut args = criu_paths(&self.image_path, &self.work_path)?;
+        if let Some(parent_path) = &self.parent_path {
+            args.push(PARENT_PATH.to_string());
+            args.push(utils::abs_string(parent_path)?);
+        }
+        if self.pre_dump {
+            args.push(PRE_DUMP.to_string());
+        } else if self.leave_running {
+            args.push(LEAVE_RUNNING.to_string());
+        }
+        args.append(&mut criu_flags(
+            self.tcp_established,
+            self.ext_unix_sk,
+            self.shell_job,
+            self.file_locks,
+        ));
+        Ok(args)
+    }
+}
+
+impl CheckpointOpts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn image_path<P>(mut self, image_path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.image_path = Some(image_path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn work_path<P>(mut self, work_path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.work_path = Some(work_path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn parent_path<P>(mut self, parent_path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.parent_path = Some(parent_path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn leave_running(mut self, leave_running: bool) -> Self {
+        self.leave_running = leave_running;
+        self
+    }
+
+    /// Only dump the memory of the container, to chain iterative pre-dumps before the final
+    /// checkpoint and minimize the downtime of a migration.
+    ///
+    /// Each dump after the first one sets its [parent path](CheckpointOpts::parent_path) to the
+    /// image path of the previous one, so that only the memory changed since is dumped. A
+    /// pre-dump always leaves the container running, `--leave-running` is then left out whatever
+    /// [CheckpointOpts::leave_running] is.
+    pub fn pre_dump(mut self, pre_dump: bool) -> Self {
+        self.pre_dump = pre_dump;
+        self
+    }
+
+    pub fn tcp_established(mut self, tcp_established: bool) -> Self {
+        self.tcp_established = tcp_established;
+        self
+    }
+
+    pub fn ext_unix_sk(mut self, ext_unix_sk: bool) -> Self {
+        self.ext_unix_sk = ext_unix_sk;
+        self
+    }
+
+    pub fn shell_job(mut self, shell_job: bool) -> Self {
+        self.shell_job = shell_job;
+        self
+    }
+
+    pub fn file_locks(mut self, file_locks: bool) -> Self {
+        self.file_locks = file_locks;
+        self
+    }
+
+    /// Remove the image path, with whatever CRIU wrote to it, when the checkpoint fails.
+    ///
+    /// By default a failed checkpoint leaves the partial images in place to help debugging, and
+    /// the caller has to remove them before checkpointing to the same path again. Only an
+    /// explicit [image path](CheckpointOpts::image_path) is removed, so it must be dedicated to
+    /// this checkpoint; a separate work path, holding CRIU's logs, is always left.
+    pub fn cleanup_on_failure(mut self, cleanup: bool) -> Self {
+        self.cleanup_on_failure = cleanup;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Image path to remove after a failed checkpoint, if any.
+    pub(crate) fn failed_image_path(&self) -> Option<&Path> {
+        self.image_path
+            .as_deref()
+            .filter(|_| self.cleanup_on_failure)
+    }
+}
+
+/// Container restore options
+#[derive(Clone, Default)]
+pub struct RestoreOpts {
+    pub io: Option<Arc<dyn Io>>,
+    /// Path to the checkpoint images, runc defaults to `checkpoint` in its working directory.
+    pub image_path: Option<PathBuf>,
+    /// Path where CRIU writes its work files and logs, defaults to the image path.
+    pub work_path: Option<PathBuf>,
+    /// Path to where a pid file should be created.
+    pub pid_file: Option<PathBuf>,
+    /// Path to where a console socket should be created.
+    pub console_socket: Option<PathBuf>,
+    /// Detach from the container's process, instead of waiting for it to exit.
+    pub detach: bool,
+    /// Don't use pivot_root to jail process inside rootfs.
+    pub no_pivot: bool,
+    /// Don't make runc the subreaper of the restored process.
+    pub no_subreaper: bool,
+    /// Restore established TCP connections.
+    pub tcp_established: bool,
+    /// Restore external unix sockets.
+    pub ext_unix_sk: bool,
+    /// Restore a shell job, i.e. a process attached to a terminal.
+    pub shell_job: bool,
+    /// Restore file locks.
+    pub file_locks: bool,
+    /// Override the global timeout for this call, only used by the async client.
+    pub timeout: Option<Duration>,
+}
+
+impl Args for RestoreOpts {
+    type Output = Result<Vec<String>, Error>;
+
+    fn args(&self) -> Self::Output {
+        let mut args = criu_paths(&self.image_path, &self.work_path)?;
+        if let Some(pid_file) = &self.pid_file {
+            args.push(PID_FILE.to_string());
+            args.push(utils::abs_string(pid_file)?);
+        }
+        if let Some(console_socket) = &self.console_socket {
+            args.push(CONSOLE_SOCKET.to_string());
+            args.push(utils::abs_string(console_socket)?);
+        }
+        if self.detach {
+            args.push(DETACH.to_string());
+        }
+        if self.no_pivot {
+            args.push(NO_PIVOT.to_string());
+        }
+        if self.no_subreaper {
+            args.push(NO_SUBREAPER.to_string());
+        }
+        args.append(&mut criu_flags(
+            self.tcp_established,
+            self.ext_unix_sk,
+            self.shell_job,
+            self.file_locks,
+        ));
+        Ok(args)
+    }
+}
+
+impl RestoreOpts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
     pub fn io(mut self, io: Arc<dyn Io>) -> Self {
         self.io = Some(io);
         self
     }
 
+    pub fn image_path<P>(mut self, image_path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.image_path = Some(image_path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn work_path<P>(mut self, work_path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.work_path = Some(work_path.as_ref().to_path_buf());
+        self
+    }
+
     pub fn pid_file<P>(mut self, pid_file: P) -> Self
     where
         P: AsRef<Path>,
@@ -411,6 +1270,41 @@ impl ExecOpts {
         self.detach = detach;
         self
     }
+
+    pub fn no_pivot(mut self, no_pivot: bool) -> Self {
+        self.no_pivot = no_pivot;
+        self
+    }
+
+    pub fn no_subreaper(mut self, no_subreaper: bool) -> Self {
+        self.no_subreaper = no_subreaper;
+        self
+    }
+
+    pub fn tcp_established(mut self, tcp_established: bool) -> Self {
+        self.tcp_established = tcp_established;
+        self
+    }
+
+    pub fn ext_unix_sk(mut self, ext_unix_sk: bool) -> Self {
+        self.ext_unix_sk = ext_unix_sk;
+        self
+    }
+
+    pub fn shell_job(mut self, shell_job: bool) -> Self {
+        self.shell_job = shell_job;
+        self
+    }
+
+    pub fn file_locks(mut self, file_locks: bool) -> Self {
+        self.file_locks = file_locks;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
 }
 
 /// Container deletion options
@@ -418,6 +1312,8 @@ impl ExecOpts {
 pub struct DeleteOpts {
     /// Forcibly delete the container if it is still running
     pub force: bool,
+    /// Override the global timeout for this call, only used by the async client.
+    pub timeout: Option<Duration>,
 }
 
 impl Args for DeleteOpts {
@@ -441,6 +1337,11 @@ impl DeleteOpts {
         self.force = force;
         self
     }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
 }
 
 /// Container killing options
@@ -448,6 +1349,11 @@ impl DeleteOpts {
 pub struct KillOpts {
     /// Seng the kill signal to all the processes inside the container
     pub all: bool,
+    /// Return an error when the init process has already exited, instead of treating the
+    /// container as successfully killed.
+    pub strict: bool,
+    /// Override the global timeout for this call, only used by the async client.
+    pub timeout: Option<Duration>,
 }
 
 impl Args for KillOpts {
@@ -471,11 +1377,21 @@ impl KillOpts {
         self.all = all;
         self
     }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::env;
+    use std::{env, os::unix::io::AsRawFd};
 
     use super::*;
 
@@ -485,7 +1401,7 @@ mod tests {
     fn create_opts_test() {
         assert_eq!(
             CreateOpts::new().args().expect(ARGS_FAIL_MSG),
-            vec![String::new(); 0]
+            Vec::<String>::new()
         );
 
         assert_eq!(
@@ -530,13 +1446,27 @@ mod tests {
                 "--detach".to_string(),
             ]
         );
+
+        assert_eq!(
+            CreateOpts::new()
+                .detach(true)
+                .no_subreaper(true)
+                .run_args()
+                .expect(ARGS_FAIL_MSG),
+            vec!["--detach".to_string(), "--no-subreaper".to_string()]
+        );
+
+        assert!(matches!(
+            CreateOpts::new().no_subreaper(true).create_args(),
+            Err(Error::RunOnlyOption("--no-subreaper"))
+        ));
     }
 
     #[test]
     fn exec_opts_test() {
         assert_eq!(
             ExecOpts::new().args().expect(ARGS_FAIL_MSG),
-            vec![String::new(); 0]
+            Vec::<String>::new()
         );
 
         assert_eq!(
@@ -575,12 +1505,93 @@ mod tests {
     }
 
     #[test]
-    fn delete_opts_test() {
+    fn checkpoint_opts_test() {
+        assert_eq!(
+            CheckpointOpts::new().args().expect(ARGS_FAIL_MSG),
+            Vec::<String>::new()
+        );
+
+        let cwd = env::current_dir().unwrap();
+        assert_eq!(
+            CheckpointOpts::new()
+                .image_path("images")
+                .parent_path("previous")
+                .leave_running(true)
+                .tcp_established(true)
+                .file_locks(true)
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--image-path".to_string(),
+                cwd.join("images").to_string_lossy().to_string(),
+                "--parent-path".to_string(),
+                cwd.join("previous").to_string_lossy().to_string(),
+                "--leave-running".to_string(),
+                "--tcp-established".to_string(),
+                "--file-locks".to_string(),
+            ]
+        );
+
+        // A pre-dump, then the final dump referencing it.
+        assert_eq!(
+            CheckpointOpts::new()
+                .image_path("pre-1")
+                .pre_dump(true)
+                .leave_running(true)
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--image-path".to_string(),
+                cwd.join("pre-1").to_string_lossy().to_string(),
+                "--pre-dump".to_string(),
+            ]
+        );
+        assert_eq!(
+            CheckpointOpts::new()
+                .image_path("final")
+                .parent_path("pre-1")
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--image-path".to_string(),
+                cwd.join("final").to_string_lossy().to_string(),
+                "--parent-path".to_string(),
+                cwd.join("pre-1").to_string_lossy().to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn restore_opts_test() {
         assert_eq!(
-            DeleteOpts::new().force(false).args(),
-            vec![String::new(); 0]
+            RestoreOpts::new().args().expect(ARGS_FAIL_MSG),
+            Vec::<String>::new()
         );
 
+        let cwd = env::current_dir().unwrap();
+        assert_eq!(
+            RestoreOpts::new()
+                .image_path("images")
+                .work_path("work")
+                .detach(true)
+                .tcp_established(true)
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--image-path".to_string(),
+                cwd.join("images").to_string_lossy().to_string(),
+                "--work-path".to_string(),
+                cwd.join("work").to_string_lossy().to_string(),
+                "--detach".to_string(),
+                "--tcp-established".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn delete_opts_test() {
+        assert_eq!(DeleteOpts::new().force(false).args(), Vec::<String>::new());
+
         assert_eq!(
             DeleteOpts::new().force(true).args(),
             vec!["--force".to_string()],
@@ -589,7 +1600,7 @@ mod tests {
 
     #[test]
     fn kill_opts_test() {
-        assert_eq!(KillOpts::new().all(false).args(), vec![String::new(); 0]);
+        assert_eq!(KillOpts::new().all(false).args(), Vec::<String>::new());
 
         assert_eq!(KillOpts::new().all(true).args(), vec!["--all".to_string()],);
     }
@@ -628,5 +1639,81 @@ mod tests {
         assert!(args.contains(&"--rootless=true".to_string()));
         assert!(args.contains(&SYSTEMD_CGROUP.to_string()));
         assert_eq!(args.len(), 9);
+
+        let log = tempfile::tempfile().unwrap();
+        let fd = log.as_raw_fd();
+        let cfg = GlobalOpts::default()
+            .command("true")
+            .log("/tmp/runc.log")
+            .log_fd(fd);
+        let runc = cfg.build().unwrap();
+        assert_eq!(
+            runc.args[..2],
+            [LOG.to_string(), format!("/proc/self/fd/{}", fd)]
+        );
+        assert!(!runc.args.contains(&"/tmp/runc.log".to_string()));
+        assert_eq!(runc.log_fd, Some(fd));
+
+        let cfg = GlobalOpts::default()
+            .command("true")
+            .log_fd(fd)
+            .launcher(Launcher::new("systemd-run"));
+        assert!(matches!(cfg.build(), Err(Error::LogFdUnavailable(f, _)) if f == fd));
+        drop(log);
+        let cfg = GlobalOpts::default().command("true").log_fd(fd);
+        assert!(matches!(cfg.build(), Err(Error::LogFdUnavailable(f, _)) if f == fd));
+
+        let cfg = GlobalOpts::default()
+            .command("true")
+            .criu("/opt/criu/sbin/criu");
+        let runc = cfg.build().unwrap();
+        assert_eq!(runc.args[..2], [CRIU, "/opt/criu/sbin/criu"]);
+        assert_eq!(runc.criu, Path::new("/opt/criu/sbin/criu"));
+        let runc = GlobalOpts::default().command("true").build().unwrap();
+        assert_eq!(runc.criu, Path::new(DEFAULT_CRIU));
+    }
+
+    #[test]
+    fn oom_score_adj_test() {
+        for score in [-1000, 0, 1000] {
+            let runc = GlobalOpts::new()
+                .command("true")
+                .oom_score_adj(score)
+                .build()
+                .unwrap();
+            assert_eq!(runc.oom_score_adj, Some(score));
+        }
+        for score in [-1001, 1001] {
+            assert!(matches!(
+                GlobalOpts::new().command("true").oom_score_adj(score).build(),
+                Err(Error::InvalidOomScoreAdj(s)) if s == score
+            ));
+        }
+    }
+
+    #[test]
+    fn rootless_root_test() {
+        let runc = GlobalOpts::default()
+            .command("true")
+            .rootless(true)
+            .build()
+            .unwrap();
+        let root = utils::abs_string(utils::default_root(true)).unwrap();
+        assert_eq!(runc.args[..2], [ROOT.to_string(), root]);
+
+        let runc = GlobalOpts::default()
+            .command("true")
+            .rootless(false)
+            .build()
+            .unwrap();
+        assert!(!runc.args.contains(&ROOT.to_string()));
+
+        let runc = GlobalOpts::default()
+            .command("true")
+            .root("/tmp")
+            .rootless(true)
+            .build()
+            .unwrap();
+        assert_eq!(runc.args[..2], [ROOT, "/tmp"]);
     }
 }
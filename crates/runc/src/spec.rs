@@ -0,0 +1,1615 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Helpers to adjust a bundle's OCI spec (`config.json`) before it is handed to runc.
+
+use std::{
+    collections::HashSet,
+    fs,
+    io::Write,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use oci_spec::{
+    runtime::{
+        Capabilities, Capability, Hook, Hooks, LinuxBuilder, LinuxCapabilities, LinuxDeviceCgroup,
+        LinuxIdMapping, LinuxNamespaceBuilder, LinuxNamespaceType, LinuxSeccomp,
+        LinuxSeccompAction, Mount, PosixRlimit, Process, Spec,
+    },
+    OciSpecError,
+};
+
+use crate::{error::Error, features::Features, utils};
+
+/// Name of the OCI spec file inside a bundle.
+pub const CONFIG_FILE_NAME: &str = "config.json";
+
+/// Lifecycle phase an OCI hook is attached to.
+///
+/// See <https://github.com/opencontainers/runtime-spec/blob/main/config.md#posix-platform-hooks>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookPhase {
+    /// Deprecated in favor of `CreateRuntime`, `CreateContainer` and `StartContainer`.
+    Prestart,
+    CreateRuntime,
+    CreateContainer,
+    StartContainer,
+    Poststart,
+    Poststop,
+}
+
+/// Network namespace of a container, see [network_namespace].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkNamespace {
+    /// The container shares the network namespace of runc.
+    Host,
+    /// runc creates a new network namespace for the container.
+    New,
+    /// The container joins the existing network namespace at this path, e.g. one created by a
+    /// CNI plugin.
+    Join(PathBuf),
+}
+
+/// Return the path of the spec file of `bundle`.
+pub fn config_path<P: AsRef<Path>>(bundle: P) -> PathBuf {
+    bundle.as_ref().join(CONFIG_FILE_NAME)
+}
+
+/// Load the OCI spec of `bundle`.
+pub fn load<P: AsRef<Path>>(bundle: P) -> Result<Spec, Error> {
+    Spec::load(config_path(bundle)).map_err(Error::SpecProcessingFailed)
+}
+
+/// Write `spec` as the OCI spec of `bundle`, replacing the existing one.
+pub fn save<P: AsRef<Path>>(bundle: P, spec: &Spec) -> Result<(), Error> {
+    spec.save(config_path(bundle))
+        .map_err(Error::SpecProcessingFailed)
+}
+
+/// Build a minimal spec running `args`, modeled on the one `runc spec` writes, to be tweaked and
+/// written to a bundle with [save].
+///
+/// The root filesystem is the `rootfs` directory of the bundle, mounted read-only. The process
+/// runs as root in `/` with the default capabilities and environment, in new pid, network, ipc,
+/// uts, mount and cgroup namespaces, with the default mounts, masked and read-only paths, and
+/// access to devices denied. Unlike `runc spec`, the process has no terminal, which would
+/// require a console socket.
+pub fn minimal(args: &[String]) -> Spec {
+    let mut spec = Spec::default();
+    spec.set_hostname(Some("runc".to_string()));
+    if let Some(process) = spec.process_mut() {
+        process.set_args(Some(args.to_vec()));
+    }
+    let resources = spec
+        .linux_mut()
+        .as_mut()
+        .and_then(|linux| linux.resources_mut().as_mut());
+    if let Some(resources) = resources {
+        let mut deny_all = LinuxDeviceCgroup::default();
+        deny_all
+            .set_allow(false)
+            .set_access(Some("rwm".to_string()));
+        resources.set_devices(Some(vec![deny_all]));
+    }
+    spec
+}
+
+/// Append `hooks` to the given `phase` of `spec`.
+///
+/// Hooks already present in the spec are kept and run first, the new ones are appended in the
+/// order they are given.
+#[allow(deprecated)]
+pub fn merge_hooks(spec: &mut Spec, phase: HookPhase, hooks: Vec<Hook>) {
+    if hooks.is_empty() {
+        return;
+    }
+    let all = spec.hooks_mut().get_or_insert_with(Hooks::default);
+    let existing = match phase {
+        HookPhase::Prestart => all.prestart_mut(),
+        HookPhase::CreateRuntime => all.create_runtime_mut(),
+        HookPhase::CreateContainer => all.create_container_mut(),
+        HookPhase::StartContainer => all.start_container_mut(),
+        HookPhase::Poststart => all.poststart_mut(),
+        HookPhase::Poststop => all.poststop_mut(),
+    };
+    existing.get_or_insert_with(Vec::new).extend(hooks);
+}
+
+/// Merge hooks into the `config.json` of `bundle`, e.g. to let a network plugin set up the
+/// container's network namespace in `createRuntime`.
+pub fn inject_hooks<P, I>(bundle: P, hooks: I) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = (HookPhase, Vec<Hook>)>,
+{
+    let mut spec = load(&bundle)?;
+    for (phase, hooks) in hooks {
+        merge_hooks(&mut spec, phase, hooks);
+    }
+    save(&bundle, &spec)
+}
+
+/// Check that the `ociVersion` of `spec` is within the range supported by the runtime.
+///
+/// Pre-release and build suffixes (e.g. `-rc.1` or `+dev`) are ignored, so `1.1.0+dev` accepts
+/// specs up to `1.1.0`.
+pub fn check_oci_version(spec: &Spec, features: &Features) -> Result<(), Error> {
+    let incompatible = || Error::IncompatibleOciVersion {
+        version: spec.version().clone(),
+        min: features.oci_version_min.clone(),
+        max: features.oci_version_max.clone(),
+    };
+    let version = parse_oci_version(spec.version()).ok_or_else(incompatible)?;
+    let min = parse_oci_version(&features.oci_version_min).ok_or_else(incompatible)?;
+    let max = parse_oci_version(&features.oci_version_max).ok_or_else(incompatible)?;
+    if version < min || version > max {
+        return Err(incompatible());
+    }
+    Ok(())
+}
+
+fn parse_oci_version(version: &str) -> Option<(u64, u64, u64)> {
+    let version = version.split(['-', '+']).next()?;
+    let mut parts = version.split('.').map(|p| p.parse::<u64>());
+    let major = parts.next()?.ok()?;
+    let minor = parts.next()?.ok()?;
+    let patch = match parts.next() {
+        Some(p) => p.ok()?,
+        None => 0,
+    };
+    Some((major, minor, patch))
+}
+
+/// Build the [Process] of a simple exec, running `argv` with `env`.
+///
+/// The working directory and the user are taken from `base`, usually the process of the
+/// container, falling back to the OCI defaults (`/` and root). `env` extends the default `PATH`
+/// and `TERM` variables, overriding them if set.
+pub fn exec_process(base: Option<&Process>, argv: &[String], env: &[(String, String)]) -> Process {
+    let mut process = Process::default();
+    process.set_args(Some(argv.to_vec()));
+
+    merge_env(&mut process, env);
+
+    if let Some(base) = base {
+        process.set_cwd(base.cwd().clone());
+        process.set_user(base.user().clone());
+    }
+    process
+}
+
+/// Set the variables `env` in the environment of `process`.
+///
+/// A variable which is already set is overridden in place, keeping the order of the environment,
+/// the others are appended in the order they are given.
+pub fn merge_env(process: &mut Process, env: &[(String, String)]) {
+    let mut vars = process.env().clone().unwrap_or_default();
+    for (key, value) in env {
+        let prefix = format!("{}=", key);
+        let var = format!("{}={}", key, value);
+        match vars.iter_mut().find(|v| v.starts_with(&prefix)) {
+            Some(existing) => *existing = var,
+            None => vars.push(var),
+        }
+    }
+    process.set_env(Some(vars));
+}
+
+/// Merge `env` into the environment of the init process in the `config.json` of `bundle`, see
+/// [merge_env], e.g. to pass last-minute settings to a container before creating it.
+pub fn inject_env<P: AsRef<Path>>(bundle: P, env: &[(String, String)]) -> Result<(), Error> {
+    let mut spec = load(&bundle)?;
+    merge_env(spec.process_mut().get_or_insert_with(Process::default), env);
+    save(&bundle, &spec)
+}
+
+/// Append `mounts` to the mounts of `spec`.
+///
+/// Mounts already present in the spec are kept and mounted first, so a new mount on the same
+/// destination as an existing one is mounted over it.
+pub fn merge_mounts(spec: &mut Spec, mounts: Vec<Mount>) {
+    if mounts.is_empty() {
+        return;
+    }
+    spec.mounts_mut()
+        .get_or_insert_with(Vec::new)
+        .extend(mounts);
+}
+
+/// Merge mounts into the `config.json` of `bundle`, see [merge_mounts], e.g. to bind mount a
+/// configuration file into a container before creating it.
+///
+/// The sources of bind mounts, relative ones being relative to the bundle like runc resolves
+/// them, must exist or [Error::MountSourceNotFound] is returned and the spec is left untouched.
+pub fn inject_mounts<P: AsRef<Path>>(bundle: P, mounts: Vec<Mount>) -> Result<(), Error> {
+    let base = utils::abs_path_buf(&bundle)?;
+    for mount in mounts.iter().filter(|m| is_bind_mount(m)) {
+        if let Some(source) = mount.source() {
+            if !base.join(source).exists() {
+                return Err(Error::MountSourceNotFound(source.clone()));
+            }
+        }
+    }
+    let mut spec = load(&bundle)?;
+    merge_mounts(&mut spec, mounts);
+    save(&bundle, &spec)
+}
+
+/// Whether `mount` is a bind mount, by its type or its options.
+fn is_bind_mount(mount: &Mount) -> bool {
+    mount.typ().as_deref() == Some("bind")
+        || mount
+            .options()
+            .iter()
+            .flatten()
+            .any(|o| o == "bind" || o == "rbind")
+}
+
+/// Set the hostname of the container, and its NIS domain name if `domainname` is set, failing
+/// with [Error::InvalidHostname] if one of them isn't a valid host name, see
+/// [validate_hostname].
+///
+/// runc only sets them in a new UTS namespace, which the spec must have.
+pub fn set_hostname(
+    spec: &mut Spec,
+    hostname: &str,
+    domainname: Option<&str>,
+) -> Result<(), Error> {
+    validate_hostname(hostname)?;
+    if let Some(domainname) = domainname {
+        validate_hostname(domainname)?;
+        spec.set_domainname(Some(domainname.to_string()));
+    }
+    spec.set_hostname(Some(hostname.to_string()));
+    Ok(())
+}
+
+/// Set the hostname (and domain name) in the `config.json` of `bundle`, see [set_hostname].
+pub fn inject_hostname<P: AsRef<Path>>(
+    bundle: P,
+    hostname: &str,
+    domainname: Option<&str>,
+) -> Result<(), Error> {
+    let mut spec = load(&bundle)?;
+    set_hostname(&mut spec, hostname, domainname)?;
+    save(&bundle, &spec)
+}
+
+/// Check that `name` is a valid host name per RFC 1123, and fits in the 64 bytes the kernel
+/// allows: dot-separated labels of 1 to 63 letters, digits or hyphens, not starting nor ending
+/// with a hyphen.
+pub fn validate_hostname(name: &str) -> Result<(), Error> {
+    let valid_label = |label: &str| {
+        (1..=63).contains(&label.len())
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+    };
+    if name.is_empty() || name.len() > 64 || !name.split('.').all(valid_label) {
+        return Err(Error::InvalidHostname(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Parse the capability `name`, e.g. "CAP_NET_ADMIN", failing with [Error::UnknownCapability]
+/// if it isn't a known Linux capability.
+pub fn parse_capability(name: &str) -> Result<Capability, Error> {
+    serde_json::from_value(serde_json::Value::String(name.to_string()))
+        .map_err(|_| Error::UnknownCapability(name.to_string()))
+}
+
+/// Add `caps` to the bounding, effective and permitted sets of `process`, like `runc exec --cap`
+/// does.
+pub fn add_capabilities(process: &mut Process, caps: &[Capability]) {
+    if caps.is_empty() {
+        return;
+    }
+    let mut capabilities = capabilities_or_empty(process);
+    let extend = |set: &Option<Capabilities>| {
+        let mut set = set.clone().unwrap_or_default();
+        set.extend(caps.iter().copied());
+        Some(set)
+    };
+    let bounding = extend(capabilities.bounding());
+    let effective = extend(capabilities.effective());
+    let permitted = extend(capabilities.permitted());
+    capabilities
+        .set_bounding(bounding)
+        .set_effective(effective)
+        .set_permitted(permitted);
+    process.set_capabilities(Some(capabilities));
+}
+
+/// Set `rlimits` on `process`, replacing its limits of the same types.
+///
+/// A soft limit above the hard one, which the kernel refuses, fails with [Error::InvalidRlimit].
+pub fn merge_rlimits(process: &mut Process, rlimits: &[PosixRlimit]) -> Result<(), Error> {
+    if let Some(invalid) = rlimits.iter().find(|r| r.soft() > r.hard()) {
+        return Err(Error::InvalidRlimit {
+            typ: invalid.typ().to_string(),
+            soft: invalid.soft(),
+            hard: invalid.hard(),
+        });
+    }
+    if rlimits.is_empty() {
+        return Ok(());
+    }
+    let mut merged = process.rlimits().clone().unwrap_or_default();
+    merged.retain(|r| !rlimits.iter().any(|o| o.typ() == r.typ()));
+    merged.extend(rlimits.iter().copied());
+    process.set_rlimits(Some(merged));
+    Ok(())
+}
+
+/// Set the ambient capabilities of `process` to `caps`, e.g. to grant capabilities to a non-root
+/// process without setuid binaries.
+///
+/// The kernel drops the ambient capabilities which aren't both permitted and inheritable: `caps`
+/// are added to the inheritable set, but granting them is left to the permitted set, which
+/// [validate_ambient_capabilities] checks.
+pub fn set_ambient_capabilities(process: &mut Process, caps: &[Capability]) {
+    let mut capabilities = capabilities_or_empty(process);
+    let mut inheritable = capabilities.inheritable().clone().unwrap_or_default();
+    inheritable.extend(caps.iter().copied());
+    capabilities
+        .set_inheritable(Some(inheritable))
+        .set_ambient(Some(caps.iter().copied().collect()));
+    process.set_capabilities(Some(capabilities));
+}
+
+/// Check that the ambient capabilities of `process` are all permitted and inheritable, failing
+/// with [Error::AmbientCapabilityNotPermitted] otherwise, instead of the kernel silently dropping
+/// them.
+pub fn validate_ambient_capabilities(process: &Process) -> Result<(), Error> {
+    let Some(capabilities) = process.capabilities() else {
+        return Ok(());
+    };
+    let empty = Capabilities::default();
+    let permitted = capabilities.permitted().as_ref().unwrap_or(&empty);
+    let inheritable = capabilities.inheritable().as_ref().unwrap_or(&empty);
+    let ambient = capabilities.ambient().as_ref().unwrap_or(&empty);
+    match ambient
+        .iter()
+        .find(|cap| !permitted.contains(cap) || !inheritable.contains(cap))
+    {
+        Some(cap) => Err(Error::AmbientCapabilityNotPermitted(
+            serde_json::to_value(cap)
+                .ok()
+                .and_then(|v| v.as_str().map(str::to_string))
+                .unwrap_or_else(|| format!("{:?}", cap)),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Capabilities of `process`, all sets being empty if it has none.
+fn capabilities_or_empty(process: &Process) -> LinuxCapabilities {
+    process.capabilities().clone().unwrap_or_else(|| {
+        let mut empty = LinuxCapabilities::default();
+        empty
+            .set_bounding(None)
+            .set_effective(None)
+            .set_inheritable(None)
+            .set_permitted(None)
+            .set_ambient(None);
+        empty
+    })
+}
+
+/// Return the network namespace `spec` runs its container in, from its `linux.namespaces`.
+pub fn network_namespace(spec: &Spec) -> NetworkNamespace {
+    let entry = spec
+        .linux()
+        .as_ref()
+        .and_then(|l| l.namespaces().as_ref())
+        .and_then(|n| n.iter().find(|n| n.typ() == LinuxNamespaceType::Network));
+    match entry.map(|n| n.path()) {
+        None => NetworkNamespace::Host,
+        Some(None) => NetworkNamespace::New,
+        Some(Some(path)) => NetworkNamespace::Join(path.clone()),
+    }
+}
+
+/// Make `spec` run its container in the network namespace `netns`, replacing the network entry
+/// of its `linux.namespaces`.
+///
+/// The namespace to join must exist, otherwise [Error::NetnsNotFound] is returned.
+pub fn set_network_namespace(spec: &mut Spec, netns: &NetworkNamespace) -> Result<(), Error> {
+    let entry = match netns {
+        NetworkNamespace::Host => None,
+        NetworkNamespace::New => Some(LinuxNamespaceBuilder::default()),
+        NetworkNamespace::Join(path) => {
+            if !path.exists() {
+                return Err(Error::NetnsNotFound(path.clone()));
+            }
+            Some(LinuxNamespaceBuilder::default().path(path.clone()))
+        }
+    };
+    let entry = entry
+        .map(|b| b.typ(LinuxNamespaceType::Network).build())
+        .transpose()
+        .map_err(Error::SpecProcessingFailed)?;
+
+    match spec.linux_mut() {
+        Some(linux) => {
+            let namespaces = linux.namespaces_mut().get_or_insert_with(Vec::new);
+            namespaces.retain(|n| n.typ() != LinuxNamespaceType::Network);
+            namespaces.extend(entry);
+        }
+        None => {
+            if let Some(entry) = entry {
+                let linux = LinuxBuilder::default()
+                    .namespaces(vec![entry])
+                    .build()
+                    .map_err(Error::SpecProcessingFailed)?;
+                spec.set_linux(Some(linux));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Load a seccomp profile (the `linux.seccomp` object of an OCI spec) from `path` and validate it.
+pub fn load_seccomp_profile<P: AsRef<Path>>(path: P) -> Result<LinuxSeccomp, Error> {
+    let data =
+        fs::read(path.as_ref()).map_err(|e| Error::SpecProcessingFailed(OciSpecError::Io(e)))?;
+    let profile = serde_json::from_slice(&data)
+        .map_err(|e| Error::InvalidSeccompProfile(format!("{}: {}", path.as_ref().display(), e)))?;
+    check_seccomp(&profile)?;
+    Ok(profile)
+}
+
+/// Replace the seccomp profile of `bundle` with the one stored at `profile`.
+pub fn set_seccomp_profile<P, Q>(bundle: P, profile: Q) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let profile = load_seccomp_profile(profile)?;
+    let mut spec = load(&bundle)?;
+    match spec.linux_mut() {
+        Some(linux) => {
+            linux.set_seccomp(Some(profile));
+        }
+        None => {
+            let linux = LinuxBuilder::default()
+                .seccomp(profile)
+                .build()
+                .map_err(Error::SpecProcessingFailed)?;
+            spec.set_linux(Some(linux));
+        }
+    }
+    save(&bundle, &spec)
+}
+
+/// Check that the seccomp profile of `bundle`, if any, is well-formed.
+///
+/// The profile must deserialize into [LinuxSeccomp] (so unknown actions, architectures or
+/// operators are rejected), and every syscall rule must name valid syscalls.
+pub fn validate_seccomp<P: AsRef<Path>>(bundle: P) -> Result<(), Error> {
+    let data = fs::read(config_path(bundle))
+        .map_err(|e| Error::SpecProcessingFailed(OciSpecError::Io(e)))?;
+    let config: serde_json::Value = serde_json::from_slice(&data)?;
+    let seccomp = match config.get("linux").and_then(|l| l.get("seccomp")) {
+        Some(seccomp) if !seccomp.is_null() => seccomp.clone(),
+        _ => return Ok(()),
+    };
+    let profile: LinuxSeccomp =
+        serde_json::from_value(seccomp).map_err(|e| Error::InvalidSeccompProfile(e.to_string()))?;
+    check_seccomp(&profile)
+}
+
+fn check_seccomp(profile: &LinuxSeccomp) -> Result<(), Error> {
+    for (i, syscall) in profile.syscalls().iter().flatten().enumerate() {
+        if syscall.names().is_empty() {
+            return Err(Error::InvalidSeccompProfile(format!(
+                "syscalls[{}]: no syscall names",
+                i
+            )));
+        }
+        for name in syscall.names() {
+            let valid = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+            if !valid {
+                return Err(Error::InvalidSeccompProfile(format!(
+                    "syscalls[{}]: invalid syscall name {:?}",
+                    i, name
+                )));
+            }
+        }
+        // Same restriction as runc, the errno is only returned by these actions.
+        if syscall.errno_ret().is_some()
+            && !matches!(
+                syscall.action(),
+                LinuxSeccompAction::ScmpActErrno | LinuxSeccompAction::ScmpActTrace
+            )
+        {
+            return Err(Error::InvalidSeccompProfile(format!(
+                "syscalls[{}]: errnoRet is only supported with SCMP_ACT_ERRNO and SCMP_ACT_TRACE",
+                i
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Return the absolute path of the root filesystem of `spec`, the spec of `bundle`.
+///
+/// Like runc does, a relative `root.path` is resolved against the bundle, whatever the current
+/// directory. `.` and `..` components are resolved lexically, without following symbolic links.
+pub fn rootfs_path<P: AsRef<Path>>(bundle: P, spec: &Spec) -> Result<Option<PathBuf>, Error> {
+    let bundle = utils::abs_path_buf(bundle)?;
+    spec.root()
+        .as_ref()
+        .map(|root| utils::abs_path_buf(bundle.join(root.path())))
+        .transpose()
+}
+
+/// Check that the root filesystem of `bundle` exists and is a directory.
+///
+/// The root filesystem is resolved by [rootfs_path]. Specs without a root are left for runc to
+/// reject.
+pub fn validate_rootfs<P: AsRef<Path>>(bundle: P) -> Result<(), Error> {
+    let spec = load(&bundle)?;
+    let root = match rootfs_path(&bundle, &spec)? {
+        Some(root) => root,
+        None => return Ok(()),
+    };
+    if !root.is_dir() {
+        return Err(Error::RootfsNotFound(root));
+    }
+    Ok(())
+}
+
+/// Filesystem types and kernel version of the host, which [validate_mounts] checks the mounts
+/// of a spec against.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostMountSupport {
+    /// Filesystem types the kernel supports, from `/proc/filesystems`.
+    pub filesystems: HashSet<String>,
+    /// Major and minor version of the kernel.
+    pub kernel_version: (u32, u32),
+}
+
+impl HostMountSupport {
+    /// Read the filesystem types and kernel version of the running kernel.
+    ///
+    /// Filesystems built as modules are only listed once their module is loaded, which mounting
+    /// them usually does: an `overlay` mount may then be rejected by [validate_mounts] although
+    /// it would succeed.
+    pub fn probe() -> Result<Self, Error> {
+        let filesystems =
+            fs::read_to_string("/proc/filesystems").map_err(Error::FileSystemError)?;
+        let release =
+            fs::read_to_string("/proc/sys/kernel/osrelease").map_err(Error::FileSystemError)?;
+        Ok(Self {
+            // Lines are "[nodev]\t<type>".
+            filesystems: filesystems
+                .lines()
+                .filter_map(|l| l.rsplit('\t').next())
+                .map(|t| t.trim().to_string())
+                .filter(|t| !t.is_empty())
+                .collect(),
+            kernel_version: parse_kernel_version(&release).unwrap_or_default(),
+        })
+    }
+}
+
+/// Parse the major and minor version of a kernel release, e.g. `6.1.0-18-amd64`.
+fn parse_kernel_version(release: &str) -> Option<(u32, u32)> {
+    let mut parts = release.trim().splitn(3, '.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts
+        .next()?
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()?;
+    Some((major, minor))
+}
+
+/// Kernel version a mount option was introduced in, for the options younger than the kernels
+/// runc supports.
+fn mount_option_min_kernel(option: &str) -> Option<(u32, u32)> {
+    match option {
+        // Applied with mount_setattr(2).
+        "idmap" | "ridmap" => Some((5, 12)),
+        o if o.len() > 1 && o.starts_with('r') && is_recursive_mount_attr(&o[1..]) => Some((5, 12)),
+        "nosymfollow" => Some((5, 10)),
+        "lazytime" | "nolazytime" => Some((4, 0)),
+        _ => None,
+    }
+}
+
+/// Whether `option` is one of the mount attributes runc also accepts as a recursive `r` option.
+fn is_recursive_mount_attr(option: &str) -> bool {
+    matches!(
+        option,
+        "ro" | "rw"
+            | "nosuid"
+            | "suid"
+            | "nodev"
+            | "dev"
+            | "noexec"
+            | "exec"
+            | "atime"
+            | "noatime"
+            | "relatime"
+            | "norelatime"
+            | "strictatime"
+            | "nostrictatime"
+            | "diratime"
+            | "nodiratime"
+            | "symfollow"
+            | "nosymfollow"
+    )
+}
+
+/// Check that the mounts of `spec` only use filesystem types and options supported by `host`,
+/// e.g. as probed by [HostMountSupport::probe].
+///
+/// This catches specs written for newer kernels (e.g. `idmap` mounts, which require Linux 5.12)
+/// before runc fails to create the container. Bind mounts and options the kernel doesn't know
+/// about are left for runc to handle, as they may be filesystem specific.
+pub fn validate_mounts(spec: &Spec, host: &HostMountSupport) -> Result<(), Error> {
+    for mount in spec.mounts().iter().flatten() {
+        let unsupported = |option: String, reason: String| Error::UnsupportedMountOption {
+            destination: mount.destination().clone(),
+            option,
+            reason,
+        };
+        if let Some(typ) = mount.typ().as_deref() {
+            // FUSE filesystems have the type "fuse.<subtype>".
+            let fs = typ.split('.').next().unwrap_or_default();
+            if !is_bind_mount(mount) && !matches!(fs, "" | "none") && !host.filesystems.contains(fs)
+            {
+                return Err(unsupported(
+                    format!("type={}", typ),
+                    format!("filesystem {} is not supported by the kernel", fs),
+                ));
+            }
+        }
+        for option in mount.options().iter().flatten() {
+            if let Some(required) = mount_option_min_kernel(option) {
+                if host.kernel_version < required {
+                    return Err(unsupported(
+                        option.clone(),
+                        format!(
+                            "requires Linux {}.{}, the kernel is {}.{}",
+                            required.0, required.1, host.kernel_version.0, host.kernel_version.1
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Host id of the container id `id` per `mappings`, `None` if it isn't mapped.
+pub fn map_id(mappings: &[LinuxIdMapping], id: u32) -> Option<u32> {
+    mappings.iter().find_map(|m| {
+        let offset = id.checked_sub(m.container_id())?;
+        if offset < m.size() {
+            m.host_id().checked_add(offset)
+        } else {
+            None
+        }
+    })
+}
+
+/// Chown the files of `rootfs`, owned by ids of the container, to the host ids they are mapped
+/// to, so that a container in a user namespace with these mappings can access them.
+///
+/// The tree is walked once without following symbolic links, which are chowned themselves,
+/// files with several hard links are chowned once, and the setuid and setgid bits which chown
+/// clears are restored. Ids which aren't mapped fail with [Error::UnmappedId], leaving the tree
+/// partially chowned.
+pub fn chown_rootfs<P: AsRef<Path>>(
+    rootfs: P,
+    uid_mappings: &[LinuxIdMapping],
+    gid_mappings: &[LinuxIdMapping],
+) -> Result<(), Error> {
+    let mut hard_links = HashSet::new();
+    let mut chown = |path: &Path, metadata: &fs::Metadata| {
+        if !metadata.is_dir()
+            && metadata.nlink() > 1
+            && !hard_links.insert((metadata.dev(), metadata.ino()))
+        {
+            return Ok(());
+        }
+        let uid = map_id(uid_mappings, metadata.uid()).ok_or(Error::UnmappedId(metadata.uid()))?;
+        let gid = map_id(gid_mappings, metadata.gid()).ok_or(Error::UnmappedId(metadata.gid()))?;
+        nix::unistd::fchownat(
+            None,
+            path,
+            Some(uid.into()),
+            Some(gid.into()),
+            nix::fcntl::AtFlags::AT_SYMLINK_NOFOLLOW,
+        )
+        .map_err(|e| Error::FileSystemError(e.into()))?;
+        let mode = metadata.mode();
+        if !metadata.is_symlink() && mode & 0o6000 != 0 {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777))
+                .map_err(Error::FileSystemError)?;
+        }
+        Ok::<_, Error>(())
+    };
+
+    let rootfs = rootfs.as_ref();
+    chown(
+        rootfs,
+        &fs::symlink_metadata(rootfs).map_err(Error::FileSystemError)?,
+    )?;
+    let mut dirs = vec![rootfs.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir).map_err(Error::FileSystemError)? {
+            let entry = entry.map_err(Error::FileSystemError)?;
+            // Unlike fs::metadata, doesn't follow symbolic links.
+            let metadata = entry.metadata().map_err(Error::FileSystemError)?;
+            chown(&entry.path(), &metadata)?;
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Chown the root filesystem of `bundle` for the user namespace of its spec, see
+/// [chown_rootfs], returning whether it was chowned.
+///
+/// Nothing is done when the spec has no id mappings, or when the rootfs already belongs to the
+/// host ids of the container's root, e.g. because it was chowned before or it's an idmapped
+/// mount.
+pub fn chown_bundle_rootfs<P: AsRef<Path>>(bundle: P) -> Result<bool, Error> {
+    let spec = load(&bundle)?;
+    let linux = spec.linux().as_ref();
+    let uid_mappings = linux
+        .and_then(|l| l.uid_mappings().clone())
+        .unwrap_or_default();
+    let gid_mappings = linux
+        .and_then(|l| l.gid_mappings().clone())
+        .unwrap_or_default();
+    let root = match rootfs_path(&bundle, &spec)? {
+        Some(root) if !uid_mappings.is_empty() || !gid_mappings.is_empty() => root,
+        _ => return Ok(false),
+    };
+    let metadata = fs::symlink_metadata(&root).map_err(Error::FileSystemError)?;
+    if map_id(&uid_mappings, 0) == Some(metadata.uid())
+        && map_id(&gid_mappings, 0) == Some(metadata.gid())
+    {
+        return Ok(false);
+    }
+    chown_rootfs(&root, &uid_mappings, &gid_mappings)?;
+    Ok(true)
+}
+
+/// Write `bundle` as a tar archive to `writer`, with paths relative to the bundle.
+///
+/// The root filesystem is left out unless `include_rootfs` is set, in which case it's archived
+/// as `rootfs` if it lives outside of the bundle. A root filesystem nested in the bundle, e.g.
+/// at `fs/rootfs`, is left out along with everything under it, while the rest of its parent
+/// directories is archived. Symbolic links are archived as links.
+pub fn export_bundle<P, W>(bundle: P, writer: W, include_rootfs: bool) -> Result<W, Error>
+where
+    P: AsRef<Path>,
+    W: Write,
+{
+    let bundle = fs::canonicalize(utils::abs_path_buf(bundle)?).map_err(Error::FileSystemError)?;
+    let spec = load(&bundle)?;
+    // Resolve symbolic links too, so that the rootfs is recognized however its path is spelled.
+    let rootfs = rootfs_path(&bundle, &spec)?.map(|root| fs::canonicalize(&root).unwrap_or(root));
+
+    let mut archive = tar::Builder::new(writer);
+    archive.follow_symlinks(false);
+    let skip = rootfs.as_deref().filter(|_| !include_rootfs);
+    append_dir_entries(&mut archive, &bundle, Path::new(""), skip)?;
+    if let Some(rootfs) = rootfs.filter(|r| include_rootfs && !r.starts_with(&bundle)) {
+        archive
+            .append_dir_all("rootfs", rootfs)
+            .map_err(Error::FileSystemError)?;
+    }
+    archive.into_inner().map_err(Error::FileSystemError)
+}
+
+/// Append the entries of `dir` to `archive` under `name`, leaving out `skip` and everything
+/// under it.
+fn append_dir_entries<W: Write>(
+    archive: &mut tar::Builder<W>,
+    dir: &Path,
+    name: &Path,
+    skip: Option<&Path>,
+) -> Result<(), Error> {
+    for entry in fs::read_dir(dir).map_err(Error::FileSystemError)? {
+        let entry = entry.map_err(Error::FileSystemError)?;
+        let path = entry.path();
+        let name = name.join(entry.file_name());
+        let is_dir = entry.file_type().map_err(Error::FileSystemError)?.is_dir();
+        let res = match skip {
+            Some(skip) if skip == path => continue,
+            Some(skip) if is_dir && skip.starts_with(&path) => {
+                archive
+                    .append_dir(&name, &path)
+                    .map_err(Error::FileSystemError)?;
+                append_dir_entries(archive, &path, &name, Some(skip))?;
+                continue;
+            }
+            _ if is_dir => archive.append_dir_all(&name, &path),
+            _ => archive.append_path_with_name(&path, &name),
+        };
+        res.map_err(Error::FileSystemError)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::{HookBuilder, LinuxIdMappingBuilder, MountBuilder};
+
+    use super::*;
+
+    fn hook(path: &str) -> Hook {
+        HookBuilder::default().path(path).build().unwrap()
+    }
+
+    fn paths(hooks: &Option<Vec<Hook>>) -> Vec<String> {
+        hooks
+            .as_ref()
+            .map(|v| v.iter().map(|h| h.path().display().to_string()).collect())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_inject_hooks() {
+        let bundle = tempfile::tempdir().unwrap();
+        let config = r#"
+            {
+                "ociVersion": "1.0.2",
+                "hooks": {
+                    "createRuntime": [{"path": "/bin/existing"}],
+                    "poststop": [{"path": "/bin/cleanup"}]
+                }
+            }"#;
+        std::fs::write(config_path(bundle.path()), config).unwrap();
+
+        inject_hooks(
+            bundle.path(),
+            vec![
+                (
+                    HookPhase::CreateRuntime,
+                    vec![hook("/bin/cni-add"), hook("/bin/cni-check")],
+                ),
+                (HookPhase::Poststart, vec![hook("/bin/notify")]),
+            ],
+        )
+        .unwrap();
+
+        let spec = load(bundle.path()).unwrap();
+        let hooks = spec.hooks().as_ref().unwrap();
+        assert_eq!(
+            paths(hooks.create_runtime()),
+            vec!["/bin/existing", "/bin/cni-add", "/bin/cni-check"]
+        );
+        assert_eq!(paths(hooks.poststart()), vec!["/bin/notify"]);
+        assert_eq!(paths(hooks.poststop()), vec!["/bin/cleanup"]);
+        assert!(hooks.create_container().is_none());
+    }
+
+    #[test]
+    fn test_check_oci_version() {
+        let features = Features {
+            oci_version_min: "1.0.0".to_string(),
+            oci_version_max: "1.1.0+dev".to_string(),
+            ..Default::default()
+        };
+        let mut spec = Spec::default();
+        for version in ["1.0.0", "1.0.2-dev", "1.1.0", "1.1.0-rc.1"] {
+            spec.set_version(version.to_string());
+            check_oci_version(&spec, &features).unwrap();
+        }
+        for version in ["0.9.0", "1.2.0", "2.0.0", "latest"] {
+            spec.set_version(version.to_string());
+            match check_oci_version(&spec, &features) {
+                Err(Error::IncompatibleOciVersion {
+                    version: v,
+                    min,
+                    max,
+                }) => {
+                    assert_eq!(v, version);
+                    assert_eq!(min, "1.0.0");
+                    assert_eq!(max, "1.1.0+dev");
+                }
+                other => panic!("unexpected result for {}: {:?}", version, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_inject_mounts() {
+        let bundle = tempfile::tempdir().unwrap();
+        let config = r#"
+            {
+                "ociVersion": "1.0.2",
+                "mounts": [
+                    {"destination": "/proc", "type": "proc", "source": "proc"},
+                    {"destination": "/dev", "type": "tmpfs", "source": "tmpfs"}
+                ]
+            }"#;
+        std::fs::write(config_path(bundle.path()), config).unwrap();
+        std::fs::write(bundle.path().join("app.conf"), "").unwrap();
+
+        let bind = |source: &str, destination: &str| {
+            MountBuilder::default()
+                .destination(destination)
+                .source(source)
+                .options(vec!["rbind".to_string(), "ro".to_string()])
+                .build()
+                .unwrap()
+        };
+        let tmpfs = MountBuilder::default()
+            .destination("/run")
+            .typ("tmpfs")
+            .source("tmpfs")
+            .build()
+            .unwrap();
+
+        // Bind mount sources must exist, nothing is written otherwise.
+        let missing = bind("/does/not/exist", "/etc/missing.conf");
+        assert!(matches!(
+            inject_mounts(bundle.path(), vec![tmpfs.clone(), missing]),
+            Err(Error::MountSourceNotFound(p)) if p == Path::new("/does/not/exist")
+        ));
+        assert_eq!(
+            load(bundle.path())
+                .unwrap()
+                .mounts()
+                .as_ref()
+                .unwrap()
+                .len(),
+            2
+        );
+
+        // Relative sources are relative to the bundle.
+        let conf = bind("app.conf", "/etc/app.conf");
+        inject_mounts(bundle.path(), vec![conf.clone(), tmpfs.clone()]).unwrap();
+        let spec = load(bundle.path()).unwrap();
+        let destinations: Vec<_> = spec
+            .mounts()
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|m| m.destination().clone())
+            .collect();
+        assert_eq!(
+            destinations,
+            ["/proc", "/dev", "/etc/app.conf", "/run"].map(PathBuf::from)
+        );
+        assert_eq!(spec.mounts().as_ref().unwrap()[2], conf);
+    }
+
+    #[test]
+    fn test_validate_mounts() {
+        let spec: Spec = serde_json::from_str(
+            r#"{
+                "ociVersion": "1.0.2",
+                "mounts": [
+                    {"destination": "/proc", "type": "proc", "source": "proc"},
+                    {"destination": "/data", "type": "bind", "source": "/srv/data",
+                     "options": ["rbind", "idmap"]},
+                    {"destination": "/mnt", "type": "fuse.sshfs", "source": "host:/",
+                     "options": ["nosymfollow"]}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let host = |filesystems: &[&str], kernel_version| HostMountSupport {
+            filesystems: filesystems.iter().map(|f| f.to_string()).collect(),
+            kernel_version,
+        };
+
+        validate_mounts(&spec, &host(&["proc", "fuse"], (5, 12))).unwrap();
+        assert!(matches!(
+            validate_mounts(&spec, &host(&["proc", "fuse"], (5, 10))),
+            Err(Error::UnsupportedMountOption { destination, option, .. })
+                if destination == Path::new("/data") && option == "idmap"
+        ));
+        assert!(matches!(
+            validate_mounts(&spec, &host(&["proc"], (6, 1))),
+            Err(Error::UnsupportedMountOption { destination, option, .. })
+                if destination == Path::new("/mnt") && option == "type=fuse.sshfs"
+        ));
+        assert!(matches!(
+            validate_mounts(&spec, &host(&["proc", "fuse"], (4, 19))),
+            Err(Error::UnsupportedMountOption { option, .. }) if option == "idmap"
+        ));
+
+        assert_eq!(parse_kernel_version("6.1.0-18-amd64\n"), Some((6, 1)));
+        assert_eq!(parse_kernel_version("5.15rc1"), Some((5, 15)));
+        assert_eq!(mount_option_min_kernel("rnosuid"), Some((5, 12)));
+        assert_eq!(mount_option_min_kernel("rbind"), None);
+        let probed = HostMountSupport::probe().unwrap();
+        assert!(probed.filesystems.contains("proc"));
+        assert!(probed.kernel_version >= (3, 0));
+    }
+
+    #[test]
+    fn test_inject_env() {
+        let bundle = tempfile::tempdir().unwrap();
+        let config = r#"
+            {
+                "ociVersion": "1.0.2",
+                "process": {
+                    "user": {"uid": 0, "gid": 0},
+                    "cwd": "/",
+                    "env": ["PATH=/bin", "HOME=/root", "TERM=xterm"]
+                }
+            }"#;
+        std::fs::write(config_path(bundle.path()), config).unwrap();
+
+        let env = vec![
+            ("HOME".to_string(), "/home/app".to_string()),
+            ("FOO".to_string(), "bar=baz".to_string()),
+        ];
+        inject_env(bundle.path(), &env).unwrap();
+
+        let spec = load(bundle.path()).unwrap();
+        assert_eq!(
+            spec.process().as_ref().unwrap().env().as_ref().unwrap(),
+            &vec![
+                "PATH=/bin".to_string(),
+                "HOME=/home/app".to_string(),
+                "TERM=xterm".to_string(),
+                "FOO=bar=baz".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_network_namespace() {
+        let mut spec: Spec = serde_json::from_str(
+            r#"{
+                "ociVersion": "1.0.2",
+                "linux": {"namespaces": [{"type": "pid"}, {"type": "network"}, {"type": "mount"}]}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(network_namespace(&spec), NetworkNamespace::New);
+
+        let netns = tempfile::NamedTempFile::new().unwrap();
+        let join = NetworkNamespace::Join(netns.path().to_path_buf());
+        set_network_namespace(&mut spec, &join).unwrap();
+        assert_eq!(network_namespace(&spec), join);
+        let namespaces = spec
+            .linux()
+            .as_ref()
+            .unwrap()
+            .namespaces()
+            .as_ref()
+            .unwrap();
+        assert_eq!(namespaces.len(), 3);
+        assert_eq!(namespaces[0].typ(), LinuxNamespaceType::Pid);
+
+        let missing = NetworkNamespace::Join(PathBuf::from("/var/run/netns/missing"));
+        assert!(matches!(
+            set_network_namespace(&mut spec, &missing),
+            Err(Error::NetnsNotFound(_))
+        ));
+        assert_eq!(network_namespace(&spec), join);
+
+        set_network_namespace(&mut spec, &NetworkNamespace::Host).unwrap();
+        assert_eq!(network_namespace(&spec), NetworkNamespace::Host);
+        let namespaces = spec
+            .linux()
+            .as_ref()
+            .unwrap()
+            .namespaces()
+            .as_ref()
+            .unwrap();
+        assert_eq!(namespaces.len(), 2);
+
+        let mut spec: Spec = serde_json::from_str(r#"{"ociVersion": "1.0.2"}"#).unwrap();
+        assert_eq!(network_namespace(&spec), NetworkNamespace::Host);
+        set_network_namespace(&mut spec, &NetworkNamespace::New).unwrap();
+        assert_eq!(network_namespace(&spec), NetworkNamespace::New);
+    }
+
+    #[test]
+    fn test_exec_process() {
+        let base: Process = serde_json::from_str(
+            r#"{"user": {"uid": 1000, "gid": 1000}, "cwd": "/srv", "args": ["sleep", "inf"]}"#,
+        )
+        .unwrap();
+        let argv = vec!["ls".to_string(), "-l".to_string()];
+        let env = vec![
+            ("PATH".to_string(), "/bin".to_string()),
+            ("FOO".to_string(), "bar".to_string()),
+        ];
+
+        let process = exec_process(Some(&base), &argv, &env);
+        assert_eq!(process.args().as_ref().unwrap(), &argv);
+        assert_eq!(process.cwd(), &PathBuf::from("/srv"));
+        assert_eq!(process.user().uid(), 1000);
+        let vars = process.env().as_ref().unwrap();
+        assert!(vars.contains(&"PATH=/bin".to_string()));
+        assert!(vars.contains(&"FOO=bar".to_string()));
+        assert_eq!(vars.iter().filter(|v| v.starts_with("PATH=")).count(), 1);
+
+        let process = exec_process(None, &argv, &[]);
+        assert_eq!(process.cwd(), &PathBuf::from("/"));
+        assert_eq!(process.user().uid(), 0);
+    }
+
+    #[test]
+    fn test_validate_seccomp() {
+        let bundle = tempfile::tempdir().unwrap();
+
+        // No profile at all is fine.
+        std::fs::write(config_path(bundle.path()), r#"{"ociVersion": "1.0.2"}"#).unwrap();
+        validate_seccomp(bundle.path()).unwrap();
+
+        let good = r#"
+            {
+                "ociVersion": "1.0.2",
+                "linux": {
+                    "seccomp": {
+                        "defaultAction": "SCMP_ACT_ERRNO",
+                        "architectures": ["SCMP_ARCH_X86_64"],
+                        "syscalls": [
+                            {"names": ["read", "write", "exit_group"], "action": "SCMP_ACT_ALLOW"},
+                            {"names": ["mkdir"], "action": "SCMP_ACT_ERRNO", "errnoRet": 1}
+                        ]
+                    }
+                }
+            }"#;
+        std::fs::write(config_path(bundle.path()), good).unwrap();
+        validate_seccomp(bundle.path()).unwrap();
+
+        let bad_action = good.replace("SCMP_ACT_ALLOW", "SCMP_ACT_ALOW");
+        std::fs::write(config_path(bundle.path()), bad_action).unwrap();
+        let err = validate_seccomp(bundle.path()).unwrap_err();
+        assert!(matches!(err, Error::InvalidSeccompProfile(ref e) if e.contains("SCMP_ACT_ALOW")));
+
+        let bad_name = good.replace("exit_group", "exit group");
+        std::fs::write(config_path(bundle.path()), bad_name).unwrap();
+        let err = validate_seccomp(bundle.path()).unwrap_err();
+        assert!(matches!(err, Error::InvalidSeccompProfile(ref e) if e.contains("exit group")));
+    }
+
+    #[test]
+    fn test_set_seccomp_profile() {
+        let bundle = tempfile::tempdir().unwrap();
+        std::fs::write(config_path(bundle.path()), r#"{"ociVersion": "1.0.2"}"#).unwrap();
+        let profile = bundle.path().join("seccomp.json");
+        std::fs::write(
+            &profile,
+            r#"{"defaultAction": "SCMP_ACT_ALLOW", "syscalls": [{"names": ["kexec_load"], "action": "SCMP_ACT_KILL"}]}"#,
+        )
+        .unwrap();
+
+        set_seccomp_profile(bundle.path(), &profile).unwrap();
+        validate_seccomp(bundle.path()).unwrap();
+        let spec = load(bundle.path()).unwrap();
+        let seccomp = spec.linux().as_ref().unwrap().seccomp().as_ref().unwrap();
+        assert_eq!(seccomp.default_action(), LinuxSeccompAction::ScmpActAllow);
+    }
+
+    #[test]
+    fn test_parse_capability() {
+        assert_eq!(
+            parse_capability("CAP_NET_ADMIN").unwrap(),
+            Capability::NetAdmin
+        );
+        assert!(matches!(
+            parse_capability("CAP_NET_ADMN"),
+            Err(Error::UnknownCapability(ref name)) if name == "CAP_NET_ADMN"
+        ));
+    }
+
+    #[test]
+    fn test_inject_hostname() {
+        let bundle = tempfile::tempdir().unwrap();
+        std::fs::write(config_path(bundle.path()), r#"{"ociVersion": "1.0.2"}"#).unwrap();
+
+        inject_hostname(bundle.path(), "web-1", Some("example.com")).unwrap();
+        let spec = load(bundle.path()).unwrap();
+        assert_eq!(spec.hostname().as_deref(), Some("web-1"));
+        assert_eq!(spec.domainname().as_deref(), Some("example.com"));
+
+        for invalid in ["", "-web", "web-", "web_1", "a..b", &"a".repeat(65)] {
+            assert!(
+                matches!(
+                    inject_hostname(bundle.path(), invalid, None),
+                    Err(Error::InvalidHostname(ref name)) if name == invalid
+                ),
+                "{:?}",
+                invalid
+            );
+        }
+        assert!(matches!(
+            inject_hostname(bundle.path(), "web-2", Some("example..com")),
+            Err(Error::InvalidHostname(_))
+        ));
+        // Invalid names leave the spec untouched.
+        assert_eq!(
+            load(bundle.path()).unwrap().hostname().as_deref(),
+            Some("web-1")
+        );
+    }
+
+    #[test]
+    fn test_chown_rootfs() {
+        use std::os::unix::fs::symlink;
+
+        use nix::unistd::{chown, Gid, Uid};
+
+        let mapping = |container_id: u32, host_id: u32, size: u32| {
+            LinuxIdMappingBuilder::default()
+                .container_id(container_id)
+                .host_id(host_id)
+                .size(size)
+                .build()
+                .unwrap()
+        };
+        let uids = [mapping(0, 100000, 1000), mapping(1000, 1000, 1)];
+        let gids = [mapping(0, 200000, 65536)];
+        assert_eq!(map_id(&uids, 0), Some(100000));
+        assert_eq!(map_id(&uids, 999), Some(100999));
+        assert_eq!(map_id(&uids, 1000), Some(1000));
+        assert_eq!(map_id(&uids, 1001), None);
+        assert_eq!(map_id(&gids, 65535), Some(265535));
+        assert_eq!(map_id(&[mapping(0, u32::MAX, 2)], 1), None);
+
+        // Chowning needs CAP_CHOWN.
+        if !nix::unistd::geteuid().is_root() {
+            return;
+        }
+        let bundle = tempfile::tempdir().unwrap();
+        let rootfs = bundle.path().join("rootfs");
+        fs::create_dir_all(rootfs.join("home/user")).unwrap();
+        fs::write(rootfs.join("home/user/file"), "").unwrap();
+        chown(
+            &rootfs.join("home/user"),
+            Some(Uid::from_raw(1000)),
+            Some(Gid::from_raw(1000)),
+        )
+        .unwrap();
+        chown(
+            &rootfs.join("home/user/file"),
+            Some(Uid::from_raw(1000)),
+            Some(Gid::from_raw(1000)),
+        )
+        .unwrap();
+        fs::write(rootfs.join("su"), "").unwrap();
+        fs::set_permissions(rootfs.join("su"), fs::Permissions::from_mode(0o4755)).unwrap();
+        fs::hard_link(rootfs.join("su"), rootfs.join("su-link")).unwrap();
+        symlink("/does/not/exist", rootfs.join("dangling")).unwrap();
+        fs::write(
+            config_path(bundle.path()),
+            r#"{
+                "ociVersion": "1.0.2",
+                "root": {"path": "rootfs"},
+                "linux": {
+                    "uidMappings": [
+                        {"containerID": 0, "hostID": 100000, "size": 1000},
+                        {"containerID": 1000, "hostID": 1000, "size": 1}
+                    ],
+                    "gidMappings": [{"containerID": 0, "hostID": 200000, "size": 65536}]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert!(chown_bundle_rootfs(bundle.path()).unwrap());
+        let owner = |path: &str| {
+            let metadata = fs::symlink_metadata(rootfs.join(path)).unwrap();
+            (metadata.uid(), metadata.gid())
+        };
+        assert_eq!(owner(""), (100000, 200000));
+        assert_eq!(owner("home"), (100000, 200000));
+        assert_eq!(owner("home/user"), (1000, 201000));
+        assert_eq!(owner("home/user/file"), (1000, 201000));
+        assert_eq!(owner("dangling"), (100000, 200000));
+        // Chowned once through its two links, keeping its setuid bit.
+        assert_eq!(owner("su-link"), (100000, 200000));
+        let mode = fs::metadata(rootfs.join("su")).unwrap().mode();
+        assert_eq!(mode & 0o7777, 0o4755);
+
+        // Already chowned.
+        assert!(!chown_bundle_rootfs(bundle.path()).unwrap());
+        assert_eq!(owner("home/user"), (1000, 201000));
+    }
+
+    #[test]
+    fn test_merge_rlimits() {
+        use oci_spec::runtime::{PosixRlimitBuilder, PosixRlimitType};
+
+        let rlimit = |typ, soft: u64, hard: u64| {
+            PosixRlimitBuilder::default()
+                .typ(typ)
+                .soft(soft)
+                .hard(hard)
+                .build()
+                .unwrap()
+        };
+        let mut process = Process::default();
+        let defaults = process.rlimits().clone().unwrap();
+        merge_rlimits(&mut process, &[]).unwrap();
+        assert_eq!(process.rlimits().as_ref(), Some(&defaults));
+
+        let nproc = rlimit(PosixRlimitType::RlimitNproc, 100, 200);
+        merge_rlimits(&mut process, &[nproc]).unwrap();
+        let rlimits = process.rlimits().clone().unwrap();
+        assert_eq!(rlimits.len(), defaults.len() + 1);
+        assert_eq!(rlimits.last(), Some(&nproc));
+
+        let nofile = rlimit(PosixRlimitType::RlimitNofile, 8192, 8192);
+        let mut process = Process::default();
+        merge_rlimits(&mut process, &[nofile]).unwrap();
+        let rlimits = process.rlimits().clone().unwrap();
+        let nofiles: Vec<_> = rlimits
+            .iter()
+            .filter(|r| r.typ() == PosixRlimitType::RlimitNofile)
+            .collect();
+        assert_eq!(nofiles, [&nofile]);
+
+        let invalid = rlimit(PosixRlimitType::RlimitNofile, 2, 1);
+        assert!(matches!(
+            merge_rlimits(&mut process, &[invalid]),
+            Err(Error::InvalidRlimit {
+                soft: 2,
+                hard: 1,
+                ..
+            })
+        ));
+        assert_eq!(process.rlimits().as_ref(), Some(&rlimits));
+    }
+
+    #[test]
+    fn test_ambient_capabilities() {
+        let mut process: Process = serde_json::from_str(
+            r#"
+            {
+                "user": {"uid": 1000, "gid": 1000},
+                "cwd": "/",
+                "capabilities": {
+                    "bounding": ["CAP_NET_BIND_SERVICE"],
+                    "permitted": ["CAP_NET_BIND_SERVICE"],
+                    "inheritable": ["CAP_CHOWN"]
+                }
+            }"#,
+        )
+        .unwrap();
+        validate_ambient_capabilities(&process).unwrap();
+
+        set_ambient_capabilities(&mut process, &[Capability::NetBindService]);
+        let caps = process.capabilities().as_ref().unwrap();
+        assert_eq!(
+            caps.ambient().as_ref().unwrap(),
+            &[Capability::NetBindService].into_iter().collect()
+        );
+        assert_eq!(
+            caps.inheritable().as_ref().unwrap(),
+            &[Capability::Chown, Capability::NetBindService]
+                .into_iter()
+                .collect()
+        );
+        validate_ambient_capabilities(&process).unwrap();
+
+        // Not permitted, the kernel would drop it.
+        set_ambient_capabilities(&mut process, &[Capability::NetRaw]);
+        assert!(matches!(
+            validate_ambient_capabilities(&process),
+            Err(Error::AmbientCapabilityNotPermitted(ref name)) if name == "CAP_NET_RAW"
+        ));
+    }
+
+    #[test]
+    fn test_rootfs_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("bundle");
+        let with_root = |path: &str| -> Spec {
+            serde_json::from_str(&format!(
+                r#"{{"ociVersion": "1.0.2", "root": {{"path": "{}"}}}}"#,
+                path
+            ))
+            .unwrap()
+        };
+
+        for (root, expected) in [
+            ("rootfs", bundle.join("rootfs")),
+            ("./rootfs/", bundle.join("rootfs")),
+            ("../shared/rootfs", dir.path().join("shared/rootfs")),
+            ("/srv/rootfs", PathBuf::from("/srv/rootfs")),
+        ] {
+            assert_eq!(
+                rootfs_path(&bundle, &with_root(root)).unwrap(),
+                Some(expected),
+                "root.path {}",
+                root
+            );
+        }
+
+        let mut no_root = with_root("rootfs");
+        no_root.set_root(None);
+        assert_eq!(rootfs_path(&bundle, &no_root).unwrap(), None);
+    }
+
+    #[test]
+    fn test_export_bundle_shared_rootfs() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("bundle");
+        let rootfs = dir.path().join("shared/rootfs");
+        fs::create_dir_all(&bundle).unwrap();
+        fs::create_dir_all(&rootfs).unwrap();
+        fs::write(rootfs.join("hostname"), "fake").unwrap();
+        fs::write(
+            config_path(&bundle),
+            r#"{"ociVersion": "1.0.2", "root": {"path": "../shared/rootfs"}}"#,
+        )
+        .unwrap();
+        validate_rootfs(&bundle).unwrap();
+
+        // The rootfs is outside of the bundle, so it's archived on its own.
+        let archive = export_bundle(&bundle, Vec::new(), true).unwrap();
+        let mut archive = tar::Archive::new(archive.as_slice());
+        let names: Vec<String> = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().display().to_string())
+            .collect();
+        assert!(
+            names.contains(&"rootfs/hostname".to_string()),
+            "{:?}",
+            names
+        );
+    }
+
+    #[test]
+    fn test_export_bundle_nested_rootfs() {
+        let bundle = tempfile::tempdir().unwrap();
+        let rootfs = bundle.path().join("fs/rootfs");
+        fs::create_dir_all(rootfs.join("etc")).unwrap();
+        fs::write(rootfs.join("etc/hostname"), "fake").unwrap();
+        fs::write(bundle.path().join("fs/notes"), "kept").unwrap();
+        fs::write(
+            config_path(bundle.path()),
+            r#"{"ociVersion": "1.0.2", "root": {"path": "fs/./rootfs"}}"#,
+        )
+        .unwrap();
+
+        let names = |include_rootfs| {
+            let archive = export_bundle(bundle.path(), Vec::new(), include_rootfs).unwrap();
+            let mut archive = tar::Archive::new(archive.as_slice());
+            archive
+                .entries()
+                .unwrap()
+                .map(|e| e.unwrap().path().unwrap().display().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // Only the rootfs is left out of its parent directory.
+        let without = names(false);
+        assert!(
+            without.contains(&"config.json".to_string()),
+            "{:?}",
+            without
+        );
+        assert!(without.contains(&"fs/notes".to_string()), "{:?}", without);
+        assert!(
+            !without.iter().any(|n| n.starts_with("fs/rootfs")),
+            "{:?}",
+            without
+        );
+
+        let with = names(true);
+        assert!(
+            with.contains(&"fs/rootfs/etc/hostname".to_string()),
+            "{:?}",
+            with
+        );
+        assert!(!with.iter().any(|n| n.starts_with("rootfs")), "{:?}", with);
+    }
+
+    #[test]
+    fn test_validate_rootfs() {
+        let bundle = tempfile::tempdir().unwrap();
+        std::fs::write(
+            config_path(bundle.path()),
+            r#"{"ociVersion": "1.0.2", "root": {"path": "rootfs"}}"#,
+        )
+        .unwrap();
+        let rootfs = bundle.path().join("rootfs");
+        assert!(matches!(
+            validate_rootfs(bundle.path()),
+            Err(Error::RootfsNotFound(ref p)) if p == &rootfs
+        ));
+
+        // A file is not a root filesystem.
+        std::fs::write(&rootfs, "").unwrap();
+        assert!(matches!(
+            validate_rootfs(bundle.path()),
+            Err(Error::RootfsNotFound(_))
+        ));
+
+        std::fs::remove_file(&rootfs).unwrap();
+        std::fs::create_dir(&rootfs).unwrap();
+        validate_rootfs(bundle.path()).unwrap();
+
+        // Absolute paths are used as is.
+        let elsewhere = tempfile::tempdir().unwrap();
+        std::fs::write(
+            config_path(bundle.path()),
+            format!(
+                r#"{{"ociVersion": "1.0.2", "root": {{"path": "{}"}}}}"#,
+                elsewhere.path().display()
+            ),
+        )
+        .unwrap();
+        validate_rootfs(bundle.path()).unwrap();
+    }
+
+    #[test]
+    fn test_minimal() {
+        let args = vec!["sleep".to_string(), "inf".to_string()];
+        let spec = minimal(&args);
+        let process = spec.process().as_ref().unwrap();
+        assert_eq!(process.args().as_ref().unwrap(), &args);
+        assert_eq!(process.cwd(), Path::new("/"));
+        assert_eq!(process.terminal(), Some(false));
+        assert_eq!(spec.hostname().as_deref(), Some("runc"));
+        assert_eq!(network_namespace(&spec), NetworkNamespace::New);
+        let devices = spec
+            .linux()
+            .as_ref()
+            .and_then(|l| l.resources().as_ref())
+            .and_then(|r| r.devices().as_ref())
+            .unwrap();
+        assert_eq!(devices.len(), 1);
+        assert!(!devices[0].allow());
+        assert_eq!(devices[0].access().as_deref(), Some("rwm"));
+
+        // The spec passes the checks run before creating a container.
+        let features = Features {
+            oci_version_min: "1.0.0".to_string(),
+            oci_version_max: "1.1.0".to_string(),
+            ..Default::default()
+        };
+        check_oci_version(&spec, &features).unwrap();
+        validate_hostname(spec.hostname().as_ref().unwrap()).unwrap();
+        validate_ambient_capabilities(process).unwrap();
+        let host = HostMountSupport {
+            filesystems: ["proc", "tmpfs", "devpts", "mqueue", "sysfs", "cgroup"]
+                .iter()
+                .map(|f| f.to_string())
+                .collect(),
+            kernel_version: (5, 15),
+        };
+        validate_mounts(&spec, &host).unwrap();
+
+        let bundle = tempfile::tempdir().unwrap();
+        std::fs::create_dir(bundle.path().join("rootfs")).unwrap();
+        save(bundle.path(), &spec).unwrap();
+        validate_rootfs(bundle.path()).unwrap();
+        validate_seccomp(bundle.path()).unwrap();
+        assert_eq!(load(bundle.path()).unwrap(), spec);
+    }
+}
@@ -0,0 +1,296 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Rolling averages of container statistics.
+//!
+//! [StatsAverager] keeps exponentially-weighted moving averages of the samples returned by
+//! [Runc::stats](crate::Runc::stats), or yielded by a stream of them, so that monitoring doesn't
+//! need to store every sample.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "async")]
+use futures::{Stream, StreamExt};
+
+use crate::{events::Stats, Result, Runc};
+
+/// Default weight of the previous average, see [StatsAverager::new].
+pub const DEFAULT_DECAY: f64 = 0.8;
+
+/// Averages maintained by [StatsAverager].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RollingStats {
+    /// CPU usage in cores, e.g. 1.5 when the container used one and a half cores since the
+    /// previous sample. Computed from two consecutive samples, so it stays 0 until the second
+    /// sample.
+    pub cpu: f64,
+    /// Memory usage in bytes.
+    pub memory: f64,
+    /// Number of samples observed.
+    pub samples: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    current: RollingStats,
+    /// Average memory usage, `None` until a sample carries the memory usage.
+    memory: Option<f64>,
+    /// Cumulative CPU time of the last sample, and when it was taken.
+    last_cpu: Option<(u64, Instant)>,
+    cpu_initialized: bool,
+}
+
+/// Exponentially-weighted moving averages of the CPU and memory usage of a container.
+///
+/// Clones share the same averages, so one clone can be fed (e.g. by [StatsAverager::follow])
+/// while others read [StatsAverager::current].
+#[derive(Debug, Clone)]
+pub struct StatsAverager {
+    decay: f64,
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for StatsAverager {
+    fn default() -> Self {
+        Self::new(DEFAULT_DECAY)
+    }
+}
+
+impl StatsAverager {
+    /// Create an averager giving the weight `decay` to the previous average and `1 - decay` to
+    /// each new sample: the closer to 1, the smoother and the slower to react.
+    ///
+    /// `decay` is clamped to `[0, 1)`.
+    pub fn new(decay: f64) -> Self {
+        Self {
+            decay: decay.clamp(0.0, 0.999),
+            state: Default::default(),
+        }
+    }
+
+    /// Current averages.
+    pub fn current(&self) -> RollingStats {
+        self.state.lock().unwrap().current
+    }
+
+    /// Account for a new sample.
+    pub fn observe(&self, stats: &Stats) {
+        let memory = stats.memory.usage.as_ref().and_then(|m| m.usage);
+        self.update(stats.cpu.usage, memory, Instant::now());
+    }
+
+    fn update(&self, cpu_total: Option<u64>, memory: Option<u64>, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        state.current.samples += 1;
+
+        if let Some(memory) = memory {
+            let average = match state.memory {
+                Some(average) => self.average(average, memory as f64),
+                None => memory as f64,
+            };
+            state.memory = Some(average);
+            state.current.memory = average;
+        }
+
+        if let Some(total) = cpu_total {
+            if let Some((last, at)) = state.last_cpu {
+                let elapsed = now.saturating_duration_since(at).as_nanos() as f64;
+                if elapsed > 0.0 {
+                    let cores = total.saturating_sub(last) as f64 / elapsed;
+                    state.current.cpu = if state.cpu_initialized {
+                        self.average(state.current.cpu, cores)
+                    } else {
+                        cores
+                    };
+                    state.cpu_initialized = true;
+                }
+            }
+            state.last_cpu = Some((total, now));
+        }
+    }
+
+    fn average(&self, previous: f64, sample: f64) -> f64 {
+        self.decay * previous + (1.0 - self.decay) * sample
+    }
+
+    /// Sample the statistics of container `id` every `interval` until they can't be read
+    /// anymore, e.g. because the container was deleted, and return that error.
+    #[cfg(not(feature = "async"))]
+    pub fn follow(&self, runc: &Runc, id: &str, interval: Duration) -> Result<()> {
+        loop {
+            self.observe(&runc.stats(id)?);
+            std::thread::sleep(interval);
+        }
+    }
+
+    /// Observe the samples yielded by `stats`, passing them through, e.g. to also forward them
+    /// elsewhere. Errors are passed through without being observed.
+    #[cfg(feature = "async")]
+    pub fn track<S>(&self, stats: S) -> impl Stream<Item = Result<Stats>>
+    where
+        S: Stream<Item = Result<Stats>>,
+    {
+        let averager = self.clone();
+        stats.inspect(move |sample| {
+            if let Ok(sample) = sample {
+                averager.observe(sample);
+            }
+        })
+    }
+
+    /// Observe the samples yielded by `stats` until it ends, or until it yields an error which
+    /// is returned.
+    #[cfg(feature = "async")]
+    pub async fn consume<S>(&self, stats: S) -> Result<()>
+    where
+        S: Stream<Item = Result<Stats>>,
+    {
+        let stats = self.track(stats);
+        futures::pin_mut!(stats);
+        while let Some(sample) = stats.next().await {
+            sample?;
+        }
+        Ok(())
+    }
+
+    /// Sample the statistics of container `id` every `interval` until they can't be read
+    /// anymore, e.g. because the container was deleted, and return that error.
+    #[cfg(feature = "async")]
+    pub async fn follow(&self, runc: &Runc, id: &str, interval: Duration) -> Result<()> {
+        self.consume(sample(runc, id, interval)).await
+    }
+}
+
+/// Stream of the statistics of container `id`, sampled every `interval` with
+/// [Runc::stats](crate::Runc::stats), the first sample being taken right away.
+#[cfg(feature = "async")]
+pub fn sample<'a>(
+    runc: &'a Runc,
+    id: &'a str,
+    interval: Duration,
+) -> impl Stream<Item = Result<Stats>> + 'a {
+    futures::stream::unfold(
+        tokio::time::interval(interval),
+        move |mut ticker| async move {
+            ticker.tick().await;
+            Some((runc.stats(id).await, ticker))
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ewma() {
+        let averager = StatsAverager::new(0.5);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        averager.update(Some(0), Some(100), at(0));
+        assert_eq!(
+            averager.current(),
+            RollingStats {
+                cpu: 0.0,
+                memory: 100.0,
+                samples: 1
+            }
+        );
+
+        // 1 core during 1s.
+        averager.update(Some(1_000_000_000), Some(300), at(1000));
+        assert_eq!(
+            averager.current(),
+            RollingStats {
+                cpu: 1.0,
+                memory: 200.0,
+                samples: 2
+            }
+        );
+
+        // 2 cores during 0.5s.
+        averager.update(Some(2_000_000_000), Some(200), at(1500));
+        assert_eq!(
+            averager.current(),
+            RollingStats {
+                cpu: 1.5,
+                memory: 200.0,
+                samples: 3
+            }
+        );
+
+        // Missing values leave the averages untouched.
+        averager.update(None, None, at(2000));
+        assert_eq!(averager.current().cpu, 1.5);
+        assert_eq!(averager.current().memory, 200.0);
+        assert_eq!(averager.current().samples, 4);
+    }
+
+    #[test]
+    fn test_memory_seed() {
+        // A first sample without memory usage doesn't seed the memory average.
+        let averager = StatsAverager::new(0.5);
+        let now = Instant::now();
+        averager.update(Some(0), None, now);
+        averager.update(Some(0), Some(100), now);
+        assert_eq!(averager.current().memory, 100.0);
+        averager.update(Some(0), Some(300), now);
+        assert_eq!(averager.current().memory, 200.0);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_consume() {
+        let sample = |memory: u64| {
+            let stats = format!(
+                r#"{{"cpu":{{}},"memory":{{"usage":{{"limit":0,"usage":{},"failcnt":0}}}},"pids":{{}},"blkio":{{}},"hugetlb":{{"failcnt":0}}}}"#,
+                memory
+            );
+            Ok(serde_json::from_str::<Stats>(&stats).unwrap())
+        };
+        let averager = StatsAverager::new(0.5);
+
+        let stats = averager.track(futures::stream::iter([sample(100), sample(300)]));
+        assert_eq!(stats.count().await, 2);
+        assert_eq!(averager.current().memory, 200.0);
+
+        let stats = futures::stream::iter([
+            sample(200),
+            Err(crate::error::Error::MissingContainerStats),
+            sample(1000),
+        ]);
+        assert!(matches!(
+            averager.consume(stats).await,
+            Err(crate::error::Error::MissingContainerStats)
+        ));
+        assert_eq!(averager.current().samples, 3);
+        assert_eq!(averager.current().memory, 200.0);
+    }
+
+    #[test]
+    fn test_clones_share_averages() {
+        let averager = StatsAverager::new(0.75);
+        let reader = averager.clone();
+        let now = Instant::now();
+        averager.update(None, Some(0), now);
+        averager.update(None, Some(400), now);
+        assert_eq!(reader.current().memory, 100.0);
+    }
+}
@@ -0,0 +1,202 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Following of log files across rotations, like `tail -F`.
+
+use std::{
+    io::{ErrorKind, Result, SeekFrom},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use futures::Stream;
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, AsyncSeekExt, BufReader},
+};
+
+/// Follow the file at `path` and yield its lines, without their trailing newline.
+///
+/// Lines are read from the start of the file, then as they are appended. The file is checked
+/// every `poll_interval` once its end is reached:
+/// - if `path` now refers to another file (e.g. the log was rotated by renaming it), the rest of
+///   the old file is yielded and the new one is followed from its start,
+/// - if the file shrank (e.g. it was truncated by `copytruncate`), it's followed from its start
+///   again,
+/// - if it doesn't exist, it's waited for.
+///
+/// The stream never ends, errors are yielded and following goes on.
+pub fn follow(
+    path: impl AsRef<Path>,
+    poll_interval: Duration,
+) -> impl Stream<Item = Result<String>> {
+    let tail = Tail {
+        path: path.as_ref().to_path_buf(),
+        poll_interval,
+        file: None,
+        ino: (0, 0),
+        pos: 0,
+        partial: Vec::new(),
+    };
+    futures::stream::unfold(tail, |mut tail| async move {
+        let line = tail.next_line().await;
+        Some((line, tail))
+    })
+}
+
+struct Tail {
+    path: PathBuf,
+    poll_interval: Duration,
+    file: Option<BufReader<File>>,
+    /// Device and inode of `file`.
+    ino: (u64, u64),
+    /// Bytes of `file` read so far.
+    pos: u64,
+    /// Incomplete last line of `file`.
+    partial: Vec<u8>,
+}
+
+impl Tail {
+    async fn next_line(&mut self) -> Result<String> {
+        loop {
+            let file = match &mut self.file {
+                Some(file) => file,
+                None => {
+                    match File::open(&self.path).await {
+                        Ok(file) => {
+                            let metadata = file.metadata().await?;
+                            self.ino = (metadata.dev(), metadata.ino());
+                            self.pos = 0;
+                            self.file = Some(BufReader::new(file));
+                        }
+                        Err(e) if e.kind() == ErrorKind::NotFound => {
+                            tokio::time::sleep(self.poll_interval).await
+                        }
+                        Err(e) => return Err(e),
+                    }
+                    continue;
+                }
+            };
+
+            let n = file.read_until(b'\n', &mut self.partial).await?;
+            self.pos += n as u64;
+            if self.partial.ends_with(b"\n") {
+                return Ok(self.take_line());
+            }
+
+            // End of file, wait for more data unless the file was rotated or truncated.
+            match tokio::fs::metadata(&self.path).await {
+                Ok(metadata) if (metadata.dev(), metadata.ino()) == self.ino => {
+                    if metadata.len() < self.pos {
+                        file.seek(SeekFrom::Start(0)).await?;
+                        self.pos = 0;
+                        self.partial.clear();
+                    } else if n == 0 {
+                        tokio::time::sleep(self.poll_interval).await;
+                    }
+                }
+                Ok(_) => {
+                    self.file = None;
+                    if !self.partial.is_empty() {
+                        return Ok(self.take_line());
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    tokio::time::sleep(self.poll_interval).await
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn take_line(&mut self) -> String {
+        if self.partial.ends_with(b"\n") {
+            self.partial.pop();
+        }
+        let line = String::from_utf8_lossy(&self.partial).to_string();
+        self.partial.clear();
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use futures::StreamExt;
+
+    use super::*;
+
+    fn append(path: &Path, data: &str) {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(data.as_bytes()).unwrap();
+    }
+
+    async fn next_line(lines: &mut (impl Stream<Item = Result<String>> + Unpin)) -> String {
+        tokio::time::timeout(Duration::from_secs(5), lines.next())
+            .await
+            .expect("no line was yielded")
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_follow_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("container.log");
+        append(&path, "one\ntw");
+
+        let mut lines = Box::pin(follow(&path, Duration::from_millis(10)));
+
+        assert_eq!(next_line(&mut lines).await, "one");
+        append(&path, "o\n");
+        assert_eq!(next_line(&mut lines).await, "two");
+
+        // Rotation by renaming: the end of the old file comes before the new file.
+        append(&path, "three");
+        std::fs::rename(&path, dir.path().join("container.log.1")).unwrap();
+        append(&path, "four\n");
+        assert_eq!(next_line(&mut lines).await, "three");
+        assert_eq!(next_line(&mut lines).await, "four");
+
+        // Rotation by truncation, noticed because the file is now shorter than what was read.
+        std::fs::File::create(&path).unwrap();
+        append(&path, "5\n");
+        assert_eq!(next_line(&mut lines).await, "5");
+    }
+
+    #[tokio::test]
+    async fn test_follow_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("container.log");
+        let mut lines = Box::pin(follow(&path, Duration::from_millis(10)));
+
+        let writer = {
+            let path = path.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                append(&path, "created\n");
+            })
+        };
+        assert_eq!(next_line(&mut lines).await, "created");
+        writer.await.unwrap();
+    }
+}
@@ -0,0 +1,369 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Fake runc backend for testing how callers handle the failures of runc, enabled by the
+//! `test-util` feature.
+//!
+//! A [FakeRunc] answers the commands of a [crate::Runc] with the responses programmed for
+//! their subcommand, without running anything:
+//!
+//! ```ignore
+//! let fake = Arc::new(FakeRunc::new());
+//! fake.respond("state", FakeResponse::failure(1, "container does not exist"));
+//! let mut opts = GlobalOpts::new();
+//! opts.custom_spawner(fake.clone());
+//! let runc = opts.build()?;
+//! ```
+//!
+//! Tests needing a real runc process, e.g. to check how it is spawned, can use a shell script
+//! written by [fake_runc_script] instead.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    os::unix::{fs::PermissionsExt, process::ExitStatusExt},
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+
+#[cfg(not(feature = "async"))]
+use crate::error::Error;
+use crate::{subcommand_of, Command, Result, Spawner};
+
+/// What a [FakeRunc] answers to a command.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FakeResponse {
+    /// Exit code of runc.
+    pub exit_code: i32,
+    /// Pid reported for the runc process.
+    pub pid: u32,
+    pub stdout: String,
+    pub stderr: String,
+    /// Time runc takes to answer, e.g. to trigger the timeout of the command.
+    pub delay: Option<Duration>,
+}
+
+impl FakeResponse {
+    /// A successful command printing `stdout`.
+    pub fn success(stdout: impl Into<String>) -> Self {
+        Self {
+            stdout: stdout.into(),
+            ..Default::default()
+        }
+    }
+
+    /// A command failing with `exit_code` and printing `stderr`, e.g. "container does not
+    /// exist".
+    pub fn failure(exit_code: i32, stderr: impl Into<String>) -> Self {
+        Self {
+            exit_code,
+            stderr: stderr.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn pid(mut self, pid: u32) -> Self {
+        self.pid = pid;
+        self
+    }
+
+    /// Answer after `delay`.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    fn output(self) -> (ExitStatus, u32, String, String) {
+        (
+            ExitStatus::from_raw(self.exit_code << 8),
+            self.pid,
+            self.stdout,
+            self.stderr,
+        )
+    }
+}
+
+type Responder = Arc<dyn Fn(&[String]) -> FakeResponse + Send + Sync>;
+
+/// Runc backend answering commands with programmed responses, to be passed to
+/// [crate::options::GlobalOpts::custom_spawner].
+///
+/// Responses are programmed per subcommand, e.g. "create". The ones given to
+/// [FakeRunc::respond_once] are used first, in order, then the one given to
+/// [FakeRunc::respond] or [FakeRunc::respond_with]. Commands without any programmed response
+/// succeed without output.
+///
+/// Launchers aren't supported, the subcommand is looked up in the arguments of runc itself.
+#[derive(Default)]
+pub struct FakeRunc {
+    once: Mutex<HashMap<String, VecDeque<FakeResponse>>>,
+    always: Mutex<HashMap<String, Responder>>,
+    calls: Mutex<Vec<Vec<String>>>,
+    running: AtomicUsize,
+    max_running: AtomicUsize,
+}
+
+impl fmt::Debug for FakeRunc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FakeRunc")
+            .field("once", &self.once)
+            .field(
+                "always",
+                &self.always.lock().unwrap().keys().collect::<Vec<_>>(),
+            )
+            .field("calls", &self.calls)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Command of a [FakeRunc] being answered.
+struct Running<'a>(&'a AtomicUsize);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl FakeRunc {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer every `subcommand` with `response`.
+    pub fn respond(&self, subcommand: &str, response: FakeResponse) -> &Self {
+        self.respond_with(subcommand, move |_| response.clone())
+    }
+
+    /// Answer every `subcommand` with the response built by `responder` from the arguments of
+    /// runc, e.g. to read the files they point to before they are removed.
+    pub fn respond_with<F>(&self, subcommand: &str, responder: F) -> &Self
+    where
+        F: Fn(&[String]) -> FakeResponse + Send + Sync + 'static,
+    {
+        self.always
+            .lock()
+            .unwrap()
+            .insert(subcommand.to_string(), Arc::new(responder));
+        self
+    }
+
+    /// Answer the next `subcommand` with `response`, after the responses programmed before.
+    pub fn respond_once(&self, subcommand: &str, response: FakeResponse) -> &Self {
+        self.once
+            .lock()
+            .unwrap()
+            .entry(subcommand.to_string())
+            .or_default()
+            .push_back(response);
+        self
+    }
+
+    /// Arguments of the commands run so far, including the global flags.
+    pub fn calls(&self) -> Vec<Vec<String>> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Largest number of commands answered at the same time so far.
+    pub fn max_running(&self) -> usize {
+        self.max_running.load(Ordering::SeqCst)
+    }
+
+    fn start(&self) -> Running<'_> {
+        let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_running.fetch_max(running, Ordering::SeqCst);
+        Running(&self.running)
+    }
+
+    fn response(&self, cmd: &Command) -> FakeResponse {
+        #[cfg(feature = "async")]
+        let cmd = cmd.as_std();
+        let args: Vec<String> = cmd
+            .get_args()
+            .map(|a| a.to_string_lossy().to_string())
+            .collect();
+        let subcommand = subcommand_of(args.iter().cloned());
+        self.calls.lock().unwrap().push(args.clone());
+        let once = self
+            .once
+            .lock()
+            .unwrap()
+            .get_mut(&subcommand)
+            .and_then(|responses| responses.pop_front());
+        if let Some(response) = once {
+            return response;
+        }
+        let responder = self.always.lock().unwrap().get(&subcommand).cloned();
+        responder.map_or_else(FakeResponse::default, |responder| responder(&args))
+    }
+}
+
+/// Write an executable shell script running `body` in `dir`, to be used as the runc (or criu)
+/// command of tests, and return its path.
+///
+/// Each call writes a new script, so several can share `dir`. Panics if the script can't be
+/// written.
+pub fn fake_runc_script(dir: &Path, body: &str) -> PathBuf {
+    let mut file = tempfile::Builder::new()
+        .prefix("runc")
+        .suffix(".sh")
+        .tempfile_in(dir)
+        .expect("failed to create the fake runc script");
+    std::io::Write::write_all(&mut file, format!("#!/bin/sh\n{}", body).as_bytes())
+        .expect("failed to write the fake runc script");
+    // Closes the script, executing it while it's open for writing fails with ETXTBSY.
+    let path = file
+        .into_temp_path()
+        .keep()
+        .expect("failed to keep the fake runc script");
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+        .expect("failed to make the fake runc script executable");
+    path
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl Spawner for FakeRunc {
+    async fn execute(&self, cmd: Command) -> Result<(ExitStatus, u32, String, String)> {
+        let _running = self.start();
+        let response = self.response(&cmd);
+        if let Some(delay) = response.delay {
+            tokio::time::sleep(delay).await;
+        }
+        Ok(response.output())
+    }
+}
+
+#[cfg(not(feature = "async"))]
+impl Spawner for FakeRunc {
+    fn execute(&self, cmd: Command) -> Result<(ExitStatus, u32, String, String)> {
+        let _running = self.start();
+        let response = self.response(&cmd);
+        if let Some(delay) = response.delay {
+            std::thread::sleep(delay);
+        }
+        Ok(response.output())
+    }
+
+    fn execute_with_timeout(
+        &self,
+        cmd: Command,
+        timeout: Duration,
+    ) -> Result<(ExitStatus, u32, String, String)> {
+        let _running = self.start();
+        let response = self.response(&cmd);
+        match response.delay {
+            Some(delay) if delay > timeout => {
+                std::thread::sleep(timeout);
+                Err(Error::Timeout(timeout))
+            }
+            Some(delay) => {
+                std::thread::sleep(delay);
+                Ok(response.output())
+            }
+            None => Ok(response.output()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{error::Error, options::GlobalOpts};
+
+    fn fake_client(fake: &Arc<FakeRunc>) -> crate::Runc {
+        let mut opts = GlobalOpts::new()
+            .command("/bin/false")
+            .blocking_timeout(Duration::from_millis(50));
+        opts.timeout(50).custom_spawner(fake.clone());
+        opts.build().expect("unable to create runc instance")
+    }
+
+    #[cfg(not(feature = "async"))]
+    #[test]
+    fn test_fake_runc() {
+        let fake = Arc::new(FakeRunc::new());
+        fake.respond("ps", FakeResponse::success("[1, 2]"))
+            .respond_once("ps", FakeResponse::success("[42]"))
+            .respond(
+                "exec",
+                FakeResponse::failure(1, "cannot exec in a stopped container"),
+            )
+            .respond(
+                "pause",
+                FakeResponse::default().delay(Duration::from_secs(10)),
+            );
+        let runc = fake_client(&fake);
+
+        assert_eq!(runc.ps("fake-id").unwrap(), [42]);
+        assert_eq!(runc.ps("fake-id").unwrap(), [1, 2]);
+        let process = oci_spec::runtime::Process::default();
+        assert!(matches!(
+            runc.exec("fake-id", &process, None),
+            Err(Error::ContainerNotRunning { .. })
+        ));
+        assert!(matches!(runc.pause("fake-id"), Err(Error::Timeout(_))));
+        runc.resume("fake-id").unwrap();
+
+        let calls = fake.calls();
+        assert_eq!(calls.len(), 5);
+        assert!(calls[4].ends_with(&["resume".to_string(), "fake-id".to_string()]));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_fake_runc() {
+        let fake = Arc::new(FakeRunc::new());
+        fake.respond("ps", FakeResponse::success("[1, 2]"))
+            .respond_once("ps", FakeResponse::success("[42]"))
+            .respond(
+                "exec",
+                FakeResponse::failure(1, "cannot exec in a stopped container"),
+            )
+            .respond(
+                "pause",
+                FakeResponse::default().delay(Duration::from_secs(10)),
+            );
+        let runc = fake_client(&fake);
+
+        assert_eq!(runc.ps("fake-id").await.unwrap(), [42]);
+        assert_eq!(runc.ps("fake-id").await.unwrap(), [1, 2]);
+        let process = oci_spec::runtime::Process::default();
+        assert!(matches!(
+            runc.exec("fake-id", &process, None).await,
+            Err(Error::ContainerNotRunning { .. })
+        ));
+        assert!(matches!(
+            runc.pause("fake-id").await,
+            Err(Error::CommandTimeout(_))
+        ));
+        runc.resume("fake-id").await.unwrap();
+
+        let calls = fake.calls();
+        assert_eq!(calls.len(), 5);
+        assert!(calls[4].ends_with(&["resume".to_string(), "fake-id".to_string()]));
+    }
+}
@@ -14,22 +14,18 @@
    limitations under the License.
 */
 
-#[cfg(not(feature = "async"))]
-use std::io::Write;
 use std::{
     env,
+    fs::OpenOptions,
+    os::unix::{fs::OpenOptionsExt, io::AsRawFd},
     path::{Path, PathBuf},
 };
 
 use path_absolutize::*;
-use serde::Serialize;
-#[cfg(not(feature = "async"))]
-use tempfile::{Builder, NamedTempFile};
-#[cfg(feature = "async")]
-use tokio::io::AsyncWriteExt;
+use serde::{de::DeserializeOwned, Serialize};
 use uuid::Uuid;
 
-use crate::error::Error;
+use crate::{error::Error, Version};
 
 // helper to resolve path (such as path for runc binary, pid files, etc. )
 pub fn abs_path_buf<P>(path: P) -> Result<PathBuf, Error>
@@ -70,11 +66,69 @@ fn xdg_runtime_dir() -> String {
         .unwrap_or_else(|_| abs_string(env::temp_dir()).unwrap_or_else(|_| ".".to_string()))
 }
 
+/// File in the runtime dir (`$XDG_RUNTIME_DIR`, else the temp dir), removed when dropped.
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl TempFile {
+    /// Create an empty file named `<prefix>-<uuid>` in the runtime dir, only accessible by its
+    /// owner.
+    pub fn new(prefix: &str) -> Result<Self, Error> {
+        let path = Self::new_path(Path::new(&xdg_runtime_dir()), prefix);
+        Ok(Self::create(path).map_err(Error::FileSystemError)?.0)
+    }
+
+    fn new_path(dir: &Path, prefix: &str) -> PathBuf {
+        dir.join(format!("{}-{}", prefix, Uuid::new_v4()))
+    }
+
+    /// Create the file at `path`, and return it along with its open handle.
+    fn create(path: PathBuf) -> std::io::Result<(Self, std::fs::File)> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+        Ok((Self { path, keep: false }, file))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Path of the file as a string, e.g. to pass it to runc.
+    pub fn path_string(&self) -> Result<String, Error> {
+        path_to_string(&self.path)
+    }
+
+    /// Leave the file in place when dropped, e.g. to inspect it after a failure.
+    pub fn keep(&mut self) {
+        self.keep = true;
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
 /// Write the serialized 'value' to a temp file
 #[cfg(not(feature = "async"))]
-pub fn write_value_to_temp_file<T: Serialize>(value: &T) -> Result<(NamedTempFile, String), Error> {
+#[deprecated(note = "use `write_value_to_temp_file_with_context`, which reports what failed")]
+#[allow(deprecated)]
+pub fn write_value_to_temp_file<T: Serialize>(
+    value: &T,
+) -> Result<(tempfile::NamedTempFile, String), Error> {
+    use std::io::Write;
+
     let filename = format!("{}/runc-process-{}", xdg_runtime_dir(), Uuid::new_v4());
-    let mut temp_file = Builder::new()
+    let mut temp_file = tempfile::Builder::new()
         .prefix(&filename)
         .rand_bytes(0)
         .tempfile()
@@ -94,11 +148,16 @@ pub fn write_value_to_temp_file<T: Serialize>(value: &T) -> Result<(NamedTempFil
 /// the async Drop is still not supported in rust,
 /// in async context, the created file should be removed by the caller
 #[cfg(feature = "async")]
+#[deprecated(note = "use `write_value_to_temp_file_with_context`, which removes the file")]
+#[allow(deprecated)]
 pub async fn write_value_to_temp_file<T: Serialize>(value: &T) -> Result<String, Error> {
+    use tokio::io::AsyncWriteExt;
+
     let filename = format!("{}/runc-process-{}", xdg_runtime_dir(), Uuid::new_v4());
     let mut f = tokio::fs::OpenOptions::new()
         .create(true)
         .write(true)
+        .truncate(true)
         .open(&filename)
         .await
         .map_err(Error::FileSystemError)?;
@@ -110,6 +169,394 @@ pub async fn write_value_to_temp_file<T: Serialize>(value: &T) -> Result<String,
     Ok(filename)
 }
 
+/// Write the serialized 'value' to a temp file, removed when the returned [TempFile] is dropped.
+///
+/// `context` tells what the value is for (e.g. "exec process") in the error if the file can't
+/// be written.
+#[cfg(not(feature = "async"))]
+pub fn write_value_to_temp_file_with_context<T: Serialize>(
+    value: &T,
+    context: &'static str,
+) -> Result<TempFile, Error> {
+    write_value_file(Path::new(&xdg_runtime_dir()), value, context)
+}
+
+/// Write the serialized 'value' to a temp file, removed when the returned [TempFile] is dropped.
+///
+/// `context` tells what the value is for (e.g. "exec process") in the error if the file can't
+/// be written.
+#[cfg(feature = "async")]
+pub async fn write_value_to_temp_file_with_context<T: Serialize>(
+    value: &T,
+    context: &'static str,
+) -> Result<TempFile, Error> {
+    write_value_file_async(Path::new(&xdg_runtime_dir()), value, context).await
+}
+
+/// Create a temp file in `dir` and stream the serialized `value` to it, without holding the
+/// whole serialization in memory.
+fn write_value_file<T: Serialize>(
+    dir: &Path,
+    value: &T,
+    context: &'static str,
+) -> Result<TempFile, Error> {
+    use std::io::Write;
+
+    let path = TempFile::new_path(dir, "runc-process");
+    let (temp, file) = TempFile::create(path.clone()).map_err(spec_file_failed(context, &path))?;
+    let mut writer = std::io::BufWriter::new(file);
+    serde_json::to_writer(&mut writer, value).map_err(|e| {
+        if e.is_io() {
+            spec_file_failed(context, &path)(e.into())
+        } else {
+            Error::JsonDeserializationFailed(e)
+        }
+    })?;
+    writer.flush().map_err(spec_file_failed(context, &path))?;
+    Ok(temp)
+}
+
+/// Like [write_value_file], from the blocking pool so that the runtime isn't blocked. The
+/// blocking task needs an owned `value`, so it's converted to a [serde_json::Value] first.
+#[cfg(feature = "async")]
+async fn write_value_file_async<T: Serialize>(
+    dir: &Path,
+    value: &T,
+    context: &'static str,
+) -> Result<TempFile, Error> {
+    let value = serde_json::to_value(value).map_err(Error::JsonDeserializationFailed)?;
+    let task_dir = dir.to_path_buf();
+    tokio::task::spawn_blocking(move || write_value_file(&task_dir, &value, context))
+        .await
+        .map_err(|e| {
+            spec_file_failed(context, dir)(std::io::Error::new(std::io::ErrorKind::Other, e))
+        })?
+}
+
+fn spec_file_failed(context: &'static str, path: &Path) -> impl FnOnce(std::io::Error) -> Error {
+    let path = path.to_path_buf();
+    move |source| Error::SpecFileWriteFailed {
+        context,
+        path,
+        source,
+    }
+}
+
+/// Write the serialized `value` to a new pipe and return its read end, or `None` if it doesn't
+/// fit in the pipe buffer, which would block the write until the pipe is read.
+pub(crate) fn write_value_to_pipe<T: Serialize>(
+    value: &T,
+    context: &'static str,
+) -> Result<Option<os_pipe::PipeReader>, Error> {
+    use std::io::Write;
+
+    use nix::fcntl::{fcntl, FcntlArg};
+
+    let spec_json = serde_json::to_vec(value).map_err(Error::JsonDeserializationFailed)?;
+    // The pipe is read by runc as /dev/stdin.
+    let failed = || spec_file_failed(context, Path::new("/dev/stdin"));
+    let (reader, mut writer) = os_pipe::pipe().map_err(failed())?;
+    let capacity =
+        fcntl(writer.as_raw_fd(), FcntlArg::F_GETPIPE_SZ).map_err(|e| failed()(e.into()))?;
+    if spec_json.len() > capacity as usize {
+        // Growing the pipe is capped by /proc/sys/fs/pipe-max-size for unprivileged callers.
+        let grown = fcntl(
+            writer.as_raw_fd(),
+            FcntlArg::F_SETPIPE_SZ(spec_json.len() as libc::c_int),
+        );
+        if grown.is_err() {
+            return Ok(None);
+        }
+    }
+    writer.write_all(&spec_json).map_err(failed())?;
+    Ok(Some(reader))
+}
+
+/// Whether a child can read its stdin as `/dev/stdin`, which is a link to `/proc/self/fd/0`
+/// and may be missing, e.g. in a minimal chroot.
+pub(crate) fn dev_stdin_available() -> bool {
+    std::fs::symlink_metadata("/dev/stdin").is_ok() && Path::new("/proc/self/fd").is_dir()
+}
+
+/// Read the pid written by runc to `path` (see `--pid-file`).
+#[cfg(not(feature = "async"))]
+pub fn read_pid_file<P: AsRef<Path>>(path: P) -> Result<u32, Error> {
+    let data = std::fs::read_to_string(path.as_ref()).map_err(Error::FileSystemError)?;
+    parse_pid(&data)
+}
+
+/// Read the pid written by runc to `path` (see `--pid-file`).
+#[cfg(feature = "async")]
+pub async fn read_pid_file<P: AsRef<Path>>(path: P) -> Result<u32, Error> {
+    let data = tokio::fs::read_to_string(path.as_ref())
+        .await
+        .map_err(Error::FileSystemError)?;
+    parse_pid(&data)
+}
+
+fn parse_pid(data: &str) -> Result<u32, Error> {
+    data.trim().parse().map_err(|e| {
+        Error::FileSystemError(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("invalid pid {:?}: {}", data, e),
+        ))
+    })
+}
+
+/// Mount point of the cgroup filesystems.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Path in the unified hierarchy, if any, and (controller, path) pairs of the v1 hierarchies.
+type CgroupPaths<'a> = (Option<&'a str>, Vec<(&'a str, &'a str)>);
+
+/// Split the content of a `/proc/<pid>/cgroup` file into its unified and v1 paths.
+fn parse_cgroup(cgroup: &str) -> Option<CgroupPaths<'_>> {
+    let mut unified = None;
+    let mut v1 = Vec::new();
+    for line in cgroup.lines() {
+        let mut fields = line.splitn(3, ':');
+        let (_, controllers, path) = (fields.next()?, fields.next()?, fields.next()?);
+        let path = path.trim_start_matches('/');
+        if controllers.is_empty() {
+            unified = Some(path);
+        } else {
+            for controller in controllers.split(',') {
+                v1.push((controller, path));
+            }
+        }
+    }
+    Some((unified, v1))
+}
+
+/// Return the path of the `cgroup.procs` file of the cgroup described by `cgroup`, the content
+/// of a `/proc/<pid>/cgroup` file.
+///
+/// On cgroup v1 (and hybrid) hosts, the `pids` hierarchy is preferred, then `memory`, and the
+/// unified hierarchy is used as a last resort.
+pub fn cgroup_procs_path(cgroup: &str) -> Option<PathBuf> {
+    let (unified, v1) = parse_cgroup(cgroup)?;
+    let root = Path::new(CGROUP_ROOT);
+    let v1_path = ["pids", "memory"].iter().find_map(|name| {
+        v1.iter()
+            .find(|(controller, _)| controller == name)
+            .map(|(controller, path)| root.join(controller).join(path))
+    });
+    let path = match (v1_path, unified) {
+        (Some(path), _) => path,
+        // Hybrid hosts mount the unified hierarchy separately.
+        (None, Some(path)) if !v1.is_empty() => root.join("unified").join(path),
+        (None, Some(path)) => root.join(path),
+        (None, None) => return None,
+    };
+    Some(path.join("cgroup.procs"))
+}
+
+/// Return the path of the file counting the OOM kills of the memory cgroup described by
+/// `cgroup`, the content of a `/proc/<pid>/cgroup` file.
+///
+/// That's `memory.oom_control` of the memory hierarchy on cgroup v1 hosts, and `memory.events`
+/// on cgroup v2 hosts. Hybrid hosts without a v1 memory hierarchy have no memory controller.
+pub fn oom_events_path(cgroup: &str) -> Option<PathBuf> {
+    match memory_cgroup(cgroup)? {
+        (dir, false) => Some(dir.join("memory.oom_control")),
+        (dir, true) => Some(dir.join("memory.events")),
+    }
+}
+
+/// Return the directory of the memory cgroup described by `cgroup`, the content of a
+/// `/proc/<pid>/cgroup` file, and whether it's a cgroup v2 one.
+fn memory_cgroup(cgroup: &str) -> Option<(PathBuf, bool)> {
+    let (unified, v1) = parse_cgroup(cgroup)?;
+    let root = Path::new(CGROUP_ROOT);
+    if let Some((_, path)) = v1.iter().find(|(controller, _)| *controller == "memory") {
+        return Some((root.join("memory").join(path), false));
+    }
+    match unified {
+        Some(path) if v1.is_empty() => Some((root.join(path), true)),
+        _ => None,
+    }
+}
+
+/// Return the paths of the files holding the memory usage and limit of the memory cgroup
+/// described by `cgroup`, the content of a `/proc/<pid>/cgroup` file.
+///
+/// That's `memory.usage_in_bytes` and `memory.limit_in_bytes` on cgroup v1 hosts, and
+/// `memory.current` and `memory.max` on cgroup v2 hosts.
+pub fn memory_stat_paths(cgroup: &str) -> Option<(PathBuf, PathBuf)> {
+    match memory_cgroup(cgroup)? {
+        (dir, false) => Some((
+            dir.join("memory.usage_in_bytes"),
+            dir.join("memory.limit_in_bytes"),
+        )),
+        (dir, true) => Some((dir.join("memory.current"), dir.join("memory.max"))),
+    }
+}
+
+/// Parse a memory limit file, `None` meaning unlimited: "max" with cgroup v2, a huge value with
+/// cgroup v1.
+pub fn parse_memory_limit(limit: &str) -> Result<Option<u64>, Error> {
+    match limit.trim() {
+        "max" => Ok(None),
+        limit => {
+            let limit: u64 = limit.parse().map_err(|_| {
+                Error::FileSystemError(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("invalid memory limit {:?}", limit),
+                ))
+            })?;
+            Ok(Some(limit).filter(|&l| l < crate::events::UNLIMITED_MEMORY))
+        }
+    }
+}
+
+/// Read the memory usage and limit of the memory cgroup of process `pid` from its cgroup files.
+pub fn read_memory_stat(pid: i32) -> Result<crate::events::MemStat, Error> {
+    let cgroup =
+        std::fs::read_to_string(format!("/proc/{}/cgroup", pid)).map_err(Error::FileSystemError)?;
+    let (usage, limit) = memory_stat_paths(&cgroup).ok_or_else(|| {
+        Error::FileSystemError(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("no memory cgroup for process {}", pid),
+        ))
+    })?;
+    let usage = std::fs::read_to_string(usage).map_err(Error::FileSystemError)?;
+    let limit = std::fs::read_to_string(limit).map_err(Error::FileSystemError)?;
+    Ok(crate::events::MemStat {
+        usage: usage.trim().parse().map_err(|_| {
+            Error::FileSystemError(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid memory usage {:?}", usage.trim()),
+            ))
+        })?,
+        limit: parse_memory_limit(&limit)?,
+    })
+}
+
+/// Parse the `oom_kill` counter of a `memory.oom_control` or `memory.events` file.
+pub fn parse_oom_kill(events: &str) -> Option<u64> {
+    events.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        match (fields.next(), fields.next()) {
+            (Some("oom_kill"), Some(count)) => count.parse().ok(),
+            _ => None,
+        }
+    })
+}
+
+/// Write `score`, already formatted, to the `oom_score_adj` of the calling process.
+///
+/// Meant to run in a forked child, it only makes raw syscalls: no allocation nor lock.
+pub(crate) fn write_oom_score_adj(score: &[u8]) -> std::io::Result<()> {
+    const PATH: &[u8] = b"/proc/self/oom_score_adj\0";
+    // Safety: PATH is nul-terminated and score outlives the write.
+    unsafe {
+        let fd = libc::open(
+            PATH.as_ptr() as *const libc::c_char,
+            libc::O_WRONLY | libc::O_CLOEXEC,
+        );
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let written = libc::write(fd, score.as_ptr() as *const libc::c_void, score.len());
+        let err = std::io::Error::last_os_error();
+        libc::close(fd);
+        if written < 0 {
+            return Err(err);
+        }
+        if written as usize != score.len() {
+            return Err(std::io::ErrorKind::WriteZero.into());
+        }
+    }
+    Ok(())
+}
+
+/// Read `pipe` to its end on a new thread, which gives up once `stop` is set: the pipe may be
+/// held open by a process outliving the one it was created for. `done` is notified when the
+/// thread returns.
+#[cfg(not(feature = "async"))]
+pub(crate) fn drain_pipe<R>(
+    mut pipe: R,
+    stop: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    done: std::sync::mpsc::Sender<()>,
+) -> std::thread::JoinHandle<std::io::Result<Vec<u8>>>
+where
+    R: std::io::Read + AsRawFd + Send + 'static,
+{
+    std::thread::spawn(move || {
+        let mut data = Vec::new();
+        let mut buf = [0u8; 8192];
+        let res = loop {
+            if stop.load(std::sync::atomic::Ordering::Relaxed) {
+                break Ok(data);
+            }
+            let mut fds = [libc::pollfd {
+                fd: pipe.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            }];
+            // Woken up regularly to check `stop`.
+            match unsafe { libc::poll(fds.as_mut_ptr(), 1, 50) } {
+                0 => continue,
+                n if n < 0 => {
+                    let err = std::io::Error::last_os_error();
+                    if err.kind() == std::io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    break Err(err);
+                }
+                _ => {}
+            }
+            match pipe.read(&mut buf) {
+                Ok(0) => break Ok(data),
+                Ok(n) => data.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => break Err(e),
+            }
+        };
+        let _ = done.send(());
+        res
+    })
+}
+
+/// Whether the child `pid` exited, without reaping it: until it's waited for, neither its pid
+/// nor the id of the process group it leads can be reused.
+#[cfg(not(feature = "async"))]
+pub(crate) fn has_exited(pid: u32) -> std::io::Result<bool> {
+    // Safety: siginfo_t is plain data, filled by waitid.
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let ret = unsafe {
+        libc::waitid(
+            libc::P_PID,
+            pid as libc::id_t,
+            &mut info,
+            libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+        )
+    };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // si_pid is left to 0 when the child is still running.
+    Ok(unsafe { info.si_pid() } != 0)
+}
+
+/// Parse the content of a `cgroup.procs` file.
+pub fn parse_pids(procs: &str) -> Vec<usize> {
+    procs
+        .lines()
+        .filter_map(|line| line.trim().parse().ok())
+        .collect()
+}
+
+/// Parse the JSON `output` of a runc command, which is `null` instead of e.g. an empty list when
+/// there's nothing to output, as Go encodes nil slices so: `T::default()` is returned then.
+pub fn parse_json_or_null<T: DeserializeOwned + Default>(output: &str) -> Result<T, Error> {
+    let output = output.trim();
+    if output == "null" {
+        return Ok(T::default());
+    }
+    serde_json::from_str(output).map_err(Error::JsonDeserializationFailed)
+}
+
 /// Resolve a binary path according to the `PATH` environment variable.
 ///
 /// Note, the case that `path` is already an absolute path is implicitly handled by
@@ -130,3 +577,713 @@ where
         })
     })
 }
+
+/// Messages printed by runc when execing into a container which is not running.
+const NOT_RUNNING_MESSAGES: &[&str] = &[
+    "cannot exec in a stopped container",
+    "cannot exec a container that has stopped",
+    "container not running",
+];
+
+/// Turn the failure of `runc exec` on a container which is not running into
+/// [Error::ContainerNotRunning], other errors are returned as is.
+pub fn map_exec_error(err: Error) -> Error {
+    match err {
+        Error::CommandFailed { stderr, .. }
+            if NOT_RUNNING_MESSAGES.iter().any(|m| stderr.contains(m)) =>
+        {
+            Error::ContainerNotRunning { stderr }
+        }
+        err => err,
+    }
+}
+
+/// Messages printed by runc when signaling a container whose init process has already exited.
+const PROCESS_DONE_MESSAGES: &[&str] = &[
+    "process already finished",
+    "container not running",
+    "no such process",
+];
+
+/// Whether `err` is the failure of `runc kill` on a container whose init process has already
+/// exited.
+pub fn is_process_done(err: &Error) -> bool {
+    matches!(err, Error::CommandFailed { stderr, .. }
+        if PROCESS_DONE_MESSAGES.iter().any(|m| stderr.contains(m)))
+}
+
+/// Messages printed by runc when creating a container with an id already in use.
+const ALREADY_EXISTS_MESSAGES: &[&str] = &[
+    "container with id exists",
+    "container with given ID already exists",
+];
+
+/// Whether `err` is the failure of `runc create` on an id which is already in use.
+pub fn is_already_exists(err: &Error) -> bool {
+    matches!(err, Error::CommandFailed { stderr, .. }
+        if ALREADY_EXISTS_MESSAGES.iter().any(|m| stderr.contains(m)))
+}
+
+/// Turn a failure of CRIU during `runc checkpoint` or `runc restore` into [Error::CriuFailed],
+/// other errors are returned as is.
+///
+/// runc reports the path of CRIU's log in its message, e.g.
+/// "criu failed: type RESTORE errno 0\nlog file: /run/runc/work/restore.log".
+pub fn map_criu_error(err: Error) -> Error {
+    match err {
+        Error::CommandFailed { stderr, .. } if stderr.contains("criu failed") => {
+            let log_path = stderr.split("log file: ").nth(1).and_then(|rest| {
+                let path: String = rest
+                    .chars()
+                    .take_while(|c| !c.is_whitespace() && *c != '"' && *c != '\\')
+                    .collect();
+                (!path.is_empty()).then(|| PathBuf::from(path))
+            });
+            Error::CriuFailed { log_path, stderr }
+        }
+        err => err,
+    }
+}
+
+/// Default state root of runc, used when [crate::options::GlobalOpts::root] isn't set.
+pub fn default_state_root() -> PathBuf {
+    default_root(!nix::unistd::geteuid().is_root())
+}
+
+/// Default state root of runc in rootless mode or not, like runc's own defaults:
+/// `$XDG_RUNTIME_DIR/runc` in rootless mode if `XDG_RUNTIME_DIR` is set, `/run/runc` otherwise.
+pub fn default_root(rootless: bool) -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if rootless && !dir.is_empty() => Path::new(&dir).join("runc"),
+        _ => PathBuf::from("/run/runc"),
+    }
+}
+
+/// Check that the filesystem of the state root `root` has at least `min_free` bytes available.
+///
+/// runc creates the state root on demand, so the closest existing ancestor is checked if `root`
+/// doesn't exist yet.
+pub fn check_state_root(root: &Path, min_free: u64) -> Result<(), Error> {
+    let existing = root.ancestors().find(|p| p.exists()).unwrap_or(root);
+    let stat =
+        nix::sys::statvfs::statvfs(existing).map_err(|e| Error::FileSystemError(e.into()))?;
+    let available = stat.blocks_available() as u64 * stat.fragment_size() as u64;
+    if available < min_free {
+        return Err(Error::StateDirFull {
+            path: root.to_path_buf(),
+            available,
+            required: min_free,
+        });
+    }
+    Ok(())
+}
+
+/// Return the container state directories of the state root `root`: its subdirectories holding
+/// runc's `state.json`, sorted. A missing root has none.
+pub fn state_dirs(root: &Path) -> Result<Vec<PathBuf>, Error> {
+    let entries = match std::fs::read_dir(root) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        entries => entries.map_err(Error::FileSystemError)?,
+    };
+    let mut dirs = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(Error::FileSystemError)?;
+        let is_dir = entry.file_type().map_err(Error::FileSystemError)?.is_dir();
+        if is_dir && entry.path().join("state.json").is_file() {
+            dirs.push(entry.path());
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// Longest container id: runc names the state directory of a container after its id.
+pub const MAX_ID_LEN: usize = 255;
+
+/// Check that `id` is a container id runc accepts: made of ASCII letters, digits, `_`, `+`, `-`
+/// and `.`, other than `.` and `..`, and at most [MAX_ID_LEN] long.
+pub fn validate_id(id: &str) -> Result<(), Error> {
+    let invalid = |reason| {
+        Err(Error::InvalidContainerId {
+            id: id.to_string(),
+            reason,
+        })
+    };
+    if id.is_empty() {
+        return invalid("it is empty");
+    }
+    if id.len() > MAX_ID_LEN {
+        return invalid("it is longer than 255 characters");
+    }
+    if id == "." || id == ".." {
+        return invalid("it is a special directory name");
+    }
+    let allowed = |c: char| c.is_ascii_alphanumeric() || "_+-.".contains(c);
+    if !id.chars().all(allowed) {
+        return invalid("only letters, digits, '_', '+', '-' and '.' are allowed");
+    }
+    Ok(())
+}
+
+/// Read the pid of the init process of container `id` from its state in `root`, which runc
+/// keeps once the container stopped, unlike `runc state`.
+pub fn read_init_pid(root: &Path, id: &str) -> Result<i32, Error> {
+    #[derive(serde::Deserialize)]
+    struct State {
+        init_process_pid: i32,
+    }
+
+    let state = std::fs::read_to_string(root.join(id).join("state.json"))
+        .map_err(Error::FileSystemError)?;
+    let state: State = serde_json::from_str(&state).map_err(Error::JsonDeserializationFailed)?;
+    Ok(state.init_process_pid)
+}
+
+/// Read the pid of the init process of container `id` from its state in `root`, if the
+/// container is running or paused.
+///
+/// Only the pid and start time of the init process are deserialized. Like runc, the container
+/// is considered created but not started while its `exec.fifo` exists, and stopped once its
+/// init process is gone, is a zombie or is another process with the same pid: `None` is
+/// returned then.
+pub fn read_running_init_pid(root: &Path, id: &str) -> Result<Option<i32>, Error> {
+    #[derive(serde::Deserialize)]
+    struct State {
+        init_process_pid: i32,
+        #[serde(default)]
+        init_process_start: u64,
+    }
+
+    let dir = root.join(id);
+    let state = std::fs::read_to_string(dir.join("state.json")).map_err(Error::FileSystemError)?;
+    let state: State = serde_json::from_str(&state).map_err(Error::JsonDeserializationFailed)?;
+    if state.init_process_pid <= 0 || dir.join("exec.fifo").exists() {
+        return Ok(None);
+    }
+    let stat = match std::fs::read_to_string(format!("/proc/{}/stat", state.init_process_pid)) {
+        Ok(stat) => stat,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(Error::FileSystemError(e)),
+    };
+    match parse_proc_stat(&stat) {
+        Some(('Z' | 'X', _)) => Ok(None),
+        Some((_, start)) if state.init_process_start != 0 && start != state.init_process_start => {
+            Ok(None)
+        }
+        _ => Ok(Some(state.init_process_pid)),
+    }
+}
+
+/// Parse the state and start time (in clock ticks since boot) of a process from its
+/// `/proc/<pid>/stat`.
+fn parse_proc_stat(stat: &str) -> Option<(char, u64)> {
+    // The command name may contain spaces and parentheses, the fields start after the last ')'.
+    let mut fields = stat.get(stat.rfind(')')? + 1..)?.split_whitespace();
+    let state = fields.next()?.chars().next()?;
+    // The start time is the 22nd field, the state being the 3rd.
+    let start = fields.nth(18)?.parse().ok()?;
+    Some((state, start))
+}
+
+/// The cgroup settings of runc's container config, as found in its `state.json`.
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct CgroupConfig {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub parent: String,
+    #[serde(default)]
+    pub path: String,
+    #[serde(default)]
+    pub scope_prefix: String,
+    #[serde(default, rename = "Systemd")]
+    pub systemd: bool,
+}
+
+/// Read the absolute path of the cgroup of container `id` from its state in `root`.
+///
+/// runc records the path of each hierarchy in `cgroup_paths`. When it doesn't, the path is
+/// derived from the cgroup config, see [cgroup_path_from_config].
+pub fn read_cgroup_path(root: &Path, id: &str) -> Result<PathBuf, Error> {
+    #[derive(serde::Deserialize)]
+    struct Config {
+        #[serde(default)]
+        cgroups: CgroupConfig,
+    }
+    #[derive(serde::Deserialize)]
+    struct State {
+        #[serde(default)]
+        cgroup_paths: std::collections::HashMap<String, PathBuf>,
+        config: Config,
+    }
+
+    let state = std::fs::read_to_string(root.join(id).join("state.json"))
+        .map_err(Error::FileSystemError)?;
+    let state: State = serde_json::from_str(&state).map_err(Error::JsonDeserializationFailed)?;
+    // Like for cgroup_procs_path, the pids hierarchy is preferred on cgroup v1 hosts.
+    let recorded = ["pids", "memory", ""]
+        .iter()
+        .find_map(|controller| state.cgroup_paths.get(*controller).cloned());
+    let unified = Path::new(CGROUP_ROOT).join("cgroup.controllers").exists();
+    recorded
+        .or_else(|| cgroup_path_from_config(&state.config.cgroups, unified))
+        .ok_or_else(|| {
+            Error::FileSystemError(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no cgroup path for container {}", id),
+            ))
+        })
+}
+
+/// Derive the absolute cgroup path runc places a container with the cgroup config `cgroups` in,
+/// on a cgroup v2 host if `unified`, or else in the `pids` hierarchy of a cgroup v1 host.
+///
+/// With the systemd driver, that's the scope `<scope_prefix>-<name>.scope` (or the slice `name`)
+/// in the slice `parent`, `system.slice` by default. Otherwise it's `path`, or `parent/name`.
+pub fn cgroup_path_from_config(cgroups: &CgroupConfig, unified: bool) -> Option<PathBuf> {
+    let path = if cgroups.systemd {
+        let parent = match cgroups.parent.as_str() {
+            "" => "system.slice",
+            parent => parent,
+        };
+        let unit = if cgroups.name.ends_with(".slice") {
+            cgroups.name.clone()
+        } else {
+            format!("{}-{}.scope", cgroups.scope_prefix, cgroups.name)
+        };
+        expand_slice(parent)?.join(unit)
+    } else if !cgroups.path.is_empty() {
+        PathBuf::from(&cgroups.path)
+    } else if !cgroups.name.is_empty() {
+        Path::new(&cgroups.parent).join(&cgroups.name)
+    } else {
+        return None;
+    };
+    let root = Path::new(CGROUP_ROOT);
+    let root = if unified {
+        root.into()
+    } else {
+        root.join("pids")
+    };
+    Some(root.join(path.strip_prefix("/").unwrap_or(&path)))
+}
+
+/// Expand a systemd slice name into its path in the cgroup tree, e.g. `a-b.slice` into
+/// `a.slice/a-b.slice`. The root slice `-.slice` is the root of the tree.
+fn expand_slice(slice: &str) -> Option<PathBuf> {
+    let name = slice.strip_suffix(".slice")?;
+    let mut path = PathBuf::new();
+    if name == "-" {
+        return Some(path);
+    }
+    let mut prefix = String::new();
+    for part in name.split('-') {
+        if part.is_empty() {
+            return None;
+        }
+        if !prefix.is_empty() {
+            prefix.push('-');
+        }
+        prefix.push_str(part);
+        path.push(format!("{}.slice", prefix));
+    }
+    Some(path)
+}
+
+/// Parse the output of `runc --version`.
+///
+/// Vendor-patched builds don't always print the version at the same place, e.g.
+/// "runc version 1.1.0+vendor" or "runc version (patched) v1.1.12", so the first semver-looking
+/// token of the "runc version" line is used.
+pub fn parse_version(output: &str) -> Result<Version, Error> {
+    let mut version: Option<Version> = None;
+    let mut commit = None;
+    let mut spec_version = None;
+    for line in output.lines().map(str::trim) {
+        if line.starts_with("runc version") {
+            version = Some(Version {
+                runc_version: semver_token(line),
+                spec_version: None,
+                commit: None,
+                raw: line.to_string(),
+            });
+        } else if let Some(c) = line.strip_prefix("commit:") {
+            commit = Some(c.trim().to_string());
+        } else if let Some(s) = line.strip_prefix("spec:") {
+            spec_version = Some(s.trim().to_string());
+        }
+    }
+    let mut version = version.ok_or(Error::InvalidVersion)?;
+    version.commit = commit;
+    version.spec_version = spec_version;
+    Ok(version)
+}
+
+// Return the first token looking like "<major>.<minor>[...]", with an optional "v" prefix.
+fn semver_token(line: &str) -> Option<String> {
+    line.split_whitespace().find_map(|token| {
+        let token = token
+            .trim_matches(|c: char| c == '(' || c == ')' || c == ',')
+            .trim_start_matches('v');
+        let mut parts = token.splitn(3, '.');
+        let major = parts.next()?;
+        let minor = parts.next()?;
+        if !major.is_empty()
+            && major.chars().all(|c| c.is_ascii_digit())
+            && minor.starts_with(|c: char| c.is_ascii_digit())
+        {
+            Some(token.to_string())
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_temp_file() {
+        let file = TempFile::new("runc-test").unwrap();
+        let path = file.path().to_path_buf();
+        assert!(path.starts_with(xdg_runtime_dir()));
+        assert!(path
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("runc-test-"));
+        assert_eq!(file.path_string().unwrap(), path.to_string_lossy());
+        drop(file);
+        assert!(!path.exists());
+
+        let mut file = TempFile::new("runc-test").unwrap();
+        file.keep();
+        let path = file.path().to_path_buf();
+        drop(file);
+        assert!(path.exists());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_write_value_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let process: oci_spec::runtime::Process = serde_json::from_str(
+            r#"{"user": {"uid": 1000, "gid": 1000}, "cwd": "/", "args": ["sh", "-c", "echo é"]}"#,
+        )
+        .unwrap();
+        let file = write_value_file(dir.path(), &process, "exec process").unwrap();
+        assert!(file.path().starts_with(dir.path()));
+        let written: oci_spec::runtime::Process =
+            serde_json::from_slice(&std::fs::read(file.path()).unwrap()).unwrap();
+        assert_eq!(written, process);
+
+        // The file is removed along with the TempFile.
+        let path = file.path().to_path_buf();
+        drop(file);
+        assert!(!path.exists());
+    }
+
+    #[cfg(not(feature = "async"))]
+    #[test]
+    #[allow(deprecated)]
+    fn test_deprecated_write_value_to_temp_file() {
+        let (file, filename) = write_value_to_temp_file(&vec!["sh"]).unwrap();
+        assert_eq!(file.path(), Path::new(&filename));
+        assert_eq!(std::fs::read_to_string(&filename).unwrap(), r#"["sh"]"#);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_write_value_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let process: oci_spec::runtime::Process = serde_json::from_str(
+            r#"{"user": {"uid": 1000, "gid": 1000}, "cwd": "/", "args": ["sh", "-c", "echo é"]}"#,
+        )
+        .unwrap();
+        let file = write_value_file_async(dir.path(), &process, "exec process")
+            .await
+            .unwrap();
+        assert!(file.path().starts_with(dir.path()));
+        let metadata = std::fs::metadata(file.path()).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        let written: oci_spec::runtime::Process =
+            serde_json::from_slice(&std::fs::read(file.path()).unwrap()).unwrap();
+        assert_eq!(written, process);
+
+        let not_a_dir = dir.path().join("file");
+        std::fs::write(&not_a_dir, "").unwrap();
+        match write_value_file_async(&not_a_dir, &"{}", "update resources").await {
+            Err(Error::SpecFileWriteFailed { context, path, .. }) => {
+                assert_eq!(context, "update resources");
+                assert!(path.starts_with(&not_a_dir));
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_spec_file_creation_failed() {
+        // A regular file as runtime dir fails the creation, even for root.
+        let dir = tempfile::tempdir().unwrap();
+        let not_a_dir = dir.path().join("file");
+        std::fs::write(&not_a_dir, "").unwrap();
+        match write_value_file(&not_a_dir, &"{}", "exec process") {
+            Err(Error::SpecFileWriteFailed {
+                context,
+                path,
+                source,
+            }) => {
+                assert_eq!(context, "exec process");
+                assert!(path.starts_with(&not_a_dir));
+                assert_eq!(source.raw_os_error(), Some(libc::ENOTDIR));
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let err = write_value_file(&not_a_dir, &"{}", "update resources").unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Failed to write the update resources spec file"));
+    }
+
+    #[test]
+    fn test_default_root() {
+        assert_eq!(default_root(false), Path::new("/run/runc"));
+        let rootless = match env::var("XDG_RUNTIME_DIR") {
+            Ok(dir) if !dir.is_empty() => Path::new(&dir).join("runc"),
+            _ => PathBuf::from("/run/runc"),
+        };
+        assert_eq!(default_root(true), rootless);
+    }
+
+    #[test]
+    fn test_cgroup_procs_path() {
+        let v2 = "0::/system.slice/runc-abc.scope\n";
+        assert_eq!(
+            cgroup_procs_path(v2).unwrap(),
+            Path::new("/sys/fs/cgroup/system.slice/runc-abc.scope/cgroup.procs")
+        );
+
+        let v1 =
+            "12:pids:/runc/abc\n4:memory:/runc/abc\n3:cpu,cpuacct:/runc/abc\n1:name=systemd:/\n";
+        assert_eq!(
+            cgroup_procs_path(v1).unwrap(),
+            Path::new("/sys/fs/cgroup/pids/runc/abc/cgroup.procs")
+        );
+
+        let hybrid = "3:cpu,cpuacct:/runc/abc\n1:name=systemd:/\n0::/runc/abc\n";
+        assert_eq!(
+            cgroup_procs_path(hybrid).unwrap(),
+            Path::new("/sys/fs/cgroup/unified/runc/abc/cgroup.procs")
+        );
+
+        assert!(cgroup_procs_path("").is_none());
+        assert_eq!(parse_pids("1\n42\n\n"), vec![1, 42]);
+    }
+
+    #[test]
+    fn test_oom_events_path() {
+        assert_eq!(
+            oom_events_path("0::/system.slice/runc-abc.scope\n").unwrap(),
+            Path::new("/sys/fs/cgroup/system.slice/runc-abc.scope/memory.events")
+        );
+        assert_eq!(
+            oom_events_path("12:pids:/runc/abc\n4:memory:/runc/abc\n0::/\n").unwrap(),
+            Path::new("/sys/fs/cgroup/memory/runc/abc/memory.oom_control")
+        );
+        assert!(oom_events_path("12:pids:/runc/abc\n0::/runc/abc\n").is_none());
+
+        let v2 = "low 0\nhigh 0\nmax 12\noom 3\noom_kill 2\noom_group_kill 0\n";
+        assert_eq!(parse_oom_kill(v2), Some(2));
+        let v1 = "oom_kill_disable 0\nunder_oom 0\noom_kill 5\n";
+        assert_eq!(parse_oom_kill(v1), Some(5));
+        assert_eq!(parse_oom_kill("oom_kill_disable 0\nunder_oom 0\n"), None);
+    }
+
+    #[test]
+    fn test_memory_stat_paths() {
+        assert_eq!(
+            memory_stat_paths("0::/system.slice/runc-abc.scope\n").unwrap(),
+            (
+                PathBuf::from("/sys/fs/cgroup/system.slice/runc-abc.scope/memory.current"),
+                PathBuf::from("/sys/fs/cgroup/system.slice/runc-abc.scope/memory.max")
+            )
+        );
+        assert_eq!(
+            memory_stat_paths("12:pids:/runc/abc\n4:memory:/runc/abc\n0::/\n").unwrap(),
+            (
+                PathBuf::from("/sys/fs/cgroup/memory/runc/abc/memory.usage_in_bytes"),
+                PathBuf::from("/sys/fs/cgroup/memory/runc/abc/memory.limit_in_bytes")
+            )
+        );
+        assert!(memory_stat_paths("12:pids:/runc/abc\n0::/runc/abc\n").is_none());
+
+        assert_eq!(parse_memory_limit("max\n").unwrap(), None);
+        assert_eq!(parse_memory_limit("9223372036854771712\n").unwrap(), None);
+        assert_eq!(parse_memory_limit("268435456\n").unwrap(), Some(268435456));
+        assert!(parse_memory_limit("lots").is_err());
+    }
+
+    #[test]
+    fn test_cgroup_path_from_config() {
+        let systemd = CgroupConfig {
+            name: "abc".to_string(),
+            parent: "machine-pods.slice".to_string(),
+            scope_prefix: "runc".to_string(),
+            systemd: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            cgroup_path_from_config(&systemd, true).unwrap(),
+            Path::new("/sys/fs/cgroup/machine.slice/machine-pods.slice/runc-abc.scope")
+        );
+        assert_eq!(
+            cgroup_path_from_config(&systemd, false).unwrap(),
+            Path::new("/sys/fs/cgroup/pids/machine.slice/machine-pods.slice/runc-abc.scope")
+        );
+        let default_slice = CgroupConfig {
+            parent: String::new(),
+            ..systemd
+        };
+        assert_eq!(
+            cgroup_path_from_config(&default_slice, true).unwrap(),
+            Path::new("/sys/fs/cgroup/system.slice/runc-abc.scope")
+        );
+        let root_slice = CgroupConfig {
+            name: "pod.slice".to_string(),
+            parent: "-.slice".to_string(),
+            ..default_slice
+        };
+        assert_eq!(
+            cgroup_path_from_config(&root_slice, true).unwrap(),
+            Path::new("/sys/fs/cgroup/pod.slice")
+        );
+        let bad_slice = CgroupConfig {
+            parent: "a--b.slice".to_string(),
+            ..root_slice
+        };
+        assert!(cgroup_path_from_config(&bad_slice, true).is_none());
+
+        let fs = CgroupConfig {
+            path: "/runc/abc".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            cgroup_path_from_config(&fs, true).unwrap(),
+            Path::new("/sys/fs/cgroup/runc/abc")
+        );
+        assert_eq!(
+            cgroup_path_from_config(&fs, false).unwrap(),
+            Path::new("/sys/fs/cgroup/pids/runc/abc")
+        );
+        let named = CgroupConfig {
+            name: "abc".to_string(),
+            parent: "runc".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            cgroup_path_from_config(&named, true).unwrap(),
+            Path::new("/sys/fs/cgroup/runc/abc")
+        );
+        assert!(cgroup_path_from_config(&CgroupConfig::default(), true).is_none());
+    }
+
+    #[test]
+    fn test_validate_id() {
+        for id in ["fake-id", "a", "pod_1.web+2", &"x".repeat(MAX_ID_LEN)] {
+            assert!(validate_id(id).is_ok(), "{} should be valid", id);
+        }
+        for id in [
+            "",
+            ".",
+            "..",
+            "a/b",
+            "../etc",
+            "with space",
+            "tab\t",
+            "émoji",
+            &"x".repeat(MAX_ID_LEN + 1),
+        ] {
+            match validate_id(id) {
+                Err(Error::InvalidContainerId { id: invalid, .. }) => assert_eq!(invalid, id),
+                res => panic!("{:?} should be invalid, got {:?}", id, res),
+            }
+        }
+    }
+
+    #[test]
+    fn test_check_state_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("not-created-yet");
+        check_state_root(&root, 0).unwrap();
+        check_state_root(&root, 1).unwrap();
+        match check_state_root(&root, u64::MAX) {
+            Err(Error::StateDirFull {
+                path,
+                available,
+                required,
+            }) => {
+                assert_eq!(path, root);
+                assert!(available > 0);
+                assert_eq!(required, u64::MAX);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_json_or_null() {
+        assert_eq!(
+            parse_json_or_null::<Vec<usize>>("null\n").unwrap(),
+            Vec::<usize>::new()
+        );
+        assert_eq!(
+            parse_json_or_null::<Vec<usize>>("[1, 42]\n").unwrap(),
+            vec![1, 42]
+        );
+        assert!(matches!(
+            parse_json_or_null::<Vec<usize>>("[1,"),
+            Err(Error::JsonDeserializationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_version() {
+        let v = parse_version(
+            "runc version 1.0.0-rc3\ncommit: 17f3e2a07439a024e54566774d597df9177ee216\nspec: 1.0.0-rc5-dev\n",
+        )
+        .unwrap();
+        assert_eq!(v.runc_version.as_deref(), Some("1.0.0-rc3"));
+        assert_eq!(
+            v.commit.as_deref(),
+            Some("17f3e2a07439a024e54566774d597df9177ee216")
+        );
+        assert_eq!(v.spec_version.as_deref(), Some("1.0.0-rc5-dev"));
+        assert_eq!(v.raw, "runc version 1.0.0-rc3");
+
+        let v = parse_version(
+            "runc version 1.1.0+vendor\ncommit: v1.1.0-0-g067aaf85\nspec: 1.0.2-dev\ngo: go1.17.6\nlibseccomp: 2.5.3\n",
+        )
+        .unwrap();
+        assert_eq!(v.runc_version.as_deref(), Some("1.1.0+vendor"));
+        assert_eq!(v.commit.as_deref(), Some("v1.1.0-0-g067aaf85"));
+        assert_eq!(v.spec_version.as_deref(), Some("1.0.2-dev"));
+
+        let v = parse_version("runc version (patched by vendor) v1.1.12\nspec: 1.0.2-dev").unwrap();
+        assert_eq!(v.runc_version.as_deref(), Some("1.1.12"));
+        assert_eq!(v.raw, "runc version (patched by vendor) v1.1.12");
+        assert!(v.commit.is_none());
+
+        let v = parse_version("runc version unknown\n").unwrap();
+        assert!(v.runc_version.is_none());
+        assert_eq!(v.raw, "runc version unknown");
+
+        assert!(matches!(
+            parse_version("crun version 1.8.4"),
+            Err(Error::InvalidVersion)
+        ));
+    }
+}